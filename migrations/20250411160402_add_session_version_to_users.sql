@@ -0,0 +1,4 @@
+-- Add migration script here
+-- Bumping the version invalidates every session opened by the user so far
+ALTER TABLE users
+    ADD COLUMN session_version INTEGER NOT NULL DEFAULT 0;
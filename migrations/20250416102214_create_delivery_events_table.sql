@@ -0,0 +1,15 @@
+-- Add migration script here
+CREATE TABLE delivery_events
+(
+    event_id         uuid        NOT NULL PRIMARY KEY,
+    record_type      TEXT        NOT NULL,
+    message_id       TEXT        NOT NULL,
+    subscriber_email TEXT        NOT NULL,
+    bounce_type      TEXT        NULL,
+    details          TEXT        NULL,
+    occurred_at      timestamptz NOT NULL,
+    received_at      timestamptz NOT NULL,
+    -- Providers retry webhooks: we only want to record an event once
+    UNIQUE (record_type, message_id, subscriber_email)
+);
+CREATE INDEX delivery_events_subscriber_email_idx ON delivery_events (subscriber_email);
@@ -0,0 +1,14 @@
+-- Add migration script here
+ALTER TABLE newsletter_issues ADD COLUMN slug TEXT NULL;
+-- The author of issues published before this migration is unknown
+ALTER TABLE newsletter_issues ADD COLUMN author_id uuid NULL REFERENCES users (user_id);
+
+-- Existing issues get a slug made of their title and the beginning of their id
+UPDATE newsletter_issues
+SET slug = concat_ws(
+    '-',
+    nullif(trim(BOTH '-' FROM regexp_replace(lower(title), '[^a-z0-9]+', '-', 'g')), ''),
+    left(newsletter_issue_id::text, 8)
+);
+ALTER TABLE newsletter_issues ALTER COLUMN slug SET NOT NULL;
+ALTER TABLE newsletter_issues ADD CONSTRAINT newsletter_issues_slug_key UNIQUE (slug);
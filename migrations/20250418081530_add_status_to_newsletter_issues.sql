@@ -0,0 +1,18 @@
+-- Add migration script here
+-- Issues go through a lifecycle: draft -> scheduled -> published.
+-- Everything stored so far went out as soon as it was created.
+ALTER TABLE newsletter_issues ADD COLUMN status TEXT NULL;
+ALTER TABLE newsletter_issues ADD COLUMN send_at timestamptz NULL;
+ALTER TABLE newsletter_issues ADD COLUMN created_at timestamptz NULL;
+UPDATE newsletter_issues SET status = 'published', created_at = published_at;
+ALTER TABLE newsletter_issues ALTER COLUMN status SET NOT NULL;
+ALTER TABLE newsletter_issues ALTER COLUMN created_at SET NOT NULL;
+
+-- Drafts and scheduled issues are not published yet: they get their
+-- publication date, and their slug, when they go out.
+ALTER TABLE newsletter_issues ALTER COLUMN published_at DROP NOT NULL;
+ALTER TABLE newsletter_issues ALTER COLUMN slug DROP NOT NULL;
+
+CREATE INDEX newsletter_issues_scheduled_idx
+    ON newsletter_issues (send_at)
+    WHERE status = 'scheduled';
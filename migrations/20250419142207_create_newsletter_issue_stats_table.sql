@@ -0,0 +1,18 @@
+-- Add migration script here
+CREATE TABLE newsletter_issue_stats (
+    newsletter_issue_id uuid NOT NULL
+        REFERENCES newsletter_issues (newsletter_issue_id),
+    n_queued INTEGER NOT NULL DEFAULT 0,
+    n_sent INTEGER NOT NULL DEFAULT 0,
+    n_failed INTEGER NOT NULL DEFAULT 0,
+    n_skipped_invalid INTEGER NOT NULL DEFAULT 0,
+    n_bounced INTEGER NOT NULL DEFAULT 0,
+    PRIMARY KEY (newsletter_issue_id)
+);
+
+-- Nothing was counted for the issues published so far
+INSERT INTO newsletter_issue_stats (newsletter_issue_id)
+SELECT newsletter_issue_id FROM newsletter_issues WHERE status = 'published';
+
+-- The issue an email event is about, when the email belongs to one
+ALTER TABLE delivery_events ADD COLUMN newsletter_issue_id uuid NULL;
@@ -0,0 +1,50 @@
+-- Add migration script here
+CREATE TABLE lists (
+    list_id uuid NOT NULL,
+    slug TEXT NOT NULL UNIQUE,
+    name TEXT NOT NULL,
+    created_at timestamptz NOT NULL,
+    PRIMARY KEY (list_id)
+);
+
+-- The list everybody has subscribed to so far
+INSERT INTO lists (list_id, slug, name, created_at)
+VALUES (gen_random_uuid(), 'newsletter', 'Newsletter', now());
+
+-- The status of a subscription is tracked for each list, the one in
+-- `subscriptions` is the one of the email address (e.g. `suppressed`).
+CREATE TABLE list_subscriptions (
+    list_id uuid NOT NULL
+        REFERENCES lists (list_id),
+    subscriber_id uuid NOT NULL
+        REFERENCES subscriptions (id) ON DELETE CASCADE,
+    status TEXT NOT NULL,
+    subscribed_at timestamptz NOT NULL,
+    PRIMARY KEY (list_id, subscriber_id)
+);
+
+INSERT INTO list_subscriptions (list_id, subscriber_id, status, subscribed_at)
+SELECT
+    (SELECT list_id FROM lists WHERE slug = 'newsletter'),
+    id,
+    -- Suppression applies to the address, whatever the list
+    CASE WHEN status = 'suppressed' THEN 'confirmed' ELSE status END,
+    subscribed_at
+FROM subscriptions;
+
+-- The list a subscription token confirms. Tokens without a list, e.g. sent
+-- again by an admin, confirm all the pending subscriptions of the subscriber.
+ALTER TABLE subscription_tokens ADD COLUMN list_id uuid NULL REFERENCES lists (list_id);
+UPDATE subscription_tokens SET list_id = (SELECT list_id FROM lists WHERE slug = 'newsletter');
+
+CREATE TABLE newsletter_issue_lists (
+    newsletter_issue_id uuid NOT NULL
+        REFERENCES newsletter_issues (newsletter_issue_id),
+    list_id uuid NOT NULL
+        REFERENCES lists (list_id),
+    PRIMARY KEY (newsletter_issue_id, list_id)
+);
+
+INSERT INTO newsletter_issue_lists (newsletter_issue_id, list_id)
+SELECT newsletter_issue_id, (SELECT list_id FROM lists WHERE slug = 'newsletter')
+FROM newsletter_issues;
@@ -0,0 +1,8 @@
+-- Add migration script here
+ALTER TABLE subscriptions
+    ADD COLUMN tags TEXT[] NOT NULL DEFAULT '{}',
+    ADD COLUMN attributes JSONB NOT NULL DEFAULT '{}';
+CREATE INDEX subscriptions_tags_idx ON subscriptions USING GIN (tags);
+
+-- Restricts the recipients of an issue, within its lists, e.g. `tag:rust AND country=DE`
+ALTER TABLE newsletter_issues ADD COLUMN segment TEXT NULL;
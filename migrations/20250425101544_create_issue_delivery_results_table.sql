@@ -0,0 +1,21 @@
+-- Add migration script here
+-- What happened to the delivery of an issue to each subscriber, once the
+-- delivery has left the queue
+CREATE TABLE issue_delivery_results
+(
+    newsletter_issue_id uuid        NOT NULL
+        REFERENCES newsletter_issues (newsletter_issue_id),
+    subscriber_email    TEXT        NOT NULL,
+    -- 'sent', 'failed', 'skipped_unsubscribed' or 'skipped_invalid'
+    outcome             TEXT        NOT NULL,
+    n_attempts          SMALLINT    NOT NULL,
+    recorded_at         timestamptz NOT NULL,
+    PRIMARY KEY (newsletter_issue_id, subscriber_email)
+);
+
+-- Successful deliveries were not recorded so far, failures were
+INSERT INTO issue_delivery_results (
+    newsletter_issue_id, subscriber_email, outcome, n_attempts, recorded_at
+)
+SELECT newsletter_issue_id, subscriber_email, 'failed', n_attempts, failed_at
+FROM issue_delivery_dead_letters;
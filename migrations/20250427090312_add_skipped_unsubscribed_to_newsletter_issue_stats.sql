@@ -0,0 +1,10 @@
+-- Add migration script here
+-- Deliveries skipped because the subscriber left after the issue was enqueued
+ALTER TABLE newsletter_issue_stats ADD COLUMN n_skipped_unsubscribed INTEGER NOT NULL DEFAULT 0;
+
+UPDATE newsletter_issue_stats s
+SET n_skipped_unsubscribed = (
+    SELECT COUNT(*)
+    FROM issue_delivery_results r
+    WHERE r.newsletter_issue_id = s.newsletter_issue_id AND r.outcome = 'skipped_unsubscribed'
+);
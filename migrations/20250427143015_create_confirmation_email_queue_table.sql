@@ -0,0 +1,9 @@
+-- Add migration script here
+-- Confirmation emails waiting to be sent by the background worker.
+-- Tokens that no longer exist or were consumed are dropped by the worker.
+CREATE TABLE confirmation_email_queue (
+    subscription_token TEXT NOT NULL,
+    n_retries SMALLINT NOT NULL DEFAULT 0,
+    execute_after timestamptz NOT NULL DEFAULT now(),
+    PRIMARY KEY (subscription_token)
+);
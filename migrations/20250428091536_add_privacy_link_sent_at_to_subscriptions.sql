@@ -0,0 +1,3 @@
+-- Add migration script here
+-- Privacy links are sent at most once in a while to each address
+ALTER TABLE subscriptions ADD COLUMN privacy_link_sent_at timestamptz NULL;
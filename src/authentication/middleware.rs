@@ -0,0 +1,88 @@
+use crate::authentication::get_session_version;
+use crate::session_state::TypedSession;
+use crate::utils::{e500, see_other};
+use actix_web::body::MessageBody;
+use actix_web::dev::{ServiceRequest, ServiceResponse};
+use actix_web::error::InternalError;
+use actix_web::middleware::Next;
+use actix_web::{web, FromRequest, HttpMessage};
+use anyhow::Context;
+use sqlx::PgPool;
+use std::ops::Deref;
+use uuid::Uuid;
+
+/// The id of the logged in user, injected in the request extensions
+/// by [`reject_anonymous_users`].
+#[derive(Copy, Clone, Debug)]
+pub struct UserId(Uuid);
+
+impl std::fmt::Display for UserId {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        self.0.fmt(f)
+    }
+}
+
+impl Deref for UserId {
+    type Target = Uuid;
+
+    fn deref(&self) -> &Self::Target {
+        &self.0
+    }
+}
+
+pub async fn reject_anonymous_users(
+    mut req: ServiceRequest,
+    next: Next<impl MessageBody>,
+) -> Result<ServiceResponse<impl MessageBody>, actix_web::Error> {
+    let session = {
+        let (http_request, payload) = req.parts_mut();
+        TypedSession::from_request(http_request, payload).await
+    }?;
+    let pool = req
+        .app_data::<web::Data<PgPool>>()
+        .cloned()
+        .context("The database pool is not registered as application data")
+        .map_err(e500)?;
+
+    match get_logged_in_user_id(&session, &pool).await.map_err(e500)? {
+        Some(user_id) => {
+            req.extensions_mut().insert(UserId(user_id));
+            next.call(req).await
+        }
+        None => {
+            let response = see_other("/login");
+            let e = anyhow::anyhow!("The user has not logged in");
+            Err(InternalError::from_response(e, response).into())
+        }
+    }
+}
+
+/// Retrieve the id of the user logged in with the given session.
+///
+/// Sessions opened before the user's last "log out everywhere" are purged
+/// and treated as anonymous.
+#[tracing::instrument(name = "Get logged in user id", skip(session, pool))]
+async fn get_logged_in_user_id(
+    session: &TypedSession,
+    pool: &PgPool,
+) -> Result<Option<Uuid>, anyhow::Error> {
+    let Some(user_id) = session
+        .get_user_id()
+        .context("Failed to retrieve the user id from the session")?
+    else {
+        return Ok(None);
+    };
+    let session_version = session
+        .get_session_version()
+        .context("Failed to retrieve the session version from the session")?;
+
+    match (session_version, get_session_version(user_id, pool).await?) {
+        (Some(session_version), Some(current_version)) if session_version == current_version => {
+            Ok(Some(user_id))
+        }
+        _ => {
+            session.log_out();
+            Ok(None)
+        }
+    }
+}
@@ -0,0 +1,7 @@
+mod middleware;
+mod password;
+mod session_version;
+
+pub use middleware::{reject_anonymous_users, UserId};
+pub use password::{change_password, validate_credentials, AuthError, Credentials};
+pub use session_version::{get_session_version, invalidate_sessions};
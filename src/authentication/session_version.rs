@@ -0,0 +1,42 @@
+use anyhow::Context;
+use sqlx::PgPool;
+use uuid::Uuid;
+
+#[tracing::instrument(name = "Get session version", skip(pool))]
+pub async fn get_session_version(
+    user_id: Uuid,
+    pool: &PgPool,
+) -> Result<Option<i32>, anyhow::Error> {
+    let session_version = sqlx::query!(
+        r#"
+        SELECT session_version
+        FROM users
+        WHERE user_id = $1
+        "#,
+        user_id
+    )
+    .fetch_optional(pool)
+    .await
+    .context("Failed to retrieve the session version")?
+    .map(|r| r.session_version);
+
+    Ok(session_version)
+}
+
+/// Invalidate all the sessions of the given user.
+#[tracing::instrument(name = "Invalidate all sessions", skip(pool))]
+pub async fn invalidate_sessions(user_id: Uuid, pool: &PgPool) -> Result<(), anyhow::Error> {
+    sqlx::query!(
+        r#"
+        UPDATE users
+        SET session_version = session_version + 1
+        WHERE user_id = $1
+        "#,
+        user_id
+    )
+    .execute(pool)
+    .await
+    .context("Failed to bump the session version")?;
+
+    Ok(())
+}
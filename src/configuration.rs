@@ -1,4 +1,8 @@
 use crate::domain::SubscriberEmail;
+use crate::email_client::{
+    EmailClient, FileEmailSender, InMemoryEmailSender, PostmarkEmailSender, RetryPolicy,
+    SmtpEmailSender,
+};
 use config::Config;
 use secrecy::{ExposeSecret, SecretString};
 use serde::Deserialize;
@@ -14,6 +18,14 @@ pub struct Settings {
     pub application: ApplicationSettings,
     pub email_client: EmailClientSettings,
     pub redis_uri: SecretString,
+    pub webhooks: WebhookSettings,
+}
+
+/// The credentials the email provider uses to call our webhooks.
+#[derive(Debug, Deserialize, Clone)]
+pub struct WebhookSettings {
+    pub username: String,
+    pub password: SecretString,
 }
 
 #[derive(Debug, Deserialize, Clone)]
@@ -34,14 +46,56 @@ pub struct ApplicationSettings {
     pub host: String,
     pub base_url: String,
     pub hmac_secret: SecretString,
+    /// How long a subscription confirmation link stays valid.
+    pub subscription_token_ttl_secs: u64,
+    /// Where the email templates are loaded from.
+    pub templates_directory: String,
+}
+
+impl ApplicationSettings {
+    pub fn subscription_token_ttl(&self) -> Duration {
+        Duration::from_secs(self.subscription_token_ttl_secs)
+    }
 }
 
 #[derive(Debug, Deserialize, Clone)]
 pub struct EmailClientSettings {
+    #[serde(default)]
+    pub transport: EmailTransport,
     pub base_url: String,
     pub sender_email: String,
     pub authorization_token: SecretString,
     pub timeout_ms: u64,
+    pub retry_max_attempts: u32,
+    pub retry_base_delay_ms: u64,
+    pub retry_max_delay_ms: u64,
+    /// Required when `transport` is `smtp`.
+    pub smtp: Option<SmtpSettings>,
+    /// Where emails are written when `transport` is `file`.
+    pub outbox_directory: Option<String>,
+}
+
+/// The backend used to deliver emails.
+#[derive(Debug, Deserialize, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum EmailTransport {
+    #[default]
+    Postmark,
+    Smtp,
+    File,
+    /// Keep the emails in memory: the ones sent by the server can be read
+    /// through `Application::email_outbox`.
+    InMemory,
+}
+
+#[derive(Debug, Deserialize, Clone)]
+pub struct SmtpSettings {
+    pub host: String,
+    #[serde(deserialize_with = "deserialize_number_from_string")]
+    pub port: u16,
+    pub username: Option<String>,
+    pub password: Option<SecretString>,
+    pub require_tls: bool,
 }
 
 impl DatabaseSettings {
@@ -103,6 +157,55 @@ impl EmailClientSettings {
     pub fn timeout(&self) -> Duration {
         Duration::from_millis(self.timeout_ms)
     }
+
+    pub fn retry_policy(&self) -> RetryPolicy {
+        RetryPolicy {
+            max_attempts: self.retry_max_attempts,
+            base_delay: Duration::from_millis(self.retry_base_delay_ms),
+            max_delay: Duration::from_millis(self.retry_max_delay_ms),
+        }
+    }
+
+    pub fn client(&self) -> EmailClient {
+        let sender_email = self.sender().expect("Incorrect sender email in settings");
+        match self.transport {
+            EmailTransport::Postmark => EmailClient::new(
+                sender_email,
+                PostmarkEmailSender::new(
+                    self.base_url.clone(),
+                    self.authorization_token.clone(),
+                    self.timeout(),
+                ),
+            ),
+            EmailTransport::Smtp => {
+                let smtp = self
+                    .smtp
+                    .as_ref()
+                    .expect("Missing SMTP settings for the smtp email transport");
+                let credentials = smtp.username.clone().map(|username| {
+                    let password = smtp.password.clone().unwrap_or_default();
+                    (username, password)
+                });
+                let transport = SmtpEmailSender::new(
+                    &smtp.host,
+                    smtp.port,
+                    credentials,
+                    smtp.require_tls,
+                    self.timeout(),
+                )
+                .expect("Invalid SMTP settings");
+                EmailClient::new(sender_email, transport)
+            }
+            EmailTransport::File => {
+                let directory = self
+                    .outbox_directory
+                    .clone()
+                    .expect("Missing outbox directory for the file email transport");
+                EmailClient::new(sender_email, FileEmailSender::new(directory))
+            }
+            EmailTransport::InMemory => EmailClient::new(sender_email, InMemoryEmailSender::new()),
+        }
+    }
 }
 
 pub fn get_configuration() -> Result<Settings, config::ConfigError> {
@@ -0,0 +1,194 @@
+//! Confirmation emails sent in the background.
+//!
+//! Imports can create thousands of pending subscribers at once: rather than
+//! sending their confirmation emails while the admin waits, they queue them
+//! along with the subscribers, and this worker drains the queue. Sign-ups
+//! queue theirs too: the response doesn't depend on whether an email went out.
+use crate::configuration::Settings;
+use crate::domain::SubscriberEmail;
+use crate::email_client::{EmailClient, RetryPolicy, SendEmailError};
+use crate::routes::send_confirmation_email;
+use crate::startup::get_connection_pool;
+use crate::templates::Templates;
+use chrono::Utc;
+use sqlx::{PgPool, Postgres, Transaction};
+use std::time::Duration;
+
+/// How many queued emails a worker claims at once.
+const BATCH_SIZE: i64 = 100;
+/// How long the emails claimed by a worker stay out of reach of the others.
+const CLAIM_DURATION: Duration = Duration::from_secs(5 * 60);
+
+pub async fn run_confirmation_worker_until_stopped(
+    configuration: Settings,
+) -> Result<(), anyhow::Error> {
+    let pool = get_connection_pool(&configuration.database);
+    let email_client = configuration.email_client.client();
+    let templates = Templates::load(&configuration.application.templates_directory)?;
+    let retry_policy = configuration.email_client.retry_policy();
+    loop {
+        match send_queued_confirmation_emails(
+            &pool,
+            &email_client,
+            &templates,
+            &retry_policy,
+            &configuration.application.base_url,
+        )
+        .await
+        {
+            Ok(0) => tokio::time::sleep(Duration::from_secs(10)).await,
+            Ok(_) => {}
+            Err(e) => {
+                tracing::error!(
+                    error.cause_chain = ?e,
+                    error.message = %e,
+                    "Failed to send queued confirmation emails",
+                );
+                tokio::time::sleep(Duration::from_secs(1)).await;
+            }
+        }
+    }
+}
+
+/// Queue the confirmation emails carrying the given subscription tokens.
+///
+/// A token that is already queued is only sent once.
+#[tracing::instrument(skip_all, fields(n_emails = subscription_tokens.len()))]
+pub async fn enqueue_confirmation_emails(
+    transaction: &mut Transaction<'_, Postgres>,
+    subscription_tokens: &[String],
+) -> Result<(), sqlx::Error> {
+    sqlx::query!(
+        r#"
+        INSERT INTO confirmation_email_queue (subscription_token)
+        SELECT * FROM UNNEST($1::text[])
+        ON CONFLICT (subscription_token) DO NOTHING
+        "#,
+        subscription_tokens
+    )
+    .execute(&mut **transaction)
+    .await?;
+
+    Ok(())
+}
+
+/// Send a batch of queued confirmation emails.
+///
+/// Failures are retried as the retry policy says, like issue deliveries.
+/// Emails we give up on are dropped: admins can still resend them from the
+/// page of the subscriber.
+///
+/// Returns the number of emails handled, 0 when the queue is empty.
+#[tracing::instrument(skip_all, err)]
+pub async fn send_queued_confirmation_emails(
+    pool: &PgPool,
+    email_client: &EmailClient,
+    templates: &Templates,
+    retry_policy: &RetryPolicy,
+    base_url: &str,
+) -> Result<usize, anyhow::Error> {
+    let claimed_until = Utc::now() + chrono::Duration::from_std(CLAIM_DURATION)?;
+    let tasks = sqlx::query!(
+        r#"
+        UPDATE confirmation_email_queue q
+        SET execute_after = $2
+        FROM (
+            SELECT subscription_token
+            FROM confirmation_email_queue
+            WHERE execute_after <= now()
+            FOR UPDATE
+            SKIP LOCKED
+            LIMIT $1
+        ) due
+        WHERE q.subscription_token = due.subscription_token
+        RETURNING q.subscription_token, q.n_retries
+        "#,
+        BATCH_SIZE,
+        claimed_until
+    )
+    .fetch_all(pool)
+    .await?;
+
+    for task in &tasks {
+        // Subscribers might have confirmed, or been removed, in the meantime
+        let subscriber = sqlx::query!(
+            r#"
+            SELECT s.email, s.name
+            FROM subscription_tokens t
+            JOIN subscriptions s ON s.id = t.subscriber_id
+            WHERE t.subscription_token = $1 AND t.consumed_at IS NULL
+            "#,
+            task.subscription_token
+        )
+        .fetch_optional(pool)
+        .await?;
+        let result = match subscriber {
+            Some(subscriber) => match SubscriberEmail::parse(subscriber.email) {
+                Ok(email) => {
+                    send_confirmation_email(
+                        email_client,
+                        templates,
+                        &email,
+                        &subscriber.name,
+                        base_url,
+                        &task.subscription_token,
+                    )
+                    .await
+                }
+                Err(e) => Err(anyhow::anyhow!(e)),
+            },
+            None => Ok(()),
+        };
+
+        let n_attempts = task.n_retries as u32 + 1;
+        match result {
+            Err(e) if is_retryable(&e) && !retry_policy.is_exhausted(n_attempts) => {
+                tracing::warn!(
+                    error.cause_chain = ?e,
+                    error.message = %e,
+                    n_attempts,
+                    "Failed to send a confirmation email, it will be retried",
+                );
+                let retry_after =
+                    Utc::now() + chrono::Duration::from_std(retry_policy.backoff(n_attempts))?;
+                sqlx::query!(
+                    r#"
+                    UPDATE confirmation_email_queue
+                    SET n_retries = $2, execute_after = $3
+                    WHERE subscription_token = $1
+                    "#,
+                    task.subscription_token,
+                    n_attempts as i16,
+                    retry_after
+                )
+                .execute(pool)
+                .await?;
+                continue;
+            }
+            Err(e) => {
+                tracing::error!(
+                    error.cause_chain = ?e,
+                    error.message = %e,
+                    n_attempts,
+                    "Giving up on a confirmation email",
+                );
+            }
+            Ok(()) => {}
+        }
+        sqlx::query!(
+            "DELETE FROM confirmation_email_queue WHERE subscription_token = $1",
+            task.subscription_token
+        )
+        .execute(pool)
+        .await?;
+    }
+
+    Ok(tasks.len())
+}
+
+/// Only failures of the email client can go away on their own: the others,
+/// e.g. an invalid address, would fail the same way every time.
+fn is_retryable(e: &anyhow::Error) -> bool {
+    e.downcast_ref::<SendEmailError>()
+        .is_some_and(SendEmailError::is_retryable)
+}
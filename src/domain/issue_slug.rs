@@ -0,0 +1,79 @@
+use unicode_segmentation::UnicodeSegmentation;
+
+/// The identifier of a newsletter issue in its public URL, e.g. `/issues/our-first-issue`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct IssueSlug(String);
+
+impl IssueSlug {
+    const MAX_LENGTH: usize = 80;
+
+    /// Derive a slug from the title of an issue: lowercase ASCII letters and
+    /// digits, with words separated by dashes.
+    pub fn from_title(title: &str) -> Self {
+        let mut slug = String::new();
+        for grapheme in title.graphemes(true) {
+            let c = grapheme.chars().next().unwrap_or_default();
+            if grapheme.len() == 1 && c.is_ascii_alphanumeric() {
+                slug.push(c.to_ascii_lowercase());
+            } else if !slug.is_empty() && !slug.ends_with('-') {
+                slug.push('-');
+            }
+            if slug.len() >= Self::MAX_LENGTH {
+                break;
+            }
+        }
+        let slug = slug.trim_end_matches('-');
+
+        if slug.is_empty() {
+            Self("issue".into())
+        } else {
+            Self(slug.to_owned())
+        }
+    }
+
+    /// Make the slug unique by appending a suffix, e.g. a piece of the issue id.
+    pub fn with_suffix(&self, suffix: &str) -> Self {
+        Self(format!("{}-{}", self.0, suffix))
+    }
+}
+
+impl AsRef<str> for IssueSlug {
+    fn as_ref(&self) -> &str {
+        &self.0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::IssueSlug;
+
+    #[test]
+    fn words_are_lowercased_and_separated_by_dashes() {
+        let slug = IssueSlug::from_title("Our First Issue");
+        assert_eq!(slug.as_ref(), "our-first-issue");
+    }
+
+    #[test]
+    fn punctuation_and_non_ascii_characters_are_dropped() {
+        let slug = IssueSlug::from_title("  Ça va? Rust 2025: what's new!  ");
+        assert_eq!(slug.as_ref(), "a-va-rust-2025-what-s-new");
+    }
+
+    #[test]
+    fn titles_without_any_usable_character_get_a_default_slug() {
+        assert_eq!(IssueSlug::from_title("").as_ref(), "issue");
+        assert_eq!(IssueSlug::from_title("ёжик 🦔").as_ref(), "issue");
+    }
+
+    #[test]
+    fn long_titles_are_truncated() {
+        let slug = IssueSlug::from_title(&"a".repeat(200));
+        assert_eq!(slug.as_ref().len(), 80);
+    }
+
+    #[test]
+    fn a_suffix_can_be_appended() {
+        let slug = IssueSlug::from_title("Our first issue").with_suffix("3f2a9c1b");
+        assert_eq!(slug.as_ref(), "our-first-issue-3f2a9c1b");
+    }
+}
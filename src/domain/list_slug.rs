@@ -0,0 +1,70 @@
+/// The identifier of a mailing list in its subscription URL, e.g. `/lists/rust-weekly/subscriptions`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ListSlug(String);
+
+impl ListSlug {
+    const MAX_LENGTH: usize = 50;
+
+    /// Slugs are made of lowercase ASCII letters and digits, with words
+    /// separated by single dashes.
+    pub fn parse(s: String) -> Result<ListSlug, String> {
+        let is_valid = !s.is_empty()
+            && s.len() <= Self::MAX_LENGTH
+            && s.split('-').all(|word| {
+                !word.is_empty()
+                    && word
+                        .chars()
+                        .all(|c| c.is_ascii_lowercase() || c.is_ascii_digit())
+            });
+
+        if is_valid {
+            Ok(Self(s))
+        } else {
+            Err(format!("{} is not a valid list slug.", s))
+        }
+    }
+}
+
+impl AsRef<str> for ListSlug {
+    fn as_ref(&self) -> &str {
+        &self.0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::ListSlug;
+    use claims::{assert_err, assert_ok};
+
+    #[test]
+    fn lowercase_words_separated_by_dashes_are_valid() {
+        assert_ok!(ListSlug::parse("rust-weekly-2025".into()));
+        assert_ok!(ListSlug::parse("newsletter".into()));
+    }
+
+    #[test]
+    fn empty_slugs_are_rejected() {
+        assert_err!(ListSlug::parse("".into()));
+    }
+
+    #[test]
+    fn uppercase_letters_and_punctuation_are_rejected() {
+        assert_err!(ListSlug::parse("Rust-Weekly".into()));
+        assert_err!(ListSlug::parse("rust_weekly".into()));
+        assert_err!(ListSlug::parse("rust weekly".into()));
+        assert_err!(ListSlug::parse("ça-va".into()));
+    }
+
+    #[test]
+    fn leading_trailing_and_repeated_dashes_are_rejected() {
+        assert_err!(ListSlug::parse("-rust".into()));
+        assert_err!(ListSlug::parse("rust-".into()));
+        assert_err!(ListSlug::parse("rust--weekly".into()));
+    }
+
+    #[test]
+    fn a_51_character_long_slug_is_rejected() {
+        assert_ok!(ListSlug::parse("a".repeat(50)));
+        assert_err!(ListSlug::parse("a".repeat(51)));
+    }
+}
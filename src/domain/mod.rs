@@ -1,7 +1,21 @@
+mod issue_slug;
+mod list_slug;
 mod new_subscriber;
+mod privacy_token;
+mod subscriber_attributes;
 mod subscriber_email;
 mod subscriber_name;
+mod subscriber_tag;
+mod subscription_token;
+mod unsubscribe_token;
 
+pub use issue_slug::IssueSlug;
+pub use list_slug::ListSlug;
 pub use new_subscriber::NewSubscriber;
+pub use privacy_token::PrivacyToken;
+pub use subscriber_attributes::{AttributeName, SubscriberAttributes};
 pub use subscriber_email::SubscriberEmail;
 pub use subscriber_name::SubscriberName;
+pub use subscriber_tag::SubscriberTag;
+pub use subscription_token::SubscriptionToken;
+pub use unsubscribe_token::{UnsubscribeToken, Unsubscription};
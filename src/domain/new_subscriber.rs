@@ -1,7 +1,9 @@
 use crate::domain::subscriber_name::SubscriberName;
-use crate::domain::SubscriberEmail;
+use crate::domain::{SubscriberAttributes, SubscriberEmail, SubscriberTag};
 
 pub struct NewSubscriber {
     pub email: SubscriberEmail,
     pub name: SubscriberName,
+    pub tags: Vec<SubscriberTag>,
+    pub attributes: SubscriberAttributes,
 }
@@ -0,0 +1,127 @@
+use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+use base64::Engine;
+use chrono::{DateTime, Utc};
+use hmac::{Hmac, Mac};
+use secrecy::{ExposeSecret, SecretString};
+use sha2::Sha256;
+use uuid::Uuid;
+
+/// A signed token giving access to the data we store about a subscriber,
+/// sent to them by email when they ask for it.
+///
+/// The token carries the subscriber id and an expiry date, followed by an
+/// HMAC-SHA256 tag of both. Unlike unsubscribe links, these links expire:
+/// whoever gets hold of one can read and erase the subscriber's data.
+#[derive(Debug)]
+pub struct PrivacyToken(String);
+
+impl PrivacyToken {
+    pub fn generate(subscriber_id: Uuid, expires_at: DateTime<Utc>, secret: &SecretString) -> Self {
+        let expires_at = expires_at.timestamp().to_be_bytes();
+        let tag = mac(subscriber_id, &expires_at, secret)
+            .finalize()
+            .into_bytes();
+        let mut bytes = subscriber_id.as_bytes().to_vec();
+        bytes.extend_from_slice(&expires_at);
+        bytes.extend_from_slice(&tag);
+        Self(URL_SAFE_NO_PAD.encode(bytes))
+    }
+
+    /// Check the signature and the expiry date of `token`, and return the
+    /// subscriber id it carries.
+    pub fn verify(token: &str, secret: &SecretString) -> Result<Uuid, anyhow::Error> {
+        let bytes = URL_SAFE_NO_PAD
+            .decode(token)
+            .map_err(|_| anyhow::anyhow!("The privacy token is not valid base64"))?;
+        if bytes.len() <= 24 {
+            anyhow::bail!("The privacy token is too short");
+        }
+        let (id, rest) = bytes.split_at(16);
+        let (expires_at, tag) = rest.split_at(8);
+        let subscriber_id = Uuid::from_slice(id)?;
+        mac(subscriber_id, expires_at, secret)
+            .verify_slice(tag)
+            .map_err(|_| anyhow::anyhow!("The privacy token has an invalid signature"))?;
+        let expires_at = i64::from_be_bytes(expires_at.try_into()?);
+        if expires_at < Utc::now().timestamp() {
+            anyhow::bail!("The privacy token has expired");
+        }
+
+        Ok(subscriber_id)
+    }
+}
+
+fn mac(subscriber_id: Uuid, expires_at: &[u8], secret: &SecretString) -> Hmac<Sha256> {
+    let mut mac = Hmac::<Sha256>::new_from_slice(secret.expose_secret().as_bytes())
+        .expect("HMAC can take a key of any size");
+    // Unsubscribe tokens must not give access to anybody's data.
+    mac.update(b"privacy");
+    mac.update(subscriber_id.as_bytes());
+    mac.update(expires_at);
+    mac
+}
+
+impl AsRef<str> for PrivacyToken {
+    fn as_ref(&self) -> &str {
+        &self.0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::PrivacyToken;
+    use crate::domain::UnsubscribeToken;
+    use chrono::{Duration, Utc};
+    use claims::{assert_err, assert_ok_eq};
+    use secrecy::SecretString;
+    use uuid::Uuid;
+
+    fn secret(s: &str) -> SecretString {
+        SecretString::new(Box::from(s))
+    }
+
+    #[test]
+    fn a_generated_token_is_verified() {
+        let subscriber_id = Uuid::new_v4();
+        let expires_at = Utc::now() + Duration::hours(1);
+        let token = PrivacyToken::generate(subscriber_id, expires_at, &secret("secret"));
+
+        assert_ok_eq!(
+            PrivacyToken::verify(token.as_ref(), &secret("secret")),
+            subscriber_id
+        );
+    }
+
+    #[test]
+    fn an_expired_token_is_rejected() {
+        let expires_at = Utc::now() - Duration::seconds(1);
+        let token = PrivacyToken::generate(Uuid::new_v4(), expires_at, &secret("secret"));
+
+        assert_err!(PrivacyToken::verify(token.as_ref(), &secret("secret")));
+    }
+
+    #[test]
+    fn a_token_signed_with_another_secret_is_rejected() {
+        let expires_at = Utc::now() + Duration::hours(1);
+        let token = PrivacyToken::generate(Uuid::new_v4(), expires_at, &secret("secret"));
+
+        assert_err!(PrivacyToken::verify(
+            token.as_ref(),
+            &secret("another-secret")
+        ));
+    }
+
+    #[test]
+    fn an_unsubscribe_token_is_rejected() {
+        let token = UnsubscribeToken::generate(Uuid::new_v4(), &secret("secret"));
+
+        assert_err!(PrivacyToken::verify(token.as_ref(), &secret("secret")));
+    }
+
+    #[test]
+    fn garbage_is_rejected() {
+        assert_err!(PrivacyToken::verify("", &secret("secret")));
+        assert_err!(PrivacyToken::verify("not a token", &secret("secret")));
+        assert_err!(PrivacyToken::verify("AAAA", &secret("secret")));
+    }
+}
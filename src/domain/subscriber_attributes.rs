@@ -0,0 +1,114 @@
+use serde_json::{Map, Value};
+
+/// The name of a custom attribute of subscribers, e.g. `country`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AttributeName(String);
+
+impl AttributeName {
+    const MAX_LENGTH: usize = 50;
+
+    /// Names start with a lowercase ASCII letter, followed by lowercase
+    /// ASCII letters, digits or underscores.
+    pub fn parse(s: &str) -> Result<AttributeName, String> {
+        let mut chars = s.chars();
+        let is_valid = s.len() <= Self::MAX_LENGTH
+            && chars.next().is_some_and(|c| c.is_ascii_lowercase())
+            && chars.all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '_');
+
+        if is_valid {
+            Ok(Self(s.to_owned()))
+        } else {
+            Err(format!("{} is not a valid attribute name.", s))
+        }
+    }
+}
+
+impl AsRef<str> for AttributeName {
+    fn as_ref(&self) -> &str {
+        &self.0
+    }
+}
+
+/// Custom attributes of a subscriber, stored as a JSON object.
+///
+/// Values keep their JSON type, which segments rely on to compare them:
+/// they are either text, numbers or booleans.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct SubscriberAttributes(Map<String, Value>);
+
+impl SubscriberAttributes {
+    pub fn parse(value: Value) -> Result<SubscriberAttributes, String> {
+        let Value::Object(attributes) = value else {
+            return Err("Attributes must be a JSON object.".into());
+        };
+        for (name, value) in &attributes {
+            AttributeName::parse(name)?;
+            if !matches!(value, Value::String(_) | Value::Number(_) | Value::Bool(_)) {
+                return Err(format!(
+                    "The value of {} must be a string, a number or a boolean.",
+                    name
+                ));
+            }
+        }
+        Ok(Self(attributes))
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (&String, &Value)> {
+        self.0.iter()
+    }
+}
+
+impl From<SubscriberAttributes> for Value {
+    fn from(attributes: SubscriberAttributes) -> Self {
+        Value::Object(attributes.0)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{AttributeName, SubscriberAttributes};
+    use claims::{assert_err, assert_ok};
+    use serde_json::json;
+
+    #[test]
+    fn attribute_names_are_snake_case_identifiers() {
+        assert_ok!(AttributeName::parse("country"));
+        assert_ok!(AttributeName::parse("signup_year_2025"));
+        assert_err!(AttributeName::parse(""));
+        assert_err!(AttributeName::parse("Country"));
+        assert_err!(AttributeName::parse("2fa"));
+        assert_err!(AttributeName::parse("sign-up"));
+        assert_err!(AttributeName::parse(&"a".repeat(51)));
+    }
+
+    #[test]
+    fn text_numbers_and_booleans_are_valid_values() {
+        assert_ok!(SubscriberAttributes::parse(json!({
+            "country": "DE",
+            "age": 42,
+            "score": 4.5,
+            "vip": true,
+        })));
+    }
+
+    #[test]
+    fn nested_and_null_values_are_rejected() {
+        assert_err!(SubscriberAttributes::parse(
+            json!({ "address": { "city": "Berlin" } })
+        ));
+        assert_err!(SubscriberAttributes::parse(
+            json!({ "languages": ["en", "de"] })
+        ));
+        assert_err!(SubscriberAttributes::parse(json!({ "country": null })));
+    }
+
+    #[test]
+    fn attributes_must_be_an_object_with_valid_names() {
+        assert_err!(SubscriberAttributes::parse(json!(["country"])));
+        assert_err!(SubscriberAttributes::parse(json!({ "Country": "DE" })));
+    }
+}
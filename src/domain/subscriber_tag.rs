@@ -0,0 +1,61 @@
+/// A label put on subscribers, e.g. `rust`, to target them in segments.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SubscriberTag(String);
+
+impl SubscriberTag {
+    const MAX_LENGTH: usize = 50;
+
+    /// Tags are case-insensitive: they are stored lowercased. They are made
+    /// of ASCII letters, digits, dashes and underscores.
+    pub fn parse(s: &str) -> Result<SubscriberTag, String> {
+        let tag = s.trim().to_ascii_lowercase();
+        let is_valid = !tag.is_empty()
+            && tag.len() <= Self::MAX_LENGTH
+            && tag
+                .chars()
+                .all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '-' || c == '_');
+
+        if is_valid {
+            Ok(Self(tag))
+        } else {
+            Err(format!("{} is not a valid tag.", s))
+        }
+    }
+}
+
+impl AsRef<str> for SubscriberTag {
+    fn as_ref(&self) -> &str {
+        &self.0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::SubscriberTag;
+    use claims::{assert_err, assert_ok};
+
+    #[test]
+    fn tags_are_trimmed_and_lowercased() {
+        let tag = SubscriberTag::parse(" Rust-2025_beta ").unwrap();
+        assert_eq!(tag.as_ref(), "rust-2025_beta");
+    }
+
+    #[test]
+    fn empty_tags_are_rejected() {
+        assert_err!(SubscriberTag::parse(""));
+        assert_err!(SubscriberTag::parse("   "));
+    }
+
+    #[test]
+    fn tags_with_spaces_or_punctuation_are_rejected() {
+        assert_err!(SubscriberTag::parse("rust lang"));
+        assert_err!(SubscriberTag::parse("rust:lang"));
+        assert_err!(SubscriberTag::parse("café"));
+    }
+
+    #[test]
+    fn a_51_character_long_tag_is_rejected() {
+        assert_ok!(SubscriberTag::parse(&"a".repeat(50)));
+        assert_err!(SubscriberTag::parse(&"a".repeat(51)));
+    }
+}
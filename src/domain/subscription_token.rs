@@ -0,0 +1,58 @@
+/// The token embedded in the link of the confirmation email.
+#[derive(Debug)]
+pub struct SubscriptionToken(String);
+
+impl SubscriptionToken {
+    const LENGTH: usize = 25;
+
+    /// Check that the string looks like a token we could have generated:
+    /// 25 alphanumeric characters.
+    pub fn parse(s: String) -> Result<SubscriptionToken, String> {
+        let has_valid_length = s.chars().count() == Self::LENGTH;
+        let is_alphanumeric = s.chars().all(|c| c.is_ascii_alphanumeric());
+
+        if has_valid_length && is_alphanumeric {
+            Ok(Self(s))
+        } else {
+            Err(format!("{} is not a valid subscription token.", s))
+        }
+    }
+}
+
+impl AsRef<str> for SubscriptionToken {
+    fn as_ref(&self) -> &str {
+        &self.0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::SubscriptionToken;
+    use claims::{assert_err, assert_ok};
+
+    #[test]
+    fn a_25_alphanumeric_characters_token_is_valid() {
+        let token = "aB3dE5gH7jK9mN1pQ3sT5vW7y".to_string();
+        assert_ok!(SubscriptionToken::parse(token));
+    }
+
+    #[test]
+    fn empty_string_is_rejected() {
+        assert_err!(SubscriptionToken::parse("".to_string()));
+    }
+
+    #[test]
+    fn a_token_shorter_or_longer_than_25_characters_is_rejected() {
+        assert_err!(SubscriptionToken::parse("a".repeat(24)));
+        assert_err!(SubscriptionToken::parse("a".repeat(26)));
+    }
+
+    #[test]
+    fn a_token_with_non_alphanumeric_characters_is_rejected() {
+        assert_err!(SubscriptionToken::parse(format!("{}-", "a".repeat(24))));
+        assert_err!(SubscriptionToken::parse(format!("{}é", "a".repeat(24))));
+        assert_err!(SubscriptionToken::parse(
+            "'; DROP TABLE users; --aa".to_string()
+        ));
+    }
+}
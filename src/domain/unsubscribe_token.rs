@@ -0,0 +1,167 @@
+use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+use base64::Engine;
+use hmac::{Hmac, Mac};
+use secrecy::{ExposeSecret, SecretString};
+use sha2::Sha256;
+use uuid::Uuid;
+
+/// A signed token identifying the subscriber that wants to leave, and the
+/// list they want to leave.
+///
+/// The token carries the subscriber id, the list id if there is one, followed
+/// by an HMAC-SHA256 tag of both: we can verify it later on without storing
+/// anything, and nobody can forge a token to unsubscribe somebody else without
+/// knowing our secret. Tokens without a list unsubscribe from all the lists.
+#[derive(Debug)]
+pub struct UnsubscribeToken(String);
+
+/// What an unsubscribe token asks for.
+#[derive(Debug, PartialEq, Eq)]
+pub struct Unsubscription {
+    pub subscriber_id: Uuid,
+    /// All the lists when `None`.
+    pub list_id: Option<Uuid>,
+}
+
+/// The length of an HMAC-SHA256 tag.
+const TAG_LENGTH: usize = 32;
+
+impl UnsubscribeToken {
+    /// A token to unsubscribe from all the lists.
+    pub fn generate(subscriber_id: Uuid, secret: &SecretString) -> Self {
+        Self::sign(subscriber_id.as_bytes().to_vec(), secret)
+    }
+
+    /// A token to unsubscribe from a single list.
+    pub fn generate_for_list(subscriber_id: Uuid, list_id: Uuid, secret: &SecretString) -> Self {
+        let mut payload = subscriber_id.as_bytes().to_vec();
+        payload.extend_from_slice(list_id.as_bytes());
+        Self::sign(payload, secret)
+    }
+
+    fn sign(mut payload: Vec<u8>, secret: &SecretString) -> Self {
+        let tag = mac(&payload, secret).finalize().into_bytes();
+        payload.extend_from_slice(&tag);
+        Self(URL_SAFE_NO_PAD.encode(payload))
+    }
+
+    /// Check the signature of `token` and return what it asks for.
+    pub fn verify(token: &str, secret: &SecretString) -> Result<Unsubscription, anyhow::Error> {
+        let bytes = URL_SAFE_NO_PAD
+            .decode(token)
+            .map_err(|_| anyhow::anyhow!("The unsubscribe token is not valid base64"))?;
+        let (payload, tag) = bytes.split_at(bytes.len().saturating_sub(TAG_LENGTH));
+        let (id, list_id) = match payload.len() {
+            16 => (payload, None),
+            32 => (&payload[..16], Some(&payload[16..])),
+            _ => anyhow::bail!("The unsubscribe token has an invalid length"),
+        };
+        mac(payload, secret)
+            .verify_slice(tag)
+            .map_err(|_| anyhow::anyhow!("The unsubscribe token has an invalid signature"))?;
+
+        Ok(Unsubscription {
+            subscriber_id: Uuid::from_slice(id)?,
+            list_id: list_id.map(Uuid::from_slice).transpose()?,
+        })
+    }
+}
+
+fn mac(payload: &[u8], secret: &SecretString) -> Hmac<Sha256> {
+    let mut mac = Hmac::<Sha256>::new_from_slice(secret.expose_secret().as_bytes())
+        .expect("HMAC can take a key of any size");
+    // Keep the tags we issue for unsubscribing apart from anything else
+    // we might sign with the same secret.
+    mac.update(b"unsubscribe");
+    mac.update(payload);
+    mac
+}
+
+impl AsRef<str> for UnsubscribeToken {
+    fn as_ref(&self) -> &str {
+        &self.0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{UnsubscribeToken, Unsubscription};
+    use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+    use base64::Engine;
+    use claims::{assert_err, assert_ok_eq};
+    use secrecy::SecretString;
+    use uuid::Uuid;
+
+    fn secret(s: &str) -> SecretString {
+        SecretString::new(Box::from(s))
+    }
+
+    #[test]
+    fn a_generated_token_is_verified() {
+        let subscriber_id = Uuid::new_v4();
+        let token = UnsubscribeToken::generate(subscriber_id, &secret("secret"));
+
+        assert_ok_eq!(
+            UnsubscribeToken::verify(token.as_ref(), &secret("secret")),
+            Unsubscription {
+                subscriber_id,
+                list_id: None
+            }
+        );
+    }
+
+    #[test]
+    fn a_token_for_a_list_carries_the_list() {
+        let subscriber_id = Uuid::new_v4();
+        let list_id = Uuid::new_v4();
+        let token = UnsubscribeToken::generate_for_list(subscriber_id, list_id, &secret("secret"));
+
+        assert_ok_eq!(
+            UnsubscribeToken::verify(token.as_ref(), &secret("secret")),
+            Unsubscription {
+                subscriber_id,
+                list_id: Some(list_id)
+            }
+        );
+    }
+
+    #[test]
+    fn the_list_cannot_be_removed_from_a_token() {
+        let subscriber_id = Uuid::new_v4();
+        let token =
+            UnsubscribeToken::generate_for_list(subscriber_id, Uuid::new_v4(), &secret("secret"));
+        // The subscriber id of the token, followed by its tag
+        let mut bytes = URL_SAFE_NO_PAD.decode(token.as_ref()).unwrap();
+        bytes.drain(16..32);
+        let forged = URL_SAFE_NO_PAD.encode(bytes);
+
+        assert_err!(UnsubscribeToken::verify(&forged, &secret("secret")));
+    }
+
+    #[test]
+    fn a_token_signed_with_another_secret_is_rejected() {
+        let token = UnsubscribeToken::generate(Uuid::new_v4(), &secret("secret"));
+
+        assert_err!(UnsubscribeToken::verify(
+            token.as_ref(),
+            &secret("another-secret")
+        ));
+    }
+
+    #[test]
+    fn a_token_for_another_subscriber_is_rejected() {
+        let token = UnsubscribeToken::generate(Uuid::new_v4(), &secret("secret"));
+        let other_token = UnsubscribeToken::generate(Uuid::new_v4(), &secret("secret"));
+        // Pair the id of the second subscriber with the tag of the first one
+        let forged = format!("{}{}", &other_token.as_ref()[..22], &token.as_ref()[22..]);
+
+        assert_err!(UnsubscribeToken::verify(&forged, &secret("secret")));
+    }
+
+    #[test]
+    fn garbage_is_rejected() {
+        assert_err!(UnsubscribeToken::verify("", &secret("secret")));
+        assert_err!(UnsubscribeToken::verify("not a token", &secret("secret")));
+        assert_err!(UnsubscribeToken::verify("AAAA", &secret("secret")));
+    }
+}
@@ -0,0 +1,73 @@
+use crate::email_client::message::to_mime_message;
+use crate::email_client::{Email, EmailSender, SendEmailError};
+use std::path::PathBuf;
+use uuid::Uuid;
+
+/// Write emails to a directory instead of sending them, one `.eml` file per email.
+///
+/// Handy for local development: the files can be opened with any mail client.
+pub struct FileEmailSender {
+    directory: PathBuf,
+}
+
+impl FileEmailSender {
+    pub fn new(directory: impl Into<PathBuf>) -> Self {
+        Self {
+            directory: directory.into(),
+        }
+    }
+}
+
+#[async_trait::async_trait]
+impl EmailSender for FileEmailSender {
+    async fn send(&self, email: &Email<'_>) -> Result<(), SendEmailError> {
+        let message = to_mime_message(email)?;
+        let path = self.directory.join(format!("{}.eml", Uuid::new_v4()));
+
+        tokio::fs::create_dir_all(&self.directory)
+            .await
+            .map_err(|e| SendEmailError::Transient(Box::new(e)))?;
+        tokio::fs::write(&path, message.formatted())
+            .await
+            .map_err(|e| SendEmailError::Transient(Box::new(e)))?;
+        tracing::info!(path = %path.display(), "Email written to disk");
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::domain::SubscriberEmail;
+    use crate::email_client::{EmailClient, FileEmailSender};
+    use claims::assert_ok;
+    use uuid::Uuid;
+
+    #[tokio::test]
+    async fn emails_are_written_to_the_directory() {
+        // Arrange
+        let directory = std::env::temp_dir().join(Uuid::new_v4().to_string());
+        let sender = SubscriberEmail::parse("newsletter@example.com".into()).unwrap();
+        let email_client = EmailClient::new(sender, FileEmailSender::new(&directory));
+        let recipient = SubscriberEmail::parse("ursula_le_guin@example.com".into()).unwrap();
+
+        // Act
+        let outcome = email_client
+            .send_email(&recipient, "Newsletter title", "<p>Hello</p>", "Hello")
+            .await;
+
+        // Assert
+        assert_ok!(outcome);
+        let files = std::fs::read_dir(&directory)
+            .unwrap()
+            .map(|entry| entry.unwrap().path())
+            .collect::<Vec<_>>();
+        assert_eq!(files.len(), 1);
+        assert_eq!(files[0].extension().unwrap(), "eml");
+        let content = std::fs::read_to_string(&files[0]).unwrap();
+        assert!(content.contains("To: ursula_le_guin@example.com"));
+        assert!(content.contains("Subject: Newsletter title"));
+
+        std::fs::remove_dir_all(directory).unwrap();
+    }
+}
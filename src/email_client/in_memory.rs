@@ -0,0 +1,87 @@
+use crate::email_client::{Email, EmailSender, SendEmailError};
+use std::sync::{Arc, Mutex};
+
+/// Keep emails in memory instead of sending them.
+///
+/// Clones share the same outbox: hand a clone over to the `EmailClient`
+/// and keep one around to inspect what has been sent.
+#[derive(Clone, Default)]
+pub struct InMemoryEmailSender {
+    outbox: Arc<Mutex<Vec<SentEmail>>>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct SentEmail {
+    pub from: String,
+    pub to: String,
+    pub subject: String,
+    pub html_body: String,
+    pub text_body: String,
+    pub headers: Vec<(String, String)>,
+    pub metadata: Vec<(String, String)>,
+}
+
+impl InMemoryEmailSender {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn sent_emails(&self) -> Vec<SentEmail> {
+        self.outbox.lock().unwrap().clone()
+    }
+}
+
+#[async_trait::async_trait]
+impl EmailSender for InMemoryEmailSender {
+    async fn send(&self, email: &Email<'_>) -> Result<(), SendEmailError> {
+        let sent_email = SentEmail {
+            from: email.from.as_ref().to_owned(),
+            to: email.to.as_ref().to_owned(),
+            subject: email.subject.to_owned(),
+            html_body: email.html_body.to_owned(),
+            text_body: email.text_body.to_owned(),
+            headers: email
+                .headers
+                .iter()
+                .map(|h| (h.name.to_owned(), h.value.to_owned()))
+                .collect(),
+            metadata: email
+                .metadata
+                .iter()
+                .map(|(key, value)| (key.to_string(), value.to_string()))
+                .collect(),
+        };
+        self.outbox.lock().unwrap().push(sent_email);
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::domain::SubscriberEmail;
+    use crate::email_client::{EmailClient, InMemoryEmailSender};
+    use claims::assert_ok;
+
+    #[tokio::test]
+    async fn sent_emails_are_recorded() {
+        // Arrange
+        let outbox = InMemoryEmailSender::new();
+        let sender = SubscriberEmail::parse("newsletter@example.com".into()).unwrap();
+        let email_client = EmailClient::new(sender, outbox.clone());
+        let recipient = SubscriberEmail::parse("ursula_le_guin@example.com".into()).unwrap();
+
+        // Act
+        let outcome = email_client
+            .send_email(&recipient, "Newsletter title", "<p>Hello</p>", "Hello")
+            .await;
+
+        // Assert
+        assert_ok!(outcome);
+        let sent_emails = outbox.sent_emails();
+        assert_eq!(sent_emails.len(), 1);
+        assert_eq!(sent_emails[0].to, "ursula_le_guin@example.com");
+        assert_eq!(sent_emails[0].subject, "Newsletter title");
+        assert_eq!(sent_emails[0].text_body, "Hello");
+    }
+}
@@ -0,0 +1,35 @@
+use crate::email_client::{Email, SendEmailError};
+use lettre::message::header::{HeaderName, HeaderValue};
+use lettre::message::{Mailbox, MultiPart};
+use lettre::Message;
+
+/// Turn an email into a MIME message, with both the plain text and the HTML
+/// alternatives of the body.
+///
+/// Failures are permanent: sending the same email again would fail the same way.
+pub(super) fn to_mime_message(email: &Email<'_>) -> Result<Message, SendEmailError> {
+    let from: Mailbox = email
+        .from
+        .as_ref()
+        .parse()
+        .map_err(|e| SendEmailError::Permanent(Box::new(e)))?;
+    let to: Mailbox = email
+        .to
+        .as_ref()
+        .parse()
+        .map_err(|e| SendEmailError::Permanent(Box::new(e)))?;
+
+    let mut builder = Message::builder().from(from).to(to).subject(email.subject);
+    for header in email.headers {
+        let name = HeaderName::new_from_ascii(header.name.to_owned())
+            .map_err(|e| SendEmailError::Permanent(Box::new(e)))?;
+        builder = builder.raw_header(HeaderValue::new(name, header.value.to_owned()));
+    }
+
+    builder
+        .multipart(MultiPart::alternative_plain_html(
+            email.text_body.to_owned(),
+            email.html_body.to_owned(),
+        ))
+        .map_err(|e| SendEmailError::Permanent(Box::new(e)))
+}
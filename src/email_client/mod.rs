@@ -0,0 +1,207 @@
+use crate::domain::SubscriberEmail;
+use rand::Rng;
+use std::time::Duration;
+
+mod file;
+mod in_memory;
+mod message;
+mod postmark;
+mod smtp;
+
+pub use file::FileEmailSender;
+pub use in_memory::{InMemoryEmailSender, SentEmail};
+pub use postmark::PostmarkEmailSender;
+pub use smtp::SmtpEmailSender;
+
+/// A transport able to deliver emails: an email provider API, an SMTP relay, etc.
+#[async_trait::async_trait]
+pub trait EmailSender: Send + Sync {
+    async fn send(&self, email: &Email<'_>) -> Result<(), SendEmailError>;
+
+    /// Send several emails at once, returning the outcome of each email,
+    /// in the same order.
+    ///
+    /// Transports without a bulk API send the emails one by one.
+    async fn send_batch(&self, emails: &[Email<'_>]) -> Vec<Result<(), SendEmailError>> {
+        let mut outcomes = Vec::with_capacity(emails.len());
+        for email in emails {
+            outcomes.push(self.send(email).await);
+        }
+        outcomes
+    }
+}
+
+/// An email, ready to be handed over to an [`EmailSender`].
+pub struct Email<'a> {
+    pub from: &'a SubscriberEmail,
+    pub to: &'a SubscriberEmail,
+    pub subject: &'a str,
+    pub html_body: &'a str,
+    pub text_body: &'a str,
+    pub headers: &'a [EmailHeader<'a>],
+    /// Key/value pairs the recipient doesn't see, that the email provider
+    /// reports back with the events about the email (bounces, etc.).
+    /// Transports without such a feature ignore them.
+    pub metadata: &'a [(&'a str, &'a str)],
+}
+
+/// A custom header to be set on an outgoing email.
+#[derive(Debug, serde::Serialize)]
+#[serde(rename_all = "PascalCase")]
+pub struct EmailHeader<'a> {
+    pub name: &'a str,
+    pub value: &'a str,
+}
+
+/// One of the emails of a batch, see [`EmailClient::send_batch`].
+pub struct BatchEmail<'a> {
+    pub recipient: &'a SubscriberEmail,
+    pub subject: &'a str,
+    pub html_content: &'a str,
+    pub text_content: &'a str,
+    pub headers: &'a [EmailHeader<'a>],
+    pub metadata: &'a [(&'a str, &'a str)],
+}
+
+/// Send emails on behalf of our sender address, over the configured transport.
+pub struct EmailClient {
+    sender: SubscriberEmail,
+    transport: Box<dyn EmailSender>,
+}
+
+#[derive(thiserror::Error, Debug)]
+pub enum SendEmailError {
+    #[error("Failed to send an email, the attempt can be retried.")]
+    Transient(#[source] Box<dyn std::error::Error + Send + Sync>),
+    #[error("The email was rejected by the email provider.")]
+    Permanent(#[source] Box<dyn std::error::Error + Send + Sync>),
+}
+
+impl SendEmailError {
+    pub fn is_retryable(&self) -> bool {
+        matches!(self, SendEmailError::Transient(_))
+    }
+}
+
+/// Exponential backoff with jitter for failed email deliveries.
+#[derive(Debug, Clone)]
+pub struct RetryPolicy {
+    pub max_attempts: u32,
+    pub base_delay: Duration,
+    pub max_delay: Duration,
+}
+
+impl RetryPolicy {
+    /// How long to wait before the next attempt, given the number of attempts
+    /// that have failed so far.
+    ///
+    /// The delay doubles with every failed attempt (capped at `max_delay`) and
+    /// a random jitter spreads retries of the same issue over time.
+    pub fn backoff(&self, n_failed_attempts: u32) -> Duration {
+        let exponent = n_failed_attempts.saturating_sub(1).min(31);
+        let delay = self
+            .base_delay
+            .saturating_mul(1 << exponent)
+            .min(self.max_delay);
+        let half_delay = delay / 2;
+        half_delay + rand::thread_rng().gen_range(Duration::ZERO..=half_delay)
+    }
+
+    pub fn is_exhausted(&self, n_attempts: u32) -> bool {
+        n_attempts >= self.max_attempts
+    }
+}
+
+impl EmailClient {
+    pub fn new(sender: SubscriberEmail, transport: impl EmailSender + 'static) -> Self {
+        EmailClient {
+            sender,
+            transport: Box::new(transport),
+        }
+    }
+
+    pub async fn send_email(
+        &self,
+        recipient: &SubscriberEmail,
+        subject: &str,
+        html_content: &str,
+        text_content: &str,
+    ) -> Result<(), SendEmailError> {
+        self.send_email_with_headers(recipient, subject, html_content, text_content, &[])
+            .await
+    }
+
+    pub async fn send_email_with_headers(
+        &self,
+        recipient: &SubscriberEmail,
+        subject: &str,
+        html_content: &str,
+        text_content: &str,
+        headers: &[EmailHeader<'_>],
+    ) -> Result<(), SendEmailError> {
+        let email = Email {
+            from: &self.sender,
+            to: recipient,
+            subject,
+            html_body: html_content,
+            text_body: text_content,
+            headers,
+            metadata: &[],
+        };
+        self.transport.send(&email).await
+    }
+
+    /// Send several emails at once, using the bulk API of the transport if it has one.
+    ///
+    /// Each email gets its own outcome, in the same order as `emails`:
+    /// a failure for one recipient doesn't fail the whole batch.
+    pub async fn send_batch(&self, emails: &[BatchEmail<'_>]) -> Vec<Result<(), SendEmailError>> {
+        let emails = emails
+            .iter()
+            .map(|email| Email {
+                from: &self.sender,
+                to: email.recipient,
+                subject: email.subject,
+                html_body: email.html_content,
+                text_body: email.text_content,
+                headers: email.headers,
+                metadata: email.metadata,
+            })
+            .collect::<Vec<_>>();
+        self.transport.send_batch(&emails).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::email_client::RetryPolicy;
+    use std::time::Duration;
+
+    #[test]
+    fn backoff_grows_exponentially_and_is_capped() {
+        let policy = RetryPolicy {
+            max_attempts: 10,
+            base_delay: Duration::from_secs(1),
+            max_delay: Duration::from_secs(60),
+        };
+
+        for (n_failed_attempts, expected_delay) in [(1, 1), (2, 2), (3, 4), (7, 60), (30, 60)] {
+            let expected_delay = Duration::from_secs(expected_delay);
+            let delay = policy.backoff(n_failed_attempts);
+            assert!(delay <= expected_delay);
+            assert!(delay >= expected_delay / 2);
+        }
+    }
+
+    #[test]
+    fn policy_is_exhausted_after_max_attempts() {
+        let policy = RetryPolicy {
+            max_attempts: 3,
+            base_delay: Duration::from_secs(1),
+            max_delay: Duration::from_secs(60),
+        };
+
+        assert!(!policy.is_exhausted(2));
+        assert!(policy.is_exhausted(3));
+    }
+}
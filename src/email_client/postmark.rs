@@ -0,0 +1,602 @@
+use crate::email_client::{Email, EmailHeader, EmailSender, SendEmailError};
+use reqwest::{Client, StatusCode, Url};
+use secrecy::{ExposeSecret, SecretString};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::time::Duration;
+
+/// The maximum number of messages accepted by Postmark's batch endpoint.
+const MAX_BATCH_SIZE: usize = 500;
+
+/// Deliver emails through Postmark's HTTP API.
+pub struct PostmarkEmailSender {
+    http_client: Client,
+    base_url: String,
+    authorization_token: SecretString,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "PascalCase")]
+struct SendEmailRequest<'a> {
+    from: &'a str,
+    to: &'a str,
+    subject: &'a str,
+    html_body: &'a str,
+    text_body: &'a str,
+    #[serde(skip_serializing_if = "<[_]>::is_empty")]
+    headers: &'a [EmailHeader<'a>],
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    metadata: BTreeMap<&'a str, &'a str>,
+}
+
+impl<'a> SendEmailRequest<'a> {
+    fn new(email: &Email<'a>) -> Self {
+        Self {
+            from: email.from.as_ref(),
+            to: email.to.as_ref(),
+            subject: email.subject,
+            html_body: email.html_body,
+            text_body: email.text_body,
+            headers: email.headers,
+            metadata: email.metadata.iter().copied().collect(),
+        }
+    }
+}
+
+/// The outcome of one of the messages of a batch.
+#[derive(Deserialize)]
+#[serde(rename_all = "PascalCase")]
+struct BatchMessageResult {
+    error_code: i64,
+    message: String,
+}
+
+/// A message of a batch was rejected by Postmark.
+#[derive(thiserror::Error, Debug)]
+#[error("Postmark rejected the email (error code {error_code}): {message}")]
+struct RejectedEmail {
+    error_code: i64,
+    message: String,
+}
+
+impl From<reqwest::Error> for SendEmailError {
+    fn from(e: reqwest::Error) -> Self {
+        match e.status() {
+            // The provider is overloaded or having issues: try again later.
+            Some(status) if status.is_server_error() || status == StatusCode::TOO_MANY_REQUESTS => {
+                SendEmailError::Transient(e.into())
+            }
+            // Any other status code means that the provider rejected the email,
+            // sending the very same request again won't help.
+            Some(_) => SendEmailError::Permanent(e.into()),
+            // We failed to build the request: it is a bug on our side.
+            None if e.is_builder() => SendEmailError::Permanent(e.into()),
+            // The provider accepted the request but we can't make sense of its
+            // response: sending it again could deliver the same emails twice.
+            None if e.is_decode() => SendEmailError::Permanent(e.into()),
+            // Timeouts, connection failures, etc.
+            None => SendEmailError::Transient(e.into()),
+        }
+    }
+}
+
+impl PostmarkEmailSender {
+    pub fn new(base_url: String, authorization_token: SecretString, timeout: Duration) -> Self {
+        let http_client = Client::builder().timeout(timeout).build().unwrap();
+
+        Self {
+            http_client,
+            base_url,
+            authorization_token,
+        }
+    }
+
+    fn url(&self, path: &str) -> Url {
+        Url::parse(&self.base_url).unwrap().join(path).unwrap()
+    }
+
+    /// Send up to `MAX_BATCH_SIZE` emails with a single call to the batch endpoint.
+    async fn send_chunk(&self, emails: &[Email<'_>]) -> Vec<Result<(), SendEmailError>> {
+        let results = match self.try_send_chunk(emails).await {
+            Ok(results) => results,
+            Err(e) => {
+                // The whole request failed: every email of the chunk shares its fate.
+                let is_retryable = e.is_retryable();
+                let description = format!("{:#}", anyhow::Error::from(e));
+                return emails
+                    .iter()
+                    .map(|_| {
+                        let source = description.clone().into();
+                        Err(if is_retryable {
+                            SendEmailError::Transient(source)
+                        } else {
+                            SendEmailError::Permanent(source)
+                        })
+                    })
+                    .collect();
+            }
+        };
+
+        let mut results = results.into_iter();
+        emails
+            .iter()
+            .map(|_| match results.next() {
+                Some(result) if result.error_code == 0 => Ok(()),
+                Some(result) => Err(SendEmailError::Permanent(Box::new(RejectedEmail {
+                    error_code: result.error_code,
+                    message: result.message,
+                }))),
+                None => Err(SendEmailError::Permanent(
+                    "Postmark did not report the outcome of the email".into(),
+                )),
+            })
+            .collect()
+    }
+
+    async fn try_send_chunk(
+        &self,
+        emails: &[Email<'_>],
+    ) -> Result<Vec<BatchMessageResult>, SendEmailError> {
+        let request_body = emails.iter().map(SendEmailRequest::new).collect::<Vec<_>>();
+
+        let results = self
+            .http_client
+            .post(self.url("/email/batch"))
+            .header(
+                "X-Postmark-Server-Token",
+                self.authorization_token.expose_secret(),
+            )
+            .json(&request_body)
+            .send()
+            .await?
+            .error_for_status()?
+            .json()
+            .await?;
+
+        Ok(results)
+    }
+}
+
+#[async_trait::async_trait]
+impl EmailSender for PostmarkEmailSender {
+    async fn send(&self, email: &Email<'_>) -> Result<(), SendEmailError> {
+        let request_body = SendEmailRequest::new(email);
+
+        self.http_client
+            .post(self.url("/email"))
+            .header(
+                "X-Postmark-Server-Token",
+                self.authorization_token.expose_secret(),
+            )
+            .json(&request_body)
+            .send()
+            .await?
+            .error_for_status()?;
+
+        Ok(())
+    }
+
+    async fn send_batch(&self, emails: &[Email<'_>]) -> Vec<Result<(), SendEmailError>> {
+        // No need for the batch endpoint to send a single email
+        if let [email] = emails {
+            return vec![self.send(email).await];
+        }
+
+        let mut outcomes = Vec::with_capacity(emails.len());
+        for chunk in emails.chunks(MAX_BATCH_SIZE) {
+            outcomes.extend(self.send_chunk(chunk).await);
+        }
+        outcomes
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::domain::SubscriberEmail;
+    use crate::email_client::{BatchEmail, EmailClient, EmailHeader, PostmarkEmailSender};
+    use claims::{assert_err, assert_ok};
+    use fake::faker::internet::en::SafeEmail;
+    use fake::faker::lorem::en::{Paragraph, Sentence};
+    use fake::{Fake, Faker};
+    use secrecy::SecretString;
+    use std::time::Duration;
+    use wiremock::matchers::{any, body_partial_json, header, header_exists, method, path};
+    use wiremock::{Match, Mock, MockServer, Request, ResponseTemplate};
+
+    struct SendEmailBodyMatcher;
+
+    impl Match for SendEmailBodyMatcher {
+        fn matches(&self, request: &wiremock::Request) -> bool {
+            let json_result: Result<serde_json::Value, _> = serde_json::from_slice(&request.body);
+
+            dbg!(&json_result);
+
+            if let Ok(json) = json_result {
+                json.get("From").is_some()
+                    && json.get("To").is_some()
+                    && json.get("Subject").is_some()
+                    && json.get("HtmlBody").is_some()
+                    && json.get("TextBody").is_some()
+            } else {
+                false
+            }
+        }
+    }
+
+    fn subject() -> String {
+        Sentence(1..2).fake()
+    }
+
+    fn content() -> String {
+        Paragraph(1..10).fake()
+    }
+
+    fn email() -> SubscriberEmail {
+        SubscriberEmail::parse(SafeEmail().fake()).unwrap()
+    }
+
+    fn email_client(base_url: String) -> EmailClient {
+        let sender = SubscriberEmail::parse(SafeEmail().fake()).unwrap();
+
+        EmailClient::new(
+            sender,
+            PostmarkEmailSender::new(
+                base_url,
+                SecretString::new(Box::from(Faker.fake::<String>())),
+                Duration::from_millis(100),
+            ),
+        )
+    }
+
+    #[tokio::test]
+    async fn test_send_email_fires_expected_request() {
+        // Setup
+        let mock_server = MockServer::start().await;
+        let email_client = email_client(mock_server.uri());
+
+        Mock::given(header_exists("X-Postmark-Server-Token"))
+            .and(header("Content-Type", "application/json"))
+            .and(path("/email"))
+            .and(method("POST"))
+            .and(SendEmailBodyMatcher)
+            .respond_with(ResponseTemplate::new(200))
+            .expect(1)
+            .mount(&mock_server)
+            .await;
+
+        // Act
+        let _ = email_client
+            .send_email(&email(), &subject(), &content(), &content())
+            .await;
+
+        // Assert
+    }
+
+    #[tokio::test]
+    async fn custom_headers_are_sent_to_the_provider() {
+        // Arrange
+        let mock_server = MockServer::start().await;
+        let email_client = email_client(mock_server.uri());
+
+        Mock::given(path("/email"))
+            .and(body_partial_json(serde_json::json!({
+                "Headers": [{"Name": "List-Unsubscribe", "Value": "<https://example.com>"}]
+            })))
+            .respond_with(ResponseTemplate::new(200))
+            .expect(1)
+            .mount(&mock_server)
+            .await;
+
+        // Act
+        let outcome = email_client
+            .send_email_with_headers(
+                &email(),
+                &subject(),
+                &content(),
+                &content(),
+                &[EmailHeader {
+                    name: "List-Unsubscribe",
+                    value: "<https://example.com>",
+                }],
+            )
+            .await;
+
+        // Assert
+        assert_ok!(outcome);
+    }
+
+    #[tokio::test]
+    async fn metadata_is_sent_to_the_provider() {
+        // Arrange
+        let mock_server = MockServer::start().await;
+        let email_client = email_client(mock_server.uri());
+        let recipient = email();
+        let emails = [BatchEmail {
+            recipient: &recipient,
+            subject: "Subject",
+            html_content: "<p>Body</p>",
+            text_content: "Body",
+            headers: &[],
+            metadata: &[("newsletter_issue_id", "42")],
+        }];
+
+        Mock::given(path("/email"))
+            .and(body_partial_json(serde_json::json!({
+                "Metadata": {"newsletter_issue_id": "42"}
+            })))
+            .respond_with(ResponseTemplate::new(200))
+            .expect(1)
+            .mount(&mock_server)
+            .await;
+
+        // Act
+        let outcomes = email_client.send_batch(&emails).await;
+
+        // Assert
+        assert_ok!(&outcomes[0]);
+    }
+
+    #[tokio::test]
+    async fn send_email_succeeds_if_the_server_returns_200() {
+        // Arrange
+        let mock_server = MockServer::start().await;
+        let email_client = email_client(mock_server.uri());
+
+        Mock::given(any())
+            .respond_with(ResponseTemplate::new(200))
+            .expect(1)
+            .mount(&mock_server)
+            .await;
+
+        // Act
+        let outcome = email_client
+            .send_email(&email(), &subject(), &content(), &content())
+            .await;
+
+        // Assert
+        assert_ok!(outcome);
+    }
+
+    #[tokio::test]
+    async fn send_email_fails_if_the_server_returns_500() {
+        // Arrange
+        let mock_server = MockServer::start().await;
+        let email_client = email_client(mock_server.uri());
+
+        Mock::given(any())
+            .respond_with(ResponseTemplate::new(500))
+            .expect(1)
+            .mount(&mock_server)
+            .await;
+
+        // Act
+        let outcome = email_client
+            .send_email(&email(), &subject(), &content(), &content())
+            .await;
+
+        // Assert
+        assert_err!(outcome);
+    }
+
+    #[tokio::test]
+    async fn send_email_times_out_if_the_server_takes_too_long() {
+        // Arrange
+        let mock_server = MockServer::start().await;
+        let email_client = email_client(mock_server.uri());
+
+        let response = ResponseTemplate::new(200)
+            // 3 minutes!
+            .set_delay(Duration::from_secs(180));
+
+        Mock::given(any())
+            .respond_with(response)
+            .expect(1)
+            .mount(&mock_server)
+            .await;
+
+        // Act
+        let outcome = email_client
+            .send_email(&email(), &subject(), &content(), &content())
+            .await;
+
+        // Assert
+        assert_err!(outcome);
+    }
+
+    #[tokio::test]
+    async fn send_email_fails_with_a_retryable_error_if_the_server_returns_429() {
+        // Arrange
+        let mock_server = MockServer::start().await;
+        let email_client = email_client(mock_server.uri());
+
+        Mock::given(any())
+            .respond_with(ResponseTemplate::new(429))
+            .expect(1)
+            .mount(&mock_server)
+            .await;
+
+        // Act
+        let outcome = email_client
+            .send_email(&email(), &subject(), &content(), &content())
+            .await;
+
+        // Assert
+        assert!(assert_err!(outcome).is_retryable());
+    }
+
+    #[tokio::test]
+    async fn send_email_fails_with_a_permanent_error_if_the_server_returns_422() {
+        // Arrange
+        let mock_server = MockServer::start().await;
+        let email_client = email_client(mock_server.uri());
+
+        Mock::given(any())
+            .respond_with(ResponseTemplate::new(422))
+            .expect(1)
+            .mount(&mock_server)
+            .await;
+
+        // Act
+        let outcome = email_client
+            .send_email(&email(), &subject(), &content(), &content())
+            .await;
+
+        // Assert
+        assert!(!assert_err!(outcome).is_retryable());
+    }
+
+    /// Reply to a batch request as Postmark would, rejecting the messages
+    /// sent to `rejected_recipient`.
+    fn batch_response(request: &Request, rejected_recipient: &str) -> ResponseTemplate {
+        let messages: Vec<serde_json::Value> = serde_json::from_slice(&request.body).unwrap();
+        let results = messages
+            .iter()
+            .map(|message| {
+                if message["To"] == rejected_recipient {
+                    serde_json::json!({
+                        "ErrorCode": 406,
+                        "Message": "You tried to send to a recipient that has been marked as inactive."
+                    })
+                } else {
+                    serde_json::json!({ "ErrorCode": 0, "Message": "OK" })
+                }
+            })
+            .collect::<Vec<_>>();
+        ResponseTemplate::new(200).set_body_json(results)
+    }
+
+    #[tokio::test]
+    async fn send_batch_fires_a_single_request_to_the_batch_endpoint() {
+        // Arrange
+        let mock_server = MockServer::start().await;
+        let email_client = email_client(mock_server.uri());
+        let recipients = (0..3).map(|_| email()).collect::<Vec<_>>();
+        let emails = recipients
+            .iter()
+            .map(|recipient| BatchEmail {
+                recipient,
+                subject: "Subject",
+                html_content: "<p>Body</p>",
+                text_content: "Body",
+                headers: &[],
+                metadata: &[],
+            })
+            .collect::<Vec<_>>();
+
+        Mock::given(header_exists("X-Postmark-Server-Token"))
+            .and(path("/email/batch"))
+            .and(method("POST"))
+            .respond_with(|request: &Request| {
+                let messages: Vec<serde_json::Value> =
+                    serde_json::from_slice(&request.body).unwrap();
+                assert_eq!(messages.len(), 3);
+                batch_response(request, "")
+            })
+            .expect(1)
+            .mount(&mock_server)
+            .await;
+
+        // Act
+        let outcomes = email_client.send_batch(&emails).await;
+
+        // Assert
+        assert_eq!(outcomes.len(), 3);
+        for outcome in outcomes {
+            assert_ok!(outcome);
+        }
+    }
+
+    #[tokio::test]
+    async fn send_batch_reports_failures_per_recipient() {
+        // Arrange
+        let mock_server = MockServer::start().await;
+        let email_client = email_client(mock_server.uri());
+        let accepted = SubscriberEmail::parse("ursula_le_guin@example.com".into()).unwrap();
+        let rejected = SubscriberEmail::parse("octavia_butler@example.com".into()).unwrap();
+        let emails = [&accepted, &rejected].map(|recipient| BatchEmail {
+            recipient,
+            subject: "Subject",
+            html_content: "<p>Body</p>",
+            text_content: "Body",
+            headers: &[],
+            metadata: &[],
+        });
+
+        Mock::given(path("/email/batch"))
+            .respond_with(|request: &Request| batch_response(request, "octavia_butler@example.com"))
+            .expect(1)
+            .mount(&mock_server)
+            .await;
+
+        // Act
+        let mut outcomes = email_client.send_batch(&emails).await.into_iter();
+
+        // Assert
+        assert_ok!(outcomes.next().unwrap());
+        let e = assert_err!(outcomes.next().unwrap());
+        assert!(!e.is_retryable());
+        assert!(outcomes.next().is_none());
+    }
+
+    #[tokio::test]
+    async fn send_batch_splits_large_batches_in_chunks_of_500() {
+        // Arrange
+        let mock_server = MockServer::start().await;
+        let email_client = email_client(mock_server.uri());
+        let recipient = email();
+        let emails = (0..501)
+            .map(|_| BatchEmail {
+                recipient: &recipient,
+                subject: "Subject",
+                html_content: "<p>Body</p>",
+                text_content: "Body",
+                headers: &[],
+                metadata: &[],
+            })
+            .collect::<Vec<_>>();
+
+        Mock::given(path("/email/batch"))
+            .respond_with(|request: &Request| batch_response(request, ""))
+            .expect(2)
+            .mount(&mock_server)
+            .await;
+
+        // Act
+        let outcomes = email_client.send_batch(&emails).await;
+
+        // Assert
+        assert_eq!(outcomes.len(), 501);
+        assert!(outcomes.iter().all(|outcome| outcome.is_ok()));
+    }
+
+    #[tokio::test]
+    async fn send_batch_fails_every_email_with_a_retryable_error_if_the_server_returns_500() {
+        // Arrange
+        let mock_server = MockServer::start().await;
+        let email_client = email_client(mock_server.uri());
+        let recipients = [email(), email()];
+        let emails = recipients.each_ref().map(|recipient| BatchEmail {
+            recipient,
+            subject: "Subject",
+            html_content: "<p>Body</p>",
+            text_content: "Body",
+            headers: &[],
+            metadata: &[],
+        });
+
+        Mock::given(any())
+            .respond_with(ResponseTemplate::new(500))
+            .expect(1)
+            .mount(&mock_server)
+            .await;
+
+        // Act
+        let outcomes = email_client.send_batch(&emails).await;
+
+        // Assert
+        assert_eq!(outcomes.len(), 2);
+        for outcome in outcomes {
+            assert!(assert_err!(outcome).is_retryable());
+        }
+    }
+}
@@ -0,0 +1,214 @@
+use crate::email_client::message::to_mime_message;
+use crate::email_client::{Email, EmailSender, SendEmailError};
+use lettre::transport::smtp::authentication::Credentials;
+use lettre::{AsyncSmtpTransport, AsyncTransport, Tokio1Executor};
+use secrecy::{ExposeSecret, SecretString};
+use std::time::Duration;
+
+/// Deliver emails through an SMTP relay.
+pub struct SmtpEmailSender {
+    transport: AsyncSmtpTransport<Tokio1Executor>,
+}
+
+impl From<lettre::transport::smtp::Error> for SendEmailError {
+    fn from(e: lettre::transport::smtp::Error) -> Self {
+        // 5xx replies mean that the relay refused the email, the same goes for
+        // errors on our side (e.g. an invalid message).
+        // Everything else (4xx replies, timeouts, connection failures, etc.)
+        // is worth another attempt.
+        if e.is_permanent() || e.is_client() {
+            SendEmailError::Permanent(Box::new(e))
+        } else {
+            SendEmailError::Transient(Box::new(e))
+        }
+    }
+}
+
+impl SmtpEmailSender {
+    /// Without `require_tls`, the connection to the relay is not encrypted:
+    /// only use it for relays on the local network (e.g. a local SMTP stand-in).
+    pub fn new(
+        host: &str,
+        port: u16,
+        credentials: Option<(String, SecretString)>,
+        require_tls: bool,
+        timeout: Duration,
+    ) -> Result<Self, lettre::transport::smtp::Error> {
+        let mut builder = if require_tls {
+            AsyncSmtpTransport::<Tokio1Executor>::starttls_relay(host)?
+        } else {
+            AsyncSmtpTransport::<Tokio1Executor>::builder_dangerous(host)
+        }
+        .port(port)
+        .timeout(Some(timeout));
+        if let Some((username, password)) = credentials {
+            builder = builder.credentials(Credentials::new(
+                username,
+                password.expose_secret().to_owned(),
+            ));
+        }
+
+        Ok(Self {
+            transport: builder.build(),
+        })
+    }
+}
+
+#[async_trait::async_trait]
+impl EmailSender for SmtpEmailSender {
+    async fn send(&self, email: &Email<'_>) -> Result<(), SendEmailError> {
+        let message = to_mime_message(email)?;
+        self.transport.send(message).await?;
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::domain::SubscriberEmail;
+    use crate::email_client::{EmailClient, EmailHeader, SmtpEmailSender};
+    use claims::{assert_err, assert_ok};
+    use std::time::Duration;
+    use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+    use tokio::net::TcpListener;
+    use tokio::task::JoinHandle;
+
+    /// A bare-bones SMTP server accepting a single connection.
+    ///
+    /// It answers `recipient_reply` to `RCPT TO` and returns the message
+    /// it received, if any.
+    async fn spawn_smtp_server(recipient_reply: &'static str) -> (u16, JoinHandle<String>) {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let handle = tokio::spawn(async move {
+            let (stream, _) = listener.accept().await.unwrap();
+            let (reader, mut writer) = stream.into_split();
+            let mut lines = BufReader::new(reader).lines();
+            let mut data = String::new();
+            let mut in_data = false;
+
+            writer.write_all(b"220 localhost ready\r\n").await.unwrap();
+            while let Some(line) = lines.next_line().await.unwrap() {
+                if in_data {
+                    if line == "." {
+                        in_data = false;
+                        writer.write_all(b"250 Queued\r\n").await.unwrap();
+                    } else {
+                        data.push_str(&line);
+                        data.push('\n');
+                    }
+                    continue;
+                }
+                let command = line.to_uppercase();
+                let reply = if command.starts_with("EHLO") || command.starts_with("HELO") {
+                    "250 localhost\r\n"
+                } else if command.starts_with("RCPT TO") {
+                    recipient_reply
+                } else if command.starts_with("DATA") {
+                    in_data = true;
+                    "354 End data with <CR><LF>.<CR><LF>\r\n"
+                } else if command.starts_with("QUIT") {
+                    writer.write_all(b"221 Bye\r\n").await.unwrap();
+                    break;
+                } else {
+                    "250 OK\r\n"
+                };
+                writer.write_all(reply.as_bytes()).await.unwrap();
+            }
+            data
+        });
+        (port, handle)
+    }
+
+    fn email_client(port: u16) -> EmailClient {
+        let sender = SubscriberEmail::parse("newsletter@example.com".into()).unwrap();
+        let transport =
+            SmtpEmailSender::new("127.0.0.1", port, None, false, Duration::from_secs(1)).unwrap();
+        EmailClient::new(sender, transport)
+    }
+
+    fn recipient() -> SubscriberEmail {
+        SubscriberEmail::parse("ursula_le_guin@example.com".into()).unwrap()
+    }
+
+    #[tokio::test]
+    async fn send_email_delivers_a_multipart_message_to_the_relay() {
+        // Arrange
+        let (port, server) = spawn_smtp_server("250 OK\r\n").await;
+        let email_client = email_client(port);
+
+        // Act
+        let outcome = email_client
+            .send_email_with_headers(
+                &recipient(),
+                "Newsletter title",
+                "<p>Newsletter body as HTML</p>",
+                "Newsletter body as plain text",
+                &[EmailHeader {
+                    name: "List-Unsubscribe-Post",
+                    value: "List-Unsubscribe=One-Click",
+                }],
+            )
+            .await;
+
+        // Assert
+        assert_ok!(outcome);
+        let message = server.await.unwrap();
+        assert!(message.contains("From: newsletter@example.com"));
+        assert!(message.contains("To: ursula_le_guin@example.com"));
+        assert!(message.contains("Subject: Newsletter title"));
+        assert!(message.contains("List-Unsubscribe-Post: List-Unsubscribe=One-Click"));
+        assert!(message.contains("multipart/alternative"));
+        assert!(message.contains("Newsletter body as plain text"));
+        assert!(message.contains("<p>Newsletter body as HTML</p>"));
+    }
+
+    #[tokio::test]
+    async fn a_temporary_rejection_is_retryable() {
+        // Arrange
+        let (port, _server) = spawn_smtp_server("451 Try again later\r\n").await;
+        let email_client = email_client(port);
+
+        // Act
+        let outcome = email_client
+            .send_email(&recipient(), "Subject", "<p>Body</p>", "Body")
+            .await;
+
+        // Assert
+        assert!(assert_err!(outcome).is_retryable());
+    }
+
+    #[tokio::test]
+    async fn a_permanent_rejection_is_not_retryable() {
+        // Arrange
+        let (port, _server) = spawn_smtp_server("550 No such user\r\n").await;
+        let email_client = email_client(port);
+
+        // Act
+        let outcome = email_client
+            .send_email(&recipient(), "Subject", "<p>Body</p>", "Body")
+            .await;
+
+        // Assert
+        assert!(!assert_err!(outcome).is_retryable());
+    }
+
+    #[tokio::test]
+    async fn an_unreachable_relay_is_retryable() {
+        // Arrange
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        // Nobody is listening on the port anymore
+        drop(listener);
+        let email_client = email_client(port);
+
+        // Act
+        let outcome = email_client
+            .send_email(&recipient(), "Subject", "<p>Body</p>", "Body")
+            .await;
+
+        // Assert
+        assert!(assert_err!(outcome).is_retryable());
+    }
+}
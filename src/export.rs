@@ -0,0 +1,212 @@
+//! Streaming exports, as CSV for spreadsheets or as NDJSON (one JSON object
+//! per line) for analytics pipelines.
+//!
+//! Rows are written to the response as the database returns them: exports
+//! are never buffered in memory, whatever their size.
+use actix_web::http::header::{ContentDisposition, DispositionParam, DispositionType};
+use actix_web::web::Bytes;
+use actix_web::HttpResponse;
+use futures_util::{Stream, TryStreamExt};
+use tokio::sync::mpsc;
+
+/// How many rows are written to a chunk of the response body.
+const ROWS_PER_CHUNK: usize = 100;
+/// How many chunks can wait for a slow client before we stop reading rows
+/// from the database.
+const MAX_PENDING_CHUNKS: usize = 8;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ExportFormat {
+    Csv,
+    Ndjson,
+}
+
+impl ExportFormat {
+    /// Exports are CSV unless stated otherwise.
+    pub fn parse(s: Option<&str>) -> Result<ExportFormat, String> {
+        match s.unwrap_or("csv") {
+            "csv" => Ok(Self::Csv),
+            "ndjson" => Ok(Self::Ndjson),
+            other => Err(format!("{} is not a supported export format.", other)),
+        }
+    }
+
+    fn content_type(&self) -> &'static str {
+        match self {
+            ExportFormat::Csv => "text/csv; charset=utf-8",
+            ExportFormat::Ndjson => "application/x-ndjson",
+        }
+    }
+
+    fn extension(&self) -> &'static str {
+        match self {
+            ExportFormat::Csv => "csv",
+            ExportFormat::Ndjson => "ndjson",
+        }
+    }
+}
+
+/// A row of an export. NDJSON rows are the serialized row, CSV rows are
+/// made of its `csv_fields`.
+pub trait ExportRow: serde::Serialize {
+    /// The names of the CSV columns, in the order of `csv_fields`.
+    const CSV_HEADER: &'static [&'static str];
+
+    fn csv_fields(&self) -> Vec<String>;
+}
+
+#[derive(Debug, thiserror::Error)]
+#[error("The export failed midway")]
+pub struct ExportError;
+
+/// Start an export named `name`: the response streams the rows written to
+/// the returned writer, as they are written.
+pub fn start_export(format: ExportFormat, name: &str) -> (ExportWriter, HttpResponse) {
+    let (sender, mut receiver) = mpsc::channel(MAX_PENDING_CHUNKS);
+    let body = futures_util::stream::poll_fn(move |cx| receiver.poll_recv(cx));
+    let response = HttpResponse::Ok()
+        .content_type(format.content_type())
+        .insert_header(ContentDisposition {
+            disposition: DispositionType::Attachment,
+            parameters: vec![DispositionParam::Filename(format!(
+                "{}.{}",
+                name,
+                format.extension()
+            ))],
+        })
+        .streaming(body);
+
+    (ExportWriter { format, sender }, response)
+}
+
+pub struct ExportWriter {
+    format: ExportFormat,
+    sender: mpsc::Sender<Result<Bytes, ExportError>>,
+}
+
+impl ExportWriter {
+    /// Write all the rows, then end the response.
+    ///
+    /// Should the query fail midway, the response is aborted rather than
+    /// ended: clients cannot mistake a partial export for a complete one.
+    pub async fn write_rows<R, S>(self, rows: S)
+    where
+        R: ExportRow,
+        S: Stream<Item = Result<R, sqlx::Error>>,
+    {
+        let mut rows = std::pin::pin!(rows);
+        let mut buffer = Vec::new();
+        if self.format == ExportFormat::Csv {
+            write_csv_record(&mut buffer, R::CSV_HEADER.iter().copied());
+        }
+        let mut n_rows = 0;
+        loop {
+            let row = match rows.try_next().await {
+                Ok(Some(row)) => row,
+                Ok(None) => break,
+                Err(e) => return self.abort(e.into()).await,
+            };
+            match self.format {
+                ExportFormat::Csv => {
+                    let fields = row.csv_fields();
+                    write_csv_record(&mut buffer, fields.iter().map(String::as_str));
+                }
+                ExportFormat::Ndjson => {
+                    if let Err(e) = serde_json::to_writer(&mut buffer, &row) {
+                        return self.abort(e.into()).await;
+                    }
+                    buffer.push(b'\n');
+                }
+            }
+            n_rows += 1;
+            if n_rows % ROWS_PER_CHUNK == 0 && !self.send(std::mem::take(&mut buffer)).await {
+                return;
+            }
+        }
+        self.send(buffer).await;
+    }
+
+    /// Returns `false` when the client is gone, and there is no point in going on.
+    async fn send(&self, chunk: Vec<u8>) -> bool {
+        self.sender.send(Ok(Bytes::from(chunk))).await.is_ok()
+    }
+
+    async fn abort(self, e: anyhow::Error) {
+        tracing::error!(
+            error.cause_chain = ?e,
+            error.message = %e,
+            "Failed to export rows",
+        );
+        let _ = self.sender.send(Err(ExportError)).await;
+    }
+}
+
+fn write_csv_record<'a>(buffer: &mut Vec<u8>, fields: impl Iterator<Item = &'a str>) {
+    for (i, field) in fields.enumerate() {
+        if i > 0 {
+            buffer.push(b',');
+        }
+        write_csv_field(buffer, field);
+    }
+    buffer.extend_from_slice(b"\r\n");
+}
+
+fn write_csv_field(buffer: &mut Vec<u8>, field: &str) {
+    // Spreadsheets evaluate the cells starting with these characters as
+    // formulas, and anybody can pick the name they subscribe with.
+    let is_formula = field.starts_with(['=', '+', '-', '@', '\t', '\r']);
+    let needs_quotes = field.contains([',', '"', '\n', '\r']);
+    if needs_quotes {
+        buffer.push(b'"');
+    }
+    if is_formula {
+        buffer.push(b'\'');
+    }
+    buffer.extend_from_slice(field.replace('"', "\"\"").as_bytes());
+    if needs_quotes {
+        buffer.push(b'"');
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{write_csv_record, ExportFormat};
+    use claims::{assert_err, assert_ok_eq};
+
+    fn csv_record(fields: &[&str]) -> String {
+        let mut buffer = Vec::new();
+        write_csv_record(&mut buffer, fields.iter().copied());
+        String::from_utf8(buffer).unwrap()
+    }
+
+    #[test]
+    fn plain_fields_are_written_as_they_are() {
+        assert_eq!(
+            csv_record(&["ursula@example.com", "Ursula", ""]),
+            "ursula@example.com,Ursula,\r\n"
+        );
+    }
+
+    #[test]
+    fn fields_with_separators_quotes_or_line_breaks_are_quoted() {
+        assert_eq!(
+            csv_record(&["Le Guin, Ursula", "The \"Dispossessed\"", "two\nlines"]),
+            "\"Le Guin, Ursula\",\"The \"\"Dispossessed\"\"\",\"two\nlines\"\r\n"
+        );
+    }
+
+    #[test]
+    fn fields_that_look_like_formulas_are_neutralized() {
+        assert_eq!(
+            csv_record(&["=HYPERLINK(\"http://evil.example.com\")", "@SUM(A1)"]),
+            "\"'=HYPERLINK(\"\"http://evil.example.com\"\")\",'@SUM(A1)\r\n"
+        );
+    }
+
+    #[test]
+    fn exports_are_csv_unless_stated_otherwise() {
+        assert_ok_eq!(ExportFormat::parse(None), ExportFormat::Csv);
+        assert_ok_eq!(ExportFormat::parse(Some("ndjson")), ExportFormat::Ndjson);
+        assert_err!(ExportFormat::parse(Some("xlsx")));
+    }
+}
@@ -0,0 +1,658 @@
+use crate::configuration::Settings;
+use crate::domain::{SubscriberEmail, UnsubscribeToken};
+use crate::email_client::{BatchEmail, EmailClient, EmailHeader, RetryPolicy};
+use crate::startup::get_connection_pool;
+use crate::templates::{IssueTemplate, Templates};
+use chrono::{DateTime, Utc};
+use secrecy::SecretString;
+use sqlx::{PgConnection, PgPool, Postgres, Transaction};
+use std::collections::hash_map::Entry;
+use std::collections::HashMap;
+use std::time::Duration;
+use tracing::Span;
+use uuid::Uuid;
+
+pub enum ExecutionOutcome {
+    TaskCompleted,
+    EmptyQueue,
+}
+
+pub async fn run_worker_until_stopped(configuration: Settings) -> Result<(), anyhow::Error> {
+    let connection_pool = get_connection_pool(&configuration.database);
+    let email_client = configuration.email_client.client();
+    let retry_policy = configuration.email_client.retry_policy();
+    let templates = Templates::load(&configuration.application.templates_directory)?;
+    worker_loop(
+        connection_pool,
+        email_client,
+        templates,
+        retry_policy,
+        configuration.application.base_url,
+        configuration.application.hmac_secret,
+    )
+    .await
+}
+
+async fn worker_loop(
+    pool: PgPool,
+    email_client: EmailClient,
+    templates: Templates,
+    retry_policy: RetryPolicy,
+    base_url: String,
+    hmac_secret: SecretString,
+) -> Result<(), anyhow::Error> {
+    loop {
+        match try_execute_task(
+            &pool,
+            &email_client,
+            &templates,
+            &retry_policy,
+            &base_url,
+            &hmac_secret,
+        )
+        .await
+        {
+            Ok(ExecutionOutcome::EmptyQueue) => {
+                tokio::time::sleep(Duration::from_secs(10)).await;
+            }
+            Err(_) => {
+                tokio::time::sleep(Duration::from_secs(1)).await;
+            }
+            Ok(ExecutionOutcome::TaskCompleted) => {}
+        }
+    }
+}
+
+/// How many deliveries are handed over to the email client in a single call,
+/// the most Postmark accepts in a batch.
+const BATCH_SIZE: i64 = 500;
+/// How long the tasks claimed by a worker stay out of reach of the others.
+/// Should the worker die before recording what it sent, they go back to
+/// the queue once the claim expires.
+const CLAIM_DURATION: Duration = Duration::from_secs(5 * 60);
+
+/// Deliver a batch of queued issues.
+///
+/// The tasks are claimed, and the deliveries that don't need an email are
+/// recorded, before anything is sent. The outcome of the emails is recorded
+/// right after the call to the email client: a failure, or a crash, can only
+/// get the emails of this call sent twice, never the ones of earlier batches.
+#[tracing::instrument(skip_all, fields(n_tasks = tracing::field::Empty), err)]
+pub async fn try_execute_task(
+    pool: &PgPool,
+    email_client: &EmailClient,
+    templates: &Templates,
+    retry_policy: &RetryPolicy,
+    base_url: &str,
+    hmac_secret: &SecretString,
+) -> Result<ExecutionOutcome, anyhow::Error> {
+    let tasks = claim_tasks(pool, BATCH_SIZE).await?;
+    if tasks.is_empty() {
+        return Ok(ExecutionOutcome::EmptyQueue);
+    }
+    Span::current().record("n_tasks", tasks.len());
+
+    // Subscribers might have left (or been removed) after the issue was enqueued.
+    let subscriber_emails = tasks
+        .iter()
+        .map(|task| task.subscriber_email.clone())
+        .collect::<Vec<_>>();
+    let confirmed_subscribers = get_confirmed_subscribers(pool, &subscriber_emails).await?;
+    let delivery_lists = get_delivery_lists(pool, &tasks).await?;
+
+    let mut transaction = pool.begin().await?;
+    let mut issues = HashMap::new();
+    // Issues we could not load: their deliveries fail without holding up the others
+    let mut broken_issues = HashMap::new();
+    let mut counters: HashMap<Uuid, DeliveryCounters> = HashMap::new();
+    let mut deliveries = Vec::with_capacity(tasks.len());
+    for task in tasks {
+        let Some(subscriber) = confirmed_subscribers.get(&task.subscriber_email) else {
+            tracing::info!(
+                subscriber_email = %task.subscriber_email,
+                "Skipping a subscriber that is no longer confirmed"
+            );
+            complete_task(&mut transaction, &task, "skipped_unsubscribed", 0).await?;
+            counters
+                .entry(task.newsletter_issue_id)
+                .or_default()
+                .n_skipped_unsubscribed += 1;
+            continue;
+        };
+        let subscriber_email = match SubscriberEmail::parse(task.subscriber_email.clone()) {
+            Ok(subscriber_email) => subscriber_email,
+            Err(e) => {
+                tracing::error!(
+                    error.message = %e,
+                    "Skipping a confirmed subscriber. Their stored contact details are invalid",
+                );
+                complete_task(&mut transaction, &task, "skipped_invalid", 0).await?;
+                counters
+                    .entry(task.newsletter_issue_id)
+                    .or_default()
+                    .n_skipped_invalid += 1;
+                continue;
+            }
+        };
+        if let (Entry::Vacant(entry), Entry::Vacant(broken_entry)) = (
+            issues.entry(task.newsletter_issue_id),
+            broken_issues.entry(task.newsletter_issue_id),
+        ) {
+            match get_issue(pool, task.newsletter_issue_id).await {
+                Ok(issue) => {
+                    entry.insert(IssueTemplates::new(task.newsletter_issue_id, issue));
+                }
+                Err(e) => {
+                    broken_entry.insert(format!("{:#}", e));
+                }
+            }
+        }
+        let Some(issue) = issues.get(&task.newsletter_issue_id) else {
+            let last_error = &broken_issues[&task.newsletter_issue_id];
+            abandon_task(&mut transaction, &task, last_error).await?;
+            counters
+                .entry(task.newsletter_issue_id)
+                .or_default()
+                .n_failed += 1;
+            continue;
+        };
+        let unsubscribe_token =
+            match delivery_lists.get(&(task.newsletter_issue_id, task.subscriber_email.clone())) {
+                Some(list_id) => {
+                    UnsubscribeToken::generate_for_list(subscriber.id, *list_id, hmac_secret)
+                }
+                None => UnsubscribeToken::generate(subscriber.id, hmac_secret),
+            };
+        let unsubscribe_link = format!(
+            "{}/subscriptions/unsubscribe?token={}",
+            base_url,
+            unsubscribe_token.as_ref()
+        );
+        let view_in_browser_link = format!("{}/issues/{}", base_url, issue.slug);
+        let variables = [
+            ("title", issue.title.as_str()),
+            ("view_in_browser_url", &view_in_browser_link),
+            ("name", subscriber.name.as_str()),
+            ("email", subscriber_email.as_ref()),
+            ("unsubscribe_url", &unsubscribe_link),
+        ];
+        let body = match issue.content.render_email(templates, &variables) {
+            Ok(body) => body,
+            Err(e) => {
+                let last_error = format!("{:#}", anyhow::Error::from(e));
+                abandon_task(&mut transaction, &task, &last_error).await?;
+                counters
+                    .entry(task.newsletter_issue_id)
+                    .or_default()
+                    .n_failed += 1;
+                continue;
+            }
+        };
+        deliveries.push(Delivery {
+            subscriber_email,
+            html_content: body.html,
+            text_content: body.text,
+            list_unsubscribe: format!("<{}>", unsubscribe_link),
+            task,
+        });
+    }
+
+    let headers = deliveries
+        .iter()
+        .map(|delivery| {
+            // One-click unsubscribe, as described in RFC 8058
+            [
+                EmailHeader {
+                    name: "List-Unsubscribe",
+                    value: &delivery.list_unsubscribe,
+                },
+                EmailHeader {
+                    name: "List-Unsubscribe-Post",
+                    value: "List-Unsubscribe=One-Click",
+                },
+            ]
+        })
+        .collect::<Vec<_>>();
+    // Lets us attribute the events reported by the email provider to the issue
+    let metadata = deliveries
+        .iter()
+        .map(|delivery| {
+            [(
+                "newsletter_issue_id",
+                issues[&delivery.task.newsletter_issue_id].id.as_str(),
+            )]
+        })
+        .collect::<Vec<_>>();
+    let emails = deliveries
+        .iter()
+        .zip(headers.iter().zip(&metadata))
+        .map(|(delivery, (headers, metadata))| BatchEmail {
+            recipient: &delivery.subscriber_email,
+            subject: &issues[&delivery.task.newsletter_issue_id].title,
+            html_content: &delivery.html_content,
+            text_content: &delivery.text_content,
+            headers,
+            metadata,
+        })
+        .collect::<Vec<_>>();
+    for (newsletter_issue_id, counters) in counters.drain() {
+        update_issue_stats(&mut transaction, newsletter_issue_id, &counters).await?;
+    }
+    transaction.commit().await?;
+
+    let outcomes = email_client.send_batch(&emails).await;
+
+    let mut transaction = pool.begin().await?;
+    for (delivery, outcome) in deliveries.iter().zip(outcomes) {
+        let task = &delivery.task;
+        let counters = counters.entry(task.newsletter_issue_id).or_default();
+        match outcome {
+            Ok(()) => {
+                let n_attempts = task.n_retries as u32 + 1;
+                complete_task(&mut transaction, task, "sent", n_attempts).await?;
+                counters.n_sent += 1;
+            }
+            Err(e) => {
+                let n_attempts = task.n_retries as u32 + 1;
+                if e.is_retryable() && !retry_policy.is_exhausted(n_attempts) {
+                    tracing::warn!(
+                        error.cause_chain = ?e,
+                        error.message = %e,
+                        newsletter_issue_id = %task.newsletter_issue_id,
+                        subscriber_email = %task.subscriber_email,
+                        n_attempts,
+                        "Failed to deliver issue to a confirmed subscriber. Retrying later.",
+                    );
+                    let execute_after =
+                        Utc::now() + chrono::Duration::from_std(retry_policy.backoff(n_attempts))?;
+                    retry_task(&mut transaction, task, execute_after).await?;
+                } else {
+                    tracing::error!(
+                        error.cause_chain = ?e,
+                        error.message = %e,
+                        newsletter_issue_id = %task.newsletter_issue_id,
+                        subscriber_email = %task.subscriber_email,
+                        n_attempts,
+                        "Failed to deliver issue to a confirmed subscriber. \
+                        Moving it to the dead-letter queue.",
+                    );
+                    let last_error = format!("{:#}", anyhow::Error::from(e));
+                    dead_letter_task(&mut transaction, task, n_attempts, &last_error).await?;
+                    counters.n_failed += 1;
+                }
+            }
+        }
+    }
+    for (newsletter_issue_id, counters) in counters {
+        update_issue_stats(&mut transaction, newsletter_issue_id, &counters).await?;
+    }
+    transaction.commit().await?;
+
+    Ok(ExecutionOutcome::TaskCompleted)
+}
+
+type PgTransaction = Transaction<'static, Postgres>;
+
+struct DeliveryTask {
+    newsletter_issue_id: Uuid,
+    subscriber_email: String,
+    n_retries: i16,
+}
+
+/// What happened to the deliveries of an issue handled in a batch.
+#[derive(Default)]
+struct DeliveryCounters {
+    n_sent: i32,
+    n_failed: i32,
+    n_skipped_invalid: i32,
+    n_skipped_unsubscribed: i32,
+}
+
+/// A task, along with the email that has to be sent to complete it.
+struct Delivery {
+    task: DeliveryTask,
+    subscriber_email: SubscriberEmail,
+    html_content: String,
+    text_content: String,
+    list_unsubscribe: String,
+}
+
+/// Take up to `limit` tasks that are due, postponing them for `CLAIM_DURATION`:
+/// other workers leave them alone while we are sending the emails, without
+/// a transaction being kept open in the meantime.
+#[tracing::instrument(skip(pool))]
+async fn claim_tasks(pool: &PgPool, limit: i64) -> Result<Vec<DeliveryTask>, anyhow::Error> {
+    let claimed_until = Utc::now() + chrono::Duration::from_std(CLAIM_DURATION)?;
+    let tasks = sqlx::query_as!(
+        DeliveryTask,
+        r#"
+        UPDATE issue_delivery_queue q
+        SET execute_after = $2
+        FROM (
+            SELECT newsletter_issue_id, subscriber_email
+            FROM issue_delivery_queue
+            WHERE execute_after <= now()
+            FOR UPDATE
+            SKIP LOCKED
+            LIMIT $1
+        ) due
+        WHERE
+            q.newsletter_issue_id = due.newsletter_issue_id AND
+            q.subscriber_email = due.subscriber_email
+        RETURNING q.newsletter_issue_id, q.subscriber_email, q.n_retries
+        "#,
+        limit,
+        claimed_until
+    )
+    .fetch_all(pool)
+    .await?;
+
+    Ok(tasks)
+}
+
+#[tracing::instrument(skip_all)]
+async fn delete_task(
+    transaction: &mut PgTransaction,
+    task: &DeliveryTask,
+) -> Result<(), anyhow::Error> {
+    sqlx::query!(
+        r#"
+        DELETE FROM issue_delivery_queue
+        WHERE
+            newsletter_issue_id = $1 AND
+            subscriber_email = $2
+        "#,
+        task.newsletter_issue_id,
+        task.subscriber_email
+    )
+    .execute(&mut **transaction)
+    .await?;
+
+    Ok(())
+}
+
+/// Take the task off the queue, recording the outcome of the delivery.
+/// Should a delivery be recorded twice, the last outcome wins.
+#[tracing::instrument(skip(transaction, task))]
+async fn complete_task(
+    transaction: &mut PgTransaction,
+    task: &DeliveryTask,
+    outcome: &str,
+    n_attempts: u32,
+) -> Result<(), anyhow::Error> {
+    sqlx::query!(
+        r#"
+        INSERT INTO issue_delivery_results (
+            newsletter_issue_id,
+            subscriber_email,
+            outcome,
+            n_attempts,
+            recorded_at
+        )
+        VALUES ($1, $2, $3, $4, now())
+        ON CONFLICT (newsletter_issue_id, subscriber_email) DO UPDATE
+        SET
+            outcome = EXCLUDED.outcome,
+            n_attempts = EXCLUDED.n_attempts,
+            recorded_at = EXCLUDED.recorded_at
+        "#,
+        task.newsletter_issue_id,
+        task.subscriber_email,
+        outcome,
+        n_attempts as i16
+    )
+    .execute(&mut **transaction)
+    .await?;
+
+    delete_task(transaction, task).await
+}
+
+#[tracing::instrument(skip(transaction, task))]
+async fn retry_task(
+    transaction: &mut PgTransaction,
+    task: &DeliveryTask,
+    execute_after: DateTime<Utc>,
+) -> Result<(), anyhow::Error> {
+    sqlx::query!(
+        r#"
+        UPDATE issue_delivery_queue
+        SET
+            n_retries = n_retries + 1,
+            execute_after = $3
+        WHERE
+            newsletter_issue_id = $1 AND
+            subscriber_email = $2
+        "#,
+        task.newsletter_issue_id,
+        task.subscriber_email,
+        execute_after
+    )
+    .execute(&mut **transaction)
+    .await?;
+
+    Ok(())
+}
+
+#[tracing::instrument(skip(transaction, task))]
+async fn dead_letter_task(
+    transaction: &mut PgTransaction,
+    task: &DeliveryTask,
+    n_attempts: u32,
+    last_error: &str,
+) -> Result<(), anyhow::Error> {
+    sqlx::query!(
+        r#"
+        INSERT INTO issue_delivery_dead_letters (
+            newsletter_issue_id,
+            subscriber_email,
+            n_attempts,
+            last_error,
+            failed_at
+        )
+        VALUES ($1, $2, $3, $4, now())
+        ON CONFLICT (newsletter_issue_id, subscriber_email) DO UPDATE
+        SET
+            n_attempts = EXCLUDED.n_attempts,
+            last_error = EXCLUDED.last_error,
+            failed_at = EXCLUDED.failed_at
+        "#,
+        task.newsletter_issue_id,
+        task.subscriber_email,
+        n_attempts as i16,
+        last_error
+    )
+    .execute(&mut **transaction)
+    .await?;
+
+    complete_task(transaction, task, "failed", n_attempts).await
+}
+
+/// Give up on a delivery we could not even prepare, e.g. because its issue
+/// does not render: it would fail the same way every time we tried again.
+async fn abandon_task(
+    transaction: &mut PgTransaction,
+    task: &DeliveryTask,
+    last_error: &str,
+) -> Result<(), anyhow::Error> {
+    let n_attempts = task.n_retries as u32 + 1;
+    tracing::error!(
+        error.message = %last_error,
+        newsletter_issue_id = %task.newsletter_issue_id,
+        subscriber_email = %task.subscriber_email,
+        "Failed to prepare the delivery of an issue. Moving it to the dead-letter queue.",
+    );
+    dead_letter_task(transaction, task, n_attempts, last_error).await
+}
+
+#[tracing::instrument(skip(transaction, counters))]
+async fn update_issue_stats(
+    transaction: &mut PgTransaction,
+    newsletter_issue_id: Uuid,
+    counters: &DeliveryCounters,
+) -> Result<(), anyhow::Error> {
+    sqlx::query!(
+        r#"
+        UPDATE newsletter_issue_stats
+        SET
+            n_sent = n_sent + $2,
+            n_failed = n_failed + $3,
+            n_skipped_invalid = n_skipped_invalid + $4,
+            n_skipped_unsubscribed = n_skipped_unsubscribed + $5
+        WHERE newsletter_issue_id = $1
+        "#,
+        newsletter_issue_id,
+        counters.n_sent,
+        counters.n_failed,
+        counters.n_skipped_invalid,
+        counters.n_skipped_unsubscribed
+    )
+    .execute(&mut **transaction)
+    .await?;
+
+    Ok(())
+}
+
+/// Count the deliveries dropped from the queue because their subscriber
+/// left, one issue id per delivery: the stats of the issues keep adding up,
+/// as if the worker had skipped them.
+pub async fn count_dropped_deliveries(
+    connection: &mut PgConnection,
+    newsletter_issue_ids: &[Uuid],
+) -> Result<(), sqlx::Error> {
+    if newsletter_issue_ids.is_empty() {
+        return Ok(());
+    }
+    sqlx::query!(
+        r#"
+        UPDATE newsletter_issue_stats s
+        SET n_skipped_unsubscribed = s.n_skipped_unsubscribed + d.n_dropped
+        FROM (
+            SELECT newsletter_issue_id, COUNT(*)::int AS n_dropped
+            FROM UNNEST($1::uuid[]) AS newsletter_issue_id
+            GROUP BY newsletter_issue_id
+        ) d
+        WHERE s.newsletter_issue_id = d.newsletter_issue_id
+        "#,
+        newsletter_issue_ids
+    )
+    .execute(connection)
+    .await?;
+
+    Ok(())
+}
+
+struct ConfirmedSubscriber {
+    id: Uuid,
+    name: String,
+}
+
+/// Map the given email addresses to the confirmed subscribers they belong to.
+#[tracing::instrument(skip_all)]
+async fn get_confirmed_subscribers(
+    pool: &PgPool,
+    subscriber_emails: &[String],
+) -> Result<HashMap<String, ConfirmedSubscriber>, anyhow::Error> {
+    let rows = sqlx::query!(
+        r#"
+        SELECT id, email, name
+        FROM subscriptions
+        WHERE email = ANY($1) AND status = 'confirmed'
+        "#,
+        subscriber_emails
+    )
+    .fetch_all(pool)
+    .await?;
+
+    Ok(rows
+        .into_iter()
+        .map(|r| {
+            (
+                r.email,
+                ConfirmedSubscriber {
+                    id: r.id,
+                    name: r.name,
+                },
+            )
+        })
+        .collect())
+}
+
+/// The list each delivery goes out through, keyed by issue and address: the
+/// unsubscribe link of the email lets the subscriber leave it. Subscribers on
+/// several of the lists of an issue get the first of them.
+#[tracing::instrument(skip_all)]
+async fn get_delivery_lists(
+    pool: &PgPool,
+    tasks: &[DeliveryTask],
+) -> Result<HashMap<(Uuid, String), Uuid>, anyhow::Error> {
+    let (newsletter_issue_ids, subscriber_emails): (Vec<Uuid>, Vec<String>) = tasks
+        .iter()
+        .map(|task| (task.newsletter_issue_id, task.subscriber_email.clone()))
+        .unzip();
+    let rows = sqlx::query!(
+        r#"
+        SELECT DISTINCT ON (t.newsletter_issue_id, t.subscriber_email)
+            t.newsletter_issue_id AS "newsletter_issue_id!",
+            t.subscriber_email AS "subscriber_email!",
+            l.list_id
+        FROM UNNEST($1::uuid[], $2::text[]) AS t(newsletter_issue_id, subscriber_email)
+        JOIN subscriptions s ON s.email = t.subscriber_email
+        JOIN list_subscriptions ls ON ls.subscriber_id = s.id AND ls.status = 'confirmed'
+        JOIN newsletter_issue_lists il
+            ON il.list_id = ls.list_id AND il.newsletter_issue_id = t.newsletter_issue_id
+        JOIN lists l ON l.list_id = il.list_id
+        ORDER BY t.newsletter_issue_id, t.subscriber_email, l.slug
+        "#,
+        &newsletter_issue_ids,
+        &subscriber_emails
+    )
+    .fetch_all(pool)
+    .await?;
+
+    Ok(rows
+        .into_iter()
+        .map(|r| ((r.newsletter_issue_id, r.subscriber_email), r.list_id))
+        .collect())
+}
+
+struct NewsletterIssue {
+    title: String,
+    slug: String,
+    text_content: String,
+    html_content: String,
+}
+
+struct IssueTemplates {
+    id: String,
+    title: String,
+    slug: String,
+    content: IssueTemplate,
+}
+
+impl IssueTemplates {
+    fn new(id: Uuid, issue: NewsletterIssue) -> Self {
+        Self {
+            id: id.to_string(),
+            content: IssueTemplate::new(&issue.text_content, &issue.html_content),
+            title: issue.title,
+            slug: issue.slug,
+        }
+    }
+}
+
+#[tracing::instrument(skip_all)]
+async fn get_issue(pool: &PgPool, issue_id: Uuid) -> Result<NewsletterIssue, anyhow::Error> {
+    let issue = sqlx::query_as!(
+        NewsletterIssue,
+        r#"
+        SELECT title, slug as "slug!", text_content, html_content
+        FROM newsletter_issues
+        WHERE newsletter_issue_id = $1
+        "#,
+        issue_id
+    )
+    .fetch_one(pool)
+    .await?;
+
+    Ok(issue)
+}
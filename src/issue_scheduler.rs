@@ -0,0 +1,93 @@
+use crate::configuration::Settings;
+use crate::newsletter_issues::publish_issue;
+use crate::startup::get_connection_pool;
+use sqlx::PgPool;
+use std::time::Duration;
+use uuid::Uuid;
+
+const SCHEDULER_INTERVAL: Duration = Duration::from_secs(30);
+
+pub async fn run_scheduler_until_stopped(configuration: Settings) -> Result<(), anyhow::Error> {
+    let connection_pool = get_connection_pool(&configuration.database);
+    loop {
+        if let Err(e) = publish_due_issues(&connection_pool).await {
+            tracing::error!(
+                error.cause_chain = ?e,
+                error.message = %e,
+                "Failed to publish scheduled issues",
+            );
+        }
+        tokio::time::sleep(SCHEDULER_INTERVAL).await;
+    }
+}
+
+/// Publish the scheduled issues whose `send_at` has come.
+///
+/// Each issue is published in its own transaction: an issue that fails to
+/// publish is logged and retried on the next run, without holding up the
+/// others.
+///
+/// Returns the number of published issues.
+#[tracing::instrument(skip(pool))]
+pub async fn publish_due_issues(pool: &PgPool) -> Result<u64, anyhow::Error> {
+    let due_issue_ids = sqlx::query_scalar!(
+        r#"
+        SELECT newsletter_issue_id
+        FROM newsletter_issues
+        WHERE status = 'scheduled' AND send_at <= now()
+        ORDER BY send_at
+        "#
+    )
+    .fetch_all(pool)
+    .await?;
+
+    let mut n_published = 0;
+    for newsletter_issue_id in due_issue_ids {
+        match publish_due_issue(pool, newsletter_issue_id).await {
+            Ok(true) => {
+                tracing::info!(%newsletter_issue_id, "Published a scheduled issue");
+                n_published += 1;
+            }
+            Ok(false) => {}
+            Err(e) => {
+                tracing::error!(
+                    error.cause_chain = ?e,
+                    error.message = %e,
+                    %newsletter_issue_id,
+                    "Failed to publish a scheduled issue",
+                );
+            }
+        }
+    }
+
+    Ok(n_published)
+}
+
+/// Publish a scheduled issue, unless another scheduler is already on it or
+/// it was cancelled in the meantime.
+async fn publish_due_issue(
+    pool: &PgPool,
+    newsletter_issue_id: Uuid,
+) -> Result<bool, anyhow::Error> {
+    let mut transaction = pool.begin().await?;
+    let is_due = sqlx::query_scalar!(
+        r#"
+        SELECT newsletter_issue_id
+        FROM newsletter_issues
+        WHERE newsletter_issue_id = $1 AND status = 'scheduled' AND send_at <= now()
+        FOR UPDATE
+        SKIP LOCKED
+        "#,
+        newsletter_issue_id
+    )
+    .fetch_optional(&mut *transaction)
+    .await?
+    .is_some();
+    if !is_due {
+        return Ok(false);
+    }
+    let is_published = publish_issue(&mut transaction, newsletter_issue_id).await?;
+    transaction.commit().await?;
+
+    Ok(is_published)
+}
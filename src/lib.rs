@@ -6,5 +6,19 @@ pub mod startup;
 pub mod telemetry;
 
 pub mod authentication;
+pub mod confirmation_email_worker;
 pub mod email_client;
+pub mod export;
+pub mod idempotency;
+pub mod issue_delivery_worker;
+pub mod issue_scheduler;
+pub mod lists;
+pub mod newsletter_issues;
+pub mod privacy;
+pub mod segments;
 pub mod session_state;
+pub mod subscriber_import;
+pub mod subscribers;
+pub mod subscription_cleanup;
+pub mod templates;
+pub mod utils;
@@ -0,0 +1,106 @@
+//! Mailing lists.
+//!
+//! People subscribe to each list separately, and confirm each subscription
+//! on its own. Issues are sent to the confirmed subscribers of one or more
+//! lists. `/subscriptions` subscribes to the default list.
+use crate::domain::ListSlug;
+use chrono::Utc;
+use sqlx::{PgExecutor, PgPool};
+use uuid::Uuid;
+
+/// The list everybody subscribed to before there were several of them.
+pub const DEFAULT_LIST_SLUG: &str = "newsletter";
+
+pub struct List {
+    pub list_id: Uuid,
+    pub slug: String,
+    pub name: String,
+}
+
+#[tracing::instrument(skip(executor))]
+pub async fn get_list_by_slug(
+    executor: impl PgExecutor<'_>,
+    slug: &str,
+) -> Result<Option<List>, sqlx::Error> {
+    sqlx::query_as!(
+        List,
+        "SELECT list_id, slug, name FROM lists WHERE slug = $1",
+        slug
+    )
+    .fetch_optional(executor)
+    .await
+}
+
+#[tracing::instrument(skip(pool))]
+pub async fn get_list(pool: &PgPool, list_id: Uuid) -> Result<Option<List>, sqlx::Error> {
+    sqlx::query_as!(
+        List,
+        "SELECT list_id, slug, name FROM lists WHERE list_id = $1",
+        list_id
+    )
+    .fetch_optional(pool)
+    .await
+}
+
+/// The lists with the given slugs. Unknown slugs are ignored.
+#[tracing::instrument(skip(pool))]
+pub async fn get_lists_by_slug(pool: &PgPool, slugs: &[String]) -> Result<Vec<List>, sqlx::Error> {
+    sqlx::query_as!(
+        List,
+        "SELECT list_id, slug, name FROM lists WHERE slug = ANY($1) ORDER BY slug",
+        slugs
+    )
+    .fetch_all(pool)
+    .await
+}
+
+#[tracing::instrument(skip(pool))]
+pub async fn get_all_lists(pool: &PgPool) -> Result<Vec<List>, sqlx::Error> {
+    sqlx::query_as!(List, "SELECT list_id, slug, name FROM lists ORDER BY slug")
+        .fetch_all(pool)
+        .await
+}
+
+/// The lists an issue is sent to.
+#[tracing::instrument(skip(pool))]
+pub async fn get_issue_lists(
+    pool: &PgPool,
+    newsletter_issue_id: Uuid,
+) -> Result<Vec<List>, sqlx::Error> {
+    sqlx::query_as!(
+        List,
+        r#"
+        SELECT l.list_id, l.slug, l.name
+        FROM lists l
+        JOIN newsletter_issue_lists il ON il.list_id = l.list_id
+        WHERE il.newsletter_issue_id = $1
+        ORDER BY l.slug
+        "#,
+        newsletter_issue_id
+    )
+    .fetch_all(pool)
+    .await
+}
+
+/// Create a new list.
+///
+/// Returns `false` if there is already a list with the same slug.
+#[tracing::instrument(skip(pool))]
+pub async fn insert_list(pool: &PgPool, slug: &ListSlug, name: &str) -> Result<bool, sqlx::Error> {
+    let n_inserted = sqlx::query!(
+        r#"
+        INSERT INTO lists (list_id, slug, name, created_at)
+        VALUES ($1, $2, $3, $4)
+        ON CONFLICT (slug) DO NOTHING
+        "#,
+        Uuid::new_v4(),
+        slug.as_ref(),
+        name,
+        Utc::now()
+    )
+    .execute(pool)
+    .await?
+    .rows_affected();
+
+    Ok(n_inserted > 0)
+}
@@ -1,15 +1,115 @@
-use zero2prod::configuration::get_configuration;
-use zero2prod::startup::Application;
+use anyhow::Context;
+use std::fmt::{Debug, Display};
+use tokio::task::JoinError;
+use tokio_util::io::ReaderStream;
+use zero2prod::configuration::{get_configuration, Settings};
+use zero2prod::confirmation_email_worker::run_confirmation_worker_until_stopped;
+use zero2prod::issue_delivery_worker::run_worker_until_stopped;
+use zero2prod::issue_scheduler::run_scheduler_until_stopped;
+use zero2prod::startup::{get_connection_pool, Application};
+use zero2prod::subscriber_import::{import_subscribers, ImportMode, MAX_REPORTED_ERRORS};
+use zero2prod::subscription_cleanup::run_cleanup_until_stopped;
 use zero2prod::telemetry::{get_subscriber, init_subscriber};
 
-#[actix_web::main]
+const USAGE: &str = "Usage:
+    zero2prod
+        Run the API and the background workers.
+    zero2prod import-subscribers <confirmed|send_confirmation> <file.csv>
+        Import the subscribers of a CSV file with email and name columns.
+        Their confirmation emails are sent by the background workers.";
+
+#[tokio::main]
 async fn main() -> anyhow::Result<()> {
+    let args = std::env::args().skip(1).collect::<Vec<_>>();
+    match args.as_slice() {
+        [] => run().await,
+        [command, mode, path] if command == "import-subscribers" => {
+            // Logs go to stderr: stdout is for the import report
+            let subscriber = get_subscriber("zero2prod".into(), "warn".into(), std::io::stderr);
+            init_subscriber(subscriber);
+            let configuration = get_configuration().expect("Failed to read configuration.");
+            import_subscribers_from_file(configuration, mode, path).await
+        }
+        _ => {
+            eprintln!("{}", USAGE);
+            std::process::exit(2);
+        }
+    }
+}
+
+async fn run() -> anyhow::Result<()> {
     let subscriber = get_subscriber("zero2prod".into(), "info".into(), std::io::stdout);
     init_subscriber(subscriber);
 
     let configuration = get_configuration().expect("Failed to read configuration.");
     let app = Application::build(&configuration).await?;
-    app.run_until_stopped().await?;
+    let application_task = tokio::spawn(app.run_until_stopped());
+    let cleanup_task = tokio::spawn(run_cleanup_until_stopped(configuration.clone()));
+    let scheduler_task = tokio::spawn(run_scheduler_until_stopped(configuration.clone()));
+    let confirmation_task =
+        tokio::spawn(run_confirmation_worker_until_stopped(configuration.clone()));
+    let worker_task = tokio::spawn(run_worker_until_stopped(configuration));
+
+    tokio::select! {
+        outcome = application_task => report_exit("API", outcome),
+        outcome = worker_task => report_exit("Background worker", outcome),
+        outcome = cleanup_task => report_exit("Subscription cleanup", outcome),
+        outcome = scheduler_task => report_exit("Issue scheduler", outcome),
+        outcome = confirmation_task => report_exit("Confirmation email worker", outcome),
+    };
+
+    Ok(())
+}
+
+async fn import_subscribers_from_file(
+    configuration: Settings,
+    mode: &str,
+    path: &str,
+) -> anyhow::Result<()> {
+    let mode = ImportMode::parse(mode).map_err(anyhow::Error::msg)?;
+    let file = tokio::fs::File::open(path)
+        .await
+        .with_context(|| format!("Failed to open {}", path))?;
+    let pool = get_connection_pool(&configuration.database);
+
+    let report = import_subscribers(ReaderStream::new(file), mode, &pool).await?;
+
+    println!("Imported subscribers: {}", report.n_imported);
+    println!(
+        "Skipped, the address is already known: {}",
+        report.n_skipped
+    );
+    println!("Errors: {}", report.n_errors);
+    for error in &report.errors {
+        println!("line {}: {}", error.line, error.message);
+    }
+    if report.n_errors > MAX_REPORTED_ERRORS as u64 {
+        println!("Only the first {} errors are listed.", MAX_REPORTED_ERRORS);
+    }
 
     Ok(())
 }
+
+fn report_exit(task_name: &str, outcome: Result<Result<(), impl Debug + Display>, JoinError>) {
+    match outcome {
+        Ok(Ok(())) => {
+            tracing::info!("{} has exited", task_name)
+        }
+        Ok(Err(e)) => {
+            tracing::error!(
+                error.cause_chain = ?e,
+                error.message = %e,
+                "{} failed",
+                task_name
+            )
+        }
+        Err(e) => {
+            tracing::error!(
+                error.cause_chain = ?e,
+                error.message = %e,
+                "{} task failed to complete",
+                task_name
+            )
+        }
+    }
+}
@@ -0,0 +1,481 @@
+//! Storage of newsletter issues and of their lifecycle.
+//!
+//! An issue starts as a `draft`, which can be edited. It is then either
+//! published straight away or `scheduled` to go out at `send_at`; a scheduled
+//! issue can be cancelled, turning it back into a draft, until the scheduler
+//! picks it up. Publishing an issue enqueues its delivery to the confirmed
+//! subscribers of its lists, within its segment if it has one, and makes it
+//! part of the public archive.
+use crate::domain::IssueSlug;
+use crate::segments::Segment;
+use chrono::{DateTime, Utc};
+use sqlx::{Connection, PgPool, Postgres, QueryBuilder, Transaction};
+use uuid::Uuid;
+
+pub struct NewsletterIssue {
+    pub newsletter_issue_id: Uuid,
+    pub title: String,
+    pub text_content: String,
+    pub html_content: String,
+    pub status: String,
+    pub send_at: Option<DateTime<Utc>>,
+    pub published_at: Option<DateTime<Utc>>,
+    pub slug: Option<String>,
+    pub segment: Option<String>,
+}
+
+/// Who an issue is sent to: the confirmed subscribers of its lists, only
+/// the ones in the segment if there is one.
+#[derive(Debug)]
+pub struct IssueAudience {
+    pub list_ids: Vec<Uuid>,
+    pub segment: Option<Segment>,
+}
+
+/// What happened to the deliveries of a published issue.
+#[derive(serde::Serialize)]
+pub struct IssueStats {
+    /// Deliveries enqueued when the issue was published, one per confirmed subscriber
+    /// of its lists.
+    pub queued: i32,
+    /// Accepted by the email provider.
+    pub sent: i32,
+    /// Moved to the dead-letter queue. Re-driven deliveries no longer count as failed.
+    pub failed: i32,
+    /// Skipped because the stored email address of the subscriber is invalid.
+    pub skipped_invalid: i32,
+    /// Skipped because the subscriber was no longer confirmed when the delivery came up.
+    pub skipped_unsubscribed: i32,
+    /// Reported as hard bounces by the email provider after being sent. Soft
+    /// bounces are recorded as events, but not counted.
+    pub bounced: i32,
+}
+
+/// When a new issue should go out.
+#[derive(Debug, Clone, Copy)]
+pub enum IssueSchedule {
+    /// Not until it is explicitly scheduled or published.
+    Draft,
+    At(DateTime<Utc>),
+    Now,
+}
+
+#[tracing::instrument(name = "Save newsletter issue in the database", skip_all)]
+pub async fn insert_newsletter_issue(
+    transaction: &mut Transaction<'_, Postgres>,
+    author_id: Uuid,
+    title: &str,
+    text_content: &str,
+    html_content: &str,
+    audience: &IssueAudience,
+    schedule: IssueSchedule,
+) -> Result<Uuid, sqlx::Error> {
+    let newsletter_issue_id = Uuid::new_v4();
+    let send_at = match schedule {
+        IssueSchedule::At(send_at) => Some(send_at),
+        IssueSchedule::Draft | IssueSchedule::Now => None,
+    };
+    sqlx::query!(
+        r#"
+        INSERT INTO newsletter_issues (
+            newsletter_issue_id,
+            title,
+            text_content,
+            html_content,
+            author_id,
+            status,
+            send_at,
+            segment,
+            created_at
+        )
+        VALUES ($1, $2, $3, $4, $5, $6, $7, $8, now())
+        "#,
+        newsletter_issue_id,
+        title,
+        text_content,
+        html_content,
+        author_id,
+        if send_at.is_some() {
+            "scheduled"
+        } else {
+            "draft"
+        },
+        send_at,
+        audience.segment.as_ref().map(|segment| segment.as_ref())
+    )
+    .execute(&mut **transaction)
+    .await?;
+    set_issue_lists(transaction, newsletter_issue_id, &audience.list_ids).await?;
+    if let IssueSchedule::Now = schedule {
+        publish_issue(transaction, newsletter_issue_id).await?;
+    }
+
+    Ok(newsletter_issue_id)
+}
+
+#[tracing::instrument(skip(pool))]
+pub async fn get_newsletter_issue(
+    pool: &PgPool,
+    newsletter_issue_id: Uuid,
+) -> Result<Option<NewsletterIssue>, sqlx::Error> {
+    sqlx::query_as!(
+        NewsletterIssue,
+        r#"
+        SELECT
+            newsletter_issue_id,
+            title,
+            text_content,
+            html_content,
+            status,
+            send_at,
+            published_at,
+            slug,
+            segment
+        FROM newsletter_issues
+        WHERE newsletter_issue_id = $1
+        "#,
+        newsletter_issue_id
+    )
+    .fetch_optional(pool)
+    .await
+}
+
+/// `None` until the issue is published.
+#[tracing::instrument(skip(pool))]
+pub async fn get_issue_stats(
+    pool: &PgPool,
+    newsletter_issue_id: Uuid,
+) -> Result<Option<IssueStats>, sqlx::Error> {
+    sqlx::query_as!(
+        IssueStats,
+        r#"
+        SELECT
+            n_queued as queued,
+            n_sent as sent,
+            n_failed as failed,
+            n_skipped_invalid as skipped_invalid,
+            n_skipped_unsubscribed as skipped_unsubscribed,
+            n_bounced as bounced
+        FROM newsletter_issue_stats
+        WHERE newsletter_issue_id = $1
+        "#,
+        newsletter_issue_id
+    )
+    .fetch_optional(pool)
+    .await
+}
+
+/// All issues, whatever their status, the most recently created first.
+#[tracing::instrument(skip(pool))]
+pub async fn list_newsletter_issues(pool: &PgPool) -> Result<Vec<NewsletterIssue>, sqlx::Error> {
+    sqlx::query_as!(
+        NewsletterIssue,
+        r#"
+        SELECT
+            newsletter_issue_id,
+            title,
+            text_content,
+            html_content,
+            status,
+            send_at,
+            published_at,
+            slug,
+            segment
+        FROM newsletter_issues
+        ORDER BY created_at DESC
+        "#
+    )
+    .fetch_all(pool)
+    .await
+}
+
+/// Replace the content of a draft, and who it is going to be sent to.
+///
+/// Returns `false` if the issue is not a draft (anymore).
+#[tracing::instrument(skip(pool, title, text_content, html_content))]
+pub async fn update_draft(
+    pool: &PgPool,
+    newsletter_issue_id: Uuid,
+    title: &str,
+    text_content: &str,
+    html_content: &str,
+    audience: &IssueAudience,
+) -> Result<bool, sqlx::Error> {
+    let mut transaction = pool.begin().await?;
+    let n_updated = sqlx::query!(
+        r#"
+        UPDATE newsletter_issues
+        SET title = $2, text_content = $3, html_content = $4, segment = $5
+        WHERE newsletter_issue_id = $1 AND status = 'draft'
+        "#,
+        newsletter_issue_id,
+        title,
+        text_content,
+        html_content,
+        audience.segment.as_ref().map(|segment| segment.as_ref())
+    )
+    .execute(&mut *transaction)
+    .await?
+    .rows_affected();
+    if n_updated == 0 {
+        return Ok(false);
+    }
+    set_issue_lists(&mut transaction, newsletter_issue_id, &audience.list_ids).await?;
+    transaction.commit().await?;
+
+    Ok(true)
+}
+
+async fn set_issue_lists(
+    transaction: &mut Transaction<'_, Postgres>,
+    newsletter_issue_id: Uuid,
+    list_ids: &[Uuid],
+) -> Result<(), sqlx::Error> {
+    sqlx::query!(
+        "DELETE FROM newsletter_issue_lists WHERE newsletter_issue_id = $1",
+        newsletter_issue_id
+    )
+    .execute(&mut **transaction)
+    .await?;
+    sqlx::query!(
+        r#"
+        INSERT INTO newsletter_issue_lists (newsletter_issue_id, list_id)
+        SELECT $1, list_id FROM UNNEST($2::uuid[]) AS list_id
+        ON CONFLICT DO NOTHING
+        "#,
+        newsletter_issue_id,
+        list_ids
+    )
+    .execute(&mut **transaction)
+    .await?;
+
+    Ok(())
+}
+
+/// Schedule a draft to go out at `send_at`.
+///
+/// Returns `false` if the issue is not a draft (anymore).
+#[tracing::instrument(skip(pool))]
+pub async fn schedule_issue(
+    pool: &PgPool,
+    newsletter_issue_id: Uuid,
+    send_at: DateTime<Utc>,
+) -> Result<bool, sqlx::Error> {
+    let n_updated = sqlx::query!(
+        r#"
+        UPDATE newsletter_issues
+        SET status = 'scheduled', send_at = $2
+        WHERE newsletter_issue_id = $1 AND status = 'draft'
+        "#,
+        newsletter_issue_id,
+        send_at
+    )
+    .execute(pool)
+    .await?
+    .rows_affected();
+
+    Ok(n_updated > 0)
+}
+
+/// Turn a scheduled issue back into a draft.
+///
+/// Returns `false` if the issue is not scheduled, e.g. because the
+/// scheduler has already published it.
+#[tracing::instrument(skip(pool))]
+pub async fn cancel_scheduled_issue(
+    pool: &PgPool,
+    newsletter_issue_id: Uuid,
+) -> Result<bool, sqlx::Error> {
+    // The scheduler locks the issues it publishes: a concurrent cancellation
+    // waits for it and then finds the issue published.
+    let n_updated = sqlx::query!(
+        r#"
+        UPDATE newsletter_issues
+        SET status = 'draft', send_at = NULL
+        WHERE newsletter_issue_id = $1 AND status = 'scheduled'
+        "#,
+        newsletter_issue_id
+    )
+    .execute(pool)
+    .await?
+    .rows_affected();
+
+    Ok(n_updated > 0)
+}
+
+/// Publish a draft or a scheduled issue: give it a slug in the archive and
+/// enqueue its delivery to the confirmed subscribers of its lists.
+///
+/// Returns `false` if the issue has already been published.
+#[tracing::instrument(skip(transaction))]
+pub async fn publish_issue(
+    transaction: &mut Transaction<'_, Postgres>,
+    newsletter_issue_id: Uuid,
+) -> Result<bool, sqlx::Error> {
+    let Some(issue) = sqlx::query!(
+        r#"
+        SELECT title, segment
+        FROM newsletter_issues
+        WHERE newsletter_issue_id = $1 AND status IN ('draft', 'scheduled')
+        FOR UPDATE
+        "#,
+        newsletter_issue_id
+    )
+    .fetch_optional(&mut **transaction)
+    .await?
+    else {
+        return Ok(false);
+    };
+    let segment = parse_stored_segment(issue.segment.as_deref())?;
+
+    set_published(transaction, newsletter_issue_id, &issue.title).await?;
+    let n_queued =
+        enqueue_delivery_tasks(transaction, newsletter_issue_id, segment.as_ref()).await?;
+    sqlx::query!(
+        r#"
+        INSERT INTO newsletter_issue_stats (newsletter_issue_id, n_queued)
+        VALUES ($1, $2)
+        "#,
+        newsletter_issue_id,
+        n_queued as i32
+    )
+    .execute(&mut **transaction)
+    .await?;
+
+    Ok(true)
+}
+
+/// Mark the issue as published, with a unique slug: derived from its title,
+/// with a piece of its id appended if another issue already uses it.
+///
+/// Another issue with the same title may be published concurrently: the
+/// existence check can't see it, the unique constraint on slugs does. Each
+/// attempt runs in a savepoint, so that a violation doesn't abort the whole
+/// publication and we can retry with the next suffix.
+async fn set_published(
+    transaction: &mut Transaction<'_, Postgres>,
+    newsletter_issue_id: Uuid,
+    title: &str,
+) -> Result<(), sqlx::Error> {
+    let slug = IssueSlug::from_title(title);
+    let id = newsletter_issue_id.simple().to_string();
+    let is_taken = sqlx::query_scalar!(
+        r#"SELECT EXISTS(SELECT 1 FROM newsletter_issues WHERE slug = $1) as "exists!""#,
+        slug.as_ref()
+    )
+    .fetch_one(&mut **transaction)
+    .await?;
+    let candidates = [
+        slug.clone(),
+        slug.with_suffix(&id[..8]),
+        slug.with_suffix(&id),
+    ];
+    let candidates = if is_taken {
+        &candidates[1..]
+    } else {
+        &candidates[..]
+    };
+
+    let mut last_error = None;
+    for slug in candidates {
+        let mut savepoint = (**transaction).begin().await?;
+        let result = sqlx::query!(
+            r#"
+            UPDATE newsletter_issues
+            SET status = 'published', published_at = now(), slug = $2
+            WHERE newsletter_issue_id = $1
+            "#,
+            newsletter_issue_id,
+            slug.as_ref()
+        )
+        .execute(&mut *savepoint)
+        .await;
+        match result {
+            Ok(_) => return savepoint.commit().await,
+            Err(e) if is_unique_violation(&e) => {
+                tracing::info!(slug = slug.as_ref(), "The slug was taken concurrently");
+                savepoint.rollback().await?;
+                last_error = Some(e);
+            }
+            Err(e) => return Err(e),
+        }
+    }
+    // The last candidate contains the whole id: it can't really be taken
+    Err(last_error.expect("There is always a candidate slug"))
+}
+
+fn is_unique_violation(e: &sqlx::Error) -> bool {
+    e.as_database_error()
+        .is_some_and(|e| e.is_unique_violation())
+}
+
+/// Segments are validated before being stored.
+fn parse_stored_segment(segment: Option<&str>) -> Result<Option<Segment>, sqlx::Error> {
+    segment
+        .map(Segment::parse)
+        .transpose()
+        .map_err(|e| sqlx::Error::Decode(e.into()))
+}
+
+/// Push the query selecting the email addresses an issue goes to.
+fn push_recipients(
+    builder: &mut QueryBuilder<'_, Postgres>,
+    newsletter_issue_id: Uuid,
+    segment: Option<&Segment>,
+) {
+    builder.push(
+        r#"
+        SELECT DISTINCT s.email
+        FROM subscriptions s
+        JOIN list_subscriptions ls ON ls.subscriber_id = s.id
+        JOIN newsletter_issue_lists il ON il.list_id = ls.list_id
+        WHERE
+            ls.status = 'confirmed' AND
+            s.status = 'confirmed' AND
+            il.newsletter_issue_id = "#,
+    );
+    builder.push_bind(newsletter_issue_id);
+    if let Some(segment) = segment {
+        builder.push(" AND ");
+        segment.push_condition(builder);
+    }
+}
+
+/// How many subscribers the issue would go to if it was published now.
+#[tracing::instrument(skip(pool))]
+pub async fn count_recipients(
+    pool: &PgPool,
+    newsletter_issue_id: Uuid,
+    segment: Option<&str>,
+) -> Result<i64, sqlx::Error> {
+    let segment = parse_stored_segment(segment)?;
+    let mut builder = QueryBuilder::new("SELECT COUNT(*) FROM (");
+    push_recipients(&mut builder, newsletter_issue_id, segment.as_ref());
+    builder.push(") recipients");
+
+    builder.build_query_scalar::<i64>().fetch_one(pool).await
+}
+
+/// One delivery per subscriber, whatever the number of the issue's lists they
+/// are subscribed to.
+#[tracing::instrument(name = "Enqueue delivery tasks", skip(transaction, segment))]
+async fn enqueue_delivery_tasks(
+    transaction: &mut Transaction<'_, Postgres>,
+    newsletter_issue_id: Uuid,
+    segment: Option<&Segment>,
+) -> Result<u64, sqlx::Error> {
+    let mut builder = QueryBuilder::new(
+        "INSERT INTO issue_delivery_queue (newsletter_issue_id, subscriber_email) SELECT ",
+    );
+    builder.push_bind(newsletter_issue_id);
+    builder.push(", email FROM (");
+    push_recipients(&mut builder, newsletter_issue_id, segment);
+    builder.push(") recipients");
+    let n_queued = builder
+        .build()
+        .execute(&mut **transaction)
+        .await?
+        .rows_affected();
+
+    Ok(n_queued)
+}
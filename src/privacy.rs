@@ -0,0 +1,256 @@
+//! Data subject requests: subscribers can get a copy of everything we store
+//! about them, or have it erased.
+//!
+//! Deliveries are keyed by email address rather than by subscriber id, as
+//! they outlive the subscriptions: we look them up by address, and erase the
+//! address from them rather than the deliveries themselves.
+use crate::issue_delivery_worker::count_dropped_deliveries;
+use chrono::{DateTime, Utc};
+use sqlx::PgPool;
+use uuid::Uuid;
+
+/// Everything we store about a subscriber.
+#[derive(serde::Serialize)]
+pub struct SubscriberData {
+    pub subscriber: SubscriberRecord,
+    pub list_subscriptions: Vec<ListSubscriptionRecord>,
+    pub subscription_tokens: Vec<SubscriptionTokenRecord>,
+    pub deliveries: Vec<DeliveryRecord>,
+    pub delivery_events: Vec<DeliveryEventRecord>,
+}
+
+#[derive(serde::Serialize)]
+pub struct SubscriberRecord {
+    pub id: Uuid,
+    pub email: String,
+    pub name: String,
+    pub status: String,
+    pub subscribed_at: DateTime<Utc>,
+    pub tags: Vec<String>,
+    pub attributes: serde_json::Value,
+}
+
+#[derive(serde::Serialize)]
+pub struct ListSubscriptionRecord {
+    pub list: String,
+    pub status: String,
+    pub subscribed_at: DateTime<Utc>,
+}
+
+#[derive(serde::Serialize)]
+pub struct SubscriptionTokenRecord {
+    pub subscription_token: String,
+    /// The list the token confirms, all of them when there is none.
+    pub list: Option<String>,
+    pub created_at: DateTime<Utc>,
+    pub consumed_at: Option<DateTime<Utc>>,
+}
+
+#[derive(serde::Serialize)]
+pub struct DeliveryRecord {
+    pub newsletter_issue_id: Uuid,
+    pub issue_title: String,
+    /// 'queued' while the delivery is still waiting in the queue.
+    pub outcome: String,
+    pub n_attempts: i16,
+    pub recorded_at: Option<DateTime<Utc>>,
+    pub last_error: Option<String>,
+}
+
+#[derive(serde::Serialize)]
+pub struct DeliveryEventRecord {
+    pub record_type: String,
+    pub message_id: String,
+    pub newsletter_issue_id: Option<Uuid>,
+    pub bounce_type: Option<String>,
+    pub details: Option<String>,
+    pub occurred_at: DateTime<Utc>,
+    pub received_at: DateTime<Utc>,
+}
+
+/// Collect everything we store about the subscriber, `None` if we don't
+/// know them.
+#[tracing::instrument(skip(pool))]
+pub async fn get_subscriber_data(
+    pool: &PgPool,
+    subscriber_id: Uuid,
+) -> Result<Option<SubscriberData>, sqlx::Error> {
+    // Read everything from the same snapshot
+    let mut transaction = pool.begin().await?;
+    sqlx::query!("SET TRANSACTION ISOLATION LEVEL REPEATABLE READ")
+        .execute(&mut *transaction)
+        .await?;
+    let Some(subscriber) = sqlx::query_as!(
+        SubscriberRecord,
+        r#"
+        SELECT id, email, name, status, subscribed_at, tags, attributes
+        FROM subscriptions
+        WHERE id = $1
+        "#,
+        subscriber_id
+    )
+    .fetch_optional(&mut *transaction)
+    .await?
+    else {
+        return Ok(None);
+    };
+
+    let list_subscriptions = sqlx::query_as!(
+        ListSubscriptionRecord,
+        r#"
+        SELECT l.slug AS list, ls.status, ls.subscribed_at
+        FROM list_subscriptions ls
+        JOIN lists l ON l.list_id = ls.list_id
+        WHERE ls.subscriber_id = $1
+        ORDER BY l.slug
+        "#,
+        subscriber_id
+    )
+    .fetch_all(&mut *transaction)
+    .await?;
+    let subscription_tokens = sqlx::query_as!(
+        SubscriptionTokenRecord,
+        r#"
+        SELECT t.subscription_token, l.slug AS "list?", t.created_at, t.consumed_at
+        FROM subscription_tokens t
+        LEFT JOIN lists l ON l.list_id = t.list_id
+        WHERE t.subscriber_id = $1
+        ORDER BY t.created_at
+        "#,
+        subscriber_id
+    )
+    .fetch_all(&mut *transaction)
+    .await?;
+    let deliveries = sqlx::query_as!(
+        DeliveryRecord,
+        r#"
+        SELECT
+            d.newsletter_issue_id AS "newsletter_issue_id!",
+            i.title AS issue_title,
+            d.outcome AS "outcome!",
+            d.n_attempts AS "n_attempts!",
+            d.recorded_at,
+            dl.last_error AS "last_error?"
+        FROM (
+            SELECT newsletter_issue_id, outcome, n_attempts, recorded_at
+            FROM issue_delivery_results
+            WHERE subscriber_email = $1
+            UNION ALL
+            SELECT newsletter_issue_id, 'queued', n_retries, NULL
+            FROM issue_delivery_queue
+            WHERE subscriber_email = $1
+        ) d
+        JOIN newsletter_issues i ON i.newsletter_issue_id = d.newsletter_issue_id
+        LEFT JOIN issue_delivery_dead_letters dl
+            ON dl.newsletter_issue_id = d.newsletter_issue_id AND dl.subscriber_email = $1
+        ORDER BY d.recorded_at NULLS LAST
+        "#,
+        subscriber.email
+    )
+    .fetch_all(&mut *transaction)
+    .await?;
+    let delivery_events = sqlx::query_as!(
+        DeliveryEventRecord,
+        r#"
+        SELECT
+            record_type, message_id, newsletter_issue_id, bounce_type, details,
+            occurred_at, received_at
+        FROM delivery_events
+        WHERE subscriber_email = $1
+        ORDER BY occurred_at
+        "#,
+        subscriber.email
+    )
+    .fetch_all(&mut *transaction)
+    .await?;
+    transaction.commit().await?;
+
+    Ok(Some(SubscriberData {
+        subscriber,
+        list_subscriptions,
+        subscription_tokens,
+        deliveries,
+        delivery_events,
+    }))
+}
+
+/// Erase the subscriber: their subscriptions and tokens are deleted, as
+/// well as the deliveries still queued for them.
+///
+/// Past deliveries and the events reported by the email provider are kept,
+/// under a random pseudonym: the delivery statistics and exports of the
+/// issues they received stay the same.
+///
+/// Returns `false` if we don't know the subscriber, e.g. they have already
+/// been erased.
+#[tracing::instrument(skip(pool))]
+pub async fn erase_subscriber(pool: &PgPool, subscriber_id: Uuid) -> Result<bool, sqlx::Error> {
+    let mut transaction = pool.begin().await?;
+    let Some(email) = sqlx::query_scalar!(
+        "SELECT email FROM subscriptions WHERE id = $1 FOR UPDATE",
+        subscriber_id
+    )
+    .fetch_optional(&mut *transaction)
+    .await?
+    else {
+        return Ok(false);
+    };
+    // Not derived from the address: it must not be possible to tell whose
+    // deliveries these were.
+    let pseudonym = format!("erased-{}", Uuid::new_v4());
+
+    let dropped_issue_ids = sqlx::query_scalar!(
+        r#"
+        DELETE FROM issue_delivery_queue WHERE subscriber_email = $1
+        RETURNING newsletter_issue_id
+        "#,
+        email
+    )
+    .fetch_all(&mut *transaction)
+    .await?;
+    count_dropped_deliveries(&mut transaction, &dropped_issue_ids).await?;
+    sqlx::query!(
+        "UPDATE issue_delivery_results SET subscriber_email = $2 WHERE subscriber_email = $1",
+        email,
+        pseudonym
+    )
+    .execute(&mut *transaction)
+    .await?;
+    // Error messages and event details may quote the address
+    sqlx::query!(
+        r#"
+        UPDATE issue_delivery_dead_letters
+        SET subscriber_email = $2, last_error = replace(last_error, $1, $2)
+        WHERE subscriber_email = $1
+        "#,
+        email,
+        pseudonym
+    )
+    .execute(&mut *transaction)
+    .await?;
+    sqlx::query!(
+        r#"
+        UPDATE delivery_events
+        SET subscriber_email = $2, details = replace(details, $1, $2)
+        WHERE subscriber_email = $1
+        "#,
+        email,
+        pseudonym
+    )
+    .execute(&mut *transaction)
+    .await?;
+    sqlx::query!(
+        "DELETE FROM subscription_tokens WHERE subscriber_id = $1",
+        subscriber_id
+    )
+    .execute(&mut *transaction)
+    .await?;
+    // The list subscriptions go along with it
+    sqlx::query!("DELETE FROM subscriptions WHERE id = $1", subscriber_id)
+        .execute(&mut *transaction)
+        .await?;
+    transaction.commit().await?;
+
+    tracing::info!(pseudonym, "Erased a subscriber");
+    Ok(true)
+}
@@ -0,0 +1,188 @@
+use crate::utils::{e500, see_other};
+use actix_web::http::header::ContentType;
+use actix_web::{web, HttpResponse};
+use actix_web_flash_messages::{FlashMessage, IncomingFlashMessages};
+use anyhow::Context;
+use chrono::{DateTime, Utc};
+use htmlescape::encode_minimal;
+use sqlx::PgPool;
+use std::fmt::Write;
+use uuid::Uuid;
+
+struct DeadLetter {
+    newsletter_issue_id: Uuid,
+    title: String,
+    subscriber_email: String,
+    n_attempts: i16,
+    last_error: String,
+    failed_at: DateTime<Utc>,
+}
+
+pub async fn admin_dead_letters(
+    pool: web::Data<PgPool>,
+    flash_messages: IncomingFlashMessages,
+) -> Result<HttpResponse, actix_web::Error> {
+    let mut msg_html = String::new();
+    for m in flash_messages.iter() {
+        writeln!(msg_html, "<p><i>{}</i></p>", m.content()).unwrap();
+    }
+
+    let mut rows_html = String::new();
+    for dead_letter in get_dead_letters(&pool).await.map_err(e500)? {
+        writeln!(
+            rows_html,
+            r#"<tr>
+        <td>{title}</td>
+        <td>{email}</td>
+        <td>{n_attempts}</td>
+        <td>{failed_at}</td>
+        <td>{last_error}</td>
+        <td>
+            <form action="/admin/dead_letters" method="post">
+                <input hidden type="text" name="newsletter_issue_id" value="{issue_id}">
+                <input hidden type="text" name="subscriber_email" value="{email}">
+                <button type="submit">Re-drive</button>
+            </form>
+        </td>
+    </tr>"#,
+            title = encode_minimal(&dead_letter.title),
+            email = encode_minimal(&dead_letter.subscriber_email),
+            n_attempts = dead_letter.n_attempts,
+            failed_at = dead_letter.failed_at.to_rfc3339(),
+            last_error = encode_minimal(&dead_letter.last_error),
+            issue_id = dead_letter.newsletter_issue_id,
+        )
+        .unwrap();
+    }
+
+    Ok(HttpResponse::Ok()
+        .content_type(ContentType::html())
+        .body(format!(
+            r#"<!DOCTYPE html>
+<html lang="en">
+<head>
+    <meta http-equiv="content-type" content="text/html; charset=utf-8">
+    <title>Failed deliveries</title>
+</head>
+<body>
+    {msg_html}
+    <table>
+    <tr>
+        <th>Issue</th>
+        <th>Subscriber</th>
+        <th>Attempts</th>
+        <th>Failed at</th>
+        <th>Last error</th>
+        <th></th>
+    </tr>
+    {rows_html}
+    </table>
+    <form action="/admin/dead_letters" method="post">
+        <button type="submit">Re-drive all</button>
+    </form>
+    <p><a href="/admin/dashboard">&lt;- Back</a></p>
+</body>
+</html>"#,
+        )))
+}
+
+#[derive(serde::Deserialize)]
+pub struct RedriveFormData {
+    newsletter_issue_id: Option<Uuid>,
+    subscriber_email: Option<String>,
+}
+
+/// Move failed deliveries back into the delivery queue.
+///
+/// Without a filter, every dead letter is re-driven.
+pub async fn redrive_dead_letters(
+    form: web::Form<RedriveFormData>,
+    pool: web::Data<PgPool>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let n_redriven = redrive(
+        &pool,
+        form.0.newsletter_issue_id,
+        form.0.subscriber_email.as_deref(),
+    )
+    .await
+    .map_err(e500)?;
+    FlashMessage::info(format!(
+        "{} failed deliveries have been re-queued.",
+        n_redriven
+    ))
+    .send();
+
+    Ok(see_other("/admin/dead_letters"))
+}
+
+#[tracing::instrument(name = "Get dead letters", skip(pool))]
+async fn get_dead_letters(pool: &PgPool) -> Result<Vec<DeadLetter>, anyhow::Error> {
+    let dead_letters = sqlx::query_as!(
+        DeadLetter,
+        r#"
+        SELECT
+            d.newsletter_issue_id,
+            i.title,
+            d.subscriber_email,
+            d.n_attempts,
+            d.last_error,
+            d.failed_at
+        FROM issue_delivery_dead_letters d
+        JOIN newsletter_issues i ON i.newsletter_issue_id = d.newsletter_issue_id
+        ORDER BY d.failed_at DESC
+        "#
+    )
+    .fetch_all(pool)
+    .await
+    .context("Failed to retrieve dead letters")?;
+
+    Ok(dead_letters)
+}
+
+#[tracing::instrument(name = "Re-drive dead letters", skip(pool))]
+async fn redrive(
+    pool: &PgPool,
+    newsletter_issue_id: Option<Uuid>,
+    subscriber_email: Option<&str>,
+) -> Result<u64, anyhow::Error> {
+    let n_redriven = sqlx::query!(
+        r#"
+        WITH redriven AS (
+            DELETE FROM issue_delivery_dead_letters
+            WHERE
+                ($1::uuid IS NULL OR newsletter_issue_id = $1) AND
+                ($2::text IS NULL OR subscriber_email = $2)
+            RETURNING newsletter_issue_id, subscriber_email
+        ),
+        -- Re-driven deliveries are no longer failures, whatever comes next
+        uncounted AS (
+            UPDATE newsletter_issue_stats s
+            SET n_failed = GREATEST(s.n_failed - r.n_redriven, 0)
+            FROM (
+                SELECT newsletter_issue_id, count(*)::integer AS n_redriven
+                FROM redriven
+                GROUP BY newsletter_issue_id
+            ) r
+            WHERE s.newsletter_issue_id = r.newsletter_issue_id
+        ),
+        unrecorded AS (
+            DELETE FROM issue_delivery_results d
+            USING redriven r
+            WHERE
+                d.newsletter_issue_id = r.newsletter_issue_id AND
+                d.subscriber_email = r.subscriber_email
+        )
+        INSERT INTO issue_delivery_queue (newsletter_issue_id, subscriber_email)
+        SELECT newsletter_issue_id, subscriber_email FROM redriven
+        ON CONFLICT DO NOTHING
+        "#,
+        newsletter_issue_id,
+        subscriber_email
+    )
+    .execute(pool)
+    .await
+    .context("Failed to move dead letters back to the delivery queue")?
+    .rows_affected();
+
+    Ok(n_redriven)
+}
@@ -0,0 +1,190 @@
+use crate::export::{start_export, ExportFormat, ExportRow};
+use crate::lists::get_list_by_slug;
+use crate::newsletter_issues::get_newsletter_issue;
+use crate::utils::{e400, e500};
+use actix_web::{web, HttpResponse};
+use chrono::{DateTime, Utc};
+use sqlx::PgPool;
+use uuid::Uuid;
+
+#[derive(serde::Deserialize)]
+pub struct SubscribersExportParameters {
+    status: Option<String>,
+    /// The slug of a list: only its members are exported, with the status
+    /// of their subscription to the list.
+    list: Option<String>,
+    format: Option<String>,
+}
+
+#[derive(serde::Serialize)]
+struct SubscriberRow {
+    email: String,
+    name: String,
+    status: String,
+    subscribed_at: DateTime<Utc>,
+    tags: Vec<String>,
+    attributes: serde_json::Value,
+}
+
+impl ExportRow for SubscriberRow {
+    const CSV_HEADER: &'static [&'static str] = &[
+        "email",
+        "name",
+        "status",
+        "subscribed_at",
+        "tags",
+        "attributes",
+    ];
+
+    fn csv_fields(&self) -> Vec<String> {
+        vec![
+            self.email.clone(),
+            self.name.clone(),
+            self.status.clone(),
+            self.subscribed_at.to_rfc3339(),
+            self.tags.join(";"),
+            self.attributes.to_string(),
+        ]
+    }
+}
+
+#[derive(serde::Deserialize)]
+pub struct DeliveriesExportParameters {
+    format: Option<String>,
+}
+
+#[derive(serde::Serialize)]
+struct DeliveryRow {
+    subscriber_email: String,
+    /// 'queued' while the delivery is still waiting in the queue.
+    outcome: String,
+    n_attempts: i16,
+    recorded_at: Option<DateTime<Utc>>,
+    last_error: Option<String>,
+}
+
+impl ExportRow for DeliveryRow {
+    const CSV_HEADER: &'static [&'static str] = &[
+        "subscriber_email",
+        "outcome",
+        "n_attempts",
+        "recorded_at",
+        "last_error",
+    ];
+
+    fn csv_fields(&self) -> Vec<String> {
+        vec![
+            self.subscriber_email.clone(),
+            self.outcome.clone(),
+            self.n_attempts.to_string(),
+            self.recorded_at.map(|d| d.to_rfc3339()).unwrap_or_default(),
+            self.last_error.clone().unwrap_or_default(),
+        ]
+    }
+}
+
+/// Export the subscribers, optionally filtered by status and list.
+///
+/// Empty parameters are ignored, as browsers submit every field of the form.
+#[tracing::instrument(skip(parameters, pool))]
+pub async fn export_subscribers(
+    parameters: web::Query<SubscribersExportParameters>,
+    pool: web::Data<PgPool>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let non_empty = |value: &Option<String>| value.clone().filter(|v| !v.is_empty());
+    let format = ExportFormat::parse(non_empty(&parameters.format).as_deref()).map_err(e400)?;
+    let status = non_empty(&parameters.status);
+    let list_id = match non_empty(&parameters.list) {
+        Some(slug) => match get_list_by_slug(&**pool, &slug).await.map_err(e500)? {
+            Some(list) => Some(list.list_id),
+            None => return Err(e400(format!("There is no list named {}.", slug))),
+        },
+        None => None,
+    };
+
+    let (writer, response) = start_export(format, "subscribers");
+    let pool = pool.get_ref().clone();
+    tokio::spawn(async move {
+        let rows = sqlx::query_as!(
+            SubscriberRow,
+            r#"
+            SELECT email, name, status AS "status!", subscribed_at, tags, attributes
+            FROM (
+                SELECT
+                    s.email,
+                    s.name,
+                    -- Confirmed subscribers may still be waiting to confirm
+                    -- their subscription to the list, or have left it
+                    CASE
+                        WHEN $2::uuid IS NOT NULL AND s.status = 'confirmed' THEN ls.status
+                        ELSE s.status
+                    END AS status,
+                    s.subscribed_at,
+                    s.tags,
+                    s.attributes
+                FROM subscriptions s
+                LEFT JOIN list_subscriptions ls
+                    ON ls.subscriber_id = s.id AND ls.list_id = $2
+                WHERE $2::uuid IS NULL OR ls.list_id IS NOT NULL
+            ) subscribers
+            WHERE $1::text IS NULL OR status = $1
+            ORDER BY subscribed_at, email
+            "#,
+            status,
+            list_id
+        )
+        .fetch(&pool);
+        writer.write_rows(rows).await;
+    });
+
+    Ok(response)
+}
+
+/// Export what happened to the delivery of an issue to each subscriber.
+#[tracing::instrument(skip(parameters, pool))]
+pub async fn export_deliveries(
+    newsletter_issue_id: web::Path<Uuid>,
+    parameters: web::Query<DeliveriesExportParameters>,
+    pool: web::Data<PgPool>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let format = ExportFormat::parse(parameters.format.as_deref()).map_err(e400)?;
+    let Some(issue) = get_newsletter_issue(&pool, newsletter_issue_id.into_inner())
+        .await
+        .map_err(e500)?
+    else {
+        return Ok(HttpResponse::NotFound().finish());
+    };
+
+    let (writer, response) = start_export(format, "deliveries");
+    let pool = pool.get_ref().clone();
+    tokio::spawn(async move {
+        let rows = sqlx::query_as!(
+            DeliveryRow,
+            r#"
+            SELECT
+                d.subscriber_email AS "subscriber_email!",
+                d.outcome AS "outcome!",
+                d.n_attempts AS "n_attempts!",
+                d.recorded_at,
+                dl.last_error AS "last_error?"
+            FROM (
+                SELECT subscriber_email, outcome, n_attempts, recorded_at
+                FROM issue_delivery_results
+                WHERE newsletter_issue_id = $1
+                UNION ALL
+                SELECT subscriber_email, 'queued', n_retries, NULL
+                FROM issue_delivery_queue
+                WHERE newsletter_issue_id = $1
+            ) d
+            LEFT JOIN issue_delivery_dead_letters dl
+                ON dl.newsletter_issue_id = $1 AND dl.subscriber_email = d.subscriber_email
+            ORDER BY d.subscriber_email
+            "#,
+            issue.newsletter_issue_id
+        )
+        .fetch(&pool);
+        writer.write_rows(rows).await;
+    });
+
+    Ok(response)
+}
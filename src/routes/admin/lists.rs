@@ -0,0 +1,123 @@
+use crate::domain::ListSlug;
+use crate::lists::insert_list;
+use crate::utils::{e500, see_other};
+use actix_web::http::header::ContentType;
+use actix_web::{web, HttpResponse};
+use actix_web_flash_messages::{FlashMessage, IncomingFlashMessages};
+use htmlescape::encode_minimal;
+use sqlx::PgPool;
+use std::fmt::Write;
+
+struct ListSummary {
+    slug: String,
+    name: String,
+    n_confirmed: i64,
+    n_pending: i64,
+}
+
+/// All the mailing lists, along with the form to create a new one.
+pub async fn admin_lists(
+    pool: web::Data<PgPool>,
+    flash_messages: IncomingFlashMessages,
+) -> Result<HttpResponse, actix_web::Error> {
+    let mut msg_html = String::new();
+    for m in flash_messages.iter() {
+        writeln!(msg_html, "<p><i>{}</i></p>", m.content()).unwrap();
+    }
+
+    let mut rows_html = String::new();
+    for list in get_list_summaries(&pool).await.map_err(e500)? {
+        writeln!(
+            rows_html,
+            r#"<tr><td>{name}</td><td>/lists/{slug}/subscriptions</td><td>{n_confirmed}</td><td>{n_pending}</td></tr>"#,
+            name = encode_minimal(&list.name),
+            slug = encode_minimal(&list.slug),
+            n_confirmed = list.n_confirmed,
+            n_pending = list.n_pending,
+        )
+        .unwrap();
+    }
+
+    Ok(HttpResponse::Ok()
+        .content_type(ContentType::html())
+        .body(format!(
+            r#"<!DOCTYPE html>
+<html lang="en">
+<head>
+    <meta http-equiv="content-type" content="text/html; charset=utf-8">
+    <title>Mailing lists</title>
+</head>
+<body>
+    {msg_html}
+    <h1>Mailing lists</h1>
+    <table>
+        <tr><th>Name</th><th>Subscription endpoint</th><th>Confirmed</th><th>Pending</th></tr>
+        {rows_html}
+    </table>
+    <h2>New list</h2>
+    <form action="/admin/lists" method="post">
+        <label>Name:
+            <input type="text" placeholder="Enter the list name" name="name">
+        </label>
+        <label>Slug:
+            <input type="text" placeholder="e.g. rust-weekly" name="slug">
+        </label>
+        <button type="submit">Create</button>
+    </form>
+    <p><a href="/admin/dashboard">&lt;- Back</a></p>
+</body>
+</html>"#,
+        )))
+}
+
+#[derive(serde::Deserialize)]
+pub struct ListFormData {
+    name: String,
+    slug: String,
+}
+
+pub async fn create_list(
+    form: web::Form<ListFormData>,
+    pool: web::Data<PgPool>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let ListFormData { name, slug } = form.0;
+    let name = name.trim();
+    if name.is_empty() {
+        FlashMessage::error("The name of the list cannot be empty.").send();
+        return Ok(see_other("/admin/lists"));
+    }
+    let Ok(slug) = ListSlug::parse(slug) else {
+        FlashMessage::error(
+            "The slug can only contain lowercase letters and digits, separated by single dashes.",
+        )
+        .send();
+        return Ok(see_other("/admin/lists"));
+    };
+
+    if insert_list(&pool, &slug, name).await.map_err(e500)? {
+        FlashMessage::info("The list has been created.").send();
+    } else {
+        FlashMessage::error("There is already a list with this slug.").send();
+    }
+
+    Ok(see_other("/admin/lists"))
+}
+
+async fn get_list_summaries(pool: &PgPool) -> Result<Vec<ListSummary>, sqlx::Error> {
+    sqlx::query_as!(
+        ListSummary,
+        r#"
+        SELECT
+            l.slug,
+            l.name,
+            COUNT(*) FILTER (WHERE ls.status = 'confirmed') AS "n_confirmed!",
+            COUNT(*) FILTER (WHERE ls.status = 'pending_confirmation') AS "n_pending!"
+        FROM lists l
+        LEFT JOIN list_subscriptions ls ON ls.list_id = l.list_id
+        GROUP BY l.list_id
+        ORDER BY l.slug
+        "#
+    )
+    .fetch_all(pool)
+    .await
+}
@@ -0,0 +1,32 @@
+use crate::authentication::{invalidate_sessions, UserId};
+use crate::session_state::TypedSession;
+use crate::utils::{e500, see_other};
+use actix_web::{web, HttpResponse};
+use actix_web_flash_messages::FlashMessage;
+use sqlx::PgPool;
+
+#[derive(serde::Deserialize)]
+pub struct FormData {
+    // Log out all the sessions of the user, not just the current one
+    #[serde(default)]
+    everywhere: bool,
+}
+
+pub async fn log_out(
+    form: web::Form<FormData>,
+    user_id: web::ReqData<UserId>,
+    session: TypedSession,
+    pool: web::Data<PgPool>,
+) -> Result<HttpResponse, actix_web::Error> {
+    if form.everywhere {
+        invalidate_sessions(*user_id.into_inner(), &pool)
+            .await
+            .map_err(e500)?;
+        FlashMessage::info("You have successfully logged out from all devices.").send();
+    } else {
+        FlashMessage::info("You have successfully logged out.").send();
+    }
+    session.log_out();
+
+    Ok(see_other("/login"))
+}
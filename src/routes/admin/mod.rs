@@ -1,3 +1,24 @@
 mod dashboard;
+mod dead_letters;
+mod exports;
+mod lists;
+mod logout;
+mod newsletters;
+mod password;
+mod subscribers;
 
-pub use dashboard::admin_dashboard;
+pub use dashboard::{admin_dashboard, get_username};
+pub use dead_letters::{admin_dead_letters, redrive_dead_letters};
+pub use exports::{export_deliveries, export_subscribers};
+pub use lists::{admin_lists, create_list};
+pub use logout::log_out;
+pub use newsletters::{
+    admin_newsletter, admin_newsletters, cancel_newsletter_schedule, create_newsletter_draft,
+    preview_newsletter, publish_newsletter_now, schedule_newsletter,
+    send_test_newsletter_from_form, update_newsletter_draft,
+};
+pub use password::{change_password, change_password_form};
+pub use subscribers::{
+    admin_subscriber, admin_subscribers, import_subscribers_form, import_subscribers_from_form,
+    manually_confirm_subscriber, remove_subscriber, resend_confirmation, tag_subscribers_from_form,
+};
@@ -0,0 +1,341 @@
+use crate::authentication::UserId;
+use crate::lists::{get_all_lists, get_issue_lists, List, DEFAULT_LIST_SLUG};
+use crate::newsletter_issues::{
+    count_recipients, get_issue_stats, get_newsletter_issue, list_newsletter_issues,
+};
+use crate::routes::admin::dashboard::get_username;
+use crate::templates::{IssueTemplate, Templates};
+use crate::utils::e500;
+use actix_web::http::header::ContentType;
+use actix_web::{web, HttpResponse};
+use actix_web_flash_messages::IncomingFlashMessages;
+use chrono::{DateTime, Utc};
+use htmlescape::encode_minimal;
+use sqlx::PgPool;
+use std::fmt::Write;
+use uuid::Uuid;
+
+const CONTENT_HINT: &str = r#"<p>
+        The content can be personalised with {{name}}, {{email}},
+        {{unsubscribe_url}} and {{view_in_browser_url}}. The plain text content is generated from the HTML
+        content when left empty.
+    </p>
+    <p>
+        The issue goes to the confirmed subscribers of the lists, whose slugs are
+        separated by commas. The segment restricts who gets it among them,
+        e.g. <code>tag:rust AND (country=DE OR age&gt;=18)</code>. Leave it empty to send
+        the issue to all of them.
+    </p>"#;
+
+fn format_date(date: Option<DateTime<Utc>>) -> String {
+    date.map(|d| d.format("%Y-%m-%d %H:%M UTC").to_string())
+        .unwrap_or_default()
+}
+
+/// The input picking the lists an issue is sent to, as a comma-separated
+/// list of slugs, followed by the lists to pick from.
+fn lists_input<'a>(lists: &[List], selected: impl IntoIterator<Item = &'a str>) -> String {
+    let slugs = |slugs: &mut dyn Iterator<Item = &str>| {
+        slugs.map(encode_minimal).collect::<Vec<_>>().join(", ")
+    };
+    format!(
+        r#"<label>Lists:
+            <input type="text" name="lists" value="{selected}">
+        </label>
+        (available: {available})"#,
+        selected = slugs(&mut selected.into_iter()),
+        available = slugs(&mut lists.iter().map(|list| list.slug.as_str())),
+    )
+}
+
+/// All the issues, along with the form to start a new one.
+pub async fn admin_newsletters(
+    flash_messages: IncomingFlashMessages,
+    pool: web::Data<PgPool>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let mut msg_html = String::new();
+    for m in flash_messages.iter() {
+        writeln!(msg_html, "<p><i>{}</i></p>", m.content()).unwrap();
+    }
+    let mut rows_html = String::new();
+    for issue in list_newsletter_issues(&pool).await.map_err(e500)? {
+        let date = match issue.status.as_str() {
+            "scheduled" => issue.send_at,
+            _ => issue.published_at,
+        };
+        writeln!(
+            rows_html,
+            r#"<tr><td><a href="/admin/newsletters/{id}">{title}</a></td><td>{status}</td><td>{date}</td></tr>"#,
+            id = issue.newsletter_issue_id,
+            title = encode_minimal(&issue.title),
+            status = issue.status,
+            date = format_date(date),
+        )
+        .unwrap();
+    }
+    let lists_input = lists_input(
+        &get_all_lists(&pool).await.map_err(e500)?,
+        [DEFAULT_LIST_SLUG],
+    );
+    let idempotency_key = Uuid::new_v4();
+
+    Ok(HttpResponse::Ok()
+        .content_type(ContentType::html())
+        .body(format!(
+            r#"<!DOCTYPE html>
+<html lang="en">
+<head>
+    <meta http-equiv="content-type" content="text/html; charset=utf-8">
+    <title>Newsletter issues</title>
+</head>
+<body>
+    {msg_html}
+    <h1>Newsletter issues</h1>
+    <table>
+        <tr><th>Title</th><th>Status</th><th>Date</th></tr>
+        {rows_html}
+    </table>
+    <h2>New issue</h2>
+    {CONTENT_HINT}
+    <form action="/admin/newsletters" method="post">
+        <label>Title:<br>
+            <input
+                type="text"
+                placeholder="Enter the issue title"
+                name="title"
+            >
+        </label>
+        <br>
+        <label>Plain text content:<br>
+            <textarea
+                placeholder="Enter the content in plain text (optional)"
+                name="text_content"
+                rows="20"
+                cols="50"
+            ></textarea>
+        </label>
+        <br>
+        <label>HTML content:<br>
+            <textarea
+                placeholder="Enter the content in HTML format"
+                name="html_content"
+                rows="20"
+                cols="50"
+            ></textarea>
+        </label>
+        <br>
+        {lists_input}
+        <br>
+        <label>Segment:
+            <input type="text" placeholder="e.g. tag:rust AND country=DE" name="segment">
+        </label>
+        <br>
+        <input hidden type="text" name="idempotency_key" value="{idempotency_key}">
+        <button type="submit">Save draft</button>
+    </form>
+    <p><a href="/admin/dashboard">&lt;- Back</a></p>
+</body>
+</html>"#,
+        )))
+}
+
+/// A single issue: drafts can be edited, scheduled or sent straight away,
+/// scheduled issues can be cancelled.
+pub async fn admin_newsletter(
+    newsletter_issue_id: web::Path<Uuid>,
+    flash_messages: IncomingFlashMessages,
+    pool: web::Data<PgPool>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let Some(issue) = get_newsletter_issue(&pool, newsletter_issue_id.into_inner())
+        .await
+        .map_err(e500)?
+    else {
+        return Ok(HttpResponse::NotFound().finish());
+    };
+    let mut msg_html = String::new();
+    for m in flash_messages.iter() {
+        writeln!(msg_html, "<p><i>{}</i></p>", m.content()).unwrap();
+    }
+
+    let id = issue.newsletter_issue_id;
+    let issue_lists = get_issue_lists(&pool, id).await.map_err(e500)?;
+    let list_names = issue_lists
+        .iter()
+        .map(|list| encode_minimal(&list.name))
+        .collect::<Vec<_>>()
+        .join(", ");
+    let mut audience_html = format!(
+        "<p>Segment: {}</p>",
+        match &issue.segment {
+            Some(segment) => format!("<code>{}</code>", encode_minimal(segment)),
+            None => "all the subscribers".into(),
+        }
+    );
+    if issue.status != "published" {
+        let n_recipients = count_recipients(&pool, id, issue.segment.as_deref())
+            .await
+            .map_err(e500)?;
+        write!(
+            audience_html,
+            "\n    <p>Matching subscribers: {}</p>",
+            n_recipients
+        )
+        .unwrap();
+    }
+    let actions_html = match issue.status.as_str() {
+        "draft" => format!(
+            r#"{CONTENT_HINT}
+    <form action="/admin/newsletters/{id}" method="post">
+        <label>Title:<br>
+            <input type="text" name="title" value="{title}">
+        </label>
+        <br>
+        <label>Plain text content:<br>
+            <textarea name="text_content" rows="20" cols="50">{text_content}</textarea>
+        </label>
+        <br>
+        <label>HTML content:<br>
+            <textarea name="html_content" rows="20" cols="50">{html_content}</textarea>
+        </label>
+        <br>
+        {lists_input}
+        <br>
+        <label>Segment:
+            <input type="text" name="segment" value="{segment}">
+        </label>
+        <br>
+        <button type="submit">Save draft</button>
+    </form>
+    <form action="/admin/newsletters/{id}/schedule" method="post">
+        <label>Send at (UTC):
+            <input type="datetime-local" name="send_at">
+        </label>
+        <button type="submit">Schedule</button>
+    </form>
+    <form action="/admin/newsletters/{id}/publish" method="post">
+        <button type="submit">Send now</button>
+    </form>"#,
+            title = encode_minimal(&issue.title),
+            text_content = encode_minimal(&issue.text_content),
+            html_content = encode_minimal(&issue.html_content),
+            segment = encode_minimal(issue.segment.as_deref().unwrap_or_default()),
+            lists_input = lists_input(
+                &get_all_lists(&pool).await.map_err(e500)?,
+                issue_lists.iter().map(|list| list.slug.as_str()),
+            ),
+        ),
+        "scheduled" => format!(
+            r#"<p>Scheduled to go out on {send_at}.</p>
+    <form action="/admin/newsletters/{id}/cancel" method="post">
+        <button type="submit">Cancel</button>
+    </form>"#,
+            send_at = format_date(issue.send_at),
+        ),
+        _ => {
+            let stats_html = match get_issue_stats(&pool, id).await.map_err(e500)? {
+                Some(stats) => format!(
+                    r#"<table>
+        <tr><th>Queued</th><th>Sent</th><th>Failed</th><th>Skipped (invalid address)</th><th>Skipped (unsubscribed)</th><th>Bounced</th></tr>
+        <tr><td>{}</td><td>{}</td><td>{}</td><td>{}</td><td>{}</td><td>{}</td></tr>
+    </table>"#,
+                    stats.queued,
+                    stats.sent,
+                    stats.failed,
+                    stats.skipped_invalid,
+                    stats.skipped_unsubscribed,
+                    stats.bounced
+                ),
+                None => String::new(),
+            };
+            format!(
+                r#"<p>Published on {published_at}: <a href="/issues/{slug}">read it online</a>.</p>
+    {stats_html}
+    <p>Export the deliveries as <a href="/admin/newsletters/{id}/deliveries?format=csv">CSV</a>
+    or <a href="/admin/newsletters/{id}/deliveries?format=ndjson">NDJSON</a>.</p>"#,
+                published_at = format_date(issue.published_at),
+                slug = encode_minimal(issue.slug.as_deref().unwrap_or_default()),
+            )
+        }
+    };
+
+    Ok(HttpResponse::Ok()
+        .content_type(ContentType::html())
+        .body(format!(
+            r#"<!DOCTYPE html>
+<html lang="en">
+<head>
+    <meta http-equiv="content-type" content="text/html; charset=utf-8">
+    <title>Newsletter issue</title>
+</head>
+<body>
+    {msg_html}
+    <h1>{title}</h1>
+    <p>Status: {status}</p>
+    <p>Sent to: {list_names}</p>
+    {audience_html}
+    <p><a href="/admin/newsletters/{id}/preview">Preview</a></p>
+    <form action="/admin/newsletters/{id}/test" method="post">
+        <label>Send a test to:
+            <input
+                type="text"
+                placeholder="Email addresses, separated by commas"
+                name="recipients"
+            >
+        </label>
+        <button type="submit">Send test</button>
+    </form>
+    {actions_html}
+    <p><a href="/admin/newsletters">&lt;- Back</a></p>
+</body>
+</html>"#,
+            title = encode_minimal(&issue.title),
+            status = issue.status,
+        )))
+}
+
+#[derive(serde::Deserialize)]
+pub struct PreviewParameters {
+    format: Option<String>,
+}
+
+/// The email subscribers get for an issue, personalised with the name of
+/// the logged-in user. `?format=text` shows the plain text version.
+pub async fn preview_newsletter(
+    newsletter_issue_id: web::Path<Uuid>,
+    parameters: web::Query<PreviewParameters>,
+    user_id: web::ReqData<UserId>,
+    pool: web::Data<PgPool>,
+    templates: web::Data<Templates>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let Some(issue) = get_newsletter_issue(&pool, newsletter_issue_id.into_inner())
+        .await
+        .map_err(e500)?
+    else {
+        return Ok(HttpResponse::NotFound().finish());
+    };
+    let username = get_username(*user_id.into_inner(), &pool)
+        .await
+        .map_err(e500)?;
+
+    let email = IssueTemplate::new(&issue.text_content, &issue.html_content)
+        .render_email(
+            &templates,
+            &[
+                ("title", &issue.title),
+                ("view_in_browser_url", "#"),
+                ("name", &username),
+                ("email", "subscriber@example.com"),
+                ("unsubscribe_url", "#"),
+            ],
+        )
+        .map_err(e500)?;
+
+    Ok(match parameters.format.as_deref() {
+        Some("text") => HttpResponse::Ok()
+            .content_type(ContentType::plaintext())
+            .body(email.text),
+        _ => HttpResponse::Ok()
+            .content_type(ContentType::html())
+            .body(email.html),
+    })
+}
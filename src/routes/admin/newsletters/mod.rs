@@ -0,0 +1,8 @@
+mod get;
+mod post;
+
+pub use get::{admin_newsletter, admin_newsletters, preview_newsletter};
+pub use post::{
+    cancel_newsletter_schedule, create_newsletter_draft, publish_newsletter_now,
+    schedule_newsletter, send_test_newsletter_from_form, update_newsletter_draft,
+};
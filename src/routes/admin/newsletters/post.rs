@@ -0,0 +1,381 @@
+use crate::authentication::UserId;
+use crate::email_client::EmailClient;
+use crate::idempotency::IdempotencyKey;
+use crate::lists::{get_lists_by_slug, DEFAULT_LIST_SLUG};
+use crate::newsletter_issues::{
+    cancel_scheduled_issue, get_newsletter_issue, publish_issue, schedule_issue, update_draft,
+    IssueAudience, IssueSchedule,
+};
+use crate::routes::{
+    create_newsletter_issue, get_username, parse_test_recipients, send_test_issue,
+    validate_content, validate_send_at,
+};
+use crate::segments::Segment;
+use crate::startup::ApplicationBaseUrl;
+use crate::templates::Templates;
+use crate::utils::{e400, e500, see_other};
+use actix_web::{web, HttpResponse};
+use actix_web_flash_messages::FlashMessage;
+use anyhow::Context;
+use chrono::NaiveDateTime;
+use sqlx::PgPool;
+use uuid::Uuid;
+
+#[derive(serde::Deserialize)]
+pub struct FormData {
+    title: String,
+    text_content: String,
+    html_content: String,
+    /// The slugs of the lists the issue is sent to, separated by commas. The
+    /// default list when empty.
+    lists: Option<String>,
+    /// Everybody on the list when empty.
+    segment: Option<String>,
+    idempotency_key: String,
+}
+
+#[derive(serde::Deserialize)]
+pub struct DraftFormData {
+    title: String,
+    text_content: String,
+    html_content: String,
+    lists: Option<String>,
+    segment: Option<String>,
+}
+
+#[derive(serde::Deserialize)]
+pub struct ScheduleFormData {
+    /// As sent by a `datetime-local` input, in UTC.
+    send_at: String,
+}
+
+#[derive(serde::Deserialize)]
+pub struct TestSendFormData {
+    recipients: String,
+}
+
+fn issue_location(newsletter_issue_id: Uuid) -> String {
+    format!("/admin/newsletters/{}", newsletter_issue_id)
+}
+
+/// Who the issue goes to, according to the form.
+///
+/// Returns `None`, after sending a message explaining why, if the form is not valid.
+async fn get_form_audience(
+    pool: &PgPool,
+    lists: Option<&str>,
+    segment: Option<&str>,
+) -> Result<Option<IssueAudience>, actix_web::Error> {
+    let mut slugs = lists
+        .unwrap_or_default()
+        .split(|c: char| c == ',' || c.is_whitespace())
+        .filter(|slug| !slug.is_empty())
+        .map(String::from)
+        .collect::<Vec<_>>();
+    if slugs.is_empty() {
+        slugs.push(DEFAULT_LIST_SLUG.to_owned());
+    }
+    slugs.sort();
+    slugs.dedup();
+    let lists = get_lists_by_slug(pool, &slugs).await.map_err(e500)?;
+    if lists.len() != slugs.len() {
+        FlashMessage::error("Some of the selected lists do not exist.").send();
+        return Ok(None);
+    }
+    let segment = match segment.map(str::trim).filter(|s| !s.is_empty()) {
+        Some(segment) => match Segment::parse(segment) {
+            Ok(segment) => Some(segment),
+            Err(_) => {
+                FlashMessage::error(
+                    "The segment is not valid: combine tags like tag:rust and \
+                    comparisons like country=DE with AND, OR and NOT.",
+                )
+                .send();
+                return Ok(None);
+            }
+        },
+        None => None,
+    };
+
+    Ok(Some(IssueAudience {
+        list_ids: lists.into_iter().map(|list| list.list_id).collect(),
+        segment,
+    }))
+}
+
+fn invalid_content_message() -> FlashMessage {
+    FlashMessage::error(
+        "The content of the issue is not valid: \
+        variables must look like {{name}} or {{{name}}}, \\{{ writes literal braces.",
+    )
+}
+
+#[tracing::instrument(
+    name = "Create a newsletter draft from the admin form",
+    skip_all,
+    fields(user_id = tracing::field::Empty)
+)]
+pub async fn create_newsletter_draft(
+    form: web::Form<FormData>,
+    user_id: web::ReqData<UserId>,
+    pool: web::Data<PgPool>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let user_id = user_id.into_inner();
+    tracing::Span::current().record("user_id", tracing::field::display(&user_id));
+
+    let FormData {
+        title,
+        text_content,
+        html_content,
+        lists,
+        segment,
+        idempotency_key,
+    } = form.0;
+    let idempotency_key: IdempotencyKey = idempotency_key.try_into().map_err(e400)?;
+    if validate_content(&text_content, &html_content).is_err() {
+        invalid_content_message().send();
+        return Ok(see_other("/admin/newsletters"));
+    }
+    let Some(audience) = get_form_audience(&pool, lists.as_deref(), segment.as_deref()).await?
+    else {
+        return Ok(see_other("/admin/newsletters"));
+    };
+
+    let response = create_newsletter_issue(
+        &pool,
+        *user_id,
+        Some(idempotency_key),
+        &title,
+        &text_content,
+        &html_content,
+        &audience,
+        IssueSchedule::Draft,
+        |newsletter_issue_id| see_other(&issue_location(newsletter_issue_id)),
+    )
+    .await
+    .map_err(e500)?;
+    FlashMessage::info("The draft has been saved.").send();
+
+    Ok(response)
+}
+
+#[tracing::instrument(name = "Update a newsletter draft", skip(form, pool))]
+pub async fn update_newsletter_draft(
+    newsletter_issue_id: web::Path<Uuid>,
+    form: web::Form<DraftFormData>,
+    pool: web::Data<PgPool>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let newsletter_issue_id = newsletter_issue_id.into_inner();
+    if get_newsletter_issue(&pool, newsletter_issue_id)
+        .await
+        .map_err(e500)?
+        .is_none()
+    {
+        return Ok(HttpResponse::NotFound().finish());
+    }
+    let location = issue_location(newsletter_issue_id);
+
+    if validate_content(&form.text_content, &form.html_content).is_err() {
+        invalid_content_message().send();
+        return Ok(see_other(&location));
+    }
+    let Some(audience) =
+        get_form_audience(&pool, form.lists.as_deref(), form.segment.as_deref()).await?
+    else {
+        return Ok(see_other(&location));
+    };
+    if update_draft(
+        &pool,
+        newsletter_issue_id,
+        &form.title,
+        &form.text_content,
+        &form.html_content,
+        &audience,
+    )
+    .await
+    .map_err(e500)?
+    {
+        FlashMessage::info("The draft has been saved.").send();
+    } else {
+        FlashMessage::error("Only drafts can be edited.").send();
+    }
+
+    Ok(see_other(&location))
+}
+
+#[tracing::instrument(name = "Schedule a newsletter issue", skip(form, pool))]
+pub async fn schedule_newsletter(
+    newsletter_issue_id: web::Path<Uuid>,
+    form: web::Form<ScheduleFormData>,
+    pool: web::Data<PgPool>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let newsletter_issue_id = newsletter_issue_id.into_inner();
+    if get_newsletter_issue(&pool, newsletter_issue_id)
+        .await
+        .map_err(e500)?
+        .is_none()
+    {
+        return Ok(HttpResponse::NotFound().finish());
+    }
+    let location = issue_location(newsletter_issue_id);
+
+    // Browsers omit the seconds unless the input has a `step` below a minute
+    let Ok(send_at) = NaiveDateTime::parse_from_str(&form.send_at, "%Y-%m-%dT%H:%M")
+        .or_else(|_| NaiveDateTime::parse_from_str(&form.send_at, "%Y-%m-%dT%H:%M:%S"))
+        .map(|send_at| send_at.and_utc())
+    else {
+        FlashMessage::error("The sending date is not valid.").send();
+        return Ok(see_other(&location));
+    };
+    if validate_send_at(send_at).is_err() {
+        FlashMessage::error("An issue can only be scheduled in the future.").send();
+        return Ok(see_other(&location));
+    }
+
+    if schedule_issue(&pool, newsletter_issue_id, send_at)
+        .await
+        .map_err(e500)?
+    {
+        FlashMessage::info(format!(
+            "The issue is scheduled to go out on {}.",
+            send_at.format("%Y-%m-%d %H:%M UTC")
+        ))
+        .send();
+    } else {
+        FlashMessage::error("Only drafts can be scheduled.").send();
+    }
+
+    Ok(see_other(&location))
+}
+
+/// Send a draft, or a scheduled issue, straight away.
+#[tracing::instrument(name = "Publish a newsletter issue from the admin", skip(pool))]
+pub async fn publish_newsletter_now(
+    newsletter_issue_id: web::Path<Uuid>,
+    pool: web::Data<PgPool>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let newsletter_issue_id = newsletter_issue_id.into_inner();
+    if get_newsletter_issue(&pool, newsletter_issue_id)
+        .await
+        .map_err(e500)?
+        .is_none()
+    {
+        return Ok(HttpResponse::NotFound().finish());
+    }
+
+    let mut transaction = pool
+        .begin()
+        .await
+        .context("Failed to start a transaction")
+        .map_err(e500)?;
+    let is_published = publish_issue(&mut transaction, newsletter_issue_id)
+        .await
+        .context("Failed to publish the newsletter issue")
+        .map_err(e500)?;
+    transaction
+        .commit()
+        .await
+        .context("Failed to commit the newsletter issue")
+        .map_err(e500)?;
+
+    if is_published {
+        FlashMessage::info("The newsletter issue has been accepted - emails will go out shortly.")
+            .send();
+    } else {
+        FlashMessage::error("This issue has already been published.").send();
+    }
+
+    Ok(see_other(&issue_location(newsletter_issue_id)))
+}
+
+#[tracing::instrument(name = "Cancel the schedule of a newsletter issue", skip(pool))]
+pub async fn cancel_newsletter_schedule(
+    newsletter_issue_id: web::Path<Uuid>,
+    pool: web::Data<PgPool>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let newsletter_issue_id = newsletter_issue_id.into_inner();
+    if get_newsletter_issue(&pool, newsletter_issue_id)
+        .await
+        .map_err(e500)?
+        .is_none()
+    {
+        return Ok(HttpResponse::NotFound().finish());
+    }
+
+    if cancel_scheduled_issue(&pool, newsletter_issue_id)
+        .await
+        .map_err(e500)?
+    {
+        FlashMessage::info("The issue has been cancelled: it is a draft again.").send();
+    } else {
+        FlashMessage::error("Only scheduled issues can be cancelled.").send();
+    }
+
+    Ok(see_other(&issue_location(newsletter_issue_id)))
+}
+
+/// Send the issue to a few addresses only, e.g. the ones of the editors.
+#[tracing::instrument(
+    name = "Send a test newsletter from the admin form",
+    skip_all,
+    fields(
+        newsletter_issue_id = %newsletter_issue_id,
+        user_id = tracing::field::Empty
+    )
+)]
+pub async fn send_test_newsletter_from_form(
+    newsletter_issue_id: web::Path<Uuid>,
+    form: web::Form<TestSendFormData>,
+    user_id: web::ReqData<UserId>,
+    pool: web::Data<PgPool>,
+    email_client: web::Data<EmailClient>,
+    templates: web::Data<Templates>,
+    base_url: web::Data<ApplicationBaseUrl>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let Some(issue) = get_newsletter_issue(&pool, newsletter_issue_id.into_inner())
+        .await
+        .map_err(e500)?
+    else {
+        return Ok(HttpResponse::NotFound().finish());
+    };
+    let location = issue_location(issue.newsletter_issue_id);
+
+    let recipients = form
+        .recipients
+        .split(|c: char| c == ',' || c.is_whitespace())
+        .filter(|address| !address.is_empty())
+        .map(String::from);
+    let Ok(recipients) = parse_test_recipients(recipients) else {
+        FlashMessage::error(
+            "Please enter between one and ten valid email addresses, separated by commas.",
+        )
+        .send();
+        return Ok(see_other(&location));
+    };
+    let user_id = user_id.into_inner();
+    tracing::Span::current().record("user_id", tracing::field::display(&user_id));
+    let username = get_username(*user_id, &pool).await.map_err(e500)?;
+
+    match send_test_issue(
+        &email_client,
+        &templates,
+        &base_url.0,
+        &issue,
+        &recipients,
+        &username,
+    )
+    .await
+    {
+        Ok(()) => FlashMessage::info("The test email has been sent.").send(),
+        Err(e) => {
+            tracing::error!(
+                error.cause_chain = ?e,
+                error.message = %e,
+                "Failed to send a test newsletter"
+            );
+            FlashMessage::error("The test email could not be sent, please try again.").send();
+        }
+    }
+
+    Ok(see_other(&location))
+}
@@ -0,0 +1,549 @@
+use crate::lists::get_all_lists;
+use crate::utils::{e400, e500};
+use actix_web::http::header::ContentType;
+use actix_web::{web, HttpResponse};
+use actix_web_flash_messages::IncomingFlashMessages;
+use anyhow::Context;
+use chrono::{DateTime, NaiveDate, Utc};
+use htmlescape::encode_minimal;
+use sqlx::PgPool;
+use std::fmt::Write;
+use uuid::Uuid;
+
+const PAGE_SIZE: i64 = 50;
+
+pub(super) struct Subscriber {
+    pub(super) id: Uuid,
+    pub(super) email: String,
+    pub(super) name: String,
+    pub(super) status: String,
+    pub(super) subscribed_at: DateTime<Utc>,
+    pub(super) tags: Vec<String>,
+    pub(super) attributes: serde_json::Value,
+}
+
+#[derive(serde::Deserialize)]
+pub struct SearchParameters {
+    email: Option<String>,
+    name: Option<String>,
+    status: Option<String>,
+    /// Inclusive lower bound on the subscription date, formatted as `YYYY-MM-DD`.
+    subscribed_from: Option<String>,
+    /// Inclusive upper bound on the subscription date, formatted as `YYYY-MM-DD`.
+    subscribed_to: Option<String>,
+    page: Option<u32>,
+}
+
+/// The search criteria, as we get them from the search form.
+///
+/// Browsers submit every field of the form, so empty values are treated
+/// as if the field was not provided at all.
+struct SubscriberFilter {
+    email: Option<String>,
+    name: Option<String>,
+    status: Option<String>,
+    subscribed_from: Option<NaiveDate>,
+    subscribed_to: Option<NaiveDate>,
+}
+
+impl TryFrom<&SearchParameters> for SubscriberFilter {
+    type Error = String;
+
+    fn try_from(parameters: &SearchParameters) -> Result<Self, Self::Error> {
+        fn non_empty(value: &Option<String>) -> Option<String> {
+            value
+                .as_deref()
+                .map(str::trim)
+                .filter(|v| !v.is_empty())
+                .map(ToOwned::to_owned)
+        }
+        fn parse_date(value: &Option<String>) -> Result<Option<NaiveDate>, String> {
+            non_empty(value)
+                .map(|v| {
+                    NaiveDate::parse_from_str(&v, "%Y-%m-%d")
+                        .map_err(|_| format!("{} is not a valid date (YYYY-MM-DD).", v))
+                })
+                .transpose()
+        }
+
+        Ok(Self {
+            email: non_empty(&parameters.email),
+            name: non_empty(&parameters.name),
+            status: non_empty(&parameters.status),
+            subscribed_from: parse_date(&parameters.subscribed_from)?,
+            subscribed_to: parse_date(&parameters.subscribed_to)?,
+        })
+    }
+}
+
+impl SubscriberFilter {
+    /// Render the filter back into a query string, to build pagination links.
+    fn to_query_string(&self) -> String {
+        let mut pairs = Vec::new();
+        let fields = [
+            ("email", self.email.clone()),
+            ("name", self.name.clone()),
+            ("status", self.status.clone()),
+            (
+                "subscribed_from",
+                self.subscribed_from.map(|d| d.to_string()),
+            ),
+            ("subscribed_to", self.subscribed_to.map(|d| d.to_string())),
+        ];
+        for (key, value) in fields {
+            if let Some(value) = value {
+                pairs.push(format!("{}={}", key, urlencoding::encode(&value)));
+            }
+        }
+        pairs.join("&")
+    }
+
+    fn subscribed_from(&self) -> Option<DateTime<Utc>> {
+        self.subscribed_from
+            .and_then(|d| d.and_hms_opt(0, 0, 0))
+            .map(|d| d.and_utc())
+    }
+
+    /// The upper bound is inclusive: we look for subscriptions strictly
+    /// before the start of the following day.
+    fn subscribed_until(&self) -> Option<DateTime<Utc>> {
+        self.subscribed_to
+            .and_then(|d| d.succ_opt())
+            .and_then(|d| d.and_hms_opt(0, 0, 0))
+            .map(|d| d.and_utc())
+    }
+}
+
+pub async fn admin_subscribers(
+    parameters: web::Query<SearchParameters>,
+    pool: web::Data<PgPool>,
+    flash_messages: IncomingFlashMessages,
+) -> Result<HttpResponse, actix_web::Error> {
+    let filter = SubscriberFilter::try_from(&parameters.0).map_err(e400)?;
+    let page = parameters.page.unwrap_or(1).max(1);
+
+    let n_subscribers = count_subscribers(&pool, &filter).await.map_err(e500)?;
+    let subscribers = search_subscribers(&pool, &filter, page)
+        .await
+        .map_err(e500)?;
+    let n_pages = ((n_subscribers + PAGE_SIZE - 1) / PAGE_SIZE).max(1);
+
+    let mut msg_html = String::new();
+    for m in flash_messages.iter() {
+        writeln!(msg_html, "<p><i>{}</i></p>", m.content()).unwrap();
+    }
+
+    let mut rows_html = String::new();
+    for subscriber in subscribers {
+        writeln!(
+            rows_html,
+            r#"<tr>
+        <td><a href="/admin/subscribers/{id}">{email}</a></td>
+        <td>{name}</td>
+        <td>{status}</td>
+        <td>{subscribed_at}</td>
+    </tr>"#,
+            id = subscriber.id,
+            email = encode_minimal(&subscriber.email),
+            name = encode_minimal(&subscriber.name),
+            status = encode_minimal(&subscriber.status),
+            subscribed_at = subscriber.subscribed_at.to_rfc3339(),
+        )
+        .unwrap();
+    }
+
+    let query_string = filter.to_query_string();
+    let page_link = |page: i64| {
+        let href = if query_string.is_empty() {
+            format!("/admin/subscribers?page={}", page)
+        } else {
+            format!("/admin/subscribers?{}&page={}", query_string, page)
+        };
+        encode_minimal(&href)
+    };
+    let mut pagination_html = String::new();
+    if page > 1 {
+        write!(
+            pagination_html,
+            r#"<a href="{}">&lt;- Previous</a> "#,
+            page_link(page as i64 - 1)
+        )
+        .unwrap();
+    }
+    write!(pagination_html, "Page {} of {}", page, n_pages).unwrap();
+    if (page as i64) < n_pages {
+        write!(
+            pagination_html,
+            r#" <a href="{}">Next -&gt;</a>"#,
+            page_link(page as i64 + 1)
+        )
+        .unwrap();
+    }
+
+    let status_options = [
+        "",
+        "pending_confirmation",
+        "confirmed",
+        "unsubscribed",
+        "suppressed",
+    ]
+    .iter()
+    .map(|status| {
+        let selected = if filter.status.as_deref().unwrap_or_default() == *status {
+            " selected"
+        } else {
+            ""
+        };
+        format!(r#"<option value="{status}"{selected}>{status}</option>"#)
+    })
+    .collect::<String>();
+    let list_options = get_all_lists(&pool)
+        .await
+        .map_err(e500)?
+        .iter()
+        .map(|list| {
+            format!(
+                r#"<option value="{}">{}</option>"#,
+                encode_minimal(&list.slug),
+                encode_minimal(&list.name)
+            )
+        })
+        .collect::<String>();
+
+    Ok(HttpResponse::Ok()
+        .content_type(ContentType::html())
+        .body(format!(
+            r#"<!DOCTYPE html>
+<html lang="en">
+<head>
+    <meta http-equiv="content-type" content="text/html; charset=utf-8">
+    <title>Subscribers</title>
+</head>
+<body>
+    {msg_html}
+    <form action="/admin/subscribers" method="get">
+        <label>Email
+            <input type="text" name="email" value="{email}">
+        </label>
+        <label>Name
+            <input type="text" name="name" value="{name}">
+        </label>
+        <label>Status
+            <select name="status">{status_options}</select>
+        </label>
+        <label>Subscribed from
+            <input type="date" name="subscribed_from" value="{subscribed_from}">
+        </label>
+        <label>Subscribed to
+            <input type="date" name="subscribed_to" value="{subscribed_to}">
+        </label>
+        <button type="submit">Search</button>
+    </form>
+    <p>{n_subscribers} subscribers found. <a href="/admin/subscribers/import">Import subscribers</a></p>
+    <form action="/admin/subscribers/tags" method="post">
+        <label>Segment
+            <input type="text" name="segment" placeholder="tag:rust AND country=DE">
+        </label>
+        <label>Tag
+            <input type="text" name="tag">
+        </label>
+        <label>Action
+            <select name="action">
+                <option value="add">Add the tag</option>
+                <option value="remove">Remove the tag</option>
+            </select>
+        </label>
+        <button type="submit">Update tags</button>
+    </form>
+    <form action="/admin/subscribers/export" method="get">
+        <label>Status
+            <select name="status">{status_options}</select>
+        </label>
+        <label>List
+            <select name="list"><option value="">all subscribers</option>{list_options}</select>
+        </label>
+        <label>Format
+            <select name="format">
+                <option value="csv">CSV</option>
+                <option value="ndjson">NDJSON</option>
+            </select>
+        </label>
+        <button type="submit">Export</button>
+    </form>
+    <table>
+    <tr>
+        <th>Email</th>
+        <th>Name</th>
+        <th>Status</th>
+        <th>Subscribed at</th>
+    </tr>
+    {rows_html}
+    </table>
+    <p>{pagination_html}</p>
+    <p><a href="/admin/dashboard">&lt;- Back</a></p>
+</body>
+</html>"#,
+            email = encode_minimal(filter.email.as_deref().unwrap_or_default()),
+            name = encode_minimal(filter.name.as_deref().unwrap_or_default()),
+            subscribed_from = filter
+                .subscribed_from
+                .map(|d| d.to_string())
+                .unwrap_or_default(),
+            subscribed_to = filter
+                .subscribed_to
+                .map(|d| d.to_string())
+                .unwrap_or_default(),
+        )))
+}
+
+pub async fn admin_subscriber(
+    subscriber_id: web::Path<Uuid>,
+    pool: web::Data<PgPool>,
+    flash_messages: IncomingFlashMessages,
+) -> Result<HttpResponse, actix_web::Error> {
+    let subscriber_id = subscriber_id.into_inner();
+    let Some(subscriber) = get_subscriber(&pool, subscriber_id).await.map_err(e500)? else {
+        return Ok(HttpResponse::NotFound().finish());
+    };
+    let tokens = get_subscription_tokens(&pool, subscriber_id)
+        .await
+        .map_err(e500)?;
+
+    let mut msg_html = String::new();
+    for m in flash_messages.iter() {
+        writeln!(msg_html, "<p><i>{}</i></p>", m.content()).unwrap();
+    }
+
+    let mut tokens_html = String::new();
+    for token in tokens {
+        writeln!(
+            tokens_html,
+            r#"<tr>
+        <td><code>{token}</code></td>
+        <td>{created_at}</td>
+        <td>{consumed_at}</td>
+    </tr>"#,
+            token = encode_minimal(&token.subscription_token),
+            created_at = token.created_at.to_rfc3339(),
+            consumed_at = token
+                .consumed_at
+                .map(|d| d.to_rfc3339())
+                .unwrap_or_else(|| "-".into()),
+        )
+        .unwrap();
+    }
+
+    let tags = if subscriber.tags.is_empty() {
+        "-".to_owned()
+    } else {
+        encode_minimal(&subscriber.tags.join(", "))
+    };
+    let mut attributes_html = String::new();
+    if let Some(attributes) = subscriber.attributes.as_object() {
+        for (name, value) in attributes {
+            let value = match value {
+                serde_json::Value::String(s) => s.clone(),
+                value => value.to_string(),
+            };
+            writeln!(
+                attributes_html,
+                "<tr><td>{}</td><td>{}</td></tr>",
+                encode_minimal(name),
+                encode_minimal(&value),
+            )
+            .unwrap();
+        }
+    }
+
+    let mut actions_html = String::new();
+    if subscriber.status == "pending_confirmation" {
+        write!(
+            actions_html,
+            r#"<form action="/admin/subscribers/{id}/confirm" method="post">
+        <button type="submit">Confirm</button>
+    </form>
+    <form action="/admin/subscribers/{id}/resend_confirmation" method="post">
+        <button type="submit">Resend confirmation email</button>
+    </form>
+    "#,
+            id = subscriber.id
+        )
+        .unwrap();
+    }
+    write!(
+        actions_html,
+        r#"<form action="/admin/subscribers/{id}/remove" method="post">
+        <button type="submit">Remove</button>
+    </form>"#,
+        id = subscriber.id
+    )
+    .unwrap();
+
+    Ok(HttpResponse::Ok()
+        .content_type(ContentType::html())
+        .body(format!(
+            r#"<!DOCTYPE html>
+<html lang="en">
+<head>
+    <meta http-equiv="content-type" content="text/html; charset=utf-8">
+    <title>Subscriber</title>
+</head>
+<body>
+    {msg_html}
+    <dl>
+        <dt>Email</dt><dd>{email}</dd>
+        <dt>Name</dt><dd>{name}</dd>
+        <dt>Status</dt><dd>{status}</dd>
+        <dt>Subscribed at</dt><dd>{subscribed_at}</dd>
+        <dt>Tags</dt><dd>{tags}</dd>
+    </dl>
+    <p>Attributes:</p>
+    <table>
+    <tr>
+        <th>Name</th>
+        <th>Value</th>
+    </tr>
+    {attributes_html}
+    </table>
+    <p>Subscription tokens:</p>
+    <table>
+    <tr>
+        <th>Token</th>
+        <th>Created at</th>
+        <th>Used at</th>
+    </tr>
+    {tokens_html}
+    </table>
+    {actions_html}
+    <p><a href="/admin/subscribers">&lt;- Back</a></p>
+</body>
+</html>"#,
+            email = encode_minimal(&subscriber.email),
+            name = encode_minimal(&subscriber.name),
+            status = encode_minimal(&subscriber.status),
+            subscribed_at = subscriber.subscribed_at.to_rfc3339(),
+        )))
+}
+
+/// Turn a user-provided search term into a case-insensitive `ILIKE` pattern,
+/// escaping the wildcards it might contain.
+fn contains_pattern(term: &Option<String>) -> Option<String> {
+    term.as_deref().map(|term| {
+        let escaped = term
+            .replace('\\', "\\\\")
+            .replace('%', "\\%")
+            .replace('_', "\\_");
+        format!("%{}%", escaped)
+    })
+}
+
+#[tracing::instrument(name = "Count subscribers", skip(pool, filter))]
+async fn count_subscribers(pool: &PgPool, filter: &SubscriberFilter) -> Result<i64, anyhow::Error> {
+    let row = sqlx::query!(
+        r#"
+        SELECT COUNT(*) AS "count!"
+        FROM subscriptions
+        WHERE
+            ($1::text IS NULL OR email ILIKE $1) AND
+            ($2::text IS NULL OR name ILIKE $2) AND
+            ($3::text IS NULL OR status = $3) AND
+            ($4::timestamptz IS NULL OR subscribed_at >= $4) AND
+            ($5::timestamptz IS NULL OR subscribed_at < $5)
+        "#,
+        contains_pattern(&filter.email),
+        contains_pattern(&filter.name),
+        filter.status,
+        filter.subscribed_from(),
+        filter.subscribed_until(),
+    )
+    .fetch_one(pool)
+    .await
+    .context("Failed to count subscribers")?;
+
+    Ok(row.count)
+}
+
+#[tracing::instrument(name = "Search subscribers", skip(pool, filter))]
+async fn search_subscribers(
+    pool: &PgPool,
+    filter: &SubscriberFilter,
+    page: u32,
+) -> Result<Vec<Subscriber>, anyhow::Error> {
+    let subscribers = sqlx::query_as!(
+        Subscriber,
+        r#"
+        SELECT id, email, name, status, subscribed_at, tags, attributes
+        FROM subscriptions
+        WHERE
+            ($1::text IS NULL OR email ILIKE $1) AND
+            ($2::text IS NULL OR name ILIKE $2) AND
+            ($3::text IS NULL OR status = $3) AND
+            ($4::timestamptz IS NULL OR subscribed_at >= $4) AND
+            ($5::timestamptz IS NULL OR subscribed_at < $5)
+        ORDER BY subscribed_at DESC, id
+        LIMIT $6
+        OFFSET $7
+        "#,
+        contains_pattern(&filter.email),
+        contains_pattern(&filter.name),
+        filter.status,
+        filter.subscribed_from(),
+        filter.subscribed_until(),
+        PAGE_SIZE,
+        (page as i64 - 1) * PAGE_SIZE,
+    )
+    .fetch_all(pool)
+    .await
+    .context("Failed to search subscribers")?;
+
+    Ok(subscribers)
+}
+
+#[tracing::instrument(name = "Get subscriber", skip(pool))]
+pub(super) async fn get_subscriber(
+    pool: &PgPool,
+    subscriber_id: Uuid,
+) -> Result<Option<Subscriber>, anyhow::Error> {
+    let subscriber = sqlx::query_as!(
+        Subscriber,
+        r#"
+        SELECT id, email, name, status, subscribed_at, tags, attributes
+        FROM subscriptions
+        WHERE id = $1
+        "#,
+        subscriber_id
+    )
+    .fetch_optional(pool)
+    .await
+    .context("Failed to retrieve subscriber")?;
+
+    Ok(subscriber)
+}
+
+struct SubscriptionToken {
+    subscription_token: String,
+    created_at: DateTime<Utc>,
+    consumed_at: Option<DateTime<Utc>>,
+}
+
+#[tracing::instrument(name = "Get subscription tokens", skip(pool))]
+async fn get_subscription_tokens(
+    pool: &PgPool,
+    subscriber_id: Uuid,
+) -> Result<Vec<SubscriptionToken>, anyhow::Error> {
+    let tokens = sqlx::query_as!(
+        SubscriptionToken,
+        r#"
+        SELECT subscription_token, created_at, consumed_at
+        FROM subscription_tokens
+        WHERE subscriber_id = $1
+        ORDER BY created_at DESC
+        "#,
+        subscriber_id
+    )
+    .fetch_all(pool)
+    .await
+    .context("Failed to retrieve subscription tokens")?;
+
+    Ok(tokens)
+}
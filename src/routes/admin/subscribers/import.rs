@@ -0,0 +1,143 @@
+use crate::subscriber_import::{
+    import_subscribers, ImportError, ImportMode, ImportReport, MAX_REPORTED_ERRORS,
+};
+use crate::utils::{e400, e500, see_other};
+use actix_multipart::Multipart;
+use actix_web::http::header::ContentType;
+use actix_web::{web, HttpResponse};
+use actix_web_flash_messages::{FlashMessage, IncomingFlashMessages};
+use futures_util::TryStreamExt;
+use htmlescape::encode_minimal;
+use sqlx::PgPool;
+use std::fmt::Write;
+
+pub async fn import_subscribers_form(
+    flash_messages: IncomingFlashMessages,
+) -> Result<HttpResponse, actix_web::Error> {
+    let mut msg_html = String::new();
+    for m in flash_messages.iter() {
+        writeln!(msg_html, "<p><i>{}</i></p>", m.content()).unwrap();
+    }
+
+    Ok(HttpResponse::Ok()
+        .content_type(ContentType::html())
+        .body(format!(
+            r#"<!DOCTYPE html>
+<html lang="en">
+<head>
+    <meta http-equiv="content-type" content="text/html; charset=utf-8">
+    <title>Import subscribers</title>
+</head>
+<body>
+    {msg_html}
+    <p>Upload a CSV file whose first line names its columns, including
+    <code>email</code> and <code>name</code>. Addresses we already know are skipped.</p>
+    <form action="/admin/subscribers/import" method="post" enctype="multipart/form-data">
+        <label>Imported subscribers
+            <select name="mode">
+                <option value="send_confirmation">receive a confirmation email</option>
+                <option value="confirmed">are already confirmed</option>
+            </select>
+        </label>
+        <label>File
+            <input type="file" name="file" accept=".csv,text/csv">
+        </label>
+        <button type="submit">Import</button>
+    </form>
+    <p><a href="/admin/subscribers">&lt;- Back</a></p>
+</body>
+</html>"#,
+        )))
+}
+
+/// Import the uploaded CSV file as it comes in.
+///
+/// The mode is read first: browsers send the fields in the order of the form.
+pub async fn import_subscribers_from_form(
+    mut payload: Multipart,
+    pool: web::Data<PgPool>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let mut mode = None;
+    while let Some(mut field) = payload.try_next().await.map_err(e400)? {
+        match field.name() {
+            Some("mode") => {
+                let mut value = Vec::new();
+                while let Some(chunk) = field.try_next().await.map_err(e400)? {
+                    value.extend_from_slice(&chunk);
+                }
+                let value = String::from_utf8(value).map_err(e400)?;
+                mode = Some(ImportMode::parse(&value).map_err(e400)?);
+            }
+            Some("file") => {
+                let Some(mode) = mode else {
+                    return Err(e400("The import mode must be sent before the file."));
+                };
+                return match import_subscribers(field, mode, &pool).await {
+                    Ok(report) => Ok(HttpResponse::Ok()
+                        .content_type(ContentType::html())
+                        .body(report_page(&report, mode))),
+                    Err(ImportError::InvalidFile(message)) => {
+                        FlashMessage::error(message).send();
+                        Ok(see_other("/admin/subscribers/import"))
+                    }
+                    Err(e) => Err(e500(e)),
+                };
+            }
+            _ => {}
+        }
+    }
+
+    Err(e400("No file was uploaded."))
+}
+
+fn report_page(report: &ImportReport, mode: ImportMode) -> String {
+    let mut errors_html = String::new();
+    if !report.errors.is_empty() {
+        errors_html.push_str("<table>\n    <tr><th>Line</th><th>Error</th></tr>\n");
+        for error in &report.errors {
+            writeln!(
+                errors_html,
+                "    <tr><td>{}</td><td>{}</td></tr>",
+                error.line,
+                encode_minimal(&error.message)
+            )
+            .unwrap();
+        }
+        errors_html.push_str("    </table>");
+    }
+    if report.n_errors > MAX_REPORTED_ERRORS as u64 {
+        write!(
+            errors_html,
+            "\n    <p>Only the first {} errors are listed.</p>",
+            MAX_REPORTED_ERRORS
+        )
+        .unwrap();
+    }
+
+    let confirmation_html = match mode {
+        ImportMode::SendConfirmation if report.n_imported > 0 => {
+            "\n    <p>Their confirmation emails will go out shortly.</p>"
+        }
+        _ => "",
+    };
+
+    format!(
+        r#"<!DOCTYPE html>
+<html lang="en">
+<head>
+    <meta http-equiv="content-type" content="text/html; charset=utf-8">
+    <title>Import report</title>
+</head>
+<body>
+    <p>Imported subscribers: {n_imported}</p>{confirmation_html}
+    <p>Skipped, the address is already known: {n_skipped}</p>
+    <p>Errors: {n_errors}</p>
+    {errors_html}
+    <p><a href="/admin/subscribers">&lt;- Back</a></p>
+</body>
+</html>"#,
+        n_imported = report.n_imported,
+        n_skipped = report.n_skipped,
+        n_errors = report.n_errors,
+    )
+}
@@ -0,0 +1,9 @@
+mod get;
+mod import;
+mod post;
+
+pub use get::{admin_subscriber, admin_subscribers};
+pub use import::{import_subscribers_form, import_subscribers_from_form};
+pub use post::{
+    manually_confirm_subscriber, remove_subscriber, resend_confirmation, tag_subscribers_from_form,
+};
@@ -0,0 +1,221 @@
+use super::get::get_subscriber;
+use crate::domain::{SubscriberEmail, SubscriberTag};
+use crate::email_client::EmailClient;
+use crate::issue_delivery_worker::count_dropped_deliveries;
+use crate::routes::{
+    confirm_list_subscriptions, confirm_subscriber, generate_subscription_token,
+    send_confirmation_email, store_token,
+};
+use crate::segments::Segment;
+use crate::startup::ApplicationBaseUrl;
+use crate::subscribers::{update_tags, SubscriberSelection};
+use crate::templates::Templates;
+use crate::utils::{e400, e500, see_other};
+use actix_web::{web, HttpResponse};
+use actix_web_flash_messages::FlashMessage;
+use anyhow::Context;
+use sqlx::PgPool;
+use uuid::Uuid;
+
+pub async fn manually_confirm_subscriber(
+    subscriber_id: web::Path<Uuid>,
+    pool: web::Data<PgPool>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let subscriber_id = subscriber_id.into_inner();
+    let Some(subscriber) = get_subscriber(&pool, subscriber_id).await.map_err(e500)? else {
+        return Ok(HttpResponse::NotFound().finish());
+    };
+
+    if subscriber.status != "pending_confirmation" {
+        FlashMessage::error("Only pending subscribers can be confirmed.").send();
+    } else {
+        let mut transaction = pool
+            .begin()
+            .await
+            .context("Failed to start a transaction")
+            .map_err(e500)?;
+        confirm_subscriber(&mut *transaction, subscriber_id)
+            .await
+            .map_err(e500)?;
+        confirm_list_subscriptions(&mut *transaction, subscriber_id, None)
+            .await
+            .map_err(e500)?;
+        transaction
+            .commit()
+            .await
+            .context("Failed to commit the confirmation")
+            .map_err(e500)?;
+        FlashMessage::info("The subscriber has been confirmed.").send();
+    }
+
+    Ok(see_other(&format!("/admin/subscribers/{}", subscriber_id)))
+}
+
+/// Send a new confirmation email to a subscriber that has not confirmed yet,
+/// with a freshly generated subscription token.
+pub async fn resend_confirmation(
+    subscriber_id: web::Path<Uuid>,
+    pool: web::Data<PgPool>,
+    email_client: web::Data<EmailClient>,
+    templates: web::Data<Templates>,
+    base_url: web::Data<ApplicationBaseUrl>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let subscriber_id = subscriber_id.into_inner();
+    let Some(subscriber) = get_subscriber(&pool, subscriber_id).await.map_err(e500)? else {
+        return Ok(HttpResponse::NotFound().finish());
+    };
+    let location = format!("/admin/subscribers/{}", subscriber_id);
+
+    if subscriber.status != "pending_confirmation" {
+        FlashMessage::error("Only pending subscribers can be sent a confirmation email.").send();
+        return Ok(see_other(&location));
+    }
+    let subscriber_email = match SubscriberEmail::parse(subscriber.email) {
+        Ok(email) => email,
+        Err(_) => {
+            FlashMessage::error("The stored email address of the subscriber is invalid.").send();
+            return Ok(see_other(&location));
+        }
+    };
+
+    let subscription_token = generate_subscription_token();
+    let mut transaction = pool
+        .begin()
+        .await
+        .context("Failed to start a transaction")
+        .map_err(e500)?;
+    // The new token confirms all the lists the subscriber is waiting to join
+    store_token(&mut transaction, subscriber_id, None, &subscription_token)
+        .await
+        .context("Failed to store the new subscription token")
+        .map_err(e500)?;
+    transaction
+        .commit()
+        .await
+        .context("Failed to commit the new subscription token")
+        .map_err(e500)?;
+
+    send_confirmation_email(
+        &email_client,
+        &templates,
+        &subscriber_email,
+        &subscriber.name,
+        &base_url.0,
+        &subscription_token,
+    )
+    .await
+    .context("Failed to send the confirmation email")
+    .map_err(e500)?;
+    FlashMessage::info("A new confirmation email has been sent.").send();
+
+    Ok(see_other(&location))
+}
+
+pub async fn remove_subscriber(
+    subscriber_id: web::Path<Uuid>,
+    pool: web::Data<PgPool>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let subscriber_id = subscriber_id.into_inner();
+    let Some(subscriber) = get_subscriber(&pool, subscriber_id).await.map_err(e500)? else {
+        return Ok(HttpResponse::NotFound().finish());
+    };
+
+    delete_subscriber(&pool, subscriber_id, &subscriber.email)
+        .await
+        .map_err(e500)?;
+    FlashMessage::info("The subscriber has been removed.").send();
+
+    Ok(see_other("/admin/subscribers"))
+}
+
+/// Delete a subscriber, their subscription tokens and any delivery
+/// still waiting for them in the queue.
+#[tracing::instrument(name = "Delete subscriber", skip(pool))]
+async fn delete_subscriber(
+    pool: &PgPool,
+    subscriber_id: Uuid,
+    subscriber_email: &str,
+) -> Result<(), anyhow::Error> {
+    let mut transaction = pool
+        .begin()
+        .await
+        .context("Failed to start a transaction")?;
+    let dropped_issue_ids = sqlx::query_scalar!(
+        r#"
+        DELETE FROM issue_delivery_queue WHERE subscriber_email = $1
+        RETURNING newsletter_issue_id
+        "#,
+        subscriber_email
+    )
+    .fetch_all(&mut *transaction)
+    .await
+    .context("Failed to delete pending deliveries")?;
+    count_dropped_deliveries(&mut transaction, &dropped_issue_ids)
+        .await
+        .context("Failed to count the deleted deliveries")?;
+    sqlx::query!(
+        "DELETE FROM subscription_tokens WHERE subscriber_id = $1",
+        subscriber_id
+    )
+    .execute(&mut *transaction)
+    .await
+    .context("Failed to delete subscription tokens")?;
+    sqlx::query!("DELETE FROM subscriptions WHERE id = $1", subscriber_id)
+        .execute(&mut *transaction)
+        .await
+        .context("Failed to delete subscriber")?;
+    transaction
+        .commit()
+        .await
+        .context("Failed to commit the subscriber removal")?;
+
+    Ok(())
+}
+
+#[derive(serde::Deserialize)]
+pub struct TagsFormData {
+    segment: String,
+    tag: String,
+    action: String,
+}
+
+/// Add a tag to, or remove it from, all the subscribers in a segment.
+pub async fn tag_subscribers_from_form(
+    form: web::Form<TagsFormData>,
+    pool: web::Data<PgPool>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let TagsFormData {
+        segment,
+        tag,
+        action,
+    } = form.0;
+    let location = "/admin/subscribers";
+
+    let Ok(segment) = Segment::parse(&segment) else {
+        FlashMessage::error(
+            "The segment is not valid: combine tags like tag:rust and comparisons like country=DE with AND, OR and NOT.",
+        )
+        .send();
+        return Ok(see_other(location));
+    };
+    let Ok(tag) = SubscriberTag::parse(&tag) else {
+        FlashMessage::error("Tags can only contain letters, digits, dashes and underscores.")
+            .send();
+        return Ok(see_other(location));
+    };
+    let selection = SubscriberSelection::Segment(segment);
+    let tags = [tag];
+    let (add, remove, message): (&[SubscriberTag], &[SubscriberTag], _) = match action.as_str() {
+        "add" => (&tags, &[], "The tag has been added to"),
+        "remove" => (&[], &tags, "The tag has been removed from"),
+        _ => return Err(e400("Unknown action")),
+    };
+
+    let n_subscribers = update_tags(&pool, &selection, add, remove)
+        .await
+        .context("Failed to update the tags of subscribers")
+        .map_err(e500)?;
+    FlashMessage::info(format!("{} {} subscribers.", message, n_subscribers)).send();
+
+    Ok(see_other(location))
+}
@@ -0,0 +1,121 @@
+use crate::templates::{Format, Template};
+use crate::utils::e500;
+use actix_web::http::header::ContentType;
+use actix_web::{web, HttpResponse};
+use chrono::{DateTime, Utc};
+use htmlescape::encode_minimal;
+use sqlx::PgPool;
+use std::fmt::Write;
+
+struct IssueSummary {
+    title: String,
+    slug: String,
+    published_at: DateTime<Utc>,
+}
+
+struct Issue {
+    title: String,
+    html_content: String,
+    published_at: DateTime<Utc>,
+}
+
+/// The public archive of the newsletter: every published issue, most recent first.
+pub async fn issues_index(pool: web::Data<PgPool>) -> Result<HttpResponse, actix_web::Error> {
+    let mut items_html = String::new();
+    for issue in get_issue_summaries(&pool).await.map_err(e500)? {
+        writeln!(
+            items_html,
+            r#"<li><a href="/issues/{slug}">{title}</a> - {published_at}</li>"#,
+            slug = encode_minimal(&issue.slug),
+            title = encode_minimal(&issue.title),
+            published_at = issue.published_at.format("%Y-%m-%d"),
+        )
+        .unwrap();
+    }
+
+    Ok(HttpResponse::Ok()
+        .content_type(ContentType::html())
+        .body(format!(
+            r#"<!DOCTYPE html>
+<html lang="en">
+<head>
+    <meta http-equiv="content-type" content="text/html; charset=utf-8">
+    <title>Past issues</title>
+</head>
+<body>
+    <h1>Past issues</h1>
+    <ul>
+    {items_html}
+    </ul>
+    <p><a href="/">&lt;- Home</a></p>
+</body>
+</html>"#,
+        )))
+}
+
+/// The web version of an issue, linked from the emails as "view in browser".
+///
+/// Per-subscriber variables are left empty: the page is the same for everyone.
+pub async fn issue_page(
+    slug: web::Path<String>,
+    pool: web::Data<PgPool>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let Some(issue) = get_issue_by_slug(&pool, &slug).await.map_err(e500)? else {
+        return Ok(HttpResponse::NotFound().finish());
+    };
+    let content = Template::parse(&issue.html_content, Format::Html)
+        .unwrap_or_else(|_| Template::literal(&issue.html_content))
+        .render(&[("title", &issue.title)]);
+
+    Ok(HttpResponse::Ok()
+        .content_type(ContentType::html())
+        .body(format!(
+            r#"<!DOCTYPE html>
+<html lang="en">
+<head>
+    <meta http-equiv="content-type" content="text/html; charset=utf-8">
+    <title>{title}</title>
+</head>
+<body>
+    <h1>{title}</h1>
+    <p><i>Published on {published_at}</i></p>
+    <article>
+    {content}
+    </article>
+    <p><a href="/issues">&lt;- All issues</a></p>
+</body>
+</html>"#,
+            title = encode_minimal(&issue.title),
+            published_at = issue.published_at.format("%Y-%m-%d"),
+        )))
+}
+
+#[tracing::instrument(name = "Get issue summaries", skip(pool))]
+async fn get_issue_summaries(pool: &PgPool) -> Result<Vec<IssueSummary>, sqlx::Error> {
+    sqlx::query_as!(
+        IssueSummary,
+        r#"
+        SELECT title, slug as "slug!", published_at as "published_at!"
+        FROM newsletter_issues
+        WHERE status = 'published'
+        ORDER BY published_at DESC
+        "#
+    )
+    .fetch_all(pool)
+    .await
+}
+
+#[tracing::instrument(name = "Get issue by slug", skip(pool))]
+async fn get_issue_by_slug(pool: &PgPool, slug: &str) -> Result<Option<Issue>, sqlx::Error> {
+    sqlx::query_as!(
+        Issue,
+        r#"
+        SELECT title, html_content, published_at as "published_at!"
+        FROM newsletter_issues
+        WHERE slug = $1 AND status = 'published'
+        "#,
+        slug
+    )
+    .fetch_optional(pool)
+    .await
+}
@@ -1,33 +1,58 @@
 use crate::authentication::{validate_credentials, AuthError, Credentials};
 use crate::domain::SubscriberEmail;
-use crate::email_client::EmailClient;
+use crate::email_client::{BatchEmail, EmailClient};
+use crate::idempotency::{save_response, try_processing, IdempotencyKey, NextAction};
+use crate::lists::{get_issue_lists, get_lists_by_slug, DEFAULT_LIST_SLUG};
+use crate::newsletter_issues::{
+    cancel_scheduled_issue, get_issue_stats, get_newsletter_issue, insert_newsletter_issue,
+    IssueAudience, IssueSchedule, NewsletterIssue,
+};
 use crate::routes::error_chain_fmt;
+use crate::routes::get_username;
+use crate::segments::Segment;
+use crate::startup::ApplicationBaseUrl;
+use crate::templates::{Format, IssueTemplate, Template, TemplateError, Templates};
 use actix_web::http::header::{HeaderMap, HeaderValue, WWW_AUTHENTICATE};
 use actix_web::http::StatusCode;
 use actix_web::{web, HttpRequest, HttpResponse, ResponseError};
-use anyhow::{anyhow, Context};
+use anyhow::Context;
 use base64::engine::general_purpose::STANDARD;
 use base64::Engine;
+use chrono::{DateTime, Utc};
 use secrecy::SecretString;
 use sqlx::PgPool;
 use std::fmt::{Debug, Formatter};
+use uuid::Uuid;
 
 #[derive(serde::Deserialize)]
 pub struct BodyData {
     title: String,
     content: Content,
+    /// The issue is sent straight away when missing.
+    send_at: Option<DateTime<Utc>>,
+    /// The slugs of the lists the issue is sent to, the default list when empty.
+    #[serde(default)]
+    lists: Vec<String>,
+    /// Restricts the recipients within the lists, e.g. `tag:rust AND country=DE`.
+    segment: Option<String>,
 }
 
 #[derive(serde::Deserialize)]
 pub struct Content {
     html: String,
+    /// Generated from `html` when missing.
+    #[serde(default)]
     text: String,
 }
 
 #[derive(thiserror::Error)]
 pub enum PublishError {
+    #[error("{0}")]
+    ValidationError(String),
     #[error("Authentication failed")]
     AuthError(#[source] anyhow::Error),
+    #[error("{0}")]
+    InvalidState(String),
     #[error(transparent)]
     UnexpectedError(#[from] anyhow::Error),
 }
@@ -41,6 +66,7 @@ impl Debug for PublishError {
 impl ResponseError for PublishError {
     fn error_response(&self) -> HttpResponse {
         match self {
+            PublishError::ValidationError(_) => HttpResponse::new(StatusCode::BAD_REQUEST),
             PublishError::AuthError(_) => {
                 let mut response = HttpResponse::new(StatusCode::UNAUTHORIZED);
                 let header_value = HeaderValue::from_str(r#"Basic realm="publish""#).unwrap();
@@ -51,6 +77,7 @@ impl ResponseError for PublishError {
 
                 response
             }
+            PublishError::InvalidState(_) => HttpResponse::new(StatusCode::CONFLICT),
             PublishError::UnexpectedError(_) => {
                 HttpResponse::new(StatusCode::INTERNAL_SERVER_ERROR)
             }
@@ -58,18 +85,254 @@ impl ResponseError for PublishError {
     }
 }
 
-#[tracing::instrument(name = "Publish newsletter issue", skip(body, pool, email_client))]
+#[tracing::instrument(name = "Publish newsletter issue", skip(body, pool))]
 pub async fn publish_newsletter(
     body: web::Json<BodyData>,
     pool: web::Data<PgPool>,
+    http_request: HttpRequest,
+) -> Result<HttpResponse, PublishError> {
+    let user_id = authenticate(&http_request, &pool).await?;
+    let idempotency_key = get_idempotency_key(http_request.headers())
+        .map_err(|e| PublishError::ValidationError(e.to_string()))?;
+    validate_content(&body.content.text, &body.content.html)
+        .map_err(|e| PublishError::ValidationError(e.to_string()))?;
+    let audience = IssueAudience {
+        list_ids: resolve_lists(&pool, &body.lists).await?,
+        segment: body
+            .segment
+            .as_deref()
+            .map(Segment::parse)
+            .transpose()
+            .map_err(PublishError::ValidationError)?,
+    };
+    let (schedule, status) = match body.send_at {
+        Some(send_at) => {
+            validate_send_at(send_at).map_err(PublishError::ValidationError)?;
+            (IssueSchedule::At(send_at), "scheduled")
+        }
+        None => (IssueSchedule::Now, "published"),
+    };
+    let response = create_newsletter_issue(
+        &pool,
+        user_id,
+        idempotency_key,
+        &body.title,
+        &body.content.text,
+        &body.content.html,
+        &audience,
+        schedule,
+        |newsletter_issue_id| {
+            HttpResponse::Ok().json(serde_json::json!({
+                "newsletter_issue_id": newsletter_issue_id,
+                "status": status,
+            }))
+        },
+    )
+    .await?;
+
+    Ok(response)
+}
+
+/// The status of an issue and, once published, the statistics of its delivery.
+#[tracing::instrument(name = "Get newsletter issue", skip(pool, http_request))]
+pub async fn get_newsletter(
+    newsletter_issue_id: web::Path<Uuid>,
+    pool: web::Data<PgPool>,
+    http_request: HttpRequest,
+) -> Result<HttpResponse, PublishError> {
+    authenticate(&http_request, &pool).await?;
+    let newsletter_issue_id = newsletter_issue_id.into_inner();
+    let Some(issue) = get_newsletter_issue(&pool, newsletter_issue_id)
+        .await
+        .context("Failed to retrieve the newsletter issue")?
+    else {
+        return Ok(HttpResponse::NotFound().finish());
+    };
+    let stats = get_issue_stats(&pool, newsletter_issue_id)
+        .await
+        .context("Failed to retrieve the statistics of the newsletter issue")?;
+    let lists = get_issue_lists(&pool, newsletter_issue_id)
+        .await
+        .context("Failed to retrieve the lists of the newsletter issue")?
+        .into_iter()
+        .map(|list| list.slug)
+        .collect::<Vec<_>>();
+
+    Ok(HttpResponse::Ok().json(serde_json::json!({
+        "newsletter_issue_id": issue.newsletter_issue_id,
+        "title": issue.title,
+        "status": issue.status,
+        "lists": lists,
+        "segment": issue.segment,
+        "send_at": issue.send_at,
+        "published_at": issue.published_at,
+        "stats": stats,
+    })))
+}
+
+/// Cancel a scheduled issue before it goes out: it becomes a draft again.
+#[tracing::instrument(name = "Cancel scheduled newsletter issue", skip(pool, http_request))]
+pub async fn cancel_newsletter(
+    newsletter_issue_id: web::Path<Uuid>,
+    pool: web::Data<PgPool>,
+    http_request: HttpRequest,
+) -> Result<HttpResponse, PublishError> {
+    authenticate(&http_request, &pool).await?;
+    let newsletter_issue_id = newsletter_issue_id.into_inner();
+    if get_newsletter_issue(&pool, newsletter_issue_id)
+        .await
+        .context("Failed to retrieve the newsletter issue")?
+        .is_none()
+    {
+        return Ok(HttpResponse::NotFound().finish());
+    }
+
+    if !cancel_scheduled_issue(&pool, newsletter_issue_id)
+        .await
+        .context("Failed to cancel the newsletter issue")?
+    {
+        return Err(PublishError::InvalidState(
+            "Only scheduled issues can be cancelled".into(),
+        ));
+    }
+
+    Ok(HttpResponse::Ok().json(serde_json::json!({
+        "newsletter_issue_id": newsletter_issue_id,
+        "status": "draft",
+    })))
+}
+
+#[derive(serde::Deserialize)]
+pub struct TestSendData {
+    recipients: Vec<String>,
+}
+
+/// Send an issue to the given addresses only, so that it can be checked
+/// before it goes out to subscribers.
+#[tracing::instrument(
+    name = "Send a test newsletter issue",
+    skip(body, pool, email_client, templates, base_url, http_request)
+)]
+pub async fn send_test_newsletter(
+    newsletter_issue_id: web::Path<Uuid>,
+    body: web::Json<TestSendData>,
+    pool: web::Data<PgPool>,
     email_client: web::Data<EmailClient>,
+    templates: web::Data<Templates>,
+    base_url: web::Data<ApplicationBaseUrl>,
     http_request: HttpRequest,
 ) -> Result<HttpResponse, PublishError> {
+    let user_id = authenticate(&http_request, &pool).await?;
+    let recipients =
+        parse_test_recipients(body.0.recipients).map_err(PublishError::ValidationError)?;
+    let Some(issue) = get_newsletter_issue(&pool, newsletter_issue_id.into_inner())
+        .await
+        .context("Failed to retrieve the newsletter issue")?
+    else {
+        return Ok(HttpResponse::NotFound().finish());
+    };
+    let username = get_username(user_id, &pool).await?;
+
+    send_test_issue(
+        &email_client,
+        &templates,
+        &base_url.0,
+        &issue,
+        &recipients,
+        &username,
+    )
+    .await?;
+
+    Ok(HttpResponse::Ok().finish())
+}
+
+/// How many addresses a test issue can be sent to at once.
+const MAX_TEST_RECIPIENTS: usize = 10;
+
+pub fn parse_test_recipients(
+    recipients: impl IntoIterator<Item = String>,
+) -> Result<Vec<SubscriberEmail>, String> {
+    let recipients = recipients
+        .into_iter()
+        .map(SubscriberEmail::parse)
+        .collect::<Result<Vec<_>, _>>()?;
+    if recipients.is_empty() {
+        return Err("A test issue needs at least one recipient".into());
+    }
+    if recipients.len() > MAX_TEST_RECIPIENTS {
+        return Err(format!(
+            "A test issue can be sent to at most {} recipients",
+            MAX_TEST_RECIPIENTS
+        ));
+    }
+    Ok(recipients)
+}
+
+/// Send an issue as subscribers would get it, with a "[TEST]" prefix in the subject.
+///
+/// `name` stands in for the name of the subscribers and there is no working
+/// unsubscribe link. Nothing is recorded: the issue is left as it is, and can
+/// still be scheduled or published afterwards.
+#[tracing::instrument(skip_all, fields(n_recipients = recipients.len()))]
+pub async fn send_test_issue(
+    email_client: &EmailClient,
+    templates: &Templates,
+    base_url: &str,
+    issue: &NewsletterIssue,
+    recipients: &[SubscriberEmail],
+    name: &str,
+) -> Result<(), anyhow::Error> {
+    let content = IssueTemplate::new(&issue.text_content, &issue.html_content);
+    let view_in_browser_link = match &issue.slug {
+        Some(slug) => format!("{}/issues/{}", base_url, slug),
+        None => "#".into(),
+    };
+    let bodies = recipients
+        .iter()
+        .map(|recipient| {
+            content.render_email(
+                templates,
+                &[
+                    ("title", &issue.title),
+                    ("view_in_browser_url", &view_in_browser_link),
+                    ("name", name),
+                    ("email", recipient.as_ref()),
+                    ("unsubscribe_url", "#"),
+                ],
+            )
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+    let subject = format!("[TEST] {}", issue.title);
+    let emails = recipients
+        .iter()
+        .zip(&bodies)
+        .map(|(recipient, body)| BatchEmail {
+            recipient,
+            subject: &subject,
+            html_content: &body.html,
+            text_content: &body.text,
+            headers: &[],
+            // Test emails are not attributed to the issue: they don't count in its statistics
+            metadata: &[],
+        })
+        .collect::<Vec<_>>();
+
+    for outcome in email_client.send_batch(&emails).await {
+        outcome.context("Failed to send the test issue")?;
+    }
+    Ok(())
+}
+
+/// Check the Basic credentials of an API request, returning the id of the user.
+pub(crate) async fn authenticate(
+    http_request: &HttpRequest,
+    pool: &PgPool,
+) -> Result<Uuid, PublishError> {
     let credentials =
         basic_authentication(http_request.headers()).map_err(PublishError::AuthError)?;
     tracing::Span::current().record("username", tracing::field::display(&credentials.username));
 
-    let user_id = validate_credentials(credentials, &pool)
+    let user_id = validate_credentials(credentials, pool)
         .await
         .map_err(|e| match e {
             AuthError::InvalidCredentials(_) => PublishError::AuthError(e.into()),
@@ -77,60 +340,124 @@ pub async fn publish_newsletter(
         })?;
     tracing::Span::current().record("user_id", tracing::field::display(&user_id));
 
-    let subscribers = get_confirmed_subscribers(&pool).await?;
-
-    for subscriber in subscribers {
-        match subscriber {
-            Ok(subscriber) => {
-                email_client
-                    .send_email(
-                        &subscriber.email,
-                        &body.title,
-                        &body.content.html,
-                        &body.content.text,
-                    )
-                    .await
-                    .with_context(|| {
-                        format!("Failed to send newsletter issue to {}", subscriber.email)
-                    })?;
-            }
-            Err(error) => {
-                tracing::warn!("Failed to parse email of a confirmed subscriber: {}", error);
-            }
-        }
-    }
+    Ok(user_id)
+}
 
-    Ok(HttpResponse::Ok().finish())
+/// Check that the content of an issue is made of valid templates: it is
+/// personalised for each subscriber when the issue is delivered.
+pub fn validate_content(text_content: &str, html_content: &str) -> Result<(), TemplateError> {
+    Template::parse(text_content, Format::Text)?;
+    Template::parse(html_content, Format::Html)?;
+    Ok(())
 }
 
-struct ConfirmedSubscriber {
-    email: SubscriberEmail,
+/// The ids of the lists with the given slugs, the default list when there are none.
+async fn resolve_lists(pool: &PgPool, slugs: &[String]) -> Result<Vec<Uuid>, PublishError> {
+    let slugs = if slugs.is_empty() {
+        vec![DEFAULT_LIST_SLUG.to_owned()]
+    } else {
+        slugs.to_vec()
+    };
+    let lists = get_lists_by_slug(pool, &slugs)
+        .await
+        .context("Failed to retrieve the lists")?;
+    if let Some(unknown) = slugs
+        .iter()
+        .find(|slug| !lists.iter().any(|list| &list.slug == *slug))
+    {
+        return Err(PublishError::ValidationError(format!(
+            "There is no list named {}",
+            unknown
+        )));
+    }
+    Ok(lists.into_iter().map(|list| list.list_id).collect())
 }
 
-#[tracing::instrument(name = "Get all confirmed subscribers from the database", skip(pool))]
-async fn get_confirmed_subscribers(
+/// Issues can only be scheduled in the future.
+pub fn validate_send_at(send_at: DateTime<Utc>) -> Result<(), String> {
+    if send_at <= Utc::now() {
+        return Err("An issue can only be scheduled in the future".into());
+    }
+    Ok(())
+}
+
+/// Store a new newsletter issue for the given audience, publishing it or
+/// scheduling it according to `schedule`.
+///
+/// When an idempotency key is provided, the response built by `response` is
+/// saved against it and a retry of the same request gets the saved response
+/// back instead of creating the issue a second time.
+/// Requests without an idempotency key are processed without any deduplication.
+#[allow(clippy::too_many_arguments)]
+#[tracing::instrument(
+    name = "Store newsletter issue",
+    skip(pool, text_content, html_content, audience, response)
+)]
+pub async fn create_newsletter_issue(
     pool: &PgPool,
-) -> Result<Vec<Result<ConfirmedSubscriber, anyhow::Error>>, anyhow::Error> {
-    let confirmed_subscribers = sqlx::query!(
-        r#"
-            SELECT email
-            FROM subscriptions
-            WHERE status = 'confirmed'
-        "#
+    user_id: Uuid,
+    idempotency_key: Option<IdempotencyKey>,
+    title: &str,
+    text_content: &str,
+    html_content: &str,
+    audience: &IssueAudience,
+    schedule: IssueSchedule,
+    response: impl FnOnce(Uuid) -> HttpResponse,
+) -> Result<HttpResponse, anyhow::Error> {
+    let (mut transaction, idempotency_key) = match idempotency_key {
+        Some(idempotency_key) => match try_processing(pool, &idempotency_key, user_id).await? {
+            NextAction::StartProcessing(transaction) => (transaction, Some(idempotency_key)),
+            NextAction::ReturnSavedResponse(saved_response) => return Ok(saved_response),
+        },
+        None => (
+            pool.begin()
+                .await
+                .context("Failed to start a transaction")?,
+            None,
+        ),
+    };
+
+    let issue_id = insert_newsletter_issue(
+        &mut transaction,
+        user_id,
+        title,
+        text_content,
+        html_content,
+        audience,
+        schedule,
     )
-    .fetch_all(pool)
-    .await?
-    .into_iter()
-    .map(|r| match SubscriberEmail::parse(r.email) {
-        Ok(email) => Ok(ConfirmedSubscriber { email }),
-        Err(error) => Err(anyhow!(error)),
-    })
-    .collect();
+    .await
+    .context("Failed to store newsletter issue details")?;
+    let response = response(issue_id);
+
+    match idempotency_key {
+        Some(idempotency_key) => {
+            save_response(transaction, &idempotency_key, user_id, response).await
+        }
+        None => {
+            transaction
+                .commit()
+                .await
+                .context("Failed to commit the newsletter issue")?;
+            Ok(response)
+        }
+    }
+}
+
+fn get_idempotency_key(headers: &HeaderMap) -> Result<Option<IdempotencyKey>, anyhow::Error> {
+    let Some(header_value) = headers.get("Idempotency-Key") else {
+        return Ok(None);
+    };
+    let idempotency_key = header_value
+        .to_str()
+        .context("Idempotency-Key header is not valid UTF-8")?
+        .to_string()
+        .try_into()?;
 
-    Ok(confirmed_subscribers)
+    Ok(Some(idempotency_key))
 }
 
-fn basic_authentication(headers: &HeaderMap) -> Result<Credentials, anyhow::Error> {
+pub fn basic_authentication(headers: &HeaderMap) -> Result<Credentials, anyhow::Error> {
     let header_value = headers
         .get("Authorization")
         .context("Missing Authorization header")?
@@ -0,0 +1,239 @@
+use crate::domain::{PrivacyToken, SubscriberEmail};
+use crate::email_client::EmailClient;
+use crate::privacy::{erase_subscriber, get_subscriber_data};
+use crate::startup::{ApplicationBaseUrl, HmacSecret};
+use crate::templates::Templates;
+use crate::utils::{e400, e500};
+use actix_web::http::header::{ContentDisposition, ContentType, DispositionParam, DispositionType};
+use actix_web::http::StatusCode;
+use actix_web::{web, HttpResponse};
+use anyhow::Context;
+use chrono::Utc;
+use htmlescape::encode_attribute;
+use secrecy::SecretString;
+use serde::Deserialize;
+use sqlx::PgPool;
+use uuid::Uuid;
+
+/// How long the links we send remain usable.
+const PRIVACY_LINK_TTL_HOURS: i64 = 1;
+/// An address gets at most one link in this time, however often it is asked for.
+const PRIVACY_LINK_INTERVAL_MINUTES: i64 = 10;
+
+#[derive(Deserialize)]
+pub struct PrivacyRequestFormData {
+    email: String,
+}
+
+#[derive(Deserialize)]
+pub struct PrivacyParameters {
+    token: String,
+}
+
+pub async fn privacy_request_form() -> HttpResponse {
+    privacy_page(
+        StatusCode::OK,
+        "Your data",
+        r#"<p>Enter your email address: we will send you a link to download the data
+    we store about you, or to have it erased.</p>
+    <form action="/privacy" method="post">
+        <label>Email
+            <input type="email" name="email">
+        </label>
+        <button type="submit">Send me the link</button>
+    </form>"#,
+    )
+}
+
+/// Email a link to the privacy page to the address, if we know it and we
+/// haven't sent it one recently.
+#[tracing::instrument(
+    name = "Request access to subscriber data",
+    skip(form, pool, email_client, templates, base_url, secret)
+)]
+pub async fn request_privacy_link(
+    form: web::Form<PrivacyRequestFormData>,
+    pool: web::Data<PgPool>,
+    email_client: web::Data<EmailClient>,
+    templates: web::Data<Templates>,
+    base_url: web::Data<ApplicationBaseUrl>,
+    secret: web::Data<HmacSecret>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let email = SubscriberEmail::parse(form.0.email).map_err(e400)?;
+    let subscriber = sqlx::query!(
+        r#"
+        UPDATE subscriptions
+        SET privacy_link_sent_at = now()
+        WHERE email = $1
+            AND (privacy_link_sent_at IS NULL OR privacy_link_sent_at <= $2)
+        RETURNING id, name
+        "#,
+        email.as_ref(),
+        Utc::now() - chrono::Duration::minutes(PRIVACY_LINK_INTERVAL_MINUTES)
+    )
+    .fetch_optional(&**pool)
+    .await
+    .map_err(e500)?;
+
+    if let Some(subscriber) = subscriber {
+        if let Err(e) = send_privacy_email(
+            &email_client,
+            &templates,
+            &email,
+            &subscriber.name,
+            &base_url.0,
+            subscriber.id,
+            &secret.0,
+        )
+        .await
+        {
+            tracing::error!(
+                error.cause_chain = ?e,
+                error.message = %e,
+                "Failed to send the privacy link"
+            );
+        }
+    }
+
+    // Whether we know the address or not, and whether the email went out
+    // or not, the caller gets the same page: we don't want to disclose who
+    // is subscribed to the newsletter.
+    Ok(privacy_page(
+        StatusCode::OK,
+        "Check your inbox",
+        "<p>If we store anything about this address, we have sent it a link \
+        to download or erase it.</p>",
+    ))
+}
+
+async fn send_privacy_email(
+    email_client: &EmailClient,
+    templates: &Templates,
+    subscriber_email: &SubscriberEmail,
+    subscriber_name: &str,
+    base_url: &str,
+    subscriber_id: Uuid,
+    secret: &SecretString,
+) -> Result<(), anyhow::Error> {
+    let expires_at = Utc::now() + chrono::Duration::hours(PRIVACY_LINK_TTL_HOURS);
+    let token = PrivacyToken::generate(subscriber_id, expires_at, secret);
+    let privacy_link = format!("{}/privacy/manage?token={}", base_url, token.as_ref());
+    let body = templates.render(
+        "privacy",
+        &[
+            ("title", "Your data"),
+            ("name", subscriber_name),
+            ("privacy_url", &privacy_link),
+        ],
+    )?;
+
+    email_client
+        .send_email(subscriber_email, "Your data", &body.html, &body.text)
+        .await
+        .context("Failed to send the privacy email")?;
+
+    Ok(())
+}
+
+/// The page the link in the privacy email points to.
+///
+/// Like the unsubscribe page, it doesn't change anything on its own: link
+/// scanners follow links in emails, they must not erase anybody's data.
+#[tracing::instrument(name = "Show the privacy page", skip(parameters, secret))]
+pub async fn privacy_manage_page(
+    parameters: web::Query<PrivacyParameters>,
+    secret: web::Data<HmacSecret>,
+) -> HttpResponse {
+    if let Err(e) = PrivacyToken::verify(&parameters.token, &secret.0) {
+        return invalid_link(e);
+    }
+
+    privacy_page(
+        StatusCode::OK,
+        "Your data",
+        &format!(
+            r#"<p><a href="/privacy/data?token={token}">Download the data we store about you</a> (JSON).</p>
+    <p>You can also have it erased. You will stop receiving our newsletter, and
+    this can't be undone.</p>
+    <form action="/privacy/erase?token={token}" method="post">
+        <button type="submit">Erase my data</button>
+    </form>"#,
+            token = encode_attribute(&parameters.token)
+        ),
+    )
+}
+
+/// Download everything we store about the subscriber, as JSON.
+#[tracing::instrument(name = "Export subscriber data", skip(parameters, pool, secret))]
+pub async fn download_subscriber_data(
+    parameters: web::Query<PrivacyParameters>,
+    pool: web::Data<PgPool>,
+    secret: web::Data<HmacSecret>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let subscriber_id = match PrivacyToken::verify(&parameters.token, &secret.0) {
+        Ok(subscriber_id) => subscriber_id,
+        Err(e) => return Ok(invalid_link(e)),
+    };
+    let Some(data) = get_subscriber_data(&pool, subscriber_id)
+        .await
+        .map_err(e500)?
+    else {
+        return Ok(HttpResponse::NotFound().finish());
+    };
+
+    Ok(HttpResponse::Ok()
+        .insert_header(ContentDisposition {
+            disposition: DispositionType::Attachment,
+            parameters: vec![DispositionParam::Filename("my-data.json".into())],
+        })
+        .json(data))
+}
+
+/// Erase the subscriber the token was issued to.
+///
+/// Unknown subscribers are ignored: from the point of view of the user,
+/// their data is gone already.
+#[tracing::instrument(name = "Erase subscriber data", skip(parameters, pool, secret))]
+pub async fn erase_subscriber_data(
+    parameters: web::Query<PrivacyParameters>,
+    pool: web::Data<PgPool>,
+    secret: web::Data<HmacSecret>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let subscriber_id = match PrivacyToken::verify(&parameters.token, &secret.0) {
+        Ok(subscriber_id) => subscriber_id,
+        Err(e) => return Ok(invalid_link(e)),
+    };
+    erase_subscriber(&pool, subscriber_id).await.map_err(e500)?;
+
+    Ok(privacy_page(
+        StatusCode::OK,
+        "Data erased",
+        "<p>Your data has been erased, you won't receive any more issues of our newsletter.</p>",
+    ))
+}
+
+fn invalid_link(e: anyhow::Error) -> HttpResponse {
+    tracing::warn!(error.message = %e, "Rejected a privacy link");
+    privacy_page(
+        StatusCode::UNAUTHORIZED,
+        "Invalid link",
+        r#"<p>This link is invalid or has expired. You can <a href="/privacy">ask for a new one</a>.</p>"#,
+    )
+}
+
+fn privacy_page(status_code: StatusCode, title: &str, body: &str) -> HttpResponse {
+    HttpResponse::build(status_code)
+        .content_type(ContentType::html())
+        .body(format!(
+            r#"<!DOCTYPE html>
+<html lang="en">
+<head>
+    <meta http-equiv="content-type" content="text/html; charset=utf-8">
+    <title>{title}</title>
+</head>
+<body>
+    {body}
+</body>
+</html>"#
+        ))
+}
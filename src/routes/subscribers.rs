@@ -0,0 +1,120 @@
+use crate::domain::{AttributeName, SubscriberAttributes, SubscriberTag};
+use crate::routes::{authenticate, PublishError};
+use crate::segments::Segment;
+use crate::subscribers::{update_attributes, update_tags, SubscriberSelection};
+use actix_web::{web, HttpRequest, HttpResponse};
+use anyhow::Context;
+use sqlx::PgPool;
+
+/// Which subscribers to update: either the ones with the given email
+/// addresses, or the ones in the segment.
+#[derive(serde::Deserialize)]
+pub struct SelectionData {
+    emails: Option<Vec<String>>,
+    segment: Option<String>,
+}
+
+impl TryFrom<SelectionData> for SubscriberSelection {
+    type Error = String;
+
+    fn try_from(data: SelectionData) -> Result<Self, Self::Error> {
+        match (data.emails, data.segment) {
+            (Some(emails), None) => Ok(SubscriberSelection::Emails(emails)),
+            (None, Some(segment)) => Ok(SubscriberSelection::Segment(Segment::parse(&segment)?)),
+            _ => Err("Subscribers are selected either by email or with a segment".into()),
+        }
+    }
+}
+
+#[derive(serde::Deserialize)]
+pub struct TagsBodyData {
+    #[serde(flatten)]
+    selection: SelectionData,
+    #[serde(default)]
+    add: Vec<String>,
+    #[serde(default)]
+    remove: Vec<String>,
+}
+
+#[derive(serde::Deserialize)]
+pub struct AttributesBodyData {
+    #[serde(flatten)]
+    selection: SelectionData,
+    /// The attributes to set, as a JSON object.
+    set: Option<serde_json::Value>,
+    /// The names of the attributes to remove.
+    #[serde(default)]
+    unset: Vec<String>,
+}
+
+fn parse_tags(tags: &[String]) -> Result<Vec<SubscriberTag>, PublishError> {
+    tags.iter()
+        .map(|tag| SubscriberTag::parse(tag))
+        .collect::<Result<_, _>>()
+        .map_err(PublishError::ValidationError)
+}
+
+/// Tag and untag subscribers in bulk.
+#[tracing::instrument(
+    name = "Update the tags of subscribers",
+    skip(body, pool, http_request)
+)]
+pub async fn tag_subscribers(
+    body: web::Json<TagsBodyData>,
+    pool: web::Data<PgPool>,
+    http_request: HttpRequest,
+) -> Result<HttpResponse, PublishError> {
+    authenticate(&http_request, &pool).await?;
+    let TagsBodyData {
+        selection,
+        add,
+        remove,
+    } = body.0;
+    let selection = selection
+        .try_into()
+        .map_err(PublishError::ValidationError)?;
+    let add = parse_tags(&add)?;
+    let remove = parse_tags(&remove)?;
+
+    let n_subscribers = update_tags(&pool, &selection, &add, &remove)
+        .await
+        .context("Failed to update the tags of subscribers")?;
+
+    Ok(HttpResponse::Ok().json(serde_json::json!({ "n_subscribers": n_subscribers })))
+}
+
+/// Set and remove custom attributes of subscribers in bulk.
+#[tracing::instrument(
+    name = "Update the attributes of subscribers",
+    skip(body, pool, http_request)
+)]
+pub async fn update_subscriber_attributes(
+    body: web::Json<AttributesBodyData>,
+    pool: web::Data<PgPool>,
+    http_request: HttpRequest,
+) -> Result<HttpResponse, PublishError> {
+    authenticate(&http_request, &pool).await?;
+    let AttributesBodyData {
+        selection,
+        set,
+        unset,
+    } = body.0;
+    let selection = selection
+        .try_into()
+        .map_err(PublishError::ValidationError)?;
+    let set = match set {
+        Some(set) => SubscriberAttributes::parse(set).map_err(PublishError::ValidationError)?,
+        None => SubscriberAttributes::default(),
+    };
+    let unset = unset
+        .iter()
+        .map(|name| AttributeName::parse(name))
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(PublishError::ValidationError)?;
+
+    let n_subscribers = update_attributes(&pool, &selection, &set, &unset)
+        .await
+        .context("Failed to update the attributes of subscribers")?;
+
+    Ok(HttpResponse::Ok().json(serde_json::json!({ "n_subscribers": n_subscribers })))
+}
@@ -1,6 +1,9 @@
-use crate::domain::{NewSubscriber, SubscriberEmail, SubscriberName};
+use crate::confirmation_email_worker::enqueue_confirmation_emails;
+use crate::domain::{NewSubscriber, SubscriberAttributes, SubscriberEmail, SubscriberName};
 use crate::email_client::EmailClient;
-use crate::startup::ApplicationBaseUrl;
+use crate::lists::{get_list_by_slug, DEFAULT_LIST_SLUG};
+use crate::startup::SubscriptionTokenTtl;
+use crate::templates::Templates;
 use actix_web::http::StatusCode;
 use actix_web::{web, HttpResponse, ResponseError};
 use anyhow::Context;
@@ -12,32 +15,89 @@ use std::error::Error;
 use std::fmt::{Debug, Formatter};
 use uuid::Uuid;
 
+/// Subscribe to the default list.
 #[tracing::instrument(
     name = "Adding a new subscription",
-    skip(form, pool, email_client, base_url),
+    skip(form, pool, ttl),
     fields(subscriber_email = %form.email, subscriber_name = %form.name)
 )]
 pub async fn subscribe(
     form: web::Form<FormData>,
     pool: web::Data<PgPool>,
-    email_client: web::Data<EmailClient>,
-    base_url: web::Data<ApplicationBaseUrl>,
+    ttl: web::Data<SubscriptionTokenTtl>,
 ) -> Result<HttpResponse, SubscribeError> {
     // `web::Form` is a wrapper around `FormData`
     // `form.0` gives us access to the underlying `FormData`
     let new_subscriber = form.0.try_into().map_err(SubscribeError::ValidationError)?;
+    let list = get_list_by_slug(&**pool, DEFAULT_LIST_SLUG)
+        .await
+        .context("Failed to retrieve the default list")?
+        .context("The default list is missing")?;
 
-    // BEGIN TRANSACTION
-    let mut transaction = pool.begin().await.context("Pool error")?;
+    add_subscription(new_subscriber, list.list_id, &pool, &ttl).await
+}
 
-    let subscriber_id = insert_subscriber(&mut transaction, &new_subscriber)
+/// Subscribe to the list identified by the slug in the path.
+#[tracing::instrument(
+    name = "Adding a new subscription to a list",
+    skip(form, pool, ttl),
+    fields(subscriber_email = %form.email, subscriber_name = %form.name)
+)]
+pub async fn subscribe_to_list(
+    slug: web::Path<String>,
+    form: web::Form<FormData>,
+    pool: web::Data<PgPool>,
+    ttl: web::Data<SubscriptionTokenTtl>,
+) -> Result<HttpResponse, SubscribeError> {
+    let Some(list) = get_list_by_slug(&**pool, &slug)
         .await
-        .context("Insert subscriber error")?;
-    let subscription_token = generate_subscription_token();
+        .context("Failed to retrieve the list")?
+    else {
+        return Ok(HttpResponse::NotFound().finish());
+    };
+    let new_subscriber = form.0.try_into().map_err(SubscribeError::ValidationError)?;
+
+    add_subscription(new_subscriber, list.list_id, &pool, &ttl).await
+}
+
+/// Record a pending subscription to the list, and queue the confirmation
+/// email when there is something to confirm.
+///
+/// Whatever we find in the database, the caller gets the same response,
+/// just as fast: we don't want to disclose who is subscribed to the
+/// newsletter. The background worker sends the email.
+async fn add_subscription(
+    new_subscriber: NewSubscriber,
+    list_id: Uuid,
+    pool: &PgPool,
+    ttl: &SubscriptionTokenTtl,
+) -> Result<HttpResponse, SubscribeError> {
+    // BEGIN TRANSACTION
+    let mut transaction = pool.begin().await.context("Pool error")?;
 
-    store_token(&mut transaction, subscriber_id, &subscription_token)
+    let subscription_token = match insert_subscriber(&mut transaction, &new_subscriber)
         .await
-        .context("Store token error")?;
+        .context("Insert subscriber error")?
+    {
+        Some(subscriber_id) => {
+            add_pending_list_subscription(&mut transaction, list_id, subscriber_id)
+                .await
+                .context("Insert list subscription error")?;
+            Some(
+                issue_token(&mut transaction, subscriber_id, list_id)
+                    .await
+                    .context("Store token error")?,
+            )
+        }
+        None => handle_known_subscriber(&mut transaction, &new_subscriber, list_id, ttl)
+            .await
+            .context("Failed to handle an existing subscriber")?,
+    };
+    if let Some(subscription_token) = subscription_token {
+        enqueue_confirmation_emails(&mut transaction, &[subscription_token])
+            .await
+            .context("Queue confirmation email error")?;
+    }
 
     transaction
         .commit()
@@ -45,15 +105,6 @@ pub async fn subscribe(
         .context("transaction commit error")?;
     // COMMIT
 
-    send_confirmation_email(
-        &email_client,
-        new_subscriber,
-        &base_url.0,
-        &subscription_token,
-    )
-    .await
-    .context("send confirmation email error")?;
-
     Ok(HttpResponse::Ok().finish())
 }
 
@@ -69,7 +120,13 @@ impl TryFrom<FormData> for NewSubscriber {
     fn try_from(form: FormData) -> Result<Self, Self::Error> {
         let name = SubscriberName::parse(form.name)?;
         let email = SubscriberEmail::parse(form.email)?;
-        Ok(NewSubscriber { email, name })
+        // Tags and attributes are set by the admins, not by the subscribers themselves
+        Ok(NewSubscriber {
+            email,
+            name,
+            tags: Vec::new(),
+            attributes: SubscriberAttributes::default(),
+        })
     }
 }
 
@@ -96,6 +153,10 @@ impl ResponseError for SubscribeError {
     }
 }
 
+/// Insert a pending subscriber, unless there is already a subscriber with the
+/// same email address.
+///
+/// Returns the id of the new subscriber, `None` if the email address is known.
 #[tracing::instrument(
     name = "Saving new subscriber in the database",
     skip(transaction, new_subscriber)
@@ -103,24 +164,176 @@ impl ResponseError for SubscribeError {
 pub async fn insert_subscriber(
     transaction: &mut Transaction<'_, Postgres>,
     new_subscriber: &NewSubscriber,
-) -> Result<Uuid, sqlx::Error> {
+) -> Result<Option<Uuid>, sqlx::Error> {
     let subscriber_id = Uuid::new_v4();
-    sqlx::query!(
+    let n_inserted_rows = sqlx::query!(
         r#"
-        INSERT INTO subscriptions (id, email, name, subscribed_at, status)
-        VALUES ($1, $2, $3, $4, 'pending_confirmation')
+        INSERT INTO subscriptions (id, email, name, subscribed_at, status, tags, attributes)
+        VALUES ($1, $2, $3, $4, 'pending_confirmation', $5, $6)
+        ON CONFLICT (email) DO NOTHING
         "#,
         subscriber_id,
         new_subscriber.email.as_ref(),
         new_subscriber.name.as_ref(),
+        Utc::now(),
+        &new_subscriber
+            .tags
+            .iter()
+            .map(|tag| tag.as_ref().to_owned())
+            .collect::<Vec<_>>(),
+        serde_json::Value::from(new_subscriber.attributes.clone())
+    )
+    .execute(&mut **transaction)
+    .await?
+    .rows_affected();
+
+    Ok((n_inserted_rows > 0).then_some(subscriber_id))
+}
+
+/// Start a pending subscription of the subscriber to the list, or restart it
+/// if they had left the list.
+#[tracing::instrument(
+    name = "Saving new list subscription in the database",
+    skip(transaction)
+)]
+async fn add_pending_list_subscription(
+    transaction: &mut Transaction<'_, Postgres>,
+    list_id: Uuid,
+    subscriber_id: Uuid,
+) -> Result<(), sqlx::Error> {
+    sqlx::query!(
+        r#"
+        INSERT INTO list_subscriptions (list_id, subscriber_id, status, subscribed_at)
+        VALUES ($1, $2, 'pending_confirmation', $3)
+        ON CONFLICT (list_id, subscriber_id) DO UPDATE
+        SET status = 'pending_confirmation', subscribed_at = EXCLUDED.subscribed_at
+        "#,
+        list_id,
+        subscriber_id,
         Utc::now()
     )
     .execute(&mut **transaction)
     .await?;
 
-    Ok(subscriber_id)
+    Ok(())
 }
 
+/// Someone tried to subscribe to a list with an email address we already know about.
+///
+/// Returns the subscription token to send a confirmation email for, if any:
+/// - pending subscriptions to the list get their confirmation email again,
+///   with a new token if the previous one has expired;
+/// - unsubscribed subscribers opt in again, going through the confirmation step;
+/// - confirmed subscribers joining another list confirm that list too;
+/// - anybody else (e.g. confirmed subscribers of the list, suppressed
+///   addresses) is left untouched.
+#[tracing::instrument(name = "Handle a known subscriber", skip_all)]
+async fn handle_known_subscriber(
+    transaction: &mut Transaction<'_, Postgres>,
+    new_subscriber: &NewSubscriber,
+    list_id: Uuid,
+    ttl: &SubscriptionTokenTtl,
+) -> Result<Option<String>, sqlx::Error> {
+    // Lock the row: concurrent requests for the same email address
+    // wait for us to be done.
+    let subscriber = sqlx::query!(
+        r#"
+        SELECT id, status
+        FROM subscriptions
+        WHERE email = $1
+        FOR UPDATE
+        "#,
+        new_subscriber.email.as_ref()
+    )
+    .fetch_one(&mut **transaction)
+    .await?;
+    match subscriber.status.as_str() {
+        "suppressed" => return Ok(None),
+        "unsubscribed" => {
+            sqlx::query!(
+                r#"
+                UPDATE subscriptions
+                SET status = 'pending_confirmation', name = $2
+                WHERE id = $1
+                "#,
+                subscriber.id,
+                new_subscriber.name.as_ref()
+            )
+            .execute(&mut **transaction)
+            .await?;
+        }
+        _ => {}
+    }
+    let list_subscription_status = sqlx::query_scalar!(
+        r#"
+        SELECT status
+        FROM list_subscriptions
+        WHERE list_id = $1 AND subscriber_id = $2
+        "#,
+        list_id,
+        subscriber.id
+    )
+    .fetch_optional(&mut **transaction)
+    .await?;
+
+    match list_subscription_status.as_deref() {
+        Some("confirmed") if subscriber.status == "confirmed" => Ok(None),
+        Some("pending_confirmation") => {
+            let still_valid_after =
+                Utc::now() - chrono::Duration::from_std(ttl.0).unwrap_or(chrono::Duration::MAX);
+            let existing_token = sqlx::query!(
+                r#"
+                SELECT subscription_token
+                FROM subscription_tokens
+                WHERE
+                    subscriber_id = $1 AND
+                    list_id = $2 AND
+                    consumed_at IS NULL AND
+                    created_at > $3
+                ORDER BY created_at DESC
+                LIMIT 1
+                "#,
+                subscriber.id,
+                list_id,
+                still_valid_after
+            )
+            .fetch_optional(&mut **transaction)
+            .await?;
+            match existing_token {
+                Some(row) => Ok(Some(row.subscription_token)),
+                None => Ok(Some(
+                    issue_token(transaction, subscriber.id, list_id).await?,
+                )),
+            }
+        }
+        _ => {
+            add_pending_list_subscription(transaction, list_id, subscriber.id).await?;
+            Ok(Some(
+                issue_token(transaction, subscriber.id, list_id).await?,
+            ))
+        }
+    }
+}
+
+/// Generate a new subscription token for the subscription to the list and store it.
+async fn issue_token(
+    transaction: &mut Transaction<'_, Postgres>,
+    subscriber_id: Uuid,
+    list_id: Uuid,
+) -> Result<String, sqlx::Error> {
+    let subscription_token = generate_subscription_token();
+    store_token(
+        transaction,
+        subscriber_id,
+        Some(list_id),
+        &subscription_token,
+    )
+    .await?;
+    Ok(subscription_token)
+}
+
+/// Store a token confirming the subscription to `list_id`, or all the pending
+/// subscriptions of the subscriber when there is no list.
 #[tracing::instrument(
     name = "Store subscription token in the database",
     skip(subscription_token, transaction)
@@ -128,12 +341,14 @@ pub async fn insert_subscriber(
 pub async fn store_token(
     transaction: &mut Transaction<'_, Postgres>,
     subscriber_id: Uuid,
+    list_id: Option<Uuid>,
     subscription_token: &str,
 ) -> Result<(), sqlx::Error> {
     sqlx::query!(
-        r#"INSERT INTO subscription_tokens(subscriber_id, subscription_token)
-        VALUES ($1, $2)"#,
+        r#"INSERT INTO subscription_tokens(subscriber_id, list_id, subscription_token)
+        VALUES ($1, $2, $3)"#,
         subscriber_id,
+        list_id,
         subscription_token,
     )
     .execute(&mut **transaction)
@@ -142,37 +357,36 @@ pub async fn store_token(
     Ok(())
 }
 
-#[tracing::instrument(
-    name = "Send confirmation email",
-    skip(email_client, new_subscriber, base_url, subscription_token)
-)]
+#[tracing::instrument(name = "Send confirmation email", skip_all)]
 pub async fn send_confirmation_email(
     email_client: &EmailClient,
-    new_subscriber: NewSubscriber,
+    templates: &Templates,
+    subscriber_email: &SubscriberEmail,
+    subscriber_name: &str,
     base_url: &str,
     subscription_token: &str,
-) -> Result<(), reqwest::Error> {
+) -> Result<(), anyhow::Error> {
     let confirmation_link = format!(
         "{}/subscriptions/confirm?subscription_token={}",
         base_url, subscription_token
     );
-    let html_body = format!(
-        "Welcome to our newsletter!<br/> \
-        Click <a href=\"{}\">here</a> to confirm your subscription",
-        confirmation_link
-    );
-    let plain_body = format!(
-        "Welcome to our newsletter!\n\
-        Visit {} to confirm your subscription",
-        confirmation_link
-    );
+    let body = templates.render(
+        "confirmation",
+        &[
+            ("title", "Welcome"),
+            ("name", subscriber_name),
+            ("confirmation_url", &confirmation_link),
+        ],
+    )?;
 
     email_client
-        .send_email(&new_subscriber.email, "Welcome", &html_body, &plain_body)
-        .await
+        .send_email(subscriber_email, "Welcome", &body.html, &body.text)
+        .await?;
+
+    Ok(())
 }
 
-fn generate_subscription_token() -> String {
+pub fn generate_subscription_token() -> String {
     let mut rng = thread_rng();
     std::iter::repeat_with(|| rng.sample(Alphanumeric))
         .map(char::from)
@@ -1,6 +1,11 @@
+use crate::domain::SubscriptionToken;
+use crate::startup::SubscriptionTokenTtl;
+use actix_web::http::header::ContentType;
+use actix_web::http::StatusCode;
 use actix_web::{web, HttpResponse};
+use chrono::{DateTime, Utc};
 use serde::Deserialize;
-use sqlx::PgPool;
+use sqlx::{PgExecutor, PgPool, Postgres, Transaction};
 use uuid::Uuid;
 
 #[derive(Deserialize)]
@@ -8,33 +13,122 @@ pub struct Parameters {
     subscription_token: String,
 }
 
-#[tracing::instrument(name = "Confirm a pending subscriber", skip(pool, parameters))]
-pub async fn confirm(pool: web::Data<PgPool>, parameters: web::Query<Parameters>) -> HttpResponse {
-    let id = match get_subscriber_id_from_token(&pool, &parameters.subscription_token).await {
-        Ok(id) => id,
-        Err(_) => return HttpResponse::InternalServerError().finish(),
+/// The outcome of a confirmation attempt.
+enum ConfirmationOutcome {
+    Confirmed,
+    UnknownToken,
+    ExpiredToken,
+    AlreadyUsedToken,
+}
+
+#[tracing::instrument(name = "Confirm a pending subscriber", skip(pool, parameters, ttl))]
+pub async fn confirm(
+    pool: web::Data<PgPool>,
+    parameters: web::Query<Parameters>,
+    ttl: web::Data<SubscriptionTokenTtl>,
+) -> HttpResponse {
+    // Reject obviously malformed tokens without querying the database
+    let Ok(subscription_token) = SubscriptionToken::parse(parameters.0.subscription_token) else {
+        return confirmation_page(
+            StatusCode::BAD_REQUEST,
+            "Invalid confirmation link",
+            "This confirmation link is malformed. \
+            Please make sure you copied the whole link from the confirmation email.",
+        );
     };
 
-    match id {
-        None => HttpResponse::Unauthorized().finish(),
-        Some(subscriber_id) => {
-            if confirm_subscriber(&pool, subscriber_id).await.is_err() {
-                return HttpResponse::InternalServerError().finish();
-            }
-            HttpResponse::Ok().finish()
-        }
+    match try_confirm(&pool, &subscription_token, &ttl).await {
+        Ok(ConfirmationOutcome::Confirmed) => confirmation_page(
+            StatusCode::OK,
+            "Subscription confirmed",
+            "Thanks for confirming your subscription, \
+            you will receive the next issue of our newsletter.",
+        ),
+        Ok(ConfirmationOutcome::UnknownToken) => confirmation_page(
+            StatusCode::UNAUTHORIZED,
+            "Unknown confirmation link",
+            "We couldn't find this confirmation link. \
+            Please subscribe again to receive a new one.",
+        ),
+        Ok(ConfirmationOutcome::ExpiredToken) => confirmation_page(
+            StatusCode::GONE,
+            "Expired confirmation link",
+            "This confirmation link has expired. \
+            Please subscribe again to receive a new one.",
+        ),
+        Ok(ConfirmationOutcome::AlreadyUsedToken) => confirmation_page(
+            StatusCode::CONFLICT,
+            "Subscription already confirmed",
+            "You have already confirmed your subscription, there is nothing left to do.",
+        ),
+        Err(_) => confirmation_page(
+            StatusCode::INTERNAL_SERVER_ERROR,
+            "Something went wrong",
+            "We couldn't confirm your subscription. Please try again later.",
+        ),
+    }
+}
+
+fn confirmation_page(status_code: StatusCode, title: &str, message: &str) -> HttpResponse {
+    HttpResponse::build(status_code)
+        .content_type(ContentType::html())
+        .body(format!(
+            r#"<!DOCTYPE html>
+<html lang="en">
+<head>
+    <meta http-equiv="content-type" content="text/html; charset=utf-8">
+    <title>{title}</title>
+</head>
+<body>
+    <h1>{title}</h1>
+    <p>{message}</p>
+</body>
+</html>"#
+        ))
+}
+
+/// Confirm the subscription the token was issued for, consuming the token.
+///
+/// Tokens can only be used once and only within their time-to-live.
+async fn try_confirm(
+    pool: &PgPool,
+    subscription_token: &SubscriptionToken,
+    ttl: &SubscriptionTokenTtl,
+) -> Result<ConfirmationOutcome, sqlx::Error> {
+    let mut transaction = pool.begin().await?;
+    let Some(token) = get_token(&mut transaction, subscription_token).await? else {
+        return Ok(ConfirmationOutcome::UnknownToken);
+    };
+    if token.consumed_at.is_some() {
+        return Ok(ConfirmationOutcome::AlreadyUsedToken);
+    }
+    let expires_at =
+        token.created_at + chrono::Duration::from_std(ttl.0).unwrap_or(chrono::Duration::MAX);
+    if expires_at < Utc::now() {
+        return Ok(ConfirmationOutcome::ExpiredToken);
     }
+
+    consume_tokens(&mut transaction, token.subscriber_id, token.list_id).await?;
+    confirm_subscriber(&mut *transaction, token.subscriber_id).await?;
+    confirm_list_subscriptions(&mut *transaction, token.subscriber_id, token.list_id).await?;
+    transaction.commit().await?;
+
+    Ok(ConfirmationOutcome::Confirmed)
 }
 
-#[tracing::instrument(name = "Confirm the subscriber", skip(pool, subscriber_id))]
-pub async fn confirm_subscriber(pool: &PgPool, subscriber_id: Uuid) -> Result<(), sqlx::Error> {
+#[tracing::instrument(name = "Confirm the subscriber", skip(executor, subscriber_id))]
+pub async fn confirm_subscriber(
+    executor: impl PgExecutor<'_>,
+    subscriber_id: Uuid,
+) -> Result<(), sqlx::Error> {
     sqlx::query!(
         r#"
-        UPDATE subscriptions SET status = 'confirmed' WHERE id = $1
+        UPDATE subscriptions SET status = 'confirmed'
+        WHERE id = $1 AND status = 'pending_confirmation'
         "#,
         subscriber_id
     )
-    .execute(pool)
+    .execute(executor)
     .await
     .map_err(|e| {
         tracing::error!("Failed to confirm subscription: {}", e);
@@ -44,21 +138,91 @@ pub async fn confirm_subscriber(pool: &PgPool, subscriber_id: Uuid) -> Result<()
     Ok(())
 }
 
-#[tracing::instrument(name = "Get subscriber_id from token", skip(pool, subscription_token))]
-pub async fn get_subscriber_id_from_token(
-    pool: &PgPool,
-    subscription_token: &str,
-) -> Result<Option<Uuid>, sqlx::Error> {
-    let result = sqlx::query!(
-        "SELECT subscriber_id FROM subscription_tokens WHERE subscription_token = $1",
-        subscription_token
+/// Confirm the pending subscription of the subscriber to `list_id`, or all
+/// of their pending subscriptions when there is no list.
+#[tracing::instrument(name = "Confirm the list subscriptions", skip(executor))]
+pub async fn confirm_list_subscriptions(
+    executor: impl PgExecutor<'_>,
+    subscriber_id: Uuid,
+    list_id: Option<Uuid>,
+) -> Result<(), sqlx::Error> {
+    sqlx::query!(
+        r#"
+        UPDATE list_subscriptions SET status = 'confirmed'
+        WHERE
+            subscriber_id = $1 AND
+            ($2::uuid IS NULL OR list_id = $2) AND
+            status = 'pending_confirmation'
+        "#,
+        subscriber_id,
+        list_id
     )
-    .fetch_optional(pool)
+    .execute(executor)
     .await
     .map_err(|e| {
-        tracing::error!("Failed to fetch subscriber_id: {}", e);
+        tracing::error!("Failed to confirm list subscriptions: {}", e);
         e
     })?;
 
-    Ok(result.map(|r| r.subscriber_id))
+    Ok(())
+}
+
+struct StoredToken {
+    subscriber_id: Uuid,
+    list_id: Option<Uuid>,
+    created_at: DateTime<Utc>,
+    consumed_at: Option<DateTime<Utc>>,
+}
+
+#[tracing::instrument(name = "Get subscription token", skip(transaction, subscription_token))]
+async fn get_token(
+    transaction: &mut Transaction<'_, Postgres>,
+    subscription_token: &SubscriptionToken,
+) -> Result<Option<StoredToken>, sqlx::Error> {
+    sqlx::query_as!(
+        StoredToken,
+        r#"
+        SELECT subscriber_id, list_id, created_at, consumed_at
+        FROM subscription_tokens
+        WHERE subscription_token = $1
+        FOR UPDATE
+        "#,
+        subscription_token.as_ref()
+    )
+    .fetch_optional(&mut **transaction)
+    .await
+    .map_err(|e| {
+        tracing::error!("Failed to fetch subscription token: {}", e);
+        e
+    })
+}
+
+/// Mark all the outstanding tokens of the subscriber for the same list as
+/// used: older confirmation emails must not be usable once the subscription
+/// is confirmed. The tokens for the other lists are left alone.
+#[tracing::instrument(name = "Consume subscription tokens", skip(transaction))]
+async fn consume_tokens(
+    transaction: &mut Transaction<'_, Postgres>,
+    subscriber_id: Uuid,
+    list_id: Option<Uuid>,
+) -> Result<(), sqlx::Error> {
+    sqlx::query!(
+        r#"
+        UPDATE subscription_tokens SET consumed_at = now()
+        WHERE
+            subscriber_id = $1 AND
+            list_id IS NOT DISTINCT FROM $2 AND
+            consumed_at IS NULL
+        "#,
+        subscriber_id,
+        list_id
+    )
+    .execute(&mut **transaction)
+    .await
+    .map_err(|e| {
+        tracing::error!("Failed to consume subscription tokens: {}", e);
+        e
+    })?;
+
+    Ok(())
 }
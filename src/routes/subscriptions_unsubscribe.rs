@@ -0,0 +1,222 @@
+use crate::domain::UnsubscribeToken;
+use crate::issue_delivery_worker::count_dropped_deliveries;
+use crate::lists::get_list;
+use crate::startup::HmacSecret;
+use actix_web::http::header::ContentType;
+use actix_web::{web, HttpResponse};
+use htmlescape::{encode_attribute, encode_minimal};
+use serde::Deserialize;
+use sqlx::PgPool;
+use uuid::Uuid;
+
+#[derive(Deserialize)]
+pub struct UnsubscribeParameters {
+    token: String,
+    /// Leave all the lists, rather than the one the token was issued for.
+    #[serde(default)]
+    all: bool,
+}
+
+/// The page the unsubscribe link in our emails points to.
+///
+/// It doesn't change anything on its own: link scanners and prefetchers
+/// follow links in emails, we don't want them to unsubscribe people.
+#[tracing::instrument(name = "Show the unsubscribe form", skip(parameters, pool, secret))]
+pub async fn unsubscribe_form(
+    parameters: web::Query<UnsubscribeParameters>,
+    pool: web::Data<PgPool>,
+    secret: web::Data<HmacSecret>,
+) -> HttpResponse {
+    let Ok(unsubscription) = UnsubscribeToken::verify(&parameters.token, &secret.0) else {
+        return HttpResponse::Unauthorized().finish();
+    };
+    let token = encode_attribute(&parameters.token);
+    let list = match unsubscription.list_id {
+        Some(list_id) => match get_list(&pool, list_id).await {
+            Ok(list) => list,
+            Err(_) => return HttpResponse::InternalServerError().finish(),
+        },
+        None => None,
+    };
+
+    // A list that has been deleted since can't be left: all of them can
+    let form = match list {
+        Some(list) => format!(
+            r#"<p>Do you want to stop receiving {name}?</p>
+    <form action="/subscriptions/unsubscribe?token={token}" method="post">
+        <input hidden type="text" name="List-Unsubscribe" value="One-Click">
+        <button type="submit">Unsubscribe</button>
+    </form>
+    <p>You can also leave all our lists at once.</p>
+    <form action="/subscriptions/unsubscribe?token={token}&amp;all=true" method="post">
+        <button type="submit">Unsubscribe from everything</button>
+    </form>"#,
+            name = encode_minimal(&list.name),
+        ),
+        None => format!(
+            r#"<p>Do you want to stop receiving our newsletter?</p>
+    <form action="/subscriptions/unsubscribe?token={token}" method="post">
+        <input hidden type="text" name="List-Unsubscribe" value="One-Click">
+        <button type="submit">Unsubscribe</button>
+    </form>"#
+        ),
+    };
+
+    HttpResponse::Ok()
+        .content_type(ContentType::html())
+        .body(format!(
+            r#"<!DOCTYPE html>
+<html lang="en">
+<head>
+    <meta http-equiv="content-type" content="text/html; charset=utf-8">
+    <title>Unsubscribe</title>
+</head>
+<body>
+    {form}
+</body>
+</html>"#
+        ))
+}
+
+/// Unsubscribe the subscriber the token was issued to, from the list it was
+/// issued for, or from all of them.
+///
+/// Mail clients call this endpoint directly when the user asks to unsubscribe
+/// (RFC 8058 one-click unsubscribe), with `List-Unsubscribe=One-Click` as body:
+/// they leave the list the email was sent to.
+#[tracing::instrument(name = "Unsubscribe a subscriber", skip(parameters, pool, secret))]
+pub async fn unsubscribe(
+    parameters: web::Query<UnsubscribeParameters>,
+    pool: web::Data<PgPool>,
+    secret: web::Data<HmacSecret>,
+) -> HttpResponse {
+    let unsubscription = match UnsubscribeToken::verify(&parameters.token, &secret.0) {
+        Ok(unsubscription) => unsubscription,
+        Err(e) => {
+            tracing::warn!(error.message = %e, "Rejected an unsubscribe request");
+            return HttpResponse::Unauthorized().finish();
+        }
+    };
+    let list_id = unsubscription.list_id.filter(|_| !parameters.all);
+
+    if unsubscribe_subscriber(&pool, unsubscription.subscriber_id, list_id)
+        .await
+        .is_err()
+    {
+        return HttpResponse::InternalServerError().finish();
+    }
+
+    let message = match list_id {
+        Some(_) => {
+            "You have been unsubscribed, you won't receive any more issues sent to this list."
+        }
+        None => "You have been unsubscribed, you won't receive any more issues of our newsletter.",
+    };
+    HttpResponse::Ok()
+        .content_type(ContentType::html())
+        .body(format!(
+            r#"<!DOCTYPE html>
+<html lang="en">
+<head>
+    <meta http-equiv="content-type" content="text/html; charset=utf-8">
+    <title>Unsubscribed</title>
+</head>
+<body>
+    <p>{message}</p>
+</body>
+</html>"#
+        ))
+}
+
+/// Mark the subscriber as unsubscribed from the list, or from all the lists
+/// when there is none, and drop the deliveries still queued for them that
+/// they no longer get through any other list.
+///
+/// Leaving a single list doesn't change the status of the subscriber: they
+/// keep receiving the issues of their other lists.
+///
+/// Unknown subscribers (e.g. removed by an admin) are ignored: from the
+/// point of view of the user, they have left already.
+#[tracing::instrument(name = "Mark the subscriber as unsubscribed", skip(pool))]
+pub async fn unsubscribe_subscriber(
+    pool: &PgPool,
+    subscriber_id: Uuid,
+    list_id: Option<Uuid>,
+) -> Result<(), sqlx::Error> {
+    let mut transaction = pool.begin().await?;
+    let subscriber_email = match list_id {
+        Some(_) => {
+            sqlx::query_scalar!(
+                "SELECT email FROM subscriptions WHERE id = $1 FOR UPDATE",
+                subscriber_id
+            )
+            .fetch_optional(&mut *transaction)
+            .await
+        }
+        None => {
+            sqlx::query_scalar!(
+                r#"
+                UPDATE subscriptions SET status = 'unsubscribed'
+                WHERE id = $1
+                RETURNING email
+                "#,
+                subscriber_id
+            )
+            .fetch_optional(&mut *transaction)
+            .await
+        }
+    }
+    .map_err(|e| {
+        tracing::error!("Failed to unsubscribe: {}", e);
+        e
+    })?;
+
+    if let Some(subscriber_email) = subscriber_email {
+        sqlx::query!(
+            r#"
+            UPDATE list_subscriptions SET status = 'unsubscribed'
+            WHERE subscriber_id = $1 AND ($2::uuid IS NULL OR list_id = $2)
+            "#,
+            subscriber_id,
+            list_id
+        )
+        .execute(&mut *transaction)
+        .await
+        .map_err(|e| {
+            tracing::error!("Failed to unsubscribe from the lists: {}", e);
+            e
+        })?;
+        let dropped_issue_ids = sqlx::query_scalar!(
+            r#"
+            DELETE FROM issue_delivery_queue q
+            WHERE q.subscriber_email = $1 AND NOT EXISTS (
+                SELECT 1
+                FROM newsletter_issue_lists il
+                JOIN list_subscriptions ls ON ls.list_id = il.list_id
+                WHERE
+                    il.newsletter_issue_id = q.newsletter_issue_id AND
+                    ls.subscriber_id = $2 AND
+                    ls.status = 'confirmed'
+            )
+            RETURNING q.newsletter_issue_id
+            "#,
+            subscriber_email,
+            subscriber_id
+        )
+        .fetch_all(&mut *transaction)
+        .await
+        .map_err(|e| {
+            tracing::error!("Failed to drop queued deliveries: {}", e);
+            e
+        })?;
+        count_dropped_deliveries(&mut transaction, &dropped_issue_ids)
+            .await
+            .map_err(|e| {
+                tracing::error!("Failed to count the dropped deliveries: {}", e);
+                e
+            })?;
+    }
+    transaction.commit().await?;
+
+    Ok(())
+}
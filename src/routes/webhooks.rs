@@ -0,0 +1,296 @@
+use crate::configuration::WebhookSettings;
+use crate::issue_delivery_worker::count_dropped_deliveries;
+use crate::routes::{basic_authentication, error_chain_fmt};
+use actix_web::http::header::{HeaderValue, WWW_AUTHENTICATE};
+use actix_web::http::StatusCode;
+use actix_web::{web, HttpRequest, HttpResponse, ResponseError};
+use anyhow::Context;
+use chrono::{DateTime, Utc};
+use secrecy::ExposeSecret;
+use serde::Deserialize;
+use sha2::{Digest, Sha256};
+use sqlx::{PgPool, Postgres, Transaction};
+use std::collections::HashMap;
+use std::fmt::{Debug, Formatter};
+use uuid::Uuid;
+
+/// An event sent by Postmark about one of our emails.
+///
+/// Only the fields we care about are listed, see
+/// https://postmarkapp.com/developer/webhooks/webhooks-overview
+#[derive(Deserialize, Debug)]
+#[serde(tag = "RecordType")]
+pub enum EmailEvent {
+    Bounce(BounceEvent),
+    SpamComplaint(BounceEvent),
+    Delivery(DeliveryEvent),
+    /// Opens, clicks, etc. We don't subscribe to them, but they are no
+    /// reason to make Postmark retry.
+    #[serde(other)]
+    Unsupported,
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "PascalCase")]
+pub struct BounceEvent {
+    #[serde(rename = "MessageID")]
+    message_id: String,
+    r#type: String,
+    email: String,
+    bounced_at: DateTime<Utc>,
+    details: Option<String>,
+    #[serde(default)]
+    metadata: HashMap<String, String>,
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "PascalCase")]
+pub struct DeliveryEvent {
+    #[serde(rename = "MessageID")]
+    message_id: String,
+    recipient: String,
+    delivered_at: DateTime<Utc>,
+    details: Option<String>,
+    #[serde(default)]
+    metadata: HashMap<String, String>,
+}
+
+/// The issue an email was sent for, as set in its metadata by the delivery worker.
+fn newsletter_issue_id(metadata: &HashMap<String, String>) -> Option<Uuid> {
+    metadata.get("newsletter_issue_id")?.parse().ok()
+}
+
+/// The bounce types telling us that the address will never accept our emails.
+const HARD_BOUNCE_TYPES: [&str; 2] = ["HardBounce", "BadEmailAddress"];
+
+#[derive(thiserror::Error)]
+pub enum WebhookError {
+    #[error("Invalid event payload")]
+    ValidationError(#[source] serde_json::Error),
+    #[error("Authentication failed")]
+    AuthError(#[source] anyhow::Error),
+    #[error(transparent)]
+    UnexpectedError(#[from] anyhow::Error),
+}
+
+impl Debug for WebhookError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        error_chain_fmt(self, f)
+    }
+}
+
+impl ResponseError for WebhookError {
+    fn error_response(&self) -> HttpResponse {
+        match self {
+            WebhookError::ValidationError(_) => HttpResponse::new(StatusCode::BAD_REQUEST),
+            WebhookError::AuthError(_) => {
+                let mut response = HttpResponse::new(StatusCode::UNAUTHORIZED);
+                let header_value = HeaderValue::from_str(r#"Basic realm="webhooks""#).unwrap();
+                response
+                    .headers_mut()
+                    .insert(WWW_AUTHENTICATE, header_value);
+                response
+            }
+            WebhookError::UnexpectedError(_) => {
+                HttpResponse::new(StatusCode::INTERNAL_SERVER_ERROR)
+            }
+        }
+    }
+}
+
+/// Record bounces, spam complaints and deliveries reported by Postmark.
+///
+/// Subscribers are suppressed after a hard bounce or a spam complaint:
+/// we stop sending them anything.
+#[tracing::instrument(name = "Handle an email event", skip_all)]
+pub async fn email_webhook(
+    body: web::Bytes,
+    pool: web::Data<PgPool>,
+    settings: web::Data<WebhookSettings>,
+    http_request: HttpRequest,
+) -> Result<HttpResponse, WebhookError> {
+    let credentials =
+        basic_authentication(http_request.headers()).map_err(WebhookError::AuthError)?;
+    // Compare digests rather than the secrets themselves, so that the time
+    // taken by the comparison tells nothing about the expected password.
+    let is_valid = credentials.username == settings.username
+        && Sha256::digest(credentials.password.expose_secret().as_bytes())
+            == Sha256::digest(settings.password.expose_secret().as_bytes());
+    if !is_valid {
+        return Err(WebhookError::AuthError(anyhow::anyhow!(
+            "Invalid webhook credentials"
+        )));
+    }
+    // The payload is only parsed once the caller is authenticated
+    let event: EmailEvent = serde_json::from_slice(&body).map_err(WebhookError::ValidationError)?;
+
+    let mut transaction = pool
+        .begin()
+        .await
+        .context("Failed to start a transaction")?;
+    match event {
+        EmailEvent::Bounce(bounce) => {
+            let is_hard_bounce = HARD_BOUNCE_TYPES.contains(&bounce.r#type.as_str());
+            let is_new = record_bounce(&mut transaction, "Bounce", &bounce).await?;
+            // Postmark retries deliveries of events: only count a bounce once.
+            // Soft bounces are temporary, the email may still get through.
+            if let (true, true, Some(newsletter_issue_id)) = (
+                is_new,
+                is_hard_bounce,
+                newsletter_issue_id(&bounce.metadata),
+            ) {
+                count_bounce(&mut transaction, newsletter_issue_id).await?;
+            }
+            if is_hard_bounce {
+                suppress_subscriber(&mut transaction, &bounce.email).await?;
+            }
+        }
+        EmailEvent::SpamComplaint(complaint) => {
+            record_bounce(&mut transaction, "SpamComplaint", &complaint).await?;
+            suppress_subscriber(&mut transaction, &complaint.email).await?;
+        }
+        EmailEvent::Delivery(delivery) => {
+            record_event(
+                &mut transaction,
+                "Delivery",
+                &delivery.message_id,
+                &delivery.recipient,
+                newsletter_issue_id(&delivery.metadata),
+                None,
+                delivery.details.as_deref(),
+                delivery.delivered_at,
+            )
+            .await?;
+        }
+        EmailEvent::Unsupported => {
+            tracing::info!("Ignoring an unsupported email event");
+        }
+    }
+    transaction
+        .commit()
+        .await
+        .context("Failed to commit the email event")?;
+
+    Ok(HttpResponse::Ok().finish())
+}
+
+async fn record_bounce(
+    transaction: &mut Transaction<'_, Postgres>,
+    record_type: &str,
+    bounce: &BounceEvent,
+) -> Result<bool, anyhow::Error> {
+    record_event(
+        transaction,
+        record_type,
+        &bounce.message_id,
+        &bounce.email,
+        newsletter_issue_id(&bounce.metadata),
+        Some(&bounce.r#type),
+        bounce.details.as_deref(),
+        bounce.bounced_at,
+    )
+    .await
+}
+
+/// Returns `false` if the event had already been recorded.
+#[allow(clippy::too_many_arguments)]
+#[tracing::instrument(skip(transaction, details))]
+async fn record_event(
+    transaction: &mut Transaction<'_, Postgres>,
+    record_type: &str,
+    message_id: &str,
+    subscriber_email: &str,
+    newsletter_issue_id: Option<Uuid>,
+    bounce_type: Option<&str>,
+    details: Option<&str>,
+    occurred_at: DateTime<Utc>,
+) -> Result<bool, anyhow::Error> {
+    let n_inserted = sqlx::query!(
+        r#"
+        INSERT INTO delivery_events (
+            event_id,
+            record_type,
+            message_id,
+            subscriber_email,
+            newsletter_issue_id,
+            bounce_type,
+            details,
+            occurred_at,
+            received_at
+        )
+        VALUES ($1, $2, $3, $4, $5, $6, $7, $8, now())
+        ON CONFLICT DO NOTHING
+        "#,
+        Uuid::new_v4(),
+        record_type,
+        message_id,
+        subscriber_email,
+        newsletter_issue_id,
+        bounce_type,
+        details,
+        occurred_at
+    )
+    .execute(&mut **transaction)
+    .await
+    .context("Failed to record the email event")?
+    .rows_affected();
+
+    Ok(n_inserted > 0)
+}
+
+#[tracing::instrument(skip(transaction))]
+async fn count_bounce(
+    transaction: &mut Transaction<'_, Postgres>,
+    newsletter_issue_id: Uuid,
+) -> Result<(), anyhow::Error> {
+    sqlx::query!(
+        r#"
+        UPDATE newsletter_issue_stats
+        SET n_bounced = n_bounced + 1
+        WHERE newsletter_issue_id = $1
+        "#,
+        newsletter_issue_id
+    )
+    .execute(&mut **transaction)
+    .await
+    .context("Failed to count the bounce")?;
+
+    Ok(())
+}
+
+/// Stop sending emails to an address, including the deliveries already queued.
+#[tracing::instrument(skip(transaction))]
+async fn suppress_subscriber(
+    transaction: &mut Transaction<'_, Postgres>,
+    subscriber_email: &str,
+) -> Result<(), anyhow::Error> {
+    let n_suppressed = sqlx::query!(
+        r#"
+        UPDATE subscriptions SET status = 'suppressed'
+        WHERE email = $1 AND status <> 'suppressed'
+        "#,
+        subscriber_email
+    )
+    .execute(&mut **transaction)
+    .await
+    .context("Failed to suppress the subscriber")?
+    .rows_affected();
+    let dropped_issue_ids = sqlx::query_scalar!(
+        r#"
+        DELETE FROM issue_delivery_queue WHERE subscriber_email = $1
+        RETURNING newsletter_issue_id
+        "#,
+        subscriber_email
+    )
+    .fetch_all(&mut **transaction)
+    .await
+    .context("Failed to drop queued deliveries")?;
+    count_dropped_deliveries(transaction, &dropped_issue_ids)
+        .await
+        .context("Failed to count the dropped deliveries")?;
+
+    if n_suppressed > 0 {
+        tracing::info!("Suppressed a subscriber");
+    }
+    Ok(())
+}
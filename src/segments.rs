@@ -0,0 +1,490 @@
+//! Segments: a small query language over the tags and custom attributes of
+//! subscribers, to pick the ones an issue is sent to, or the ones to tag.
+//!
+//! ```text
+//! tag:rust AND (country=DE OR country="AT") AND NOT age<18
+//! ```
+//!
+//! - `tag:<tag>` matches the subscribers with the tag;
+//! - `<attribute><operator><value>` compares an attribute to a value, with
+//!   `=`, `!=`, `<`, `<=`, `>` or `>=`. Values are numbers, `true`, `false`,
+//!   or text, quoted when it contains anything else than letters, digits
+//!   and `_-.:@+`. Attributes only match values of the same type, and
+//!   missing attributes never match, except with `!=`. Booleans can only be
+//!   compared with `=` and `!=`;
+//! - `NOT` binds tighter than `AND`, which binds tighter than `OR`, and
+//!   parentheses group. Keywords are case-insensitive.
+//!
+//! Segments are compiled to SQL conditions with bound parameters only.
+use crate::domain::{AttributeName, SubscriberTag};
+use sqlx::{Postgres, QueryBuilder};
+
+#[derive(Debug, Clone)]
+pub struct Segment {
+    source: String,
+    expression: Expression,
+}
+
+#[derive(Debug, Clone)]
+enum Expression {
+    Tag(SubscriberTag),
+    Comparison {
+        name: AttributeName,
+        operator: Operator,
+        value: Value,
+    },
+    Not(Box<Expression>),
+    And(Box<Expression>, Box<Expression>),
+    Or(Box<Expression>, Box<Expression>),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Operator {
+    Eq,
+    Ne,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+}
+
+impl Operator {
+    fn as_sql(self) -> &'static str {
+        match self {
+            Operator::Eq => "=",
+            Operator::Ne => "<>",
+            Operator::Lt => "<",
+            Operator::Le => "<=",
+            Operator::Gt => ">",
+            Operator::Ge => ">=",
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Value {
+    Text(String),
+    /// Kept as written: it is cast to `numeric` by Postgres.
+    Number(String),
+    Bool(bool),
+}
+
+impl Segment {
+    const MAX_LENGTH: usize = 1000;
+    /// How deeply parentheses and `NOT`s can be nested.
+    const MAX_DEPTH: usize = 32;
+
+    pub fn parse(s: &str) -> Result<Segment, String> {
+        let source = s.trim();
+        if source.is_empty() {
+            return Err("The segment is empty.".into());
+        }
+        if source.len() > Self::MAX_LENGTH {
+            return Err(format!(
+                "Segments cannot be longer than {} characters.",
+                Self::MAX_LENGTH
+            ));
+        }
+        let mut parser = Parser {
+            tokens: tokenize(source)?,
+            position: 0,
+            depth: 0,
+        };
+        let expression = parser.parse_or()?;
+        if parser.position < parser.tokens.len() {
+            return Err("Unexpected text after the end of the segment.".into());
+        }
+
+        Ok(Self {
+            source: source.to_owned(),
+            expression,
+        })
+    }
+
+    /// Push the condition selecting the subscribers of the segment. The
+    /// `subscriptions` table must be aliased as `s` in the query.
+    pub fn push_condition(&self, builder: &mut QueryBuilder<'_, Postgres>) {
+        self.expression.push_sql(builder);
+    }
+}
+
+impl AsRef<str> for Segment {
+    fn as_ref(&self) -> &str {
+        &self.source
+    }
+}
+
+impl Expression {
+    fn push_sql(&self, builder: &mut QueryBuilder<'_, Postgres>) {
+        match self {
+            Expression::Tag(tag) => {
+                builder.push_bind(tag.as_ref().to_owned());
+                builder.push(" = ANY(s.tags)");
+            }
+            Expression::Comparison {
+                name,
+                operator,
+                value,
+            } => push_comparison(builder, name, *operator, value),
+            Expression::Not(expression) => {
+                builder.push("NOT (");
+                expression.push_sql(builder);
+                builder.push(")");
+            }
+            Expression::And(left, right) | Expression::Or(left, right) => {
+                builder.push("(");
+                left.push_sql(builder);
+                builder.push(if matches!(self, Expression::And(..)) {
+                    " AND "
+                } else {
+                    " OR "
+                });
+                right.push_sql(builder);
+                builder.push(")");
+            }
+        }
+    }
+}
+
+fn push_comparison(
+    builder: &mut QueryBuilder<'_, Postgres>,
+    name: &AttributeName,
+    operator: Operator,
+    value: &Value,
+) {
+    let name = name.as_ref().to_owned();
+    match (operator, value) {
+        // JSON values of different types are never equal
+        (Operator::Eq | Operator::Ne, value) => {
+            builder.push("COALESCE(s.attributes -> ");
+            builder.push_bind(name);
+            builder.push(format!(" {} ", operator.as_sql()));
+            match value {
+                Value::Text(text) => {
+                    builder.push("to_jsonb(");
+                    builder.push_bind(text.clone());
+                    builder.push("::text)");
+                }
+                Value::Number(number) => {
+                    builder.push("to_jsonb(");
+                    builder.push_bind(number.clone());
+                    builder.push("::numeric)");
+                }
+                Value::Bool(b) => {
+                    builder.push("to_jsonb(");
+                    builder.push_bind(*b);
+                    builder.push("::boolean)");
+                }
+            }
+            // Missing attributes are different from any value
+            builder.push(if operator == Operator::Eq {
+                ", false)"
+            } else {
+                ", true)"
+            });
+        }
+        (_, Value::Number(number)) => {
+            builder.push("COALESCE(CASE WHEN jsonb_typeof(s.attributes -> ");
+            builder.push_bind(name.clone());
+            builder.push(") = 'number' THEN (s.attributes ->> ");
+            builder.push_bind(name);
+            builder.push(format!(")::numeric {} ", operator.as_sql()));
+            builder.push_bind(number.clone());
+            builder.push("::numeric END, false)");
+        }
+        (_, Value::Text(text)) => {
+            builder.push("COALESCE(CASE WHEN jsonb_typeof(s.attributes -> ");
+            builder.push_bind(name.clone());
+            builder.push(") = 'string' THEN s.attributes ->> ");
+            builder.push_bind(name);
+            builder.push(format!(" {} ", operator.as_sql()));
+            builder.push_bind(text.clone());
+            builder.push(" END, false)");
+        }
+        // Rejected by the parser
+        (_, Value::Bool(_)) => {
+            builder.push("false");
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    LeftParenthesis,
+    RightParenthesis,
+    Operator(Operator),
+    Quoted(String),
+    Word(String),
+}
+
+fn is_word_character(c: char) -> bool {
+    c.is_alphanumeric() || matches!(c, '_' | '-' | '.' | ':' | '@' | '+')
+}
+
+fn tokenize(s: &str) -> Result<Vec<Token>, String> {
+    let mut tokens = Vec::new();
+    let mut chars = s.chars().peekable();
+    while let Some(c) = chars.next() {
+        let token = match c {
+            c if c.is_whitespace() => continue,
+            '(' => Token::LeftParenthesis,
+            ')' => Token::RightParenthesis,
+            '=' => Token::Operator(Operator::Eq),
+            '!' if chars.next_if_eq(&'=').is_some() => Token::Operator(Operator::Ne),
+            '<' if chars.next_if_eq(&'=').is_some() => Token::Operator(Operator::Le),
+            '<' => Token::Operator(Operator::Lt),
+            '>' if chars.next_if_eq(&'=').is_some() => Token::Operator(Operator::Ge),
+            '>' => Token::Operator(Operator::Gt),
+            '"' => {
+                let mut text = String::new();
+                loop {
+                    match chars.next() {
+                        Some('"') => break,
+                        Some('\\') => match chars.next() {
+                            Some(c) => text.push(c),
+                            None => return Err("Unterminated quoted text.".into()),
+                        },
+                        Some(c) => text.push(c),
+                        None => return Err("Unterminated quoted text.".into()),
+                    }
+                }
+                Token::Quoted(text)
+            }
+            c if is_word_character(c) => {
+                let mut word = String::from(c);
+                while let Some(c) = chars.next_if(|c| is_word_character(*c)) {
+                    word.push(c);
+                }
+                Token::Word(word)
+            }
+            c => return Err(format!("Unexpected character: {}", c)),
+        };
+        tokens.push(token);
+    }
+    Ok(tokens)
+}
+
+/// A recursive descent parser, one method per precedence level.
+struct Parser {
+    tokens: Vec<Token>,
+    position: usize,
+    depth: usize,
+}
+
+impl Parser {
+    fn next(&mut self) -> Option<Token> {
+        let token = self.tokens.get(self.position).cloned();
+        self.position += 1;
+        token
+    }
+
+    fn next_if_keyword(&mut self, keyword: &str) -> bool {
+        let is_keyword = matches!(
+            self.tokens.get(self.position),
+            Some(Token::Word(word)) if word.eq_ignore_ascii_case(keyword)
+        );
+        if is_keyword {
+            self.position += 1;
+        }
+        is_keyword
+    }
+
+    fn nest(&mut self) -> Result<(), String> {
+        self.depth += 1;
+        if self.depth > Segment::MAX_DEPTH {
+            return Err("The segment is nested too deeply.".into());
+        }
+        Ok(())
+    }
+
+    fn parse_or(&mut self) -> Result<Expression, String> {
+        let mut expression = self.parse_and()?;
+        while self.next_if_keyword("OR") {
+            let right = self.parse_and()?;
+            expression = Expression::Or(Box::new(expression), Box::new(right));
+        }
+        Ok(expression)
+    }
+
+    fn parse_and(&mut self) -> Result<Expression, String> {
+        let mut expression = self.parse_not()?;
+        while self.next_if_keyword("AND") {
+            let right = self.parse_not()?;
+            expression = Expression::And(Box::new(expression), Box::new(right));
+        }
+        Ok(expression)
+    }
+
+    fn parse_not(&mut self) -> Result<Expression, String> {
+        if !self.next_if_keyword("NOT") {
+            return self.parse_atom();
+        }
+        self.nest()?;
+        let expression = self.parse_not()?;
+        self.depth -= 1;
+        Ok(Expression::Not(Box::new(expression)))
+    }
+
+    fn parse_atom(&mut self) -> Result<Expression, String> {
+        match self.next() {
+            Some(Token::LeftParenthesis) => {
+                self.nest()?;
+                let expression = self.parse_or()?;
+                if self.next() != Some(Token::RightParenthesis) {
+                    return Err("Missing closing parenthesis.".into());
+                }
+                self.depth -= 1;
+                Ok(expression)
+            }
+            Some(Token::Word(word)) => {
+                if let Some(tag) = word.strip_prefix("tag:") {
+                    return Ok(Expression::Tag(SubscriberTag::parse(tag)?));
+                }
+                if ["and", "or", "not"].contains(&word.to_ascii_lowercase().as_str()) {
+                    return Err(format!("Unexpected {}.", word));
+                }
+                self.parse_comparison(AttributeName::parse(&word)?)
+            }
+            Some(_) => Err("Expected a tag, a comparison or a parenthesis.".into()),
+            None => Err("Unexpected end of the segment.".into()),
+        }
+    }
+
+    fn parse_comparison(&mut self, name: AttributeName) -> Result<Expression, String> {
+        let Some(Token::Operator(operator)) = self.next() else {
+            return Err(format!("Expected a comparison after {}.", name.as_ref()));
+        };
+        let value = match self.next() {
+            Some(Token::Quoted(text)) => Value::Text(text),
+            Some(Token::Word(word)) => parse_word_value(word),
+            _ => return Err(format!("Expected a value to compare {} to.", name.as_ref())),
+        };
+        if matches!(value, Value::Bool(_)) && !matches!(operator, Operator::Eq | Operator::Ne) {
+            return Err("Booleans can only be compared with = and !=.".into());
+        }
+        Ok(Expression::Comparison {
+            name,
+            operator,
+            value,
+        })
+    }
+}
+
+fn parse_word_value(word: String) -> Value {
+    match word.as_str() {
+        "true" => Value::Bool(true),
+        "false" => Value::Bool(false),
+        _ if is_number(&word) => Value::Number(word),
+        _ => Value::Text(word),
+    }
+}
+
+/// Decimal numbers, e.g. `42`, `-3` or `4.5`.
+fn is_number(s: &str) -> bool {
+    let unsigned = s.strip_prefix('-').unwrap_or(s);
+    let mut parts = unsigned.splitn(2, '.');
+    let is_digits = |part: &str| !part.is_empty() && part.chars().all(|c| c.is_ascii_digit());
+    parts.next().is_some_and(is_digits) && parts.next().is_none_or(is_digits)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Segment;
+    use claims::assert_err;
+    use sqlx::{Postgres, QueryBuilder};
+
+    fn compile(segment: &str) -> String {
+        let segment = Segment::parse(segment).unwrap();
+        let mut builder = QueryBuilder::<Postgres>::new("");
+        segment.push_condition(&mut builder);
+        builder.sql().to_owned()
+    }
+
+    #[test]
+    fn tags_are_compiled_to_an_array_lookup() {
+        assert_eq!(compile("tag:Rust"), "$1 = ANY(s.tags)");
+    }
+
+    #[test]
+    fn equality_compares_json_values() {
+        assert_eq!(
+            compile("country=DE"),
+            "COALESCE(s.attributes -> $1 = to_jsonb($2::text), false)"
+        );
+        assert_eq!(
+            compile(r#"city != "Den Haag""#),
+            "COALESCE(s.attributes -> $1 <> to_jsonb($2::text), true)"
+        );
+        assert_eq!(
+            compile("age=42"),
+            "COALESCE(s.attributes -> $1 = to_jsonb($2::numeric), false)"
+        );
+        assert_eq!(
+            compile("vip=true"),
+            "COALESCE(s.attributes -> $1 = to_jsonb($2::boolean), false)"
+        );
+    }
+
+    #[test]
+    fn ordering_only_applies_to_attributes_of_the_same_type() {
+        assert_eq!(
+            compile("age>=18"),
+            "COALESCE(CASE WHEN jsonb_typeof(s.attributes -> $1) = 'number' \
+            THEN (s.attributes ->> $2)::numeric >= $3::numeric END, false)"
+        );
+        assert_eq!(
+            compile("name<M"),
+            "COALESCE(CASE WHEN jsonb_typeof(s.attributes -> $1) = 'string' \
+            THEN s.attributes ->> $2 < $3 END, false)"
+        );
+    }
+
+    #[test]
+    fn not_binds_tighter_than_and_which_binds_tighter_than_or() {
+        assert_eq!(
+            compile("tag:a or tag:b AND NOT tag:c"),
+            "($1 = ANY(s.tags) OR ($2 = ANY(s.tags) AND NOT ($3 = ANY(s.tags))))"
+        );
+        assert_eq!(
+            compile("(tag:a OR tag:b) AND tag:c"),
+            "(($1 = ANY(s.tags) OR $2 = ANY(s.tags)) AND $3 = ANY(s.tags))"
+        );
+    }
+
+    #[test]
+    fn the_source_is_kept_trimmed() {
+        let segment = Segment::parse("  tag:rust AND country=DE ").unwrap();
+        assert_eq!(segment.as_ref(), "tag:rust AND country=DE");
+    }
+
+    #[test]
+    fn invalid_segments_are_rejected() {
+        for segment in [
+            "",
+            "tag:",
+            "tag:not a tag",
+            "country",
+            "country=",
+            "Country=DE",
+            "country==DE",
+            "(tag:rust",
+            "tag:rust)",
+            "tag:rust AND",
+            "tag:rust tag:go",
+            "AND tag:rust",
+            "vip<true",
+            r#"country="DE"#,
+            "country=DE;",
+        ] {
+            assert_err!(Segment::parse(segment), "{} should be rejected", segment);
+        }
+    }
+
+    #[test]
+    fn deeply_nested_segments_are_rejected() {
+        let segment = format!("{}tag:rust{}", "(".repeat(33), ")".repeat(33));
+        assert_err!(Segment::parse(&segment));
+        let segment = format!("{}tag:rust{}", "(".repeat(32), ")".repeat(32));
+        Segment::parse(&segment).unwrap();
+    }
+}
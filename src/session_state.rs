@@ -7,6 +7,7 @@ pub struct TypedSession(Session);
 
 impl TypedSession {
     const USER_ID_KEY: &'static str = "user_id";
+    const SESSION_VERSION_KEY: &'static str = "session_version";
 
     pub fn renew(&self) {
         self.0.renew()
@@ -19,6 +20,18 @@ impl TypedSession {
     pub fn get_user_id(&self) -> Result<Option<Uuid>, SessionGetError> {
         self.0.get(Self::USER_ID_KEY)
     }
+
+    pub fn insert_session_version(&self, session_version: i32) -> Result<(), SessionInsertError> {
+        self.0.insert(Self::SESSION_VERSION_KEY, session_version)
+    }
+
+    pub fn get_session_version(&self) -> Result<Option<i32>, SessionGetError> {
+        self.0.get(Self::SESSION_VERSION_KEY)
+    }
+
+    pub fn log_out(&self) {
+        self.0.purge()
+    }
 }
 
 impl FromRequest for TypedSession {
@@ -1,12 +1,26 @@
-use crate::configuration::{DatabaseSettings, Settings};
-use crate::email_client::EmailClient;
+use crate::authentication::reject_anonymous_users;
+use crate::configuration::{DatabaseSettings, EmailTransport, Settings, WebhookSettings};
+use crate::email_client::{EmailClient, InMemoryEmailSender};
 use crate::routes::{
-    admin_dashboard, confirm, health_check, home, login, login_form, publish_newsletter, subscribe,
+    admin_dashboard, admin_dead_letters, admin_lists, admin_newsletter, admin_newsletters,
+    admin_subscriber, admin_subscribers, cancel_newsletter, cancel_newsletter_schedule,
+    change_password, change_password_form, confirm, create_list, create_newsletter_draft,
+    download_subscriber_data, email_webhook, erase_subscriber_data, export_deliveries,
+    export_subscribers, get_newsletter, health_check, home, import_subscribers_form,
+    import_subscribers_from_form, issue_page, issues_index, log_out, login, login_form,
+    manually_confirm_subscriber, preview_newsletter, privacy_manage_page, privacy_request_form,
+    publish_newsletter, publish_newsletter_now, redrive_dead_letters, remove_subscriber,
+    request_privacy_link, resend_confirmation, schedule_newsletter, send_test_newsletter,
+    send_test_newsletter_from_form, subscribe, subscribe_to_list, tag_subscribers,
+    tag_subscribers_from_form, unsubscribe, unsubscribe_form, update_newsletter_draft,
+    update_subscriber_attributes,
 };
+use crate::templates::Templates;
 use actix_session::storage::RedisSessionStore;
 use actix_session::SessionMiddleware;
 use actix_web::cookie::Key;
 use actix_web::dev::Server;
+use actix_web::middleware::from_fn;
 use actix_web::{web, App, HttpServer};
 use actix_web_flash_messages::storage::CookieMessageStore;
 use actix_web_flash_messages::FlashMessagesFramework;
@@ -15,15 +29,22 @@ use sqlx::postgres::PgPoolOptions;
 use sqlx::PgPool;
 use std::io::Error;
 use std::net::TcpListener;
+use std::time::Duration;
 use tracing_actix_web::TracingLogger;
 
 pub struct Application {
     port: u16,
     server: Server,
+    /// Where the server keeps its emails with the in-memory email transport.
+    email_outbox: Option<InMemoryEmailSender>,
 }
 
 pub struct ApplicationBaseUrl(pub String);
 
+/// How long subscription confirmation tokens stay valid.
+pub struct SubscriptionTokenTtl(pub Duration);
+
+#[allow(clippy::too_many_arguments)]
 pub async fn run(
     listener: TcpListener,
     db_pool: PgPool,
@@ -31,12 +52,19 @@ pub async fn run(
     base_url: String,
     hmac_secret: SecretString,
     redis_uri: SecretString,
+    subscription_token_ttl: Duration,
+    templates: Templates,
+    webhook_settings: WebhookSettings,
 ) -> Result<Server, anyhow::Error> {
     // wrap connection in a smart pointer (Arc)
     let db_pool = web::Data::new(db_pool);
     let email_client = web::Data::new(email_client);
     let base_url = web::Data::new(ApplicationBaseUrl(base_url));
+    let subscription_token_ttl = web::Data::new(SubscriptionTokenTtl(subscription_token_ttl));
     let secret_key = Key::from(hmac_secret.expose_secret().as_bytes());
+    let hmac_secret = web::Data::new(HmacSecret(hmac_secret));
+    let templates = web::Data::new(templates);
+    let webhook_settings = web::Data::new(webhook_settings);
 
     let message_store = CookieMessageStore::builder(secret_key.clone()).build();
     let message_framework = FlashMessagesFramework::builder(message_store).build();
@@ -56,11 +84,124 @@ pub async fn run(
             .route("/health_check", web::get().to(health_check))
             .route("/subscriptions", web::post().to(subscribe))
             .route("/subscriptions/confirm", web::get().to(confirm))
+            .route(
+                "/lists/{slug}/subscriptions",
+                web::post().to(subscribe_to_list),
+            )
+            .route(
+                "/subscriptions/unsubscribe",
+                web::get().to(unsubscribe_form),
+            )
+            .route("/subscriptions/unsubscribe", web::post().to(unsubscribe))
+            .route("/privacy", web::get().to(privacy_request_form))
+            .route("/privacy", web::post().to(request_privacy_link))
+            .route("/privacy/manage", web::get().to(privacy_manage_page))
+            .route("/privacy/data", web::get().to(download_subscriber_data))
+            .route("/privacy/erase", web::post().to(erase_subscriber_data))
             .route("/newsletters", web::post().to(publish_newsletter))
-            .route("/admin/dashboard", web::get().to(admin_dashboard))
+            .route(
+                "/newsletters/{newsletter_issue_id}",
+                web::get().to(get_newsletter),
+            )
+            .route(
+                "/newsletters/{newsletter_issue_id}/cancel",
+                web::post().to(cancel_newsletter),
+            )
+            .route(
+                "/newsletters/{newsletter_issue_id}/test",
+                web::post().to(send_test_newsletter),
+            )
+            .route("/subscribers/tags", web::post().to(tag_subscribers))
+            .route(
+                "/subscribers/attributes",
+                web::post().to(update_subscriber_attributes),
+            )
+            .route("/issues", web::get().to(issues_index))
+            .route("/issues/{slug}", web::get().to(issue_page))
+            .route("/webhooks/email", web::post().to(email_webhook))
+            .service(
+                web::scope("/admin")
+                    .wrap(from_fn(reject_anonymous_users))
+                    .route("/dashboard", web::get().to(admin_dashboard))
+                    .route("/newsletters", web::get().to(admin_newsletters))
+                    .route("/newsletters", web::post().to(create_newsletter_draft))
+                    .route(
+                        "/newsletters/{newsletter_issue_id}",
+                        web::get().to(admin_newsletter),
+                    )
+                    .route(
+                        "/newsletters/{newsletter_issue_id}",
+                        web::post().to(update_newsletter_draft),
+                    )
+                    .route(
+                        "/newsletters/{newsletter_issue_id}/preview",
+                        web::get().to(preview_newsletter),
+                    )
+                    .route(
+                        "/newsletters/{newsletter_issue_id}/schedule",
+                        web::post().to(schedule_newsletter),
+                    )
+                    .route(
+                        "/newsletters/{newsletter_issue_id}/publish",
+                        web::post().to(publish_newsletter_now),
+                    )
+                    .route(
+                        "/newsletters/{newsletter_issue_id}/cancel",
+                        web::post().to(cancel_newsletter_schedule),
+                    )
+                    .route(
+                        "/newsletters/{newsletter_issue_id}/test",
+                        web::post().to(send_test_newsletter_from_form),
+                    )
+                    .route(
+                        "/newsletters/{newsletter_issue_id}/deliveries",
+                        web::get().to(export_deliveries),
+                    )
+                    .route("/password", web::get().to(change_password_form))
+                    .route("/password", web::post().to(change_password))
+                    .route("/logout", web::post().to(log_out))
+                    .route("/lists", web::get().to(admin_lists))
+                    .route("/lists", web::post().to(create_list))
+                    .route("/dead_letters", web::get().to(admin_dead_letters))
+                    .route("/dead_letters", web::post().to(redrive_dead_letters))
+                    .route("/subscribers", web::get().to(admin_subscribers))
+                    .route(
+                        "/subscribers/tags",
+                        web::post().to(tag_subscribers_from_form),
+                    )
+                    .route("/subscribers/export", web::get().to(export_subscribers))
+                    .route(
+                        "/subscribers/import",
+                        web::get().to(import_subscribers_form),
+                    )
+                    .route(
+                        "/subscribers/import",
+                        web::post().to(import_subscribers_from_form),
+                    )
+                    .route(
+                        "/subscribers/{subscriber_id}",
+                        web::get().to(admin_subscriber),
+                    )
+                    .route(
+                        "/subscribers/{subscriber_id}/confirm",
+                        web::post().to(manually_confirm_subscriber),
+                    )
+                    .route(
+                        "/subscribers/{subscriber_id}/resend_confirmation",
+                        web::post().to(resend_confirmation),
+                    )
+                    .route(
+                        "/subscribers/{subscriber_id}/remove",
+                        web::post().to(remove_subscriber),
+                    ),
+            )
             .app_data(db_pool.clone())
             .app_data(email_client.clone())
             .app_data(base_url.clone())
+            .app_data(hmac_secret.clone())
+            .app_data(subscription_token_ttl.clone())
+            .app_data(templates.clone())
+            .app_data(webhook_settings.clone())
     })
     .listen(listener)?
     .run();
@@ -71,18 +212,19 @@ pub async fn run(
 impl Application {
     pub async fn build(configuration: &Settings) -> Result<Self, anyhow::Error> {
         let connection_pool = get_connection_pool(&configuration.database);
-
-        let sender_email = configuration
-            .email_client
-            .sender()
-            .expect("Incorrect sender email in settings");
-        let timeout = configuration.email_client.timeout();
-        let email_client = EmailClient::new(
-            configuration.email_client.base_url.clone(),
-            sender_email,
-            configuration.email_client.authorization_token.clone(),
-            timeout,
-        );
+        // The outbox must be the one the server sends through
+        let (email_client, email_outbox) = match configuration.email_client.transport {
+            EmailTransport::InMemory => {
+                let sender = configuration
+                    .email_client
+                    .sender()
+                    .map_err(anyhow::Error::msg)?;
+                let outbox = InMemoryEmailSender::new();
+                (EmailClient::new(sender, outbox.clone()), Some(outbox))
+            }
+            _ => (configuration.email_client.client(), None),
+        };
+        let templates = Templates::load(&configuration.application.templates_directory)?;
 
         let address = format!(
             "{}:{}",
@@ -97,16 +239,28 @@ impl Application {
             configuration.application.base_url.clone(),
             configuration.application.hmac_secret.clone(),
             configuration.redis_uri.clone(),
+            configuration.application.subscription_token_ttl(),
+            templates,
+            configuration.webhooks.clone(),
         )
         .await?;
 
-        Ok(Self { port, server })
+        Ok(Self {
+            port,
+            server,
+            email_outbox,
+        })
     }
 
     pub fn port(&self) -> u16 {
         self.port
     }
 
+    /// The emails sent by the server, with the in-memory email transport.
+    pub fn email_outbox(&self) -> Option<&InMemoryEmailSender> {
+        self.email_outbox.as_ref()
+    }
+
     pub async fn run_until_stopped(self) -> Result<(), Error> {
         self.server.await
     }
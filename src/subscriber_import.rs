@@ -0,0 +1,497 @@
+//! Bulk import of subscribers from a CSV file, e.g. when migrating from
+//! another newsletter provider.
+//!
+//! The file is parsed as it is streamed in, and subscribers are inserted in
+//! batches: imports of tens of thousands of rows never sit in memory at once.
+//! Confirmation emails are queued along with the subscribers, the background
+//! worker sends them.
+use crate::confirmation_email_worker::enqueue_confirmation_emails;
+use crate::domain::{SubscriberEmail, SubscriberName};
+use crate::lists::{get_list_by_slug, DEFAULT_LIST_SLUG};
+use crate::routes::{error_chain_fmt, generate_subscription_token};
+use anyhow::Context;
+use chrono::Utc;
+use csv_core::ReadRecordResult;
+use futures_util::{Stream, StreamExt};
+use sqlx::PgPool;
+use std::collections::HashSet;
+use std::fmt::{Debug, Formatter};
+use uuid::Uuid;
+
+const BATCH_SIZE: usize = 500;
+/// Only the first errors are reported: a file in the wrong format would
+/// otherwise get one error per line.
+pub const MAX_REPORTED_ERRORS: usize = 100;
+/// Protects us from files that are not CSV at all, e.g. without any line break.
+const MAX_RECORD_LENGTH: usize = 64 * 1024;
+
+/// What happens to the imported subscribers.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ImportMode {
+    /// They had confirmed their subscription with the previous provider:
+    /// they receive the next issues right away.
+    Confirmed,
+    /// They get a confirmation email, and receive issues once they confirm.
+    /// The emails are sent in the background.
+    SendConfirmation,
+}
+
+impl ImportMode {
+    pub fn parse(s: &str) -> Result<ImportMode, String> {
+        match s {
+            "confirmed" => Ok(Self::Confirmed),
+            "send_confirmation" => Ok(Self::SendConfirmation),
+            _ => Err(format!("{} is not a valid import mode.", s)),
+        }
+    }
+
+    fn status(&self) -> &'static str {
+        match self {
+            ImportMode::Confirmed => "confirmed",
+            ImportMode::SendConfirmation => "pending_confirmation",
+        }
+    }
+}
+
+/// A line of the file that could not be imported.
+#[derive(Debug)]
+pub struct LineError {
+    pub line: u64,
+    pub message: String,
+}
+
+#[derive(Debug, Default)]
+pub struct ImportReport {
+    pub n_imported: u64,
+    /// Rows skipped because their email address is already known,
+    /// including the rows repeated in the file.
+    pub n_skipped: u64,
+    pub n_errors: u64,
+    /// The first `MAX_REPORTED_ERRORS` errors, in the order of the file.
+    pub errors: Vec<LineError>,
+}
+
+impl ImportReport {
+    fn add_error(&mut self, line: u64, message: String) {
+        self.n_errors += 1;
+        if self.errors.len() < MAX_REPORTED_ERRORS {
+            self.errors.push(LineError { line, message });
+        }
+    }
+}
+
+#[derive(thiserror::Error)]
+pub enum ImportError {
+    /// The file cannot be imported at all, e.g. it has no `email` column.
+    #[error("{0}")]
+    InvalidFile(String),
+    #[error(transparent)]
+    UnexpectedError(#[from] anyhow::Error),
+}
+
+impl Debug for ImportError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        error_chain_fmt(self, f)
+    }
+}
+
+/// Import the subscribers of a CSV file into the default list.
+///
+/// The first line names the columns: `email` and `name` are required, other
+/// columns are ignored. Invalid rows are reported and skipped, as are the
+/// email addresses we already know about, whatever their status: an import
+/// never re-subscribes somebody who left.
+#[tracing::instrument(name = "Import subscribers", skip(input, pool))]
+pub async fn import_subscribers<S, B, E>(
+    input: S,
+    mode: ImportMode,
+    pool: &PgPool,
+) -> Result<ImportReport, ImportError>
+where
+    S: Stream<Item = Result<B, E>> + Unpin,
+    B: AsRef<[u8]>,
+    E: std::fmt::Display,
+{
+    let list = get_list_by_slug(pool, DEFAULT_LIST_SLUG)
+        .await
+        .context("Failed to retrieve the default list")?
+        .context("The default list is missing")?;
+    let mut reader = CsvReader::new(input);
+    let mut report = ImportReport::default();
+
+    let Some(header) = reader.next_record().await? else {
+        return Ok(report);
+    };
+    let columns = Columns::find(&header)?;
+
+    let mut batch = Vec::with_capacity(BATCH_SIZE);
+    loop {
+        let record = match reader.next_record().await {
+            Ok(Some(record)) => record,
+            Ok(None) => break,
+            // The previous batches are committed: tell how far we went
+            Err(ImportError::InvalidFile(message)) if report.n_imported + report.n_skipped > 0 => {
+                return Err(ImportError::InvalidFile(format!(
+                    "{} The import stopped there, after importing {} subscribers: \
+                    import the fixed file again, the addresses already imported are skipped.",
+                    message, report.n_imported
+                )));
+            }
+            Err(e) => return Err(e),
+        };
+        match columns.parse(&record) {
+            Ok(row) => batch.push(row),
+            Err(e) => report.add_error(record.line, e),
+        }
+        if batch.len() == BATCH_SIZE {
+            import_batch(pool, list.list_id, mode, &batch, &mut report).await?;
+            batch.clear();
+        }
+    }
+    import_batch(pool, list.list_id, mode, &batch, &mut report).await?;
+
+    Ok(report)
+}
+
+struct Row {
+    email: SubscriberEmail,
+    name: SubscriberName,
+}
+
+/// The positions of the columns we import.
+struct Columns {
+    email: usize,
+    name: usize,
+}
+
+impl Columns {
+    fn find(header: &CsvRecord) -> Result<Columns, ImportError> {
+        let invalid_header = || {
+            ImportError::InvalidFile(
+                "The first line of the file must name its columns, including email and name."
+                    .into(),
+            )
+        };
+        let mut names = header.fields().map_err(|_| invalid_header())?;
+        // Spreadsheets often save UTF-8 files with a byte order mark
+        if let Some(first) = names.first_mut() {
+            *first = first.strip_prefix('\u{feff}').unwrap_or(first);
+        }
+        let position = |column: &str| {
+            names
+                .iter()
+                .position(|name| name.trim().eq_ignore_ascii_case(column))
+        };
+        match (position("email"), position("name")) {
+            (Some(email), Some(name)) => Ok(Columns { email, name }),
+            _ => Err(invalid_header()),
+        }
+    }
+
+    fn parse(&self, record: &CsvRecord) -> Result<Row, String> {
+        let fields = record.fields()?;
+        let field = |i: usize| fields.get(i).map(|f| f.trim()).unwrap_or_default();
+        Ok(Row {
+            email: SubscriberEmail::parse(field(self.email).to_owned())?,
+            name: SubscriberName::parse(field(self.name).to_owned())?,
+        })
+    }
+}
+
+/// Insert the new subscribers of the batch, and queue their confirmation
+/// email if they have to confirm.
+#[tracing::instrument(skip_all, fields(batch_size = batch.len()))]
+async fn import_batch(
+    pool: &PgPool,
+    list_id: Uuid,
+    mode: ImportMode,
+    batch: &[Row],
+    report: &mut ImportReport,
+) -> Result<(), anyhow::Error> {
+    if batch.is_empty() {
+        return Ok(());
+    }
+    let ids = batch.iter().map(|_| Uuid::new_v4()).collect::<Vec<_>>();
+    let emails = batch
+        .iter()
+        .map(|row| row.email.as_ref().to_owned())
+        .collect::<Vec<_>>();
+    let names = batch
+        .iter()
+        .map(|row| row.name.as_ref().to_owned())
+        .collect::<Vec<_>>();
+    let now = Utc::now();
+
+    let mut transaction = pool
+        .begin()
+        .await
+        .context("Failed to start a transaction")?;
+    // Addresses we already know, or repeated within the batch, are skipped
+    let inserted_ids = sqlx::query_scalar!(
+        r#"
+        INSERT INTO subscriptions (id, email, name, subscribed_at, status)
+        SELECT id, email, name, $4, $5
+        FROM UNNEST($1::uuid[], $2::text[], $3::text[]) AS t(id, email, name)
+        ON CONFLICT (email) DO NOTHING
+        RETURNING id
+        "#,
+        &ids,
+        &emails,
+        &names,
+        now,
+        mode.status()
+    )
+    .fetch_all(&mut *transaction)
+    .await
+    .context("Failed to insert the imported subscribers")?;
+    let inserted = inserted_ids.iter().collect::<HashSet<_>>();
+    sqlx::query!(
+        r#"
+        INSERT INTO list_subscriptions (list_id, subscriber_id, status, subscribed_at)
+        SELECT $1, subscriber_id, $3, $4
+        FROM UNNEST($2::uuid[]) AS subscriber_id
+        "#,
+        list_id,
+        &inserted_ids,
+        mode.status(),
+        now
+    )
+    .execute(&mut *transaction)
+    .await
+    .context("Failed to subscribe the imported subscribers to the list")?;
+
+    let imported = batch
+        .iter()
+        .zip(&ids)
+        .filter(|(_, id)| inserted.contains(id))
+        .collect::<Vec<_>>();
+    let tokens = match mode {
+        ImportMode::Confirmed => Vec::new(),
+        ImportMode::SendConfirmation => imported
+            .iter()
+            .map(|_| generate_subscription_token())
+            .collect::<Vec<_>>(),
+    };
+    if !tokens.is_empty() {
+        let subscriber_ids = imported.iter().map(|(_, id)| **id).collect::<Vec<_>>();
+        sqlx::query!(
+            r#"
+            INSERT INTO subscription_tokens (subscriber_id, list_id, subscription_token)
+            SELECT subscriber_id, $1, subscription_token
+            FROM UNNEST($2::uuid[], $3::text[]) AS t(subscriber_id, subscription_token)
+            "#,
+            list_id,
+            &subscriber_ids,
+            &tokens
+        )
+        .execute(&mut *transaction)
+        .await
+        .context("Failed to store the subscription tokens")?;
+        enqueue_confirmation_emails(&mut transaction, &tokens)
+            .await
+            .context("Failed to queue the confirmation emails")?;
+    }
+    transaction
+        .commit()
+        .await
+        .context("Failed to commit the imported subscribers")?;
+
+    report.n_imported += imported.len() as u64;
+    report.n_skipped += (batch.len() - imported.len()) as u64;
+
+    Ok(())
+}
+
+/// A row of the CSV file, with the line it starts on.
+struct CsvRecord {
+    line: u64,
+    data: Vec<u8>,
+    ends: Vec<usize>,
+}
+
+impl CsvRecord {
+    fn fields(&self) -> Result<Vec<&str>, String> {
+        let mut start = 0;
+        self.ends
+            .iter()
+            .map(|&end| {
+                let field = std::str::from_utf8(&self.data[start..end])
+                    .map_err(|_| "The line is not valid UTF-8.".to_string());
+                start = end;
+                field
+            })
+            .collect()
+    }
+}
+
+/// Read CSV records from a stream of bytes, as the bytes come in.
+struct CsvReader<S, B> {
+    input: S,
+    reader: csv_core::Reader,
+    chunk: Option<B>,
+    position: usize,
+    is_exhausted: bool,
+}
+
+impl<S, B, E> CsvReader<S, B>
+where
+    S: Stream<Item = Result<B, E>> + Unpin,
+    B: AsRef<[u8]>,
+    E: std::fmt::Display,
+{
+    fn new(input: S) -> Self {
+        Self {
+            input,
+            reader: csv_core::Reader::new(),
+            chunk: None,
+            position: 0,
+            is_exhausted: false,
+        }
+    }
+
+    async fn next_record(&mut self) -> Result<Option<CsvRecord>, ImportError> {
+        let mut data = vec![0; 1024];
+        let mut ends = vec![0; 16];
+        let (mut n_data, mut n_ends) = (0, 0);
+        loop {
+            let chunk_len = self.chunk.as_ref().map_or(0, |c| c.as_ref().len());
+            // An empty input tells the parser that the file is over:
+            // we only pass one once the stream is exhausted.
+            if self.position == chunk_len && !self.is_exhausted {
+                match self.input.next().await {
+                    Some(chunk) => {
+                        self.chunk = Some(
+                            chunk.map_err(|e| anyhow::anyhow!("Failed to read the file: {}", e))?,
+                        );
+                        self.position = 0;
+                    }
+                    None => self.is_exhausted = true,
+                }
+                continue;
+            }
+            let input = match &self.chunk {
+                Some(chunk) => &chunk.as_ref()[self.position..],
+                None => &[],
+            };
+            let (result, n_in, n_out, n_end) =
+                self.reader
+                    .read_record(input, &mut data[n_data..], &mut ends[n_ends..]);
+            let ends_with_newline = n_in > 0 && input[n_in - 1] == b'\n';
+            self.position += n_in;
+            n_data += n_out;
+            n_ends += n_end;
+
+            match result {
+                ReadRecordResult::InputEmpty => {}
+                ReadRecordResult::OutputFull if data.len() >= MAX_RECORD_LENGTH => {
+                    let n_breaks = data[..n_data].iter().filter(|&&b| b == b'\n').count();
+                    return Err(ImportError::InvalidFile(format!(
+                        "Line {} is longer than {} bytes.",
+                        self.reader.line() - n_breaks as u64,
+                        MAX_RECORD_LENGTH
+                    )));
+                }
+                ReadRecordResult::OutputFull => data.resize(data.len() * 2, 0),
+                ReadRecordResult::OutputEndsFull => ends.resize(ends.len() * 2, 0),
+                ReadRecordResult::Record => {
+                    data.truncate(n_data);
+                    ends.truncate(n_ends);
+                    // The parser counts the line breaks it went through: we
+                    // are past the one ending the record, if any, and quoted
+                    // fields can span several lines.
+                    let last_line = self.reader.line() - u64::from(ends_with_newline);
+                    let line = last_line - data.iter().filter(|&&b| b == b'\n').count() as u64;
+                    return Ok(Some(CsvRecord { line, data, ends }));
+                }
+                ReadRecordResult::End => return Ok(None),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Columns, CsvReader, ImportError, MAX_RECORD_LENGTH};
+    use futures_util::stream;
+
+    /// Read all the records of the input, split in chunks of `chunk_size` bytes.
+    async fn read_all(input: &str, chunk_size: usize) -> Vec<(u64, Vec<String>)> {
+        let chunks = input
+            .as_bytes()
+            .chunks(chunk_size)
+            .map(|c| Ok::<_, std::io::Error>(c.to_vec()))
+            .collect::<Vec<_>>();
+        let mut reader = CsvReader::new(stream::iter(chunks));
+        let mut records = Vec::new();
+        while let Some(record) = reader.next_record().await.unwrap() {
+            let fields = record
+                .fields()
+                .unwrap()
+                .into_iter()
+                .map(ToOwned::to_owned)
+                .collect();
+            records.push((record.line, fields));
+        }
+        records
+    }
+
+    fn fields(values: &[&str]) -> Vec<String> {
+        values.iter().map(|v| v.to_string()).collect()
+    }
+
+    #[tokio::test]
+    async fn records_are_read_whatever_the_chunk_size() {
+        let input =
+            "email,name\nursula@example.com,Ursula\n\"le guin@example.com\",\"Le Guin, Ursula\"\n";
+        for chunk_size in [1, 2, 7, input.len()] {
+            assert_eq!(
+                read_all(input, chunk_size).await,
+                vec![
+                    (1, fields(&["email", "name"])),
+                    (2, fields(&["ursula@example.com", "Ursula"])),
+                    (3, fields(&["le guin@example.com", "Le Guin, Ursula"])),
+                ]
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn records_know_the_line_they_start_on() {
+        let input = "email,name\r\n\r\na@example.com,\"Multi\nline\"\r\nb@example.com,B";
+        assert_eq!(
+            read_all(input, 3).await,
+            vec![
+                (1, fields(&["email", "name"])),
+                (3, fields(&["a@example.com", "Multi\nline"])),
+                (5, fields(&["b@example.com", "B"])),
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn an_empty_file_has_no_records() {
+        assert!(read_all("", 10).await.is_empty());
+    }
+
+    #[tokio::test]
+    async fn columns_are_found_after_a_byte_order_mark() {
+        let input = "\u{feff}email,name\nursula@example.com,Ursula\n";
+        let mut reader = CsvReader::new(stream::iter(vec![Ok::<_, std::io::Error>(input)]));
+        let header = reader.next_record().await.unwrap().unwrap();
+        let columns = Columns::find(&header).unwrap();
+        assert_eq!((columns.email, columns.name), (0, 1));
+    }
+
+    #[tokio::test]
+    async fn overly_long_lines_are_rejected() {
+        let input = format!("email,name\n{}", "a".repeat(MAX_RECORD_LENGTH * 2));
+        let mut reader = CsvReader::new(stream::iter(vec![Ok::<_, std::io::Error>(input)]));
+        reader.next_record().await.unwrap();
+        match reader.next_record().await {
+            Err(ImportError::InvalidFile(message)) => {
+                assert_eq!(message, "Line 2 is longer than 65536 bytes.")
+            }
+            _ => panic!("The line should have been rejected"),
+        }
+    }
+}
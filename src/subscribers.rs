@@ -0,0 +1,77 @@
+//! Bulk updates of the tags and custom attributes of subscribers.
+use crate::domain::{AttributeName, SubscriberAttributes, SubscriberTag};
+use crate::segments::Segment;
+use sqlx::{PgPool, Postgres, QueryBuilder};
+
+/// The subscribers a bulk update applies to.
+pub enum SubscriberSelection {
+    Emails(Vec<String>),
+    Segment(Segment),
+}
+
+impl SubscriberSelection {
+    fn push_condition(&self, builder: &mut QueryBuilder<'_, Postgres>) {
+        match self {
+            SubscriberSelection::Emails(emails) => {
+                builder.push("s.email = ANY(");
+                builder.push_bind(emails.clone());
+                builder.push(")");
+            }
+            SubscriberSelection::Segment(segment) => segment.push_condition(builder),
+        }
+    }
+}
+
+fn tag_names(tags: &[SubscriberTag]) -> Vec<String> {
+    tags.iter().map(|tag| tag.as_ref().to_owned()).collect()
+}
+
+/// Add and remove tags, in a single statement.
+///
+/// Returns the number of selected subscribers, whether their tags changed or not.
+#[tracing::instrument(skip(pool, selection))]
+pub async fn update_tags(
+    pool: &PgPool,
+    selection: &SubscriberSelection,
+    add: &[SubscriberTag],
+    remove: &[SubscriberTag],
+) -> Result<u64, sqlx::Error> {
+    // Tags are kept sorted and without duplicates
+    let mut builder = QueryBuilder::new(
+        "UPDATE subscriptions s SET tags = ARRAY(SELECT DISTINCT t FROM unnest(s.tags || ",
+    );
+    builder.push_bind(tag_names(add));
+    builder.push("::text[]) t WHERE t <> ALL(");
+    builder.push_bind(tag_names(remove));
+    builder.push("::text[]) ORDER BY t) WHERE ");
+    selection.push_condition(&mut builder);
+
+    Ok(builder.build().execute(pool).await?.rows_affected())
+}
+
+/// Set and remove custom attributes, in a single statement. Attributes that
+/// are not mentioned are left as they are.
+///
+/// Returns the number of selected subscribers, whether their attributes changed or not.
+#[tracing::instrument(skip(pool, selection))]
+pub async fn update_attributes(
+    pool: &PgPool,
+    selection: &SubscriberSelection,
+    set: &SubscriberAttributes,
+    unset: &[AttributeName],
+) -> Result<u64, sqlx::Error> {
+    let mut builder =
+        QueryBuilder::new("UPDATE subscriptions s SET attributes = (s.attributes || ");
+    builder.push_bind(serde_json::Value::from(set.clone()));
+    builder.push(") - ");
+    builder.push_bind(
+        unset
+            .iter()
+            .map(|name| name.as_ref().to_owned())
+            .collect::<Vec<_>>(),
+    );
+    builder.push("::text[] WHERE ");
+    selection.push_condition(&mut builder);
+
+    Ok(builder.build().execute(pool).await?.rows_affected())
+}
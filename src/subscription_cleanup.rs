@@ -0,0 +1,76 @@
+use crate::configuration::Settings;
+use crate::startup::get_connection_pool;
+use chrono::Utc;
+use sqlx::PgPool;
+use std::time::Duration;
+
+const CLEANUP_INTERVAL: Duration = Duration::from_secs(60 * 60);
+
+pub async fn run_cleanup_until_stopped(configuration: Settings) -> Result<(), anyhow::Error> {
+    let connection_pool = get_connection_pool(&configuration.database);
+    let ttl = configuration.application.subscription_token_ttl();
+    loop {
+        if let Err(e) = delete_stale_pending_subscriptions(&connection_pool, ttl).await {
+            tracing::error!(
+                error.cause_chain = ?e,
+                error.message = %e,
+                "Failed to delete stale pending subscriptions",
+            );
+        }
+        tokio::time::sleep(CLEANUP_INTERVAL).await;
+    }
+}
+
+/// Delete the pending subscriptions that can no longer be confirmed,
+/// i.e. the ones without a subscription token issued within `ttl`.
+///
+/// Returns the number of deleted subscriptions.
+#[tracing::instrument(skip(pool))]
+pub async fn delete_stale_pending_subscriptions(
+    pool: &PgPool,
+    ttl: Duration,
+) -> Result<u64, anyhow::Error> {
+    let stale_before = Utc::now() - chrono::Duration::from_std(ttl)?;
+    let mut transaction = pool.begin().await?;
+    let stale_subscriber_ids = sqlx::query!(
+        r#"
+        SELECT id
+        FROM subscriptions s
+        WHERE
+            status = 'pending_confirmation' AND
+            subscribed_at < $1 AND
+            NOT EXISTS (
+                SELECT 1
+                FROM subscription_tokens t
+                WHERE t.subscriber_id = s.id AND t.created_at >= $1
+            )
+        FOR UPDATE
+        "#,
+        stale_before
+    )
+    .fetch_all(&mut *transaction)
+    .await?
+    .into_iter()
+    .map(|r| r.id)
+    .collect::<Vec<_>>();
+
+    sqlx::query!(
+        "DELETE FROM subscription_tokens WHERE subscriber_id = ANY($1)",
+        &stale_subscriber_ids
+    )
+    .execute(&mut *transaction)
+    .await?;
+    let n_deleted = sqlx::query!(
+        "DELETE FROM subscriptions WHERE id = ANY($1)",
+        &stale_subscriber_ids
+    )
+    .execute(&mut *transaction)
+    .await?
+    .rows_affected();
+    transaction.commit().await?;
+
+    if n_deleted > 0 {
+        tracing::info!(n_deleted, "Deleted stale pending subscriptions");
+    }
+    Ok(n_deleted)
+}
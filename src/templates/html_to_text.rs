@@ -0,0 +1,193 @@
+use htmlescape::decode_html;
+
+/// Elements whose content is not meant to be read.
+const SKIPPED_ELEMENTS: [&str; 4] = ["head", "script", "style", "title"];
+/// Elements rendered as a paragraph of their own.
+const BLOCK_ELEMENTS: [&str; 14] = [
+    "p",
+    "div",
+    "h1",
+    "h2",
+    "h3",
+    "h4",
+    "h5",
+    "h6",
+    "ul",
+    "ol",
+    "table",
+    "tr",
+    "blockquote",
+    "hr",
+];
+
+/// Generate the plain text version of an HTML email.
+///
+/// Tags are dropped, block elements are separated by a blank line and links
+/// are written as `label (url)` so that they survive the conversion.
+pub fn html_to_text(html: &str) -> String {
+    let mut output = String::new();
+    let mut link_href: Option<String> = None;
+    let mut link_start = 0;
+    let mut rest = html;
+
+    while !rest.is_empty() {
+        let Some(start) = rest.find('<') else {
+            push_text(&mut output, rest);
+            break;
+        };
+        push_text(&mut output, &rest[..start]);
+        let Some(end) = rest[start..].find('>') else {
+            // Not a tag after all
+            push_text(&mut output, &rest[start..]);
+            break;
+        };
+        let tag = &rest[start + 1..start + end];
+        rest = &rest[start + end + 1..];
+
+        let is_closing = tag.starts_with('/');
+        let name = tag
+            .trim_start_matches('/')
+            .split(|c: char| c.is_whitespace() || c == '/')
+            .next()
+            .unwrap_or_default()
+            .to_ascii_lowercase();
+
+        if !is_closing && SKIPPED_ELEMENTS.contains(&name.as_str()) {
+            let closing_tag = format!("</{}", name);
+            rest = match rest.to_ascii_lowercase().find(&closing_tag) {
+                Some(position) => rest[position..].split_once('>').map_or("", |(_, r)| r),
+                None => "",
+            };
+            continue;
+        }
+        match name.as_str() {
+            "br" => push_line_break(&mut output, 1),
+            "li" if !is_closing => {
+                push_line_break(&mut output, 1);
+                output.push_str("- ");
+            }
+            "a" if !is_closing => {
+                link_href = attribute(tag, "href");
+                link_start = output.len();
+            }
+            "a" => {
+                if let Some(href) = link_href.take() {
+                    let label = output[link_start..].trim();
+                    if label.is_empty() {
+                        output.push_str(&href);
+                    } else if label != href {
+                        output.push_str(&format!(" ({})", href));
+                    }
+                }
+            }
+            name if BLOCK_ELEMENTS.contains(&name) => push_line_break(&mut output, 2),
+            _ => {}
+        }
+    }
+
+    output.trim().to_owned()
+}
+
+/// Append text content, collapsing whitespace as a browser would.
+fn push_text(output: &mut String, text: &str) {
+    let text = decode_html(text).unwrap_or_else(|_| text.to_owned());
+    for (i, word) in text.split_whitespace().enumerate() {
+        let starts_with_space = i > 0 || text.starts_with(char::is_whitespace);
+        if starts_with_space && !output.is_empty() && !output.ends_with([' ', '\n']) {
+            output.push(' ');
+        }
+        output.push_str(word);
+    }
+    if text.ends_with(char::is_whitespace) && !output.is_empty() && !output.ends_with([' ', '\n']) {
+        output.push(' ');
+    }
+}
+
+/// Make sure the output ends with (at least) `n` line breaks.
+fn push_line_break(output: &mut String, n: usize) {
+    let trimmed_len = output.trim_end_matches(' ').len();
+    output.truncate(trimmed_len);
+    if output.is_empty() {
+        return;
+    }
+    let n_trailing = output.len() - output.trim_end_matches('\n').len();
+    for _ in n_trailing..n {
+        output.push('\n');
+    }
+}
+
+/// Extract the value of an attribute from the inside of a tag.
+fn attribute(tag: &str, name: &str) -> Option<String> {
+    let lowercase_tag = tag.to_ascii_lowercase();
+    let position = lowercase_tag.find(&format!("{}=", name))?;
+    let value = &tag[position + name.len() + 1..];
+    let value = match value.chars().next()? {
+        quote @ ('"' | '\'') => value[1..].split(quote).next()?,
+        _ => value.split(|c: char| c.is_whitespace()).next()?,
+    };
+    Some(decode_html(value).unwrap_or_else(|_| value.to_owned()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::html_to_text;
+
+    #[test]
+    fn tags_are_removed() {
+        assert_eq!(
+            html_to_text("<p>Hello <strong>Ursula</strong>!</p>"),
+            "Hello Ursula!"
+        );
+    }
+
+    #[test]
+    fn paragraphs_and_line_breaks_are_preserved() {
+        assert_eq!(
+            html_to_text("<h1>Title</h1><p>First line<br/>Second line</p><p>Another paragraph</p>"),
+            "Title\n\nFirst line\nSecond line\n\nAnother paragraph"
+        );
+    }
+
+    #[test]
+    fn whitespace_is_collapsed() {
+        assert_eq!(
+            html_to_text("<p>\n    Hello\n    world   !\n</p>"),
+            "Hello world !"
+        );
+    }
+
+    #[test]
+    fn links_keep_their_url() {
+        assert_eq!(
+            html_to_text(r#"Click <a href="https://example.com/confirm?a=1&amp;b=2">here</a>."#),
+            "Click here (https://example.com/confirm?a=1&b=2)."
+        );
+        assert_eq!(
+            html_to_text(r#"<a href="https://example.com">https://example.com</a>"#),
+            "https://example.com"
+        );
+    }
+
+    #[test]
+    fn entities_are_decoded() {
+        assert_eq!(
+            html_to_text("<p>Fish &amp; chips &lt;3</p>"),
+            "Fish & chips <3"
+        );
+    }
+
+    #[test]
+    fn list_items_are_bulleted() {
+        assert_eq!(
+            html_to_text("<ul><li>One</li><li>Two</li></ul>"),
+            "- One\n- Two"
+        );
+    }
+
+    #[test]
+    fn head_scripts_and_styles_are_skipped() {
+        let html = r#"<html><head><title>Title</title><style>p { color: red; }</style></head>
+            <body><script>alert(1)</script><p>Body</p></body></html>"#;
+        assert_eq!(html_to_text(html), "Body");
+    }
+}
@@ -0,0 +1,49 @@
+use super::{html_to_text, Format, RenderedEmail, Template, TemplateError, Templates};
+
+/// The content of a newsletter issue, ready to be personalised for each subscriber.
+pub struct IssueTemplate {
+    html: Template,
+    /// `None` when the issue has no plain text content: it is then generated from the HTML.
+    text: Option<Template>,
+}
+
+impl IssueTemplate {
+    pub fn new(text_content: &str, html_content: &str) -> Self {
+        // Issues are validated when they are stored, but older issues
+        // might not be valid templates: they are then sent as they are.
+        let parse = |source: &str, format| {
+            Template::parse(source, format).unwrap_or_else(|_| Template::literal(source))
+        };
+        let text = if text_content.trim().is_empty() {
+            None
+        } else {
+            Some(parse(text_content, Format::Text))
+        };
+
+        Self {
+            html: parse(html_content, Format::Html),
+            text,
+        }
+    }
+
+    /// Render the issue with the given variables, wrapped in the `newsletter` email.
+    pub fn render_email(
+        &self,
+        templates: &Templates,
+        variables: &[(&str, &str)],
+    ) -> Result<RenderedEmail, TemplateError> {
+        let content = self.html.render(variables);
+        let text_content = match &self.text {
+            Some(text) => text.render(variables),
+            None => html_to_text(&content),
+        };
+        templates.render(
+            "newsletter",
+            &[
+                variables,
+                &[("content", &content), ("text_content", &text_content)],
+            ]
+            .concat(),
+        )
+    }
+}
@@ -0,0 +1,261 @@
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+
+mod html_to_text;
+mod issue;
+mod template;
+
+pub use html_to_text::html_to_text;
+pub use issue::IssueTemplate;
+pub use template::{Format, Template};
+
+#[derive(thiserror::Error, Debug)]
+pub enum TemplateError {
+    #[error("Invalid template: {0}")]
+    InvalidSyntax(String),
+    #[error("Failed to read the template at {}", .0.display())]
+    Io(PathBuf, #[source] std::io::Error),
+    #[error("There is no template named {0}")]
+    UnknownTemplate(String),
+    #[error("The template {template} uses an unknown layout, {layout}")]
+    UnknownLayout { template: String, layout: String },
+}
+
+/// The email templates, loaded from a directory at startup.
+///
+/// The directory contains one `<name>.html` file per template, with an
+/// optional `<name>.txt` plain text version. When there is no plain text
+/// version, it is generated from the rendered HTML.
+///
+/// An HTML template can be wrapped in one of the layouts of the `layouts`
+/// subdirectory by starting with `{{layout <layout name>}}`: the rendered
+/// template is then available to the layout as the `content` variable.
+#[derive(Debug)]
+pub struct Templates {
+    templates: HashMap<String, EmailTemplate>,
+    layouts: HashMap<String, Template>,
+}
+
+#[derive(Debug)]
+struct EmailTemplate {
+    layout: Option<String>,
+    html: Template,
+    text: Option<Template>,
+}
+
+/// Both versions of the body of an email.
+#[derive(Debug)]
+pub struct RenderedEmail {
+    pub html: String,
+    pub text: String,
+}
+
+impl Templates {
+    pub fn load(directory: impl AsRef<Path>) -> Result<Self, TemplateError> {
+        let directory = directory.as_ref();
+        let mut layouts = HashMap::new();
+        for (name, path) in html_files(&directory.join("layouts"))? {
+            let layout = Template::parse(&read(&path)?, Format::Html)?;
+            layouts.insert(name, layout);
+        }
+
+        let mut templates = HashMap::new();
+        for (name, path) in html_files(directory)? {
+            let source = read(&path)?;
+            let (layout, source) = match source.strip_prefix("{{layout ") {
+                Some(rest) => {
+                    let (layout, source) = rest.split_once("}}").ok_or_else(|| {
+                        TemplateError::InvalidSyntax(format!(
+                            "the layout of {} is never closed",
+                            name
+                        ))
+                    })?;
+                    let layout = layout.trim().to_owned();
+                    if !layouts.contains_key(&layout) {
+                        return Err(TemplateError::UnknownLayout {
+                            template: name,
+                            layout,
+                        });
+                    }
+                    (Some(layout), source.trim_start())
+                }
+                None => (None, source.as_str()),
+            };
+            let html = Template::parse(source, Format::Html)?;
+            let text_path = path.with_extension("txt");
+            let text = if text_path.exists() {
+                Some(Template::parse(&read(&text_path)?, Format::Text)?)
+            } else {
+                None
+            };
+            templates.insert(name, EmailTemplate { layout, html, text });
+        }
+
+        Ok(Self { templates, layouts })
+    }
+
+    /// Render both versions of the email body, with the given variables.
+    pub fn render(
+        &self,
+        name: &str,
+        variables: &[(&str, &str)],
+    ) -> Result<RenderedEmail, TemplateError> {
+        let template = self
+            .templates
+            .get(name)
+            .ok_or_else(|| TemplateError::UnknownTemplate(name.to_owned()))?;
+
+        let mut html = template.html.render(variables);
+        if let Some(layout) = &template.layout {
+            let mut layout_variables = variables.to_vec();
+            layout_variables.push(("content", &html));
+            html = self.layouts[layout].render(&layout_variables);
+        }
+        let text = match &template.text {
+            Some(text) => text.render(variables),
+            None => html_to_text(&html),
+        };
+
+        Ok(RenderedEmail { html, text })
+    }
+}
+
+/// The `.html` files of a directory, by name.
+fn html_files(directory: &Path) -> Result<Vec<(String, PathBuf)>, TemplateError> {
+    let entries =
+        std::fs::read_dir(directory).map_err(|e| TemplateError::Io(directory.to_owned(), e))?;
+    let mut files = Vec::new();
+    for entry in entries {
+        let path = entry
+            .map_err(|e| TemplateError::Io(directory.to_owned(), e))?
+            .path();
+        if path
+            .extension()
+            .is_some_and(|extension| extension == "html")
+        {
+            if let Some(name) = path.file_stem().and_then(|stem| stem.to_str()) {
+                files.push((name.to_owned(), path.clone()));
+            }
+        }
+    }
+    Ok(files)
+}
+
+fn read(path: &Path) -> Result<String, TemplateError> {
+    std::fs::read_to_string(path).map_err(|e| TemplateError::Io(path.to_owned(), e))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{TemplateError, Templates};
+    use claims::{assert_err, assert_matches};
+    use std::path::PathBuf;
+    use uuid::Uuid;
+
+    fn templates_directory(files: &[(&str, &str)]) -> PathBuf {
+        let directory = std::env::temp_dir().join(Uuid::new_v4().to_string());
+        std::fs::create_dir_all(directory.join("layouts")).unwrap();
+        for (path, content) in files {
+            std::fs::write(directory.join(path), content).unwrap();
+        }
+        directory
+    }
+
+    #[test]
+    fn templates_are_wrapped_in_their_layout() {
+        let directory = templates_directory(&[
+            (
+                "layouts/email.html",
+                "<html><title>{{title}}</title><body>{{{content}}}</body></html>",
+            ),
+            ("welcome.html", "{{layout email}}\n<p>Hi {{name}}</p>"),
+        ]);
+        let templates = Templates::load(&directory).unwrap();
+
+        let email = templates
+            .render("welcome", &[("title", "Welcome"), ("name", "<Ursula>")])
+            .unwrap();
+
+        assert_eq!(
+            email.html,
+            "<html><title>Welcome</title><body><p>Hi &lt;Ursula&gt;</p></body></html>"
+        );
+        std::fs::remove_dir_all(directory).unwrap();
+    }
+
+    #[test]
+    fn the_text_version_is_used_when_available() {
+        let directory = templates_directory(&[
+            ("welcome.html", "<p>Hi {{name}}</p>"),
+            ("welcome.txt", "Hello {{name}}"),
+        ]);
+        let templates = Templates::load(&directory).unwrap();
+
+        let email = templates
+            .render("welcome", &[("name", "<Ursula>")])
+            .unwrap();
+
+        assert_eq!(email.text, "Hello <Ursula>");
+        std::fs::remove_dir_all(directory).unwrap();
+    }
+
+    #[test]
+    fn the_text_version_is_generated_from_html_when_absent() {
+        let directory = templates_directory(&[
+            (
+                "layouts/email.html",
+                "<head><title>{{title}}</title></head>{{{content}}}",
+            ),
+            (
+                "welcome.html",
+                "{{layout email}}<p>Hi {{name}}</p><p><a href=\"{{url}}\">Confirm</a></p>",
+            ),
+        ]);
+        let templates = Templates::load(&directory).unwrap();
+
+        let email = templates
+            .render(
+                "welcome",
+                &[
+                    ("title", "Welcome"),
+                    ("name", "Ursula & co"),
+                    ("url", "https://example.com"),
+                ],
+            )
+            .unwrap();
+
+        assert_eq!(
+            email.text,
+            "Hi Ursula & co\n\nConfirm (https://example.com)"
+        );
+        std::fs::remove_dir_all(directory).unwrap();
+    }
+
+    #[test]
+    fn unknown_layouts_are_rejected_at_load_time() {
+        let directory = templates_directory(&[("welcome.html", "{{layout missing}}<p>Hi</p>")]);
+
+        let error = assert_err!(Templates::load(&directory));
+
+        assert_matches!(error, TemplateError::UnknownLayout { .. });
+        std::fs::remove_dir_all(directory).unwrap();
+    }
+
+    #[test]
+    fn rendering_an_unknown_template_fails() {
+        let directory = templates_directory(&[]);
+        let templates = Templates::load(&directory).unwrap();
+
+        assert_err!(templates.render("missing", &[]));
+        std::fs::remove_dir_all(directory).unwrap();
+    }
+
+    #[test]
+    fn the_bundled_templates_can_be_loaded() {
+        let templates = Templates::load("templates").unwrap();
+
+        for name in ["confirmation", "newsletter"] {
+            templates.render(name, &[]).unwrap();
+        }
+    }
+}
@@ -0,0 +1,201 @@
+use crate::templates::TemplateError;
+use htmlescape::encode_minimal;
+
+/// How variables are written out when rendering a template.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Format {
+    /// `{{name}}` is HTML-escaped, `{{{name}}}` is written as is.
+    Html,
+    /// Variables are always written as is.
+    Text,
+}
+
+/// A parsed template.
+///
+/// `{{name}}` is replaced with the value of the `name` variable, escaped
+/// according to the template format. `{{{name}}}` is never escaped: use it
+/// for content that is already HTML (e.g. the body of a newsletter issue).
+/// Variables without a value are rendered as an empty string.
+///
+/// A backslash escapes the braces: `\{{name}}` is rendered as `{{name}}`.
+#[derive(Debug, Clone)]
+pub struct Template {
+    segments: Vec<Segment>,
+}
+
+#[derive(Debug, Clone)]
+enum Segment {
+    Literal(String),
+    Variable { name: String, escape: bool },
+}
+
+impl Template {
+    pub fn parse(source: &str, format: Format) -> Result<Self, TemplateError> {
+        let mut segments = Vec::new();
+        let mut rest = source;
+        while let Some(start) = rest.find("{{") {
+            if let Some(before) = rest[..start].strip_suffix('\\') {
+                segments.push(Segment::Literal(format!("{}{{{{", before)));
+                rest = &rest[start + 2..];
+                continue;
+            }
+            if start > 0 {
+                segments.push(Segment::Literal(rest[..start].to_owned()));
+            }
+            let (raw, tag) = if rest[start..].starts_with("{{{") {
+                (true, &rest[start + 3..])
+            } else {
+                (false, &rest[start + 2..])
+            };
+            let closing = if raw { "}}}" } else { "}}" };
+            let end = tag.find(closing).ok_or_else(|| {
+                TemplateError::InvalidSyntax(format!("`{}` is never closed", &rest[start..]))
+            })?;
+            let name = tag[..end].trim();
+            if !is_valid_variable_name(name) {
+                return Err(TemplateError::InvalidSyntax(format!(
+                    "`{}` is not a valid variable name",
+                    name
+                )));
+            }
+            segments.push(Segment::Variable {
+                name: name.to_owned(),
+                escape: !raw && format == Format::Html,
+            });
+            rest = &tag[end + closing.len()..];
+        }
+        if !rest.is_empty() {
+            segments.push(Segment::Literal(rest.to_owned()));
+        }
+
+        Ok(Self { segments })
+    }
+
+    /// A template rendering `source` as it is, without looking for variables.
+    pub fn literal(source: &str) -> Self {
+        Self {
+            segments: vec![Segment::Literal(source.to_owned())],
+        }
+    }
+
+    pub fn render(&self, variables: &[(&str, &str)]) -> String {
+        let mut output = String::new();
+        for segment in &self.segments {
+            match segment {
+                Segment::Literal(literal) => output.push_str(literal),
+                Segment::Variable { name, escape } => {
+                    let value = variables
+                        .iter()
+                        .rev()
+                        .find(|(n, _)| n == name)
+                        .map(|(_, v)| *v)
+                        .unwrap_or_default();
+                    if *escape {
+                        output.push_str(&encode_minimal(value));
+                    } else {
+                        output.push_str(value);
+                    }
+                }
+            }
+        }
+        output
+    }
+}
+
+fn is_valid_variable_name(name: &str) -> bool {
+    let mut chars = name.chars();
+    chars
+        .next()
+        .is_some_and(|c| c.is_ascii_alphabetic() || c == '_')
+        && chars.all(|c| c.is_ascii_alphanumeric() || c == '_')
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Format, Template};
+    use claims::assert_err;
+    use htmlescape::encode_minimal;
+
+    #[test]
+    fn variables_are_replaced_with_their_value() {
+        let template = Template::parse("Hello {{ name }}, welcome!", Format::Html).unwrap();
+        assert_eq!(
+            template.render(&[("name", "Ursula")]),
+            "Hello Ursula, welcome!"
+        );
+    }
+
+    #[test]
+    fn variables_without_a_value_are_rendered_as_empty() {
+        let template = Template::parse("Hello {{name}}!", Format::Html).unwrap();
+        assert_eq!(template.render(&[]), "Hello !");
+    }
+
+    #[test]
+    fn html_variables_are_escaped() {
+        let name = r#"<script>alert("Ursula & 'friends'")</script>"#;
+        let template = Template::parse("<p>{{name}}</p>", Format::Html).unwrap();
+        assert_eq!(
+            template.render(&[("name", name)]),
+            format!("<p>{}</p>", encode_minimal(name))
+        );
+    }
+
+    #[test]
+    fn escaped_variables_cannot_break_out_of_a_quoted_attribute() {
+        let url = r#"https://example.com/" onclick="alert(1)"#;
+        let template = Template::parse(r#"<a href="{{url}}">Link</a>"#, Format::Html).unwrap();
+        let rendered = template.render(&[("url", url)]);
+        assert_eq!(
+            rendered,
+            format!(r#"<a href="{}">Link</a>"#, encode_minimal(url))
+        );
+        assert!(!rendered.contains(r#"" onclick"#));
+    }
+
+    #[test]
+    fn triple_braces_are_not_escaped() {
+        let content = "<p>Newsletter body as HTML</p>";
+        let template = Template::parse("<div>{{{content}}}</div>", Format::Html).unwrap();
+        assert_eq!(
+            template.render(&[("content", content)]),
+            "<div><p>Newsletter body as HTML</p></div>"
+        );
+    }
+
+    #[test]
+    fn text_variables_are_not_escaped() {
+        let template = Template::parse("Hello {{name}}", Format::Text).unwrap();
+        assert_eq!(
+            template.render(&[("name", "Ursula & <friends>")]),
+            "Hello Ursula & <friends>"
+        );
+    }
+
+    #[test]
+    fn unclosed_tags_are_rejected() {
+        assert_err!(Template::parse("Hello {{name", Format::Html));
+        assert_err!(Template::parse("Hello {{{name}}", Format::Html));
+    }
+
+    #[test]
+    fn escaped_braces_are_rendered_as_they_are() {
+        let template = Template::parse(
+            r"Write \{{name}} for the name of {{name}}, \{{{name}}} for raw HTML",
+            Format::Html,
+        )
+        .unwrap();
+        assert_eq!(
+            template.render(&[("name", "Ursula")]),
+            "Write {{name}} for the name of Ursula, {{{name}}} for raw HTML"
+        );
+        assert!(Template::parse(r"if (a) \{{ return {b: 1}; }}", Format::Text).is_ok());
+    }
+
+    #[test]
+    fn invalid_variable_names_are_rejected() {
+        assert_err!(Template::parse("Hello {{}}", Format::Html));
+        assert_err!(Template::parse("Hello {{first name}}", Format::Html));
+        assert_err!(Template::parse("Hello {{1name}}", Format::Html));
+    }
+}
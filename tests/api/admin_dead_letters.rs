@@ -0,0 +1,127 @@
+use crate::helpers::{assert_is_redirect_to, spawn_app, TestApp};
+use uuid::Uuid;
+
+#[actix_web::test]
+async fn you_must_be_logged_in_to_see_failed_deliveries() {
+    // Arrange
+    let app = spawn_app().await;
+
+    // Act
+    let response = app.get_admin_dead_letters().await;
+
+    // Assert
+    assert_is_redirect_to(&response, "/login");
+}
+
+#[actix_web::test]
+async fn you_must_be_logged_in_to_redrive_failed_deliveries() {
+    // Arrange
+    let app = spawn_app().await;
+    insert_dead_letter(&app, "ursula_le_guin@gmail.com").await;
+
+    // Act
+    let response = app.post_redrive_dead_letters(&serde_json::json!({})).await;
+
+    // Assert
+    assert_is_redirect_to(&response, "/login");
+    assert_eq!(count_queued_tasks(&app).await, 0);
+}
+
+#[actix_web::test]
+async fn failed_deliveries_are_listed() {
+    // Arrange
+    let app = spawn_app().await;
+    insert_dead_letter(&app, "ursula_le_guin@gmail.com").await;
+    app.test_user.login(&app).await;
+
+    // Act
+    let html_page = app.get_admin_dead_letters_html().await;
+
+    // Assert
+    assert!(html_page.contains("ursula_le_guin@gmail.com"));
+    assert!(html_page.contains("Connection reset by peer"));
+}
+
+#[actix_web::test]
+async fn a_single_failed_delivery_can_be_redriven() {
+    // Arrange
+    let app = spawn_app().await;
+    let issue_id = insert_dead_letter(&app, "ursula_le_guin@gmail.com").await;
+    insert_dead_letter(&app, "octavia_butler@gmail.com").await;
+    app.test_user.login(&app).await;
+
+    // Act - Part 1 - Re-drive
+    let response = app
+        .post_redrive_dead_letters(&serde_json::json!({
+            "newsletter_issue_id": issue_id.to_string(),
+            "subscriber_email": "ursula_le_guin@gmail.com",
+        }))
+        .await;
+    assert_is_redirect_to(&response, "/admin/dead_letters");
+
+    // Act - Part 2 - Follow the redirect
+    let html_page = app.get_admin_dead_letters_html().await;
+    assert!(html_page.contains("<p><i>1 failed deliveries have been re-queued.</i></p>"));
+
+    // Assert
+    assert_eq!(count_queued_tasks(&app).await, 1);
+    assert!(!html_page.contains("ursula_le_guin@gmail.com"));
+    assert!(html_page.contains("octavia_butler@gmail.com"));
+}
+
+#[actix_web::test]
+async fn all_failed_deliveries_can_be_redriven() {
+    // Arrange
+    let app = spawn_app().await;
+    insert_dead_letter(&app, "ursula_le_guin@gmail.com").await;
+    insert_dead_letter(&app, "octavia_butler@gmail.com").await;
+    app.test_user.login(&app).await;
+
+    // Act
+    let response = app.post_redrive_dead_letters(&serde_json::json!({})).await;
+
+    // Assert
+    assert_is_redirect_to(&response, "/admin/dead_letters");
+    assert_eq!(count_queued_tasks(&app).await, 2);
+}
+
+async fn insert_dead_letter(app: &TestApp, subscriber_email: &str) -> Uuid {
+    let issue_id = Uuid::new_v4();
+    sqlx::query!(
+        r#"
+        INSERT INTO newsletter_issues (
+            newsletter_issue_id, title, text_content, html_content, published_at, slug,
+            status, created_at
+        )
+        VALUES ($1, 'Newsletter title', 'text', '<p>html</p>', now(), $2, 'published', now())
+        "#,
+        issue_id,
+        issue_id.to_string()
+    )
+    .execute(&app.db_pool)
+    .await
+    .unwrap();
+    sqlx::query!(
+        r#"
+        INSERT INTO issue_delivery_dead_letters (
+            newsletter_issue_id, subscriber_email, n_attempts, last_error, failed_at
+        )
+        VALUES ($1, $2, 5, 'Connection reset by peer', now())
+        "#,
+        issue_id,
+        subscriber_email
+    )
+    .execute(&app.db_pool)
+    .await
+    .unwrap();
+
+    issue_id
+}
+
+async fn count_queued_tasks(app: &TestApp) -> i64 {
+    sqlx::query!(r#"SELECT COUNT(*) as "count!" FROM issue_delivery_queue"#)
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap()
+        .count
+}
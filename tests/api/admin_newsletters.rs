@@ -0,0 +1,641 @@
+use crate::helpers::{
+    assert_is_redirect_to, create_confirmed_subscriber, create_unconfirmed_subscriber, spawn_app,
+    TestApp,
+};
+use uuid::Uuid;
+use wiremock::matchers::{any, method, path};
+use wiremock::{Mock, ResponseTemplate};
+use zero2prod::issue_scheduler::publish_due_issues;
+
+/// Submit the new issue form and return the id of the draft it created.
+async fn create_draft(app: &TestApp, newsletter_request_body: &serde_json::Value) -> Uuid {
+    let response = app.post_publish_newsletter(newsletter_request_body).await;
+    assert_eq!(response.status().as_u16(), 303);
+    let location = response
+        .headers()
+        .get("Location")
+        .unwrap()
+        .to_str()
+        .unwrap();
+    location
+        .strip_prefix("/admin/newsletters/")
+        .expect("Not redirected to the page of the draft")
+        .parse()
+        .unwrap()
+}
+
+fn newsletter_request_body() -> serde_json::Value {
+    serde_json::json!({
+        "title": "Newsletter title",
+        "text_content": "Newsletter body as plain text",
+        "html_content": "<p>Newsletter body as HTML</p>",
+        "idempotency_key": uuid::Uuid::new_v4().to_string()
+    })
+}
+
+async fn get_issue_status(app: &TestApp, newsletter_issue_id: Uuid) -> String {
+    sqlx::query_scalar!(
+        "SELECT status FROM newsletter_issues WHERE newsletter_issue_id = $1",
+        newsletter_issue_id
+    )
+    .fetch_one(&app.db_pool)
+    .await
+    .unwrap()
+}
+
+#[actix_web::test]
+async fn you_must_be_logged_in_to_see_the_newsletter_form() {
+    // Arrange
+    let app = spawn_app().await;
+
+    // Act
+    let response = app.get_publish_newsletter().await;
+
+    // Assert
+    assert_is_redirect_to(&response, "/login");
+}
+
+#[actix_web::test]
+async fn you_must_be_logged_in_to_publish_a_newsletter() {
+    // Arrange
+    let app = spawn_app().await;
+
+    // Act
+    let response = app
+        .post_publish_newsletter(&newsletter_request_body())
+        .await;
+
+    // Assert
+    assert_is_redirect_to(&response, "/login");
+}
+
+#[actix_web::test]
+async fn you_must_be_logged_in_to_manage_an_issue() {
+    // Arrange
+    let app = spawn_app().await;
+    let newsletter_issue_id = Uuid::new_v4();
+
+    for action in ["", "schedule", "publish", "cancel"] {
+        // Act
+        let response = app
+            .post_admin_newsletter_action(newsletter_issue_id, action, &newsletter_request_body())
+            .await;
+
+        // Assert
+        assert_is_redirect_to(&response, "/login");
+    }
+    let response = app.get_newsletter_preview(newsletter_issue_id, "").await;
+    assert_is_redirect_to(&response, "/login");
+}
+
+#[actix_web::test]
+async fn drafts_are_not_delivered() {
+    // Arrange
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+    app.test_user.login(&app).await;
+
+    Mock::given(any())
+        .respond_with(ResponseTemplate::new(200))
+        .expect(0)
+        .mount(&app.email_server)
+        .await;
+
+    // Act
+    let newsletter_issue_id = create_draft(&app, &newsletter_request_body()).await;
+
+    // Assert
+    let html_page = app.get_admin_newsletter_html(newsletter_issue_id).await;
+    assert!(html_page.contains("<p><i>The draft has been saved.</i></p>"));
+    assert!(html_page.contains("<p>Status: draft</p>"));
+    let html_page = app.get_publish_newsletter_html().await;
+    assert!(html_page.contains(&format!(
+        r#"<a href="/admin/newsletters/{}">Newsletter title</a></td><td>draft</td>"#,
+        newsletter_issue_id
+    )));
+    assert!(!app.get_issues_html().await.contains("Newsletter title"));
+    assert_eq!(publish_due_issues(&app.db_pool).await.unwrap(), 0);
+    app.dispatch_all_pending_emails().await;
+    // Mock verifies on Drop that we haven't sent the newsletter email
+}
+
+#[actix_web::test]
+async fn newsletters_are_not_delivered_to_unconfirmed_subscribers() {
+    // Arrange
+    let app = spawn_app().await;
+    create_unconfirmed_subscriber(&app).await;
+    app.test_user.login(&app).await;
+
+    Mock::given(any())
+        .respond_with(ResponseTemplate::new(200))
+        .expect(0)
+        .mount(&app.email_server)
+        .await;
+
+    // Act - Part 1 - Create a draft and send it
+    let newsletter_issue_id = create_draft(&app, &newsletter_request_body()).await;
+    let response = app
+        .post_admin_newsletter_action(newsletter_issue_id, "publish", &())
+        .await;
+    assert_is_redirect_to(
+        &response,
+        &format!("/admin/newsletters/{}", newsletter_issue_id),
+    );
+
+    // Act - Part 2 - Follow the redirect
+    let html_page = app.get_admin_newsletter_html(newsletter_issue_id).await;
+    assert!(html_page.contains(
+        "<p><i>The newsletter issue has been accepted - emails will go out shortly.</i></p>"
+    ));
+    app.dispatch_all_pending_emails().await;
+    // Mock verifies on Drop that we haven't sent the newsletter email
+}
+
+#[actix_web::test]
+async fn newsletters_are_delivered_to_confirmed_subscribers() {
+    // Arrange
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+    app.test_user.login(&app).await;
+
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    // Act - Part 1 - Create a draft and send it
+    let newsletter_issue_id = create_draft(&app, &newsletter_request_body()).await;
+    let response = app
+        .post_admin_newsletter_action(newsletter_issue_id, "publish", &())
+        .await;
+    assert_is_redirect_to(
+        &response,
+        &format!("/admin/newsletters/{}", newsletter_issue_id),
+    );
+
+    // Act - Part 2 - Follow the redirect
+    let html_page = app.get_admin_newsletter_html(newsletter_issue_id).await;
+    assert!(html_page.contains(
+        "<p><i>The newsletter issue has been accepted - emails will go out shortly.</i></p>"
+    ));
+    assert!(html_page.contains("<p>Status: published</p>"));
+    app.dispatch_all_pending_emails().await;
+    // Mock verifies on Drop that we have sent the newsletter email
+
+    // Assert - The delivery shows up in the statistics of the issue
+    let html_page = app.get_admin_newsletter_html(newsletter_issue_id).await;
+    assert!(
+        html_page.contains("<tr><td>1</td><td>1</td><td>0</td><td>0</td><td>0</td><td>0</td></tr>")
+    );
+}
+
+#[actix_web::test]
+async fn newsletter_form_submission_is_idempotent() {
+    // Arrange
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+    app.test_user.login(&app).await;
+
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    // Act - Part 1 - Submit newsletter form
+    // We expect the idempotency key as part of the
+    // form data, not as an header
+    let newsletter_request_body = newsletter_request_body();
+    let newsletter_issue_id = create_draft(&app, &newsletter_request_body).await;
+
+    // Act - Part 2 - Submit newsletter form **again**
+    let response = app.post_publish_newsletter(&newsletter_request_body).await;
+    assert_is_redirect_to(
+        &response,
+        &format!("/admin/newsletters/{}", newsletter_issue_id),
+    );
+
+    // Act - Part 3 - Send the draft, twice
+    app.post_admin_newsletter_action(newsletter_issue_id, "publish", &())
+        .await;
+    app.post_admin_newsletter_action(newsletter_issue_id, "publish", &())
+        .await;
+
+    // Assert
+    let html_page = app.get_admin_newsletter_html(newsletter_issue_id).await;
+    assert!(html_page.contains("<p><i>This issue has already been published.</i></p>"));
+    let n_issues = sqlx::query_scalar!(r#"SELECT count(*) as "count!" FROM newsletter_issues"#)
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(n_issues, 1);
+    app.dispatch_all_pending_emails().await;
+    // Mock verifies on Drop that we have sent the newsletter email **once**
+}
+
+#[actix_web::test]
+async fn newsletter_form_rejects_an_invalid_idempotency_key() {
+    // Arrange
+    let app = spawn_app().await;
+    app.test_user.login(&app).await;
+
+    // Act
+    let newsletter_request_body = serde_json::json!({
+        "title": "Newsletter title",
+        "text_content": "Newsletter body as plain text",
+        "html_content": "<p>Newsletter body as HTML</p>",
+        "idempotency_key": ""
+    });
+    let response = app.post_publish_newsletter(&newsletter_request_body).await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 400);
+}
+
+#[actix_web::test]
+async fn drafts_can_be_edited() {
+    // Arrange
+    let app = spawn_app().await;
+    app.test_user.login(&app).await;
+    let newsletter_issue_id = create_draft(&app, &newsletter_request_body()).await;
+
+    // Act
+    let response = app
+        .post_admin_newsletter_action(
+            newsletter_issue_id,
+            "",
+            &serde_json::json!({
+                "title": "A better title",
+                "text_content": "",
+                "html_content": "<p>Hi {{name}} & welcome</p>",
+            }),
+        )
+        .await;
+    assert_is_redirect_to(
+        &response,
+        &format!("/admin/newsletters/{}", newsletter_issue_id),
+    );
+
+    // Assert
+    let html_page = app.get_admin_newsletter_html(newsletter_issue_id).await;
+    assert!(html_page.contains("<p><i>The draft has been saved.</i></p>"));
+    assert!(html_page.contains("<h1>A better title</h1>"));
+    // The content is escaped in the form
+    assert!(html_page.contains("&lt;p&gt;Hi {{name}} &amp; welcome&lt;/p&gt;"));
+}
+
+#[actix_web::test]
+async fn published_issues_cannot_be_edited() {
+    // Arrange
+    let app = spawn_app().await;
+    app.test_user.login(&app).await;
+    let newsletter_issue_id = create_draft(&app, &newsletter_request_body()).await;
+    app.post_admin_newsletter_action(newsletter_issue_id, "publish", &())
+        .await;
+
+    // Act
+    app.post_admin_newsletter_action(
+        newsletter_issue_id,
+        "",
+        &serde_json::json!({
+            "title": "A better title",
+            "text_content": "",
+            "html_content": "<p>New content</p>",
+        }),
+    )
+    .await;
+
+    // Assert
+    let html_page = app.get_admin_newsletter_html(newsletter_issue_id).await;
+    assert!(html_page.contains("<p><i>Only drafts can be edited.</i></p>"));
+    assert!(html_page.contains("<h1>Newsletter title</h1>"));
+}
+
+#[actix_web::test]
+async fn the_preview_shows_the_email_personalised_for_the_admin() {
+    // Arrange
+    let app = spawn_app().await;
+    app.test_user.login(&app).await;
+    let newsletter_issue_id = create_draft(
+        &app,
+        &serde_json::json!({
+            "title": "Newsletter title",
+            "text_content": "",
+            "html_content": "<p>Hello {{name}}!</p>",
+            "idempotency_key": uuid::Uuid::new_v4().to_string()
+        }),
+    )
+    .await;
+
+    // Act
+    let html = app
+        .get_newsletter_preview(newsletter_issue_id, "")
+        .await
+        .text()
+        .await
+        .unwrap();
+    let text = app
+        .get_newsletter_preview(newsletter_issue_id, "format=text")
+        .await
+        .text()
+        .await
+        .unwrap();
+
+    // Assert
+    let greeting = format!("Hello {}!", app.test_user.username);
+    assert!(html.contains(&format!("<p>{}</p>", greeting)));
+    assert!(html.contains("Unsubscribe"));
+    assert!(text.contains(&greeting));
+    assert!(!text.contains("<p>"));
+}
+
+#[actix_web::test]
+async fn scheduled_issues_are_delivered_once_their_time_has_come() {
+    // Arrange
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+    app.test_user.login(&app).await;
+    let newsletter_issue_id = create_draft(&app, &newsletter_request_body()).await;
+
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    // Act - Part 1 - Schedule the issue
+    let send_at = chrono::Utc::now() + chrono::Duration::days(1);
+    let response = app
+        .post_admin_newsletter_action(
+            newsletter_issue_id,
+            "schedule",
+            &serde_json::json!({ "send_at": send_at.format("%Y-%m-%dT%H:%M").to_string() }),
+        )
+        .await;
+    assert_is_redirect_to(
+        &response,
+        &format!("/admin/newsletters/{}", newsletter_issue_id),
+    );
+    let html_page = app.get_admin_newsletter_html(newsletter_issue_id).await;
+    assert!(html_page.contains(&format!(
+        "<p><i>The issue is scheduled to go out on {}.</i></p>",
+        send_at.format("%Y-%m-%d %H:%M UTC")
+    )));
+
+    // Act - Part 2 - Nothing is due yet
+    assert_eq!(publish_due_issues(&app.db_pool).await.unwrap(), 0);
+    assert_eq!(
+        get_issue_status(&app, newsletter_issue_id).await,
+        "scheduled"
+    );
+
+    // Act - Part 3 - Time flies
+    sqlx::query!(
+        "UPDATE newsletter_issues SET send_at = now() - interval '1 minute'
+        WHERE newsletter_issue_id = $1",
+        newsletter_issue_id
+    )
+    .execute(&app.db_pool)
+    .await
+    .unwrap();
+    assert_eq!(publish_due_issues(&app.db_pool).await.unwrap(), 1);
+
+    // Assert
+    assert_eq!(
+        get_issue_status(&app, newsletter_issue_id).await,
+        "published"
+    );
+    assert!(app.get_issues_html().await.contains("Newsletter title"));
+    app.dispatch_all_pending_emails().await;
+    // Mock verifies on Drop that we have sent the newsletter email
+}
+
+#[actix_web::test]
+async fn issues_cannot_be_scheduled_in_the_past() {
+    // Arrange
+    let app = spawn_app().await;
+    app.test_user.login(&app).await;
+    let newsletter_issue_id = create_draft(&app, &newsletter_request_body()).await;
+    let test_cases = vec![
+        (
+            "2020-01-01T10:00",
+            "An issue can only be scheduled in the future.",
+        ),
+        ("tomorrow", "The sending date is not valid."),
+    ];
+
+    for (send_at, error_message) in test_cases {
+        // Act
+        app.post_admin_newsletter_action(
+            newsletter_issue_id,
+            "schedule",
+            &serde_json::json!({ "send_at": send_at }),
+        )
+        .await;
+
+        // Assert
+        let html_page = app.get_admin_newsletter_html(newsletter_issue_id).await;
+        assert!(html_page.contains(&format!("<p><i>{}</i></p>", error_message)));
+        assert_eq!(get_issue_status(&app, newsletter_issue_id).await, "draft");
+    }
+}
+
+#[actix_web::test]
+async fn cancelled_issues_are_not_delivered() {
+    // Arrange
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+    app.test_user.login(&app).await;
+    let newsletter_issue_id = create_draft(&app, &newsletter_request_body()).await;
+    let send_at = chrono::Utc::now() + chrono::Duration::days(1);
+    app.post_admin_newsletter_action(
+        newsletter_issue_id,
+        "schedule",
+        &serde_json::json!({ "send_at": send_at.format("%Y-%m-%dT%H:%M").to_string() }),
+    )
+    .await;
+
+    Mock::given(any())
+        .respond_with(ResponseTemplate::new(200))
+        .expect(0)
+        .mount(&app.email_server)
+        .await;
+
+    // Act
+    let response = app
+        .post_admin_newsletter_action(newsletter_issue_id, "cancel", &())
+        .await;
+    assert_is_redirect_to(
+        &response,
+        &format!("/admin/newsletters/{}", newsletter_issue_id),
+    );
+
+    // Assert
+    let html_page = app.get_admin_newsletter_html(newsletter_issue_id).await;
+    assert!(html_page.contains("<p><i>The issue has been cancelled: it is a draft again.</i></p>"));
+    assert_eq!(get_issue_status(&app, newsletter_issue_id).await, "draft");
+    sqlx::query!("UPDATE newsletter_issues SET send_at = now() - interval '1 minute'")
+        .execute(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(publish_due_issues(&app.db_pool).await.unwrap(), 0);
+    app.dispatch_all_pending_emails().await;
+    // Mock verifies on Drop that we haven't sent the newsletter email
+}
+
+#[actix_web::test]
+async fn only_scheduled_issues_can_be_cancelled() {
+    // Arrange
+    let app = spawn_app().await;
+    app.test_user.login(&app).await;
+    let newsletter_issue_id = create_draft(&app, &newsletter_request_body()).await;
+
+    // Act
+    app.post_admin_newsletter_action(newsletter_issue_id, "cancel", &())
+        .await;
+
+    // Assert
+    let html_page = app.get_admin_newsletter_html(newsletter_issue_id).await;
+    assert!(html_page.contains("<p><i>Only scheduled issues can be cancelled.</i></p>"));
+}
+
+#[actix_web::test]
+async fn managing_an_unknown_issue_returns_404() {
+    // Arrange
+    let app = spawn_app().await;
+    app.test_user.login(&app).await;
+    let newsletter_issue_id = Uuid::new_v4();
+
+    // Act
+    let response = app.get_admin_newsletter(newsletter_issue_id).await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 404);
+    for action in ["publish", "cancel"] {
+        let response = app
+            .post_admin_newsletter_action(newsletter_issue_id, action, &())
+            .await;
+        assert_eq!(response.status().as_u16(), 404);
+    }
+}
+
+#[actix_web::test]
+async fn a_draft_can_be_sent_to_test_addresses() {
+    // Arrange
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+    app.test_user.login(&app).await;
+    let newsletter_issue_id = create_draft(&app, &newsletter_request_body()).await;
+
+    Mock::given(path("/email/batch"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(serde_json::json!([
+            { "ErrorCode": 0, "Message": "OK" },
+            { "ErrorCode": 0, "Message": "OK" },
+        ])))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    // Act
+    let response = app
+        .post_admin_newsletter_action(
+            newsletter_issue_id,
+            "test",
+            &serde_json::json!({ "recipients": "editor@example.com, proofreader@example.com" }),
+        )
+        .await;
+    assert_is_redirect_to(
+        &response,
+        &format!("/admin/newsletters/{}", newsletter_issue_id),
+    );
+
+    // Assert
+    let html_page = app.get_admin_newsletter_html(newsletter_issue_id).await;
+    assert!(html_page.contains("<p><i>The test email has been sent.</i></p>"));
+    assert!(html_page.contains("<p>Status: draft</p>"));
+    let email_request = app
+        .email_server
+        .received_requests()
+        .await
+        .unwrap()
+        .into_iter()
+        .find(|r| r.url.path() == "/email/batch")
+        .unwrap();
+    let messages: Vec<serde_json::Value> = serde_json::from_slice(&email_request.body).unwrap();
+    let recipients = messages.iter().map(|m| &m["To"]).collect::<Vec<_>>();
+    assert_eq!(
+        recipients,
+        ["editor@example.com", "proofreader@example.com"]
+    );
+    assert!(messages
+        .iter()
+        .all(|m| m["Subject"] == "[TEST] Newsletter title"));
+    app.dispatch_all_pending_emails().await;
+    // Mock verifies on Drop that the subscriber didn't get anything
+}
+
+#[actix_web::test]
+async fn failed_test_sends_are_reported_on_the_issue_page() {
+    // Arrange
+    let app = spawn_app().await;
+    app.test_user.login(&app).await;
+    let newsletter_issue_id = create_draft(&app, &newsletter_request_body()).await;
+
+    Mock::given(any())
+        .respond_with(ResponseTemplate::new(500))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    // Act
+    let response = app
+        .post_admin_newsletter_action(
+            newsletter_issue_id,
+            "test",
+            &serde_json::json!({ "recipients": "editor@example.com" }),
+        )
+        .await;
+
+    // Assert
+    assert_is_redirect_to(
+        &response,
+        &format!("/admin/newsletters/{}", newsletter_issue_id),
+    );
+    let html_page = app.get_admin_newsletter_html(newsletter_issue_id).await;
+    assert!(html_page.contains("<p><i>The test email could not be sent, please try again.</i></p>"));
+}
+
+#[actix_web::test]
+async fn test_sends_reject_invalid_addresses() {
+    // Arrange
+    let app = spawn_app().await;
+    app.test_user.login(&app).await;
+    let newsletter_issue_id = create_draft(&app, &newsletter_request_body()).await;
+
+    Mock::given(any())
+        .respond_with(ResponseTemplate::new(200))
+        .expect(0)
+        .mount(&app.email_server)
+        .await;
+
+    for recipients in ["", "editor@example.com, not-an-email"] {
+        // Act
+        app.post_admin_newsletter_action(
+            newsletter_issue_id,
+            "test",
+            &serde_json::json!({ "recipients": recipients }),
+        )
+        .await;
+
+        // Assert
+        let html_page = app.get_admin_newsletter_html(newsletter_issue_id).await;
+        assert!(html_page.contains(
+            "<p><i>Please enter between one and ten valid email addresses, separated by commas.</i></p>"
+        ));
+    }
+}
@@ -0,0 +1,360 @@
+use crate::helpers::{
+    assert_is_redirect_to, create_confirmed_subscriber_with_email,
+    create_unconfirmed_subscriber_with_email, spawn_app, TestApp,
+};
+use chrono::{DateTime, Utc};
+use uuid::Uuid;
+use wiremock::matchers::{method, path};
+use wiremock::{Mock, ResponseTemplate};
+
+async fn insert_subscriber(
+    app: &TestApp,
+    email: &str,
+    name: &str,
+    status: &str,
+    subscribed_at: DateTime<Utc>,
+) -> Uuid {
+    let subscriber_id = Uuid::new_v4();
+    sqlx::query!(
+        r#"
+        INSERT INTO subscriptions (id, email, name, subscribed_at, status)
+        VALUES ($1, $2, $3, $4, $5)
+        "#,
+        subscriber_id,
+        email,
+        name,
+        subscribed_at,
+        status
+    )
+    .execute(&app.db_pool)
+    .await
+    .unwrap();
+    subscriber_id
+}
+
+async fn get_subscriber_id(app: &TestApp, email: &str) -> Uuid {
+    sqlx::query!("SELECT id FROM subscriptions WHERE email = $1", email)
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap()
+        .id
+}
+
+#[actix_web::test]
+async fn you_must_be_logged_in_to_see_subscribers() {
+    // Arrange
+    let app = spawn_app().await;
+
+    // Act
+    let response = app.get_admin_subscribers("").await;
+
+    // Assert
+    assert_is_redirect_to(&response, "/login");
+}
+
+#[actix_web::test]
+async fn you_must_be_logged_in_to_remove_a_subscriber() {
+    // Arrange
+    let app = spawn_app().await;
+    let subscriber_id = insert_subscriber(
+        &app,
+        "ursula_le_guin@gmail.com",
+        "le guin",
+        "confirmed",
+        Utc::now(),
+    )
+    .await;
+
+    // Act
+    let response = app
+        .post_admin_subscriber_action(subscriber_id, "remove")
+        .await;
+
+    // Assert
+    assert_is_redirect_to(&response, "/login");
+    get_subscriber_id(&app, "ursula_le_guin@gmail.com").await;
+}
+
+#[actix_web::test]
+async fn subscribers_can_be_searched_by_email_name_and_status() {
+    // Arrange
+    let app = spawn_app().await;
+    insert_subscriber(
+        &app,
+        "ursula_le_guin@gmail.com",
+        "le guin",
+        "confirmed",
+        Utc::now(),
+    )
+    .await;
+    insert_subscriber(
+        &app,
+        "octavia_butler@gmail.com",
+        "butler",
+        "pending_confirmation",
+        Utc::now(),
+    )
+    .await;
+    app.test_user.login(&app).await;
+
+    // Act
+    let by_email = app.get_admin_subscribers_html("email=LE_GUIN").await;
+    let by_name = app.get_admin_subscribers_html("name=butl").await;
+    let by_status = app
+        .get_admin_subscribers_html("email=&name=&status=pending_confirmation")
+        .await;
+
+    // Assert
+    assert!(by_email.contains("ursula_le_guin@gmail.com"));
+    assert!(!by_email.contains("octavia_butler@gmail.com"));
+    assert!(by_name.contains("octavia_butler@gmail.com"));
+    assert!(!by_name.contains("ursula_le_guin@gmail.com"));
+    assert!(by_status.contains("octavia_butler@gmail.com"));
+    assert!(!by_status.contains("ursula_le_guin@gmail.com"));
+}
+
+#[actix_web::test]
+async fn subscribers_can_be_filtered_by_subscription_date() {
+    // Arrange
+    let app = spawn_app().await;
+    insert_subscriber(
+        &app,
+        "ursula_le_guin@gmail.com",
+        "le guin",
+        "confirmed",
+        "2025-01-15T12:00:00Z".parse().unwrap(),
+    )
+    .await;
+    insert_subscriber(
+        &app,
+        "octavia_butler@gmail.com",
+        "butler",
+        "confirmed",
+        "2025-02-15T12:00:00Z".parse().unwrap(),
+    )
+    .await;
+    app.test_user.login(&app).await;
+
+    // Act
+    let html_page = app
+        .get_admin_subscribers_html("subscribed_from=2025-01-01&subscribed_to=2025-01-15")
+        .await;
+
+    // Assert
+    assert!(html_page.contains("ursula_le_guin@gmail.com"));
+    assert!(!html_page.contains("octavia_butler@gmail.com"));
+}
+
+#[actix_web::test]
+async fn an_invalid_date_filter_is_rejected_with_a_400() {
+    // Arrange
+    let app = spawn_app().await;
+    app.test_user.login(&app).await;
+
+    // Act
+    let response = app.get_admin_subscribers("subscribed_from=yesterday").await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 400);
+}
+
+#[actix_web::test]
+async fn subscribers_are_paginated() {
+    // Arrange
+    let app = spawn_app().await;
+    let now = Utc::now();
+    for i in 0..51 {
+        insert_subscriber(
+            &app,
+            &format!("subscriber_{:02}@gmail.com", i),
+            "subscriber",
+            "confirmed",
+            now - chrono::Duration::minutes(i),
+        )
+        .await;
+    }
+    app.test_user.login(&app).await;
+
+    // Act
+    let first_page = app.get_admin_subscribers_html("status=confirmed").await;
+    let second_page = app
+        .get_admin_subscribers_html("status=confirmed&page=2")
+        .await;
+
+    // Assert
+    assert!(first_page.contains("51 subscribers found."));
+    assert!(first_page.contains("Page 1 of 2"));
+    assert!(first_page.contains("/admin/subscribers?status=confirmed&amp;page=2"));
+    assert!(first_page.contains("subscriber_49@gmail.com"));
+    assert!(!first_page.contains("subscriber_50@gmail.com"));
+    assert!(second_page.contains("Page 2 of 2"));
+    assert!(second_page.contains("subscriber_50@gmail.com"));
+    assert!(!second_page.contains("subscriber_49@gmail.com"));
+}
+
+#[actix_web::test]
+async fn the_detail_page_shows_the_subscription_tokens() {
+    // Arrange
+    let app = spawn_app().await;
+    let confirmation_links =
+        create_unconfirmed_subscriber_with_email(&app, "ursula_le_guin@gmail.com").await;
+    let subscriber_id = get_subscriber_id(&app, "ursula_le_guin@gmail.com").await;
+    let token = confirmation_links
+        .html
+        .query_pairs()
+        .find(|(key, _)| key == "subscription_token")
+        .unwrap()
+        .1
+        .into_owned();
+    app.test_user.login(&app).await;
+
+    // Act
+    let html_page = app.get_admin_subscriber_html(subscriber_id).await;
+
+    // Assert
+    assert!(html_page.contains("ursula_le_guin@gmail.com"));
+    assert!(html_page.contains("pending_confirmation"));
+    assert!(html_page.contains(&token));
+}
+
+#[actix_web::test]
+async fn an_unknown_subscriber_returns_a_404() {
+    // Arrange
+    let app = spawn_app().await;
+    app.test_user.login(&app).await;
+
+    // Act
+    let response = app.get_admin_subscriber(Uuid::new_v4()).await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 404);
+}
+
+#[actix_web::test]
+async fn a_pending_subscriber_can_be_confirmed_manually() {
+    // Arrange
+    let app = spawn_app().await;
+    create_unconfirmed_subscriber_with_email(&app, "ursula_le_guin@gmail.com").await;
+    let subscriber_id = get_subscriber_id(&app, "ursula_le_guin@gmail.com").await;
+    app.test_user.login(&app).await;
+
+    // Act - Part 1 - Confirm
+    let response = app
+        .post_admin_subscriber_action(subscriber_id, "confirm")
+        .await;
+    assert_is_redirect_to(&response, &format!("/admin/subscribers/{}", subscriber_id));
+
+    // Act - Part 2 - Follow the redirect
+    let html_page = app.get_admin_subscriber_html(subscriber_id).await;
+
+    // Assert
+    assert!(html_page.contains("<p><i>The subscriber has been confirmed.</i></p>"));
+    let saved = sqlx::query!(
+        "SELECT status FROM subscriptions WHERE id = $1",
+        subscriber_id
+    )
+    .fetch_one(&app.db_pool)
+    .await
+    .unwrap();
+    assert_eq!(saved.status, "confirmed");
+}
+
+#[actix_web::test]
+async fn resending_the_confirmation_sends_a_new_token() {
+    // Arrange
+    let app = spawn_app().await;
+    let first_links =
+        create_unconfirmed_subscriber_with_email(&app, "ursula_le_guin@gmail.com").await;
+    let subscriber_id = get_subscriber_id(&app, "ursula_le_guin@gmail.com").await;
+    app.test_user.login(&app).await;
+
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    // Act
+    let response = app
+        .post_admin_subscriber_action(subscriber_id, "resend_confirmation")
+        .await;
+
+    // Assert
+    assert_is_redirect_to(&response, &format!("/admin/subscribers/{}", subscriber_id));
+    let email_request = &app.email_server.received_requests().await.unwrap()[1];
+    let second_links = app.get_confirmation_links(email_request);
+    assert_ne!(first_links.html, second_links.html);
+
+    // The new link confirms the subscription
+    reqwest::get(second_links.html)
+        .await
+        .unwrap()
+        .error_for_status()
+        .unwrap();
+    let saved = sqlx::query!(
+        "SELECT status FROM subscriptions WHERE id = $1",
+        subscriber_id
+    )
+    .fetch_one(&app.db_pool)
+    .await
+    .unwrap();
+    assert_eq!(saved.status, "confirmed");
+}
+
+#[actix_web::test]
+async fn confirmed_subscribers_are_not_sent_a_new_confirmation() {
+    // Arrange
+    let app = spawn_app().await;
+    create_confirmed_subscriber_with_email(&app, "ursula_le_guin@gmail.com").await;
+    let subscriber_id = get_subscriber_id(&app, "ursula_le_guin@gmail.com").await;
+    app.test_user.login(&app).await;
+
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(0)
+        .mount(&app.email_server)
+        .await;
+
+    // Act
+    let response = app
+        .post_admin_subscriber_action(subscriber_id, "resend_confirmation")
+        .await;
+
+    // Assert
+    assert_is_redirect_to(&response, &format!("/admin/subscribers/{}", subscriber_id));
+    let html_page = app.get_admin_subscriber_html(subscriber_id).await;
+    assert!(html_page.contains("Only pending subscribers can be sent a confirmation email."));
+}
+
+#[actix_web::test]
+async fn a_subscriber_can_be_removed() {
+    // Arrange
+    let app = spawn_app().await;
+    create_unconfirmed_subscriber_with_email(&app, "ursula_le_guin@gmail.com").await;
+    let subscriber_id = get_subscriber_id(&app, "ursula_le_guin@gmail.com").await;
+    app.test_user.login(&app).await;
+
+    // Act - Part 1 - Remove
+    let response = app
+        .post_admin_subscriber_action(subscriber_id, "remove")
+        .await;
+    assert_is_redirect_to(&response, "/admin/subscribers");
+
+    // Act - Part 2 - Follow the redirect
+    let html_page = app.get_admin_subscribers_html("").await;
+
+    // Assert
+    assert!(html_page.contains("<p><i>The subscriber has been removed.</i></p>"));
+    assert!(!html_page.contains("ursula_le_guin@gmail.com"));
+    let n_tokens = sqlx::query!(
+        r#"SELECT COUNT(*) AS "count!" FROM subscription_tokens WHERE subscriber_id = $1"#,
+        subscriber_id
+    )
+    .fetch_one(&app.db_pool)
+    .await
+    .unwrap()
+    .count;
+    assert_eq!(n_tokens, 0);
+}
@@ -0,0 +1,293 @@
+use crate::helpers::{
+    assert_is_redirect_to, batch_response, create_confirmed_subscriber_with_email,
+    create_unconfirmed_subscriber_with_email, spawn_app, TestApp,
+};
+use uuid::Uuid;
+use wiremock::matchers::{method, path};
+use wiremock::{Mock, Request};
+
+/// The lines of a CSV export, without one of its columns: timestamps
+/// change from one run to the next.
+fn csv_lines_without_column(csv: &str, column: usize) -> Vec<String> {
+    csv.lines()
+        .map(|line| {
+            let mut fields: Vec<&str> = line.split(',').collect();
+            fields.remove(column);
+            fields.join(",")
+        })
+        .collect()
+}
+
+async fn publish_issue(app: &TestApp) -> Uuid {
+    let response = app
+        .post_newsletters(serde_json::json!({
+            "title": "Newsletter title",
+            "content": {
+                "html": "<p>Newsletter body as HTML</p>",
+            },
+        }))
+        .await;
+    let body: serde_json::Value = response.json().await.unwrap();
+    body["newsletter_issue_id"]
+        .as_str()
+        .unwrap()
+        .parse()
+        .unwrap()
+}
+
+#[actix_web::test]
+async fn you_must_be_logged_in_to_export_data() {
+    // Arrange
+    let app = spawn_app().await;
+
+    // Act
+    let subscribers = app.get_subscribers_export("").await;
+    let deliveries = app.get_deliveries_export(Uuid::new_v4(), "").await;
+
+    // Assert
+    assert_is_redirect_to(&subscribers, "/login");
+    assert_is_redirect_to(&deliveries, "/login");
+}
+
+#[actix_web::test]
+async fn subscribers_are_exported_as_csv_by_default() {
+    // Arrange
+    let app = spawn_app().await;
+    create_confirmed_subscriber_with_email(&app, "ursula@example.com").await;
+    create_unconfirmed_subscriber_with_email(&app, "ferris@example.com").await;
+    sqlx::query!(
+        r#"
+        UPDATE subscriptions
+        SET tags = '{rust,go}', attributes = '{"country": "DE"}'
+        WHERE email = 'ursula@example.com'
+        "#
+    )
+    .execute(&app.db_pool)
+    .await
+    .unwrap();
+    app.test_user.login(&app).await;
+
+    // Act
+    let response = app.get_subscribers_export("").await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 200);
+    assert_eq!(
+        response.headers()["Content-Type"],
+        "text/csv; charset=utf-8"
+    );
+    assert_eq!(
+        response.headers()["Content-Disposition"],
+        "attachment; filename=\"subscribers.csv\""
+    );
+    let csv = response.text().await.unwrap();
+    assert_eq!(
+        csv_lines_without_column(&csv, 3),
+        vec![
+            "email,name,status,tags,attributes",
+            r#"ursula@example.com,le guin,confirmed,rust;go,"{""country"":""DE""}""#,
+            "ferris@example.com,le guin,pending_confirmation,,{}",
+        ]
+    );
+}
+
+#[actix_web::test]
+async fn subscribers_can_be_exported_as_ndjson_and_filtered_by_status() {
+    // Arrange
+    let app = spawn_app().await;
+    create_confirmed_subscriber_with_email(&app, "ursula@example.com").await;
+    create_unconfirmed_subscriber_with_email(&app, "ferris@example.com").await;
+    app.test_user.login(&app).await;
+
+    // Act
+    let response = app
+        .get_subscribers_export("status=confirmed&format=ndjson")
+        .await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 200);
+    assert_eq!(response.headers()["Content-Type"], "application/x-ndjson");
+    let body = response.text().await.unwrap();
+    let rows: Vec<serde_json::Value> = body
+        .lines()
+        .map(|line| serde_json::from_str(line).unwrap())
+        .collect();
+    assert_eq!(rows.len(), 1);
+    assert_eq!(rows[0]["email"], "ursula@example.com");
+    assert_eq!(rows[0]["status"], "confirmed");
+    assert_eq!(rows[0]["tags"], serde_json::json!([]));
+    assert_eq!(rows[0]["attributes"], serde_json::json!({}));
+}
+
+#[actix_web::test]
+async fn exports_of_a_list_contain_its_members_with_their_status_on_the_list() {
+    // Arrange
+    let app = spawn_app().await;
+    create_confirmed_subscriber_with_email(&app, "ursula@example.com").await;
+    create_confirmed_subscriber_with_email(&app, "ferris@example.com").await;
+    sqlx::query!(
+        r#"
+        WITH list AS (
+            INSERT INTO lists (list_id, slug, name, created_at)
+            VALUES ($1, 'rust-weekly', 'Rust weekly', now())
+            RETURNING list_id
+        )
+        INSERT INTO list_subscriptions (list_id, subscriber_id, status, subscribed_at)
+        SELECT list.list_id, s.id, 'pending_confirmation', now()
+        FROM list, subscriptions s
+        WHERE s.email = 'ferris@example.com'
+        "#,
+        Uuid::new_v4()
+    )
+    .execute(&app.db_pool)
+    .await
+    .unwrap();
+    app.test_user.login(&app).await;
+
+    // Act
+    let csv = app
+        .get_subscribers_export("list=rust-weekly&status=&format=")
+        .await
+        .text()
+        .await
+        .unwrap();
+    let unknown_list = app.get_subscribers_export("list=not-a-list").await;
+
+    // Assert
+    assert_eq!(
+        csv_lines_without_column(&csv, 3),
+        vec![
+            "email,name,status,tags,attributes",
+            "ferris@example.com,le guin,pending_confirmation,,{}",
+        ]
+    );
+    assert_eq!(unknown_list.status().as_u16(), 400);
+}
+
+#[actix_web::test]
+async fn csv_fields_are_quoted_and_formulas_neutralized() {
+    // Arrange
+    let app = spawn_app().await;
+    create_confirmed_subscriber_with_email(&app, "ursula@example.com").await;
+    sqlx::query!(
+        r#"UPDATE subscriptions SET name = '=HYPERLINK("http://evil.example.com"), Ursula'"#
+    )
+    .execute(&app.db_pool)
+    .await
+    .unwrap();
+    app.test_user.login(&app).await;
+
+    // Act
+    let csv = app
+        .get_subscribers_export("format=csv")
+        .await
+        .text()
+        .await
+        .unwrap();
+
+    // Assert
+    assert!(csv.contains(
+        r#"ursula@example.com,"'=HYPERLINK(""http://evil.example.com""), Ursula",confirmed,"#
+    ));
+}
+
+#[actix_web::test]
+async fn an_unknown_export_format_is_rejected() {
+    // Arrange
+    let app = spawn_app().await;
+    app.test_user.login(&app).await;
+
+    // Act
+    let response = app.get_subscribers_export("format=xlsx").await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 400);
+}
+
+#[actix_web::test]
+async fn delivery_results_are_exported_per_issue() {
+    // Arrange
+    let app = spawn_app().await;
+    create_confirmed_subscriber_with_email(&app, "ursula@example.com").await;
+    create_confirmed_subscriber_with_email(&app, "ferris@example.com").await;
+    Mock::given(path("/email/batch"))
+        .and(method("POST"))
+        .respond_with(|request: &Request| batch_response(request, Some("ferris@example.com")))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+    app.test_user.login(&app).await;
+
+    // Act - Part 1 - Before the dispatch
+    let newsletter_issue_id = publish_issue(&app).await;
+    let queued = app
+        .get_deliveries_export(newsletter_issue_id, "format=ndjson")
+        .await
+        .text()
+        .await
+        .unwrap();
+
+    // Act - Part 2 - After the dispatch
+    app.dispatch_all_pending_emails().await;
+    let response = app
+        .get_deliveries_export(newsletter_issue_id, "format=ndjson")
+        .await;
+
+    // Assert
+    let queued_rows: Vec<serde_json::Value> = queued
+        .lines()
+        .map(|line| serde_json::from_str(line).unwrap())
+        .collect();
+    assert_eq!(queued_rows.len(), 2);
+    assert!(queued_rows.iter().all(|row| row["outcome"] == "queued"));
+
+    assert_eq!(
+        response.headers()["Content-Disposition"],
+        "attachment; filename=\"deliveries.ndjson\""
+    );
+    let rows: Vec<serde_json::Value> = response
+        .text()
+        .await
+        .unwrap()
+        .lines()
+        .map(|line| serde_json::from_str(line).unwrap())
+        .collect();
+    assert_eq!(rows.len(), 2);
+    assert_eq!(rows[0]["subscriber_email"], "ferris@example.com");
+    assert_eq!(rows[0]["outcome"], "failed");
+    assert!(rows[0]["last_error"].is_string());
+    assert_eq!(rows[1]["subscriber_email"], "ursula@example.com");
+    assert_eq!(rows[1]["outcome"], "sent");
+    assert_eq!(rows[1]["n_attempts"], 1);
+    assert!(rows[1]["last_error"].is_null());
+}
+
+#[actix_web::test]
+async fn exporting_the_deliveries_of_an_unknown_issue_returns_a_404() {
+    // Arrange
+    let app = spawn_app().await;
+    app.test_user.login(&app).await;
+
+    // Act
+    let response = app.get_deliveries_export(Uuid::new_v4(), "").await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 404);
+}
+
+#[actix_web::test]
+async fn published_issues_link_to_their_delivery_exports() {
+    // Arrange
+    let app = spawn_app().await;
+    app.test_user.login(&app).await;
+
+    // Act
+    let newsletter_issue_id = publish_issue(&app).await;
+    let html_page = app.get_admin_newsletter_html(newsletter_issue_id).await;
+
+    // Assert
+    assert!(html_page.contains(&format!(
+        r#"<a href="/admin/newsletters/{}/deliveries?format=csv">"#,
+        newsletter_issue_id
+    )));
+}
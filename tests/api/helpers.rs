@@ -2,12 +2,18 @@ use argon2::password_hash::SaltString;
 use argon2::{Algorithm, Argon2, Params, PasswordHasher, Version};
 use once_cell::sync::Lazy;
 use reqwest::Url;
+use secrecy::{ExposeSecret, SecretString};
 use sqlx::{Connection, Executor, PgConnection, PgPool};
 use uuid::Uuid;
-use wiremock::MockServer;
-use zero2prod::configuration::{get_configuration, DatabaseSettings};
+use wiremock::matchers::{method, path};
+use wiremock::{Mock, MockServer, Request, ResponseTemplate};
+use zero2prod::configuration::{get_configuration, DatabaseSettings, Settings, WebhookSettings};
+use zero2prod::confirmation_email_worker::send_queued_confirmation_emails;
+use zero2prod::email_client::{EmailClient, InMemoryEmailSender, RetryPolicy};
+use zero2prod::issue_delivery_worker::{try_execute_task, ExecutionOutcome};
 use zero2prod::startup::{get_connection_pool, Application};
 use zero2prod::telemetry::{get_subscriber, init_subscriber};
+use zero2prod::templates::Templates;
 
 static TRACING: Lazy<()> = Lazy::new(|| {
     let default_filter_level = "debug".into();
@@ -34,9 +40,51 @@ pub struct TestApp {
     pub email_server: MockServer,
     pub test_user: TestUser,
     pub api_client: reqwest::Client,
+    pub email_client: EmailClient,
+    pub templates: Templates,
+    pub retry_policy: RetryPolicy,
+    pub base_url: String,
+    pub hmac_secret: SecretString,
+    pub webhooks: WebhookSettings,
+    /// The emails sent by the server, with the in-memory email transport.
+    pub email_outbox: Option<InMemoryEmailSender>,
 }
 
 impl TestApp {
+    /// Send the queued confirmation emails, leaving the issue deliveries alone.
+    pub async fn dispatch_confirmation_emails(&self) {
+        while send_queued_confirmation_emails(
+            &self.db_pool,
+            &self.email_client,
+            &self.templates,
+            &self.retry_policy,
+            &self.base_url,
+        )
+        .await
+        .unwrap()
+            > 0
+        {}
+    }
+
+    pub async fn dispatch_all_pending_emails(&self) {
+        self.dispatch_confirmation_emails().await;
+        loop {
+            if let ExecutionOutcome::EmptyQueue = try_execute_task(
+                &self.db_pool,
+                &self.email_client,
+                &self.templates,
+                &self.retry_policy,
+                &self.base_url,
+                &self.hmac_secret,
+            )
+            .await
+            .unwrap()
+            {
+                break;
+            }
+        }
+    }
+
     pub async fn post_subscriptions(&self, body: String) -> reqwest::Response {
         self.api_client
             .post(format!("{}/subscriptions", self.address))
@@ -47,6 +95,52 @@ impl TestApp {
             .expect("failed to execute request")
     }
 
+    pub async fn post_list_subscriptions(&self, slug: &str, body: String) -> reqwest::Response {
+        self.api_client
+            .post(format!("{}/lists/{}/subscriptions", self.address, slug))
+            .header("Content-Type", "application/x-www-form-urlencoded")
+            .body(body)
+            .send()
+            .await
+            .expect("failed to execute request")
+    }
+
+    pub async fn post_privacy_request(&self, email: &str) -> reqwest::Response {
+        self.api_client
+            .post(format!("{}/privacy", self.address))
+            .form(&[("email", email)])
+            .send()
+            .await
+            .expect("failed to execute request")
+    }
+
+    pub async fn get_privacy_page(&self, token: &str) -> reqwest::Response {
+        self.api_client
+            .get(format!("{}/privacy/manage", self.address))
+            .query(&[("token", token)])
+            .send()
+            .await
+            .expect("failed to execute request")
+    }
+
+    pub async fn get_privacy_data(&self, token: &str) -> reqwest::Response {
+        self.api_client
+            .get(format!("{}/privacy/data", self.address))
+            .query(&[("token", token)])
+            .send()
+            .await
+            .expect("failed to execute request")
+    }
+
+    pub async fn post_privacy_erase(&self, token: &str) -> reqwest::Response {
+        self.api_client
+            .post(format!("{}/privacy/erase", self.address))
+            .query(&[("token", token)])
+            .send()
+            .await
+            .expect("failed to execute request")
+    }
+
     pub async fn post_newsletters(&self, body: serde_json::Value) -> reqwest::Response {
         self.api_client
             .post(format!("{}/newsletters", &self.address))
@@ -57,6 +151,108 @@ impl TestApp {
             .expect("failed to execute request")
     }
 
+    pub async fn post_subscriber_tags(&self, body: serde_json::Value) -> reqwest::Response {
+        self.api_client
+            .post(format!("{}/subscribers/tags", &self.address))
+            .basic_auth(&self.test_user.username, Some(&self.test_user.password))
+            .json(&body)
+            .send()
+            .await
+            .expect("failed to execute request")
+    }
+
+    pub async fn post_subscriber_attributes(&self, body: serde_json::Value) -> reqwest::Response {
+        self.api_client
+            .post(format!("{}/subscribers/attributes", &self.address))
+            .basic_auth(&self.test_user.username, Some(&self.test_user.password))
+            .json(&body)
+            .send()
+            .await
+            .expect("failed to execute request")
+    }
+
+    pub async fn post_email_webhook(&self, body: &serde_json::Value) -> reqwest::Response {
+        self.api_client
+            .post(format!("{}/webhooks/email", &self.address))
+            .basic_auth(
+                &self.webhooks.username,
+                Some(self.webhooks.password.expose_secret()),
+            )
+            .json(body)
+            .send()
+            .await
+            .expect("failed to execute request")
+    }
+
+    pub async fn post_newsletters_with_idempotency_key(
+        &self,
+        body: serde_json::Value,
+        idempotency_key: &str,
+    ) -> reqwest::Response {
+        self.api_client
+            .post(format!("{}/newsletters", &self.address))
+            .basic_auth(&self.test_user.username, Some(&self.test_user.password))
+            .header("Idempotency-Key", idempotency_key)
+            .json(&body)
+            .send()
+            .await
+            .expect("failed to execute request")
+    }
+
+    pub async fn post_cancel_newsletter(&self, newsletter_issue_id: Uuid) -> reqwest::Response {
+        self.api_client
+            .post(format!(
+                "{}/newsletters/{}/cancel",
+                &self.address, newsletter_issue_id
+            ))
+            .basic_auth(&self.test_user.username, Some(&self.test_user.password))
+            .send()
+            .await
+            .expect("failed to execute request")
+    }
+
+    pub async fn get_newsletter(&self, newsletter_issue_id: Uuid) -> reqwest::Response {
+        self.api_client
+            .get(format!(
+                "{}/newsletters/{}",
+                &self.address, newsletter_issue_id
+            ))
+            .basic_auth(&self.test_user.username, Some(&self.test_user.password))
+            .send()
+            .await
+            .expect("failed to execute request")
+    }
+
+    /// The statistics of an issue, as reported by the API.
+    pub async fn get_newsletter_stats(&self, newsletter_issue_id: Uuid) -> serde_json::Value {
+        let body: serde_json::Value = self
+            .get_newsletter(newsletter_issue_id)
+            .await
+            .error_for_status()
+            .unwrap()
+            .json()
+            .await
+            .unwrap();
+        body["stats"].clone()
+    }
+
+    pub async fn post_test_newsletter(
+        &self,
+        newsletter_issue_id: Uuid,
+        body: &serde_json::Value,
+    ) -> reqwest::Response {
+        self.api_client
+            .post(format!(
+                "{}/newsletters/{}/test",
+                &self.address, newsletter_issue_id
+            ))
+            .basic_auth(&self.test_user.username, Some(&self.test_user.password))
+            .json(body)
+            .send()
+            .await
+            .expect("failed to execute request")
+    }
+
     pub async fn post_login<Body>(&self, body: &Body) -> reqwest::Response
     where
         Body: serde::Serialize,
@@ -92,6 +288,358 @@ impl TestApp {
         self.get_admin_dashboard().await.text().await.unwrap()
     }
 
+    pub async fn get_issues_html(&self) -> String {
+        self.api_client
+            .get(format!("{}/issues", &self.address))
+            .send()
+            .await
+            .expect("failed to execute request")
+            .text()
+            .await
+            .unwrap()
+    }
+
+    pub async fn get_issue(&self, slug: &str) -> reqwest::Response {
+        self.api_client
+            .get(format!("{}/issues/{}", &self.address, slug))
+            .send()
+            .await
+            .expect("failed to execute request")
+    }
+
+    pub async fn get_publish_newsletter(&self) -> reqwest::Response {
+        self.api_client
+            .get(format!("{}/admin/newsletters", &self.address))
+            .send()
+            .await
+            .expect("failed to execute request")
+    }
+
+    pub async fn get_publish_newsletter_html(&self) -> String {
+        self.get_publish_newsletter().await.text().await.unwrap()
+    }
+
+    pub async fn post_publish_newsletter<Body>(&self, body: &Body) -> reqwest::Response
+    where
+        Body: serde::Serialize,
+    {
+        self.api_client
+            .post(format!("{}/admin/newsletters", &self.address))
+            .form(body)
+            .send()
+            .await
+            .expect("failed to execute request")
+    }
+
+    pub async fn get_admin_newsletter(&self, newsletter_issue_id: Uuid) -> reqwest::Response {
+        self.api_client
+            .get(format!(
+                "{}/admin/newsletters/{}",
+                &self.address, newsletter_issue_id
+            ))
+            .send()
+            .await
+            .expect("failed to execute request")
+    }
+
+    pub async fn get_admin_newsletter_html(&self, newsletter_issue_id: Uuid) -> String {
+        self.get_admin_newsletter(newsletter_issue_id)
+            .await
+            .text()
+            .await
+            .unwrap()
+    }
+
+    /// Submit one of the forms of the admin page of an issue.
+    /// `action` is empty for the edit form.
+    pub async fn post_admin_newsletter_action<Body>(
+        &self,
+        newsletter_issue_id: Uuid,
+        action: &str,
+        body: &Body,
+    ) -> reqwest::Response
+    where
+        Body: serde::Serialize,
+    {
+        let mut url = format!(
+            "{}/admin/newsletters/{}",
+            &self.address, newsletter_issue_id
+        );
+        if !action.is_empty() {
+            url = format!("{}/{}", url, action);
+        }
+        self.api_client
+            .post(url)
+            .form(body)
+            .send()
+            .await
+            .expect("failed to execute request")
+    }
+
+    pub async fn get_newsletter_preview(
+        &self,
+        newsletter_issue_id: Uuid,
+        query: &str,
+    ) -> reqwest::Response {
+        self.api_client
+            .get(format!(
+                "{}/admin/newsletters/{}/preview?{}",
+                &self.address, newsletter_issue_id, query
+            ))
+            .send()
+            .await
+            .expect("failed to execute request")
+    }
+
+    pub async fn post_logout<Body>(&self, body: &Body) -> reqwest::Response
+    where
+        Body: serde::Serialize,
+    {
+        self.api_client
+            .post(format!("{}/admin/logout", &self.address))
+            .form(body)
+            .send()
+            .await
+            .expect("failed to execute request")
+    }
+
+    pub async fn get_change_password(&self) -> reqwest::Response {
+        self.api_client
+            .get(format!("{}/admin/password", &self.address))
+            .send()
+            .await
+            .expect("failed to execute request")
+    }
+
+    pub async fn get_change_password_html(&self) -> String {
+        self.get_change_password().await.text().await.unwrap()
+    }
+
+    pub async fn post_change_password<Body>(&self, body: &Body) -> reqwest::Response
+    where
+        Body: serde::Serialize,
+    {
+        self.api_client
+            .post(format!("{}/admin/password", &self.address))
+            .form(body)
+            .send()
+            .await
+            .expect("failed to execute request")
+    }
+
+    pub async fn get_admin_lists_html(&self) -> String {
+        self.api_client
+            .get(format!("{}/admin/lists", &self.address))
+            .send()
+            .await
+            .expect("Failed to execute request.")
+            .text()
+            .await
+            .unwrap()
+    }
+
+    pub async fn post_admin_lists<Body>(&self, body: &Body) -> reqwest::Response
+    where
+        Body: serde::Serialize,
+    {
+        self.api_client
+            .post(format!("{}/admin/lists", &self.address))
+            .form(body)
+            .send()
+            .await
+            .expect("Failed to execute request.")
+    }
+
+    pub async fn get_admin_dead_letters(&self) -> reqwest::Response {
+        self.api_client
+            .get(format!("{}/admin/dead_letters", &self.address))
+            .send()
+            .await
+            .expect("failed to execute request")
+    }
+
+    pub async fn get_admin_dead_letters_html(&self) -> String {
+        self.get_admin_dead_letters().await.text().await.unwrap()
+    }
+
+    pub async fn post_redrive_dead_letters<Body>(&self, body: &Body) -> reqwest::Response
+    where
+        Body: serde::Serialize,
+    {
+        self.api_client
+            .post(format!("{}/admin/dead_letters", &self.address))
+            .form(body)
+            .send()
+            .await
+            .expect("failed to execute request")
+    }
+
+    pub async fn get_admin_subscribers(&self, query: &str) -> reqwest::Response {
+        self.api_client
+            .get(format!("{}/admin/subscribers?{}", &self.address, query))
+            .send()
+            .await
+            .expect("failed to execute request")
+    }
+
+    pub async fn get_admin_subscribers_html(&self, query: &str) -> String {
+        self.get_admin_subscribers(query)
+            .await
+            .text()
+            .await
+            .unwrap()
+    }
+
+    pub async fn get_subscribers_export(&self, query: &str) -> reqwest::Response {
+        self.api_client
+            .get(format!(
+                "{}/admin/subscribers/export?{}",
+                &self.address, query
+            ))
+            .send()
+            .await
+            .expect("failed to execute request")
+    }
+
+    pub async fn get_deliveries_export(
+        &self,
+        newsletter_issue_id: Uuid,
+        query: &str,
+    ) -> reqwest::Response {
+        self.api_client
+            .get(format!(
+                "{}/admin/newsletters/{}/deliveries?{}",
+                &self.address, newsletter_issue_id, query
+            ))
+            .send()
+            .await
+            .expect("failed to execute request")
+    }
+
+    pub async fn post_admin_subscriber_tags<Body>(&self, body: &Body) -> reqwest::Response
+    where
+        Body: serde::Serialize,
+    {
+        self.api_client
+            .post(format!("{}/admin/subscribers/tags", &self.address))
+            .form(body)
+            .send()
+            .await
+            .expect("failed to execute request")
+    }
+
+    pub async fn get_import_subscribers_html(&self) -> String {
+        self.api_client
+            .get(format!("{}/admin/subscribers/import", &self.address))
+            .send()
+            .await
+            .expect("failed to execute request")
+            .text()
+            .await
+            .unwrap()
+    }
+
+    /// Upload a CSV file from the import form, as a browser would.
+    pub async fn post_import_subscribers(&self, mode: &str, csv: &str) -> reqwest::Response {
+        let boundary = "zero2prod-import-boundary";
+        let body = format!(
+            "--{boundary}\r\n\
+            Content-Disposition: form-data; name=\"mode\"\r\n\r\n\
+            {mode}\r\n\
+            --{boundary}\r\n\
+            Content-Disposition: form-data; name=\"file\"; filename=\"subscribers.csv\"\r\n\
+            Content-Type: text/csv\r\n\r\n\
+            {csv}\r\n\
+            --{boundary}--\r\n"
+        );
+        self.api_client
+            .post(format!("{}/admin/subscribers/import", &self.address))
+            .header(
+                "Content-Type",
+                format!("multipart/form-data; boundary={}", boundary),
+            )
+            .body(body)
+            .send()
+            .await
+            .expect("failed to execute request")
+    }
+
+    pub async fn get_admin_subscriber(&self, subscriber_id: Uuid) -> reqwest::Response {
+        self.api_client
+            .get(format!(
+                "{}/admin/subscribers/{}",
+                &self.address, subscriber_id
+            ))
+            .send()
+            .await
+            .expect("failed to execute request")
+    }
+
+    pub async fn get_admin_subscriber_html(&self, subscriber_id: Uuid) -> String {
+        self.get_admin_subscriber(subscriber_id)
+            .await
+            .text()
+            .await
+            .unwrap()
+    }
+
+    pub async fn post_admin_subscriber_action(
+        &self,
+        subscriber_id: Uuid,
+        action: &str,
+    ) -> reqwest::Response {
+        self.api_client
+            .post(format!(
+                "{}/admin/subscribers/{}/{}",
+                &self.address, subscriber_id, action
+            ))
+            .send()
+            .await
+            .expect("failed to execute request")
+    }
+
+    pub async fn get_unsubscribe(&self, unsubscribe_link: &Url) -> reqwest::Response {
+        self.api_client
+            .get(unsubscribe_link.clone())
+            .send()
+            .await
+            .expect("failed to execute request")
+    }
+
+    /// RFC 8058 one-click unsubscribe, as performed by mail clients.
+    pub async fn post_unsubscribe(&self, unsubscribe_link: &Url) -> reqwest::Response {
+        self.api_client
+            .post(unsubscribe_link.clone())
+            .header("Content-Type", "application/x-www-form-urlencoded")
+            .body("List-Unsubscribe=One-Click")
+            .send()
+            .await
+            .expect("failed to execute request")
+    }
+
+    /// Extract the unsubscribe link from the `List-Unsubscribe` header
+    /// of a newsletter email.
+    pub fn get_unsubscribe_link(&self, email_request: &wiremock::Request) -> Url {
+        let body: serde_json::Value = serde_json::from_slice(&email_request.body).unwrap();
+        let header_value = body["Headers"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .find(|h| h["Name"] == "List-Unsubscribe")
+            .expect("No List-Unsubscribe header")["Value"]
+            .as_str()
+            .unwrap();
+        let raw_link = header_value
+            .strip_prefix('<')
+            .and_then(|l| l.strip_suffix('>'))
+            .unwrap();
+
+        let mut unsubscribe_link = Url::parse(raw_link).unwrap();
+        assert_eq!(unsubscribe_link.host_str().unwrap(), "127.0.0.1");
+        unsubscribe_link.set_port(Some(self.port)).unwrap();
+        unsubscribe_link
+    }
+
     pub fn get_confirmation_links(&self, email_request: &wiremock::Request) -> ConfirmationLinks {
         let body: serde_json::Value = serde_json::from_slice(&email_request.body).unwrap();
 
@@ -120,6 +668,11 @@ impl TestApp {
 }
 
 pub async fn spawn_app() -> TestApp {
+    spawn_app_with(|_| {}).await
+}
+
+/// Spawn the application, with the test configuration changed by `configure`.
+pub async fn spawn_app_with(configure: impl FnOnce(&mut Settings)) -> TestApp {
     Lazy::force(&TRACING);
 
     let client = reqwest::Client::builder()
@@ -138,6 +691,9 @@ pub async fn spawn_app() -> TestApp {
         // Use a random OS port
         c.application.port = 0;
         c.email_client.base_url = email_server.uri();
+        // Retry failed deliveries straight away
+        c.email_client.retry_base_delay_ms = 0;
+        configure(&mut c);
         c
     };
 
@@ -150,7 +706,8 @@ pub async fn spawn_app() -> TestApp {
         .expect("Failed to build application.");
     let address = format!("http://127.0.0.1:{}", app.port());
     let application_port = app.port();
-    let _ = tokio::spawn(app.run_until_stopped());
+    let email_outbox = app.email_outbox().cloned();
+    tokio::spawn(app.run_until_stopped());
 
     let test_app = TestApp {
         address,
@@ -159,6 +716,13 @@ pub async fn spawn_app() -> TestApp {
         email_server,
         test_user: TestUser::generate(),
         api_client: client,
+        email_client: configuration.email_client.client(),
+        templates: Templates::load(&configuration.application.templates_directory).unwrap(),
+        retry_policy: configuration.email_client.retry_policy(),
+        base_url: configuration.application.base_url.clone(),
+        hmac_secret: configuration.application.hmac_secret.clone(),
+        webhooks: configuration.webhooks.clone(),
+        email_outbox,
     };
     test_app.test_user.store(&test_app.db_pool).await;
     test_app
@@ -201,6 +765,14 @@ impl TestUser {
         }
     }
 
+    pub async fn login(&self, app: &TestApp) {
+        app.post_login(&serde_json::json!({
+            "username": &self.username,
+            "password": &self.password
+        }))
+        .await;
+    }
+
     async fn store(&self, pool: &PgPool) {
         let salt = SaltString::generate(&mut rand::thread_rng());
         let password_hash = Argon2::new(
@@ -229,3 +801,71 @@ pub fn assert_is_redirect_to(response: &reqwest::Response, location: &str) {
     assert_eq!(response.status().as_u16(), 303);
     assert_eq!(response.headers().get("Location").unwrap(), location);
 }
+
+/// Use the public API of the application under test to create
+/// an unconfirmed subscriber.
+pub async fn create_unconfirmed_subscriber(app: &TestApp) -> ConfirmationLinks {
+    create_unconfirmed_subscriber_with_email(app, "ursula_le_guin@gmail.com").await
+}
+
+pub async fn create_unconfirmed_subscriber_with_email(
+    app: &TestApp,
+    email: &str,
+) -> ConfirmationLinks {
+    let body = format!("name=le%20guin&email={}", urlencoding::encode(email));
+
+    let _mock_guard = Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .named("Create unconfirmed subscriber")
+        .expect(1)
+        .mount_as_scoped(&app.email_server)
+        .await;
+
+    app.post_subscriptions(body)
+        .await
+        .error_for_status()
+        .unwrap();
+    app.dispatch_confirmation_emails().await;
+
+    let email_request = &app
+        .email_server
+        .received_requests()
+        .await
+        .unwrap()
+        .pop()
+        .unwrap();
+
+    app.get_confirmation_links(email_request)
+}
+
+pub async fn create_confirmed_subscriber(app: &TestApp) {
+    create_confirmed_subscriber_with_email(app, "ursula_le_guin@gmail.com").await;
+}
+
+pub async fn create_confirmed_subscriber_with_email(app: &TestApp, email: &str) {
+    let confirmation_link = create_unconfirmed_subscriber_with_email(app, email).await;
+
+    reqwest::get(confirmation_link.html)
+        .await
+        .unwrap()
+        .error_for_status()
+        .unwrap();
+}
+
+/// Reply to a batch request as Postmark would, rejecting the message
+/// sent to `rejected_recipient`, if any.
+pub fn batch_response(request: &Request, rejected_recipient: Option<&str>) -> ResponseTemplate {
+    let messages: Vec<serde_json::Value> = serde_json::from_slice(&request.body).unwrap();
+    let results = messages
+        .iter()
+        .map(|message| {
+            if rejected_recipient.is_some_and(|r| message["To"] == r) {
+                serde_json::json!({ "ErrorCode": 406, "Message": "Inactive recipient" })
+            } else {
+                serde_json::json!({ "ErrorCode": 0, "Message": "OK" })
+            }
+        })
+        .collect::<Vec<_>>();
+    ResponseTemplate::new(200).set_body_json(results)
+}
@@ -0,0 +1,190 @@
+use crate::helpers::{create_confirmed_subscriber, spawn_app, TestApp};
+use uuid::Uuid;
+use wiremock::matchers::{method, path};
+use wiremock::{Mock, ResponseTemplate};
+
+async fn publish_issue(app: &TestApp, title: &str, html: &str) {
+    let newsletter_request_body = serde_json::json!({
+        "title": title,
+        "content": {
+            "text": "Newsletter body as plain text",
+            "html": html,
+        }
+    });
+    app.post_newsletters(newsletter_request_body)
+        .await
+        .error_for_status()
+        .unwrap();
+}
+
+#[actix_web::test]
+async fn published_issues_are_stored_with_their_author() {
+    // Arrange
+    let app = spawn_app().await;
+
+    // Act
+    publish_issue(&app, "Our first issue", "<p>Hello</p>").await;
+
+    // Assert
+    let issue = sqlx::query!(r#"SELECT slug as "slug!", author_id FROM newsletter_issues"#)
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(issue.slug, "our-first-issue");
+    assert_eq!(issue.author_id, Some(app.test_user.user_id));
+}
+
+#[actix_web::test]
+async fn issues_with_the_same_title_get_different_slugs() {
+    // Arrange
+    let app = spawn_app().await;
+
+    // Act
+    publish_issue(&app, "Weekly digest", "<p>Week 1</p>").await;
+    publish_issue(&app, "Weekly digest", "<p>Week 2</p>").await;
+
+    // Assert
+    let slugs =
+        sqlx::query!(r#"SELECT slug as "slug!" FROM newsletter_issues ORDER BY published_at"#)
+            .fetch_all(&app.db_pool)
+            .await
+            .unwrap()
+            .into_iter()
+            .map(|r| r.slug)
+            .collect::<Vec<_>>();
+    assert_eq!(slugs[0], "weekly-digest");
+    assert!(slugs[1].starts_with("weekly-digest-"));
+    let second_issue_html = app.get_issue(&slugs[1]).await.text().await.unwrap();
+    assert!(second_issue_html.contains("<p>Week 2</p>"));
+}
+
+#[actix_web::test]
+async fn a_slug_taken_by_a_concurrent_publication_is_not_reused() {
+    // Arrange
+    let app = spawn_app().await;
+    // Another issue with the same title is being published: the slug is not
+    // visible yet, but the unique constraint already holds it.
+    let mut transaction = app.db_pool.begin().await.unwrap();
+    sqlx::query!(
+        r#"
+        INSERT INTO newsletter_issues (
+            newsletter_issue_id, title, text_content, html_content, status,
+            published_at, slug, created_at
+        )
+        VALUES ($1, 'Weekly digest', '', '<p>Week 1</p>', 'published', now(), 'weekly-digest', now())
+        "#,
+        Uuid::new_v4()
+    )
+    .execute(&mut *transaction)
+    .await
+    .unwrap();
+
+    // Act
+    let commit_later = async {
+        tokio::time::sleep(std::time::Duration::from_millis(500)).await;
+        transaction.commit().await.unwrap();
+    };
+    tokio::join!(
+        publish_issue(&app, "Weekly digest", "<p>Week 2</p>"),
+        commit_later
+    );
+
+    // Assert
+    let slugs =
+        sqlx::query!(r#"SELECT slug as "slug!" FROM newsletter_issues ORDER BY published_at"#)
+            .fetch_all(&app.db_pool)
+            .await
+            .unwrap()
+            .into_iter()
+            .map(|r| r.slug)
+            .collect::<Vec<_>>();
+    assert_eq!(slugs.len(), 2);
+    assert_eq!(slugs[0], "weekly-digest");
+    assert!(slugs[1].starts_with("weekly-digest-"));
+}
+
+#[actix_web::test]
+async fn published_issues_are_listed_in_the_archive() {
+    // Arrange
+    let app = spawn_app().await;
+    publish_issue(&app, "Our first issue", "<p>Hello</p>").await;
+    publish_issue(&app, "Fish & chips", "<p>Hello again</p>").await;
+
+    // Act
+    let html_page = app.get_issues_html().await;
+
+    // Assert
+    assert!(html_page.contains(r#"<a href="/issues/our-first-issue">Our first issue</a>"#));
+    assert!(html_page.contains("Fish &amp; chips"));
+}
+
+#[actix_web::test]
+async fn the_issue_page_renders_the_html_content() {
+    // Arrange
+    let app = spawn_app().await;
+    publish_issue(
+        &app,
+        "Our first issue",
+        "<p>Hi {{name}}, here is <strong>{{title}}</strong></p>",
+    )
+    .await;
+
+    // Act
+    let response = app.get_issue("our-first-issue").await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 200);
+    let html_page = response.text().await.unwrap();
+    assert!(html_page.contains("<h1>Our first issue</h1>"));
+    // Per-subscriber variables are left empty
+    assert!(html_page.contains("<p>Hi , here is <strong>Our first issue</strong></p>"));
+}
+
+#[actix_web::test]
+async fn unknown_issues_return_404() {
+    // Arrange
+    let app = spawn_app().await;
+
+    // Act
+    let response = app.get_issue("not-an-issue").await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 404);
+}
+
+#[actix_web::test]
+async fn newsletter_emails_link_to_the_web_version_of_the_issue() {
+    // Arrange
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    // Act
+    publish_issue(&app, "Our first issue", "<p>Hello</p>").await;
+    app.dispatch_all_pending_emails().await;
+
+    // Assert
+    let email_request = app
+        .email_server
+        .received_requests()
+        .await
+        .unwrap()
+        .pop()
+        .unwrap();
+    let body: serde_json::Value = serde_json::from_slice(&email_request.body).unwrap();
+    let view_in_browser_link = format!("{}/issues/our-first-issue", app.base_url);
+    assert!(body["HtmlBody"]
+        .as_str()
+        .unwrap()
+        .contains(&format!(r#"<a href="{}">"#, view_in_browser_link)));
+    assert!(body["TextBody"]
+        .as_str()
+        .unwrap()
+        .contains(&view_in_browser_link));
+}
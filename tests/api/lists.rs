@@ -0,0 +1,664 @@
+use crate::helpers::{
+    assert_is_redirect_to, batch_response, create_confirmed_subscriber_with_email, spawn_app,
+    ConfirmationLinks, TestApp,
+};
+use std::collections::HashSet;
+use uuid::Uuid;
+use wiremock::matchers::{any, method, path};
+use wiremock::{Mock, Request, ResponseTemplate};
+
+async fn create_list(app: &TestApp, slug: &str) {
+    sqlx::query!(
+        "INSERT INTO lists (list_id, slug, name, created_at) VALUES ($1, $2, $3, now())",
+        Uuid::new_v4(),
+        slug,
+        format!("The {} list", slug)
+    )
+    .execute(&app.db_pool)
+    .await
+    .unwrap();
+}
+
+async fn subscribe_to_list(app: &TestApp, slug: &str, email: &str) -> ConfirmationLinks {
+    let _mock_guard = Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount_as_scoped(&app.email_server)
+        .await;
+
+    let body = format!("name=le%20guin&email={}", urlencoding::encode(email));
+    app.post_list_subscriptions(slug, body)
+        .await
+        .error_for_status()
+        .unwrap();
+    app.dispatch_confirmation_emails().await;
+
+    let email_request = app
+        .email_server
+        .received_requests()
+        .await
+        .unwrap()
+        .pop()
+        .unwrap();
+    app.get_confirmation_links(&email_request)
+}
+
+async fn create_confirmed_list_subscriber(app: &TestApp, slug: &str, email: &str) {
+    let confirmation_links = subscribe_to_list(app, slug, email).await;
+    reqwest::get(confirmation_links.html)
+        .await
+        .unwrap()
+        .error_for_status()
+        .unwrap();
+}
+
+async fn get_list_subscription_status(app: &TestApp, slug: &str, email: &str) -> Option<String> {
+    sqlx::query_scalar!(
+        r#"
+        SELECT ls.status
+        FROM list_subscriptions ls
+        JOIN lists l ON l.list_id = ls.list_id
+        JOIN subscriptions s ON s.id = ls.subscriber_id
+        WHERE l.slug = $1 AND s.email = $2
+        "#,
+        slug,
+        email
+    )
+    .fetch_optional(&app.db_pool)
+    .await
+    .unwrap()
+}
+
+fn newsletter_request_body(lists: &[&str]) -> serde_json::Value {
+    serde_json::json!({
+        "title": "Newsletter title",
+        "content": {
+            "text": "Newsletter body as plain text",
+            "html": "<p>Newsletter body as HTML</p>",
+        },
+        "lists": lists,
+    })
+}
+
+/// The recipients of the newsletter emails sent so far, whether they were
+/// sent on their own or in a batch.
+async fn get_newsletter_recipients(app: &TestApp) -> Vec<String> {
+    app.email_server
+        .received_requests()
+        .await
+        .unwrap()
+        .iter()
+        .flat_map(
+            |r| match serde_json::from_slice::<serde_json::Value>(&r.body).unwrap() {
+                serde_json::Value::Array(messages) => messages,
+                message => vec![message],
+            },
+        )
+        .filter(|body| body["Subject"] == "Newsletter title")
+        .map(|body| body["To"].as_str().unwrap().to_owned())
+        .collect()
+}
+
+#[actix_web::test]
+async fn subscribing_to_an_unknown_list_returns_a_404() {
+    // Arrange
+    let app = spawn_app().await;
+    Mock::given(any())
+        .respond_with(ResponseTemplate::new(200))
+        .expect(0)
+        .mount(&app.email_server)
+        .await;
+
+    // Act
+    let response = app
+        .post_list_subscriptions(
+            "not-a-list",
+            "name=le%20guin&email=ursula_le_guin%40gmail.com".into(),
+        )
+        .await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 404);
+}
+
+#[actix_web::test]
+async fn subscribing_to_a_list_with_invalid_data_returns_a_400() {
+    // Arrange
+    let app = spawn_app().await;
+    create_list(&app, "rust-weekly").await;
+
+    // Act
+    let response = app
+        .post_list_subscriptions("rust-weekly", "name=le%20guin&email=not-an-email".into())
+        .await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 400);
+}
+
+#[actix_web::test]
+async fn the_confirmation_link_confirms_the_subscription_to_the_list() {
+    // Arrange
+    let app = spawn_app().await;
+    create_list(&app, "rust-weekly").await;
+    let confirmation_links = subscribe_to_list(&app, "rust-weekly", "ursula@example.com").await;
+    assert_eq!(
+        get_list_subscription_status(&app, "rust-weekly", "ursula@example.com")
+            .await
+            .as_deref(),
+        Some("pending_confirmation")
+    );
+
+    // Act
+    let response = reqwest::get(confirmation_links.html).await.unwrap();
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 200);
+    assert_eq!(
+        get_list_subscription_status(&app, "rust-weekly", "ursula@example.com")
+            .await
+            .as_deref(),
+        Some("confirmed")
+    );
+    // The subscription to the default list is not implied
+    assert_eq!(
+        get_list_subscription_status(&app, "newsletter", "ursula@example.com").await,
+        None
+    );
+}
+
+#[actix_web::test]
+async fn confirmed_subscribers_must_confirm_each_new_list() {
+    // Arrange
+    let app = spawn_app().await;
+    create_list(&app, "rust-weekly").await;
+    create_confirmed_subscriber_with_email(&app, "ursula@example.com").await;
+
+    // Act - Part 1 - Join another list
+    let confirmation_links = subscribe_to_list(&app, "rust-weekly", "ursula@example.com").await;
+
+    // Assert - Part 1
+    assert_eq!(
+        get_list_subscription_status(&app, "rust-weekly", "ursula@example.com")
+            .await
+            .as_deref(),
+        Some("pending_confirmation")
+    );
+    assert_eq!(
+        get_list_subscription_status(&app, "newsletter", "ursula@example.com")
+            .await
+            .as_deref(),
+        Some("confirmed")
+    );
+
+    // Act - Part 2 - Confirm it
+    reqwest::get(confirmation_links.html)
+        .await
+        .unwrap()
+        .error_for_status()
+        .unwrap();
+
+    // Assert - Part 2
+    assert_eq!(
+        get_list_subscription_status(&app, "rust-weekly", "ursula@example.com")
+            .await
+            .as_deref(),
+        Some("confirmed")
+    );
+}
+
+#[actix_web::test]
+async fn subscribing_again_to_a_confirmed_list_sends_nothing() {
+    // Arrange
+    let app = spawn_app().await;
+    create_list(&app, "rust-weekly").await;
+    create_confirmed_list_subscriber(&app, "rust-weekly", "ursula@example.com").await;
+    Mock::given(any())
+        .respond_with(ResponseTemplate::new(200))
+        .expect(0)
+        .mount(&app.email_server)
+        .await;
+
+    // Act
+    let response = app
+        .post_list_subscriptions(
+            "rust-weekly",
+            "name=le%20guin&email=ursula%40example.com".into(),
+        )
+        .await;
+    app.dispatch_confirmation_emails().await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 200);
+}
+
+#[actix_web::test]
+async fn issues_are_only_delivered_to_the_subscribers_of_their_lists() {
+    // Arrange
+    let app = spawn_app().await;
+    create_list(&app, "rust-weekly").await;
+    create_list(&app, "go-weekly").await;
+    create_confirmed_list_subscriber(&app, "rust-weekly", "rustacean@example.com").await;
+    create_confirmed_list_subscriber(&app, "go-weekly", "gopher@example.com").await;
+    create_confirmed_subscriber_with_email(&app, "reader@example.com").await;
+    // Pending subscriptions don't get the issue
+    subscribe_to_list(&app, "rust-weekly", "reader@example.com").await;
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&app.email_server)
+        .await;
+
+    // Act
+    let response = app
+        .post_newsletters(newsletter_request_body(&["rust-weekly"]))
+        .await;
+    app.dispatch_all_pending_emails().await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 200);
+    assert_eq!(
+        get_newsletter_recipients(&app).await,
+        vec!["rustacean@example.com".to_string()]
+    );
+}
+
+#[actix_web::test]
+async fn subscribers_of_several_target_lists_get_the_issue_once() {
+    // Arrange
+    let app = spawn_app().await;
+    create_list(&app, "rust-weekly").await;
+    create_confirmed_subscriber_with_email(&app, "ursula@example.com").await;
+    create_confirmed_list_subscriber(&app, "rust-weekly", "ursula@example.com").await;
+    create_confirmed_list_subscriber(&app, "rust-weekly", "rustacean@example.com").await;
+    Mock::given(path("/email/batch"))
+        .and(method("POST"))
+        .respond_with(|request: &Request| batch_response(request, None))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    // Act
+    let response = app
+        .post_newsletters(newsletter_request_body(&["newsletter", "rust-weekly"]))
+        .await;
+    app.dispatch_all_pending_emails().await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 200);
+    let recipients = get_newsletter_recipients(&app).await;
+    assert_eq!(recipients.len(), 2);
+    assert_eq!(
+        recipients.into_iter().collect::<HashSet<_>>(),
+        HashSet::from([
+            "ursula@example.com".to_string(),
+            "rustacean@example.com".to_string()
+        ])
+    );
+}
+
+#[actix_web::test]
+async fn issues_go_to_the_default_list_when_no_list_is_given() {
+    // Arrange
+    let app = spawn_app().await;
+    create_list(&app, "rust-weekly").await;
+    create_confirmed_subscriber_with_email(&app, "ursula@example.com").await;
+    create_confirmed_list_subscriber(&app, "rust-weekly", "rustacean@example.com").await;
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&app.email_server)
+        .await;
+
+    // Act
+    let response = app.post_newsletters(newsletter_request_body(&[])).await;
+    app.dispatch_all_pending_emails().await;
+
+    // Assert
+    let newsletter_issue_id = response.json::<serde_json::Value>().await.unwrap()
+        ["newsletter_issue_id"]
+        .as_str()
+        .unwrap()
+        .parse()
+        .unwrap();
+    assert_eq!(
+        get_newsletter_recipients(&app).await,
+        vec!["ursula@example.com".to_string()]
+    );
+    let issue: serde_json::Value = app
+        .get_newsletter(newsletter_issue_id)
+        .await
+        .json()
+        .await
+        .unwrap();
+    assert_eq!(issue["lists"], serde_json::json!(["newsletter"]));
+}
+
+#[actix_web::test]
+async fn publishing_to_an_unknown_list_is_rejected() {
+    // Arrange
+    let app = spawn_app().await;
+
+    // Act
+    let response = app
+        .post_newsletters(newsletter_request_body(&["newsletter", "not-a-list"]))
+        .await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 400);
+    let n_issues = sqlx::query_scalar!(r#"SELECT COUNT(*) AS "count!" FROM newsletter_issues"#)
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(n_issues, 0);
+}
+
+#[actix_web::test]
+async fn unsubscribing_leaves_the_list_the_issue_was_sent_to() {
+    // Arrange
+    let app = spawn_app().await;
+    create_list(&app, "rust-weekly").await;
+    create_confirmed_subscriber_with_email(&app, "ursula@example.com").await;
+    create_confirmed_list_subscriber(&app, "rust-weekly", "ursula@example.com").await;
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&app.email_server)
+        .await;
+    app.post_newsletters(newsletter_request_body(&["rust-weekly"]))
+        .await;
+    app.dispatch_all_pending_emails().await;
+    let email_request = app
+        .email_server
+        .received_requests()
+        .await
+        .unwrap()
+        .pop()
+        .unwrap();
+    let unsubscribe_link = app.get_unsubscribe_link(&email_request);
+
+    // Act
+    let form = app
+        .get_unsubscribe(&unsubscribe_link)
+        .await
+        .text()
+        .await
+        .unwrap();
+    app.post_unsubscribe(&unsubscribe_link).await;
+
+    // Assert
+    assert!(form.contains("Do you want to stop receiving The rust-weekly list?"));
+    assert_eq!(
+        get_list_subscription_status(&app, "rust-weekly", "ursula@example.com")
+            .await
+            .as_deref(),
+        Some("unsubscribed")
+    );
+    assert_eq!(
+        get_list_subscription_status(&app, "newsletter", "ursula@example.com")
+            .await
+            .as_deref(),
+        Some("confirmed")
+    );
+    let status = sqlx::query_scalar!("SELECT status FROM subscriptions")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(status, "confirmed");
+}
+
+#[actix_web::test]
+async fn subscribers_can_leave_all_the_lists_at_once() {
+    // Arrange
+    let app = spawn_app().await;
+    create_list(&app, "rust-weekly").await;
+    create_confirmed_subscriber_with_email(&app, "ursula@example.com").await;
+    create_confirmed_list_subscriber(&app, "rust-weekly", "ursula@example.com").await;
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&app.email_server)
+        .await;
+    app.post_newsletters(newsletter_request_body(&["rust-weekly"]))
+        .await;
+    app.dispatch_all_pending_emails().await;
+    let email_request = app
+        .email_server
+        .received_requests()
+        .await
+        .unwrap()
+        .pop()
+        .unwrap();
+    let mut unsubscribe_link = app.get_unsubscribe_link(&email_request);
+    let form = app
+        .get_unsubscribe(&unsubscribe_link)
+        .await
+        .text()
+        .await
+        .unwrap();
+
+    // Act
+    let query = format!("{}&all=true", unsubscribe_link.query().unwrap());
+    unsubscribe_link.set_query(Some(&query));
+    app.post_unsubscribe(&unsubscribe_link).await;
+
+    // Assert
+    assert!(form.contains("&amp;all=true"));
+    for slug in ["newsletter", "rust-weekly"] {
+        assert_eq!(
+            get_list_subscription_status(&app, slug, "ursula@example.com")
+                .await
+                .as_deref(),
+            Some("unsubscribed")
+        );
+    }
+}
+
+#[actix_web::test]
+async fn queued_issues_of_the_other_lists_are_still_delivered_after_leaving_one() {
+    // Arrange
+    let app = spawn_app().await;
+    create_list(&app, "rust-weekly").await;
+    create_confirmed_subscriber_with_email(&app, "ursula@example.com").await;
+    create_confirmed_list_subscriber(&app, "rust-weekly", "ursula@example.com").await;
+    let mock_guard = Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount_as_scoped(&app.email_server)
+        .await;
+    app.post_newsletters(newsletter_request_body(&["rust-weekly"]))
+        .await;
+    app.dispatch_all_pending_emails().await;
+    let email_request = app
+        .email_server
+        .received_requests()
+        .await
+        .unwrap()
+        .pop()
+        .unwrap();
+    let unsubscribe_link = app.get_unsubscribe_link(&email_request);
+    drop(mock_guard);
+    // Enqueued, but not delivered yet
+    app.post_newsletters(newsletter_request_body(&["rust-weekly"]))
+        .await;
+    app.post_newsletters(newsletter_request_body(&["newsletter"]))
+        .await;
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    // Act
+    app.post_unsubscribe(&unsubscribe_link).await;
+    app.dispatch_all_pending_emails().await;
+
+    // Assert
+    // Mock verifies on Drop that only the issue of the default list was sent
+}
+
+#[actix_web::test]
+async fn you_must_be_logged_in_to_manage_lists() {
+    // Arrange
+    let app = spawn_app().await;
+
+    // Act
+    let response = app
+        .post_admin_lists(&serde_json::json!({
+            "name": "Rust weekly",
+            "slug": "rust-weekly",
+        }))
+        .await;
+
+    // Assert
+    assert_is_redirect_to(&response, "/login");
+}
+
+#[actix_web::test]
+async fn admins_can_create_lists() {
+    // Arrange
+    let app = spawn_app().await;
+    app.test_user.login(&app).await;
+
+    // Act - Part 1 - Create a list
+    let response = app
+        .post_admin_lists(&serde_json::json!({
+            "name": "Rust weekly",
+            "slug": "rust-weekly",
+        }))
+        .await;
+    assert_is_redirect_to(&response, "/admin/lists");
+
+    // Assert - Part 1
+    let html_page = app.get_admin_lists_html().await;
+    assert!(html_page.contains("<p><i>The list has been created.</i></p>"));
+    assert!(html_page.contains(
+        "<tr><td>Rust weekly</td><td>/lists/rust-weekly/subscriptions</td><td>0</td><td>0</td></tr>"
+    ));
+
+    // Act - Part 2 - People can subscribe to it
+    subscribe_to_list(&app, "rust-weekly", "ursula@example.com").await;
+
+    // Assert - Part 2
+    let html_page = app.get_admin_lists_html().await;
+    assert!(html_page.contains(
+        "<tr><td>Rust weekly</td><td>/lists/rust-weekly/subscriptions</td><td>0</td><td>1</td></tr>"
+    ));
+}
+
+#[actix_web::test]
+async fn lists_must_have_a_valid_and_unique_slug() {
+    // Arrange
+    let app = spawn_app().await;
+    app.test_user.login(&app).await;
+
+    // Act - Part 1 - Invalid slug
+    let response = app
+        .post_admin_lists(&serde_json::json!({
+            "name": "Rust weekly",
+            "slug": "Rust Weekly",
+        }))
+        .await;
+    assert_is_redirect_to(&response, "/admin/lists");
+
+    // Assert - Part 1
+    let html_page = app.get_admin_lists_html().await;
+    assert!(html_page.contains(
+        "<p><i>The slug can only contain lowercase letters and digits, separated by single dashes.</i></p>"
+    ));
+
+    // Act - Part 2 - Slug already taken
+    app.post_admin_lists(&serde_json::json!({
+        "name": "Another newsletter",
+        "slug": "newsletter",
+    }))
+    .await;
+
+    // Assert - Part 2
+    let html_page = app.get_admin_lists_html().await;
+    assert!(html_page.contains("<p><i>There is already a list with this slug.</i></p>"));
+}
+
+#[actix_web::test]
+async fn drafts_are_sent_to_the_lists_picked_in_the_admin() {
+    // Arrange
+    let app = spawn_app().await;
+    create_list(&app, "rust-weekly").await;
+    create_list(&app, "go-weekly").await;
+    create_confirmed_subscriber_with_email(&app, "ursula@example.com").await;
+    create_confirmed_list_subscriber(&app, "rust-weekly", "rustacean@example.com").await;
+    create_confirmed_list_subscriber(&app, "go-weekly", "gopher@example.com").await;
+    app.test_user.login(&app).await;
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&app.email_server)
+        .await;
+
+    // Act
+    let response = app
+        .post_publish_newsletter(&serde_json::json!({
+            "title": "Newsletter title",
+            "text_content": "Newsletter body as plain text",
+            "html_content": "<p>Newsletter body as HTML</p>",
+            "lists": "rust-weekly, go-weekly",
+            "idempotency_key": Uuid::new_v4().to_string(),
+        }))
+        .await;
+    let newsletter_issue_id: Uuid = response
+        .headers()
+        .get("Location")
+        .unwrap()
+        .to_str()
+        .unwrap()
+        .strip_prefix("/admin/newsletters/")
+        .unwrap()
+        .parse()
+        .unwrap();
+    let html_page = app.get_admin_newsletter_html(newsletter_issue_id).await;
+    app.post_admin_newsletter_action(newsletter_issue_id, "publish", &serde_json::json!({}))
+        .await;
+    app.dispatch_all_pending_emails().await;
+
+    // Assert
+    assert!(html_page.contains("<p>Sent to: The go-weekly list, The rust-weekly list</p>"));
+    assert!(
+        html_page.contains(r#"<input type="text" name="lists" value="go-weekly, rust-weekly">"#)
+    );
+    assert_eq!(
+        get_newsletter_recipients(&app)
+            .await
+            .into_iter()
+            .collect::<HashSet<_>>(),
+        HashSet::from([
+            "rustacean@example.com".to_string(),
+            "gopher@example.com".to_string()
+        ])
+    );
+}
+
+#[actix_web::test]
+async fn drafts_cannot_be_sent_to_unknown_lists_from_the_admin() {
+    // Arrange
+    let app = spawn_app().await;
+    app.test_user.login(&app).await;
+
+    // Act
+    let response = app
+        .post_publish_newsletter(&serde_json::json!({
+            "title": "Newsletter title",
+            "text_content": "Newsletter body as plain text",
+            "html_content": "<p>Newsletter body as HTML</p>",
+            "lists": "newsletter, not-a-list",
+            "idempotency_key": Uuid::new_v4().to_string(),
+        }))
+        .await;
+
+    // Assert
+    assert_is_redirect_to(&response, "/admin/newsletters");
+    let html_page = app.get_publish_newsletter_html().await;
+    assert!(html_page.contains("<p><i>Some of the selected lists do not exist.</i></p>"));
+}
@@ -1,7 +1,19 @@
 mod admin_dashboard;
+mod admin_dead_letters;
+mod admin_newsletters;
+mod admin_subscribers;
+mod change_password;
+mod exports;
 mod health_check;
 mod helpers;
+mod issues;
+mod lists;
 mod login;
 mod newsletter;
+mod privacy;
+mod segments;
+mod subscriber_import;
 mod subscriptions;
 mod subscriptions_confirm;
+mod subscriptions_unsubscribe;
+mod webhooks;
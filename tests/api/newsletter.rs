@@ -1,7 +1,12 @@
-use crate::helpers::{spawn_app, ConfirmationLinks, TestApp};
+use crate::helpers::{
+    batch_response, create_confirmed_subscriber, create_confirmed_subscriber_with_email,
+    create_unconfirmed_subscriber, spawn_app, TestApp,
+};
+use std::time::Duration;
 use uuid::Uuid;
 use wiremock::matchers::{any, method, path};
-use wiremock::{Mock, ResponseTemplate};
+use wiremock::{Mock, Request, ResponseTemplate};
+use zero2prod::issue_scheduler::publish_due_issues;
 
 #[actix_web::test]
 async fn newsletters_are_not_delivered_to_unconfirmed_subscribers() {
@@ -28,6 +33,7 @@ async fn newsletters_are_not_delivered_to_unconfirmed_subscribers() {
          }
     });
     let response = app.post_newsletters(newsletter_request_body).await;
+    app.dispatch_all_pending_emails().await;
 
     // Assert
     assert_eq!(response.status().as_u16(), 200);
@@ -57,12 +63,94 @@ async fn newsletters_are_delivered_to_confirmed_subscribers() {
     });
 
     let response = app.post_newsletters(newsletter_request_body).await;
+    app.dispatch_all_pending_emails().await;
 
     // Assert
     assert_eq!(response.status().as_u16(), 200);
     // Mock verifies on Drop that we have sent the newsletter email
 }
 
+#[actix_web::test]
+async fn newsletters_are_personalised_for_each_subscriber() {
+    // Arrange
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    // Act
+    let newsletter_request_body = serde_json::json!({
+        "title": "Newsletter title",
+        "content": {
+            "text": "Hi {{name}}, welcome to the newsletter",
+            "html": "<p>Hi {{name}}, welcome to the newsletter</p>",
+        }
+    });
+    app.post_newsletters(newsletter_request_body).await;
+    app.dispatch_all_pending_emails().await;
+
+    // Assert
+    let email_request = app
+        .email_server
+        .received_requests()
+        .await
+        .unwrap()
+        .pop()
+        .unwrap();
+    let body: serde_json::Value = serde_json::from_slice(&email_request.body).unwrap();
+    assert!(body["HtmlBody"]
+        .as_str()
+        .unwrap()
+        .contains("<p>Hi le guin, welcome to the newsletter</p>"));
+    assert!(body["TextBody"]
+        .as_str()
+        .unwrap()
+        .starts_with("Hi le guin, welcome to the newsletter"));
+}
+
+#[actix_web::test]
+async fn the_plain_text_content_is_generated_when_missing() {
+    // Arrange
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    // Act
+    let newsletter_request_body = serde_json::json!({
+        "title": "Newsletter title",
+        "content": {
+            "html": "<h1>Newsletter title</h1><p>Newsletter body as <em>HTML</em></p>",
+        }
+    });
+    let response = app.post_newsletters(newsletter_request_body).await;
+    app.dispatch_all_pending_emails().await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 200);
+    let email_request = app
+        .email_server
+        .received_requests()
+        .await
+        .unwrap()
+        .pop()
+        .unwrap();
+    let body: serde_json::Value = serde_json::from_slice(&email_request.body).unwrap();
+    let text_body = body["TextBody"].as_str().unwrap();
+    assert!(text_body.starts_with("Newsletter title\n\nNewsletter body as HTML"));
+    assert!(text_body.contains("/subscriptions/unsubscribe?token="));
+}
+
 #[actix_web::test]
 async fn newsletters_return_400_for_invalid_data() {
     // Arrange
@@ -84,6 +172,15 @@ async fn newsletters_return_400_for_invalid_data() {
             }),
             "missing content",
         ),
+        (
+            serde_json::json!({
+                "title": "Newsletter title",
+                "content": {
+                    "html": "<p>Hi {{name</p>",
+                }
+            }),
+            "malformed variable",
+        ),
     ];
 
     for (invalid_body, error_message) in test_cases {
@@ -166,7 +263,7 @@ async fn invalid_password_is_rejected() {
     assert_ne!(app.test_user.password, password);
 
     let response = reqwest::Client::new()
-        .post(&format!("{}/newsletters", &app.address))
+        .post(format!("{}/newsletters", &app.address))
         .basic_auth(username, Some(password))
         .json(&serde_json::json!({
             "title": "Newsletter title",
@@ -187,41 +284,854 @@ async fn invalid_password_is_rejected() {
     );
 }
 
-/// Use the public API of the application under test to create
-/// an unconfirmed subscriber.
-async fn create_unconfirmed_subscriber(app: &TestApp) -> ConfirmationLinks {
-    let body = "name=le%20guin&email=ursula_le_guin%40gmail.com";
+#[actix_web::test]
+async fn newsletter_creation_is_idempotent() {
+    // Arrange
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
 
-    let _mock_guard = Mock::given(path("/email"))
+    Mock::given(path("/email"))
         .and(method("POST"))
         .respond_with(ResponseTemplate::new(200))
-        .named("Create unconfirmed subscriber")
         .expect(1)
-        .mount_as_scoped(&app.email_server)
+        .mount(&app.email_server)
         .await;
 
-    app.post_subscriptions(body.into())
-        .await
-        .error_for_status()
+    let newsletter_request_body = serde_json::json!({
+        "title": "Newsletter title",
+        "content": {
+            "text": "Newsletter body as plain text",
+            "html": "<p>Newsletter body as HTML</p>",
+        }
+    });
+    let idempotency_key = Uuid::new_v4().to_string();
+
+    // Act - Part 1 - Publish the newsletter
+    let response = app
+        .post_newsletters_with_idempotency_key(newsletter_request_body.clone(), &idempotency_key)
+        .await;
+    assert_eq!(response.status().as_u16(), 200);
+
+    // Act - Part 2 - Retry the same request
+    let response = app
+        .post_newsletters_with_idempotency_key(newsletter_request_body, &idempotency_key)
+        .await;
+    assert_eq!(response.status().as_u16(), 200);
+
+    app.dispatch_all_pending_emails().await;
+    // Mock verifies on Drop that we have sent the newsletter email **once**
+}
+
+#[actix_web::test]
+async fn concurrent_newsletter_submissions_are_handled_gracefully() {
+    // Arrange
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        // Setting a long delay to ensure that the second request
+        // arrives before the first one completes
+        .respond_with(ResponseTemplate::new(200).set_delay(Duration::from_secs(2)))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    let newsletter_request_body = serde_json::json!({
+        "title": "Newsletter title",
+        "content": {
+            "text": "Newsletter body as plain text",
+            "html": "<p>Newsletter body as HTML</p>",
+        }
+    });
+    let idempotency_key = Uuid::new_v4().to_string();
+
+    // Act - Submit two newsletter forms concurrently
+    let response1 = app
+        .post_newsletters_with_idempotency_key(newsletter_request_body.clone(), &idempotency_key);
+    let response2 =
+        app.post_newsletters_with_idempotency_key(newsletter_request_body, &idempotency_key);
+    let (response1, response2) = tokio::join!(response1, response2);
+
+    // Assert
+    assert_eq!(response1.status(), response2.status());
+    assert_eq!(
+        response1.text().await.unwrap(),
+        response2.text().await.unwrap()
+    );
+    app.dispatch_all_pending_emails().await;
+
+    // Mock verifies on Drop that we have sent the newsletter email **once**
+}
+
+#[actix_web::test]
+async fn newsletters_return_400_for_an_invalid_idempotency_key() {
+    // Arrange
+    let app = spawn_app().await;
+
+    let newsletter_request_body = serde_json::json!({
+        "title": "Newsletter title",
+        "content": {
+            "text": "Newsletter body as plain text",
+            "html": "<p>Newsletter body as HTML</p>",
+        }
+    });
+
+    // Act
+    let response = app
+        .post_newsletters_with_idempotency_key(newsletter_request_body, &"a".repeat(50))
+        .await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 400);
+}
+
+#[actix_web::test]
+async fn newsletters_are_delivered_with_a_single_batch_request() {
+    // Arrange
+    let app = spawn_app().await;
+    create_confirmed_subscriber_with_email(&app, "ursula_le_guin@gmail.com").await;
+    create_confirmed_subscriber_with_email(&app, "octavia_butler@gmail.com").await;
+    create_confirmed_subscriber_with_email(&app, "n_k_jemisin@gmail.com").await;
+
+    Mock::given(path("/email/batch"))
+        .and(method("POST"))
+        .respond_with(|request: &Request| batch_response(request, None))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    // Act
+    let newsletter_request_body = serde_json::json!({
+        "title": "Newsletter title",
+        "content": {
+            "text": "Newsletter body as plain text",
+            "html": "<p>Newsletter body as HTML</p>",
+        }
+    });
+    app.post_newsletters(newsletter_request_body).await;
+    app.dispatch_all_pending_emails().await;
+
+    // Assert
+    let received_requests = app.email_server.received_requests().await.unwrap();
+    let batch_request = received_requests
+        .iter()
+        .find(|r| r.url.path() == "/email/batch")
         .unwrap();
+    let messages: Vec<serde_json::Value> = serde_json::from_slice(&batch_request.body).unwrap();
+    let mut recipients = messages
+        .iter()
+        .map(|m| m["To"].as_str().unwrap())
+        .collect::<Vec<_>>();
+    recipients.sort();
+    assert_eq!(
+        recipients,
+        [
+            "n_k_jemisin@gmail.com",
+            "octavia_butler@gmail.com",
+            "ursula_le_guin@gmail.com"
+        ]
+    );
+    // Every recipient gets their own unsubscribe link
+    for message in &messages {
+        assert!(message["HtmlBody"]
+            .as_str()
+            .unwrap()
+            .contains("/subscriptions/unsubscribe?token="));
+    }
+}
 
-    let email_request = &app
-        .email_server
-        .received_requests()
+#[actix_web::test]
+async fn a_failed_delivery_does_not_block_other_subscribers() {
+    // Arrange
+    let app = spawn_app().await;
+    create_confirmed_subscriber_with_email(&app, "ursula_le_guin@gmail.com").await;
+    create_confirmed_subscriber_with_email(&app, "octavia_butler@gmail.com").await;
+
+    Mock::given(path("/email/batch"))
+        .and(method("POST"))
+        .respond_with(|request: &Request| batch_response(request, Some("ursula_le_guin@gmail.com")))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    // Act
+    let newsletter_request_body = serde_json::json!({
+        "title": "Newsletter title",
+        "content": {
+            "text": "Newsletter body as plain text",
+            "html": "<p>Newsletter body as HTML</p>",
+        }
+    });
+    let response = app.post_newsletters(newsletter_request_body).await;
+    app.dispatch_all_pending_emails().await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 200);
+    let n_pending_tasks = sqlx::query!("SELECT COUNT(*) as \"count!\" FROM issue_delivery_queue")
+        .fetch_one(&app.db_pool)
         .await
         .unwrap()
-        .pop()
+        .count;
+    assert_eq!(n_pending_tasks, 0);
+    // The rejected email is not retried
+    let dead_letter = sqlx::query!("SELECT subscriber_email FROM issue_delivery_dead_letters")
+        .fetch_one(&app.db_pool)
+        .await
         .unwrap();
+    assert_eq!(dead_letter.subscriber_email, "ursula_le_guin@gmail.com");
+}
+
+#[actix_web::test]
+async fn transient_delivery_failures_are_retried() {
+    // Arrange
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(500))
+        .up_to_n_times(1)
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    // Act
+    let newsletter_request_body = serde_json::json!({
+        "title": "Newsletter title",
+        "content": {
+            "text": "Newsletter body as plain text",
+            "html": "<p>Newsletter body as HTML</p>",
+        }
+    });
+    app.post_newsletters(newsletter_request_body).await;
+    app.dispatch_all_pending_emails().await;
 
-    app.get_confirmation_links(email_request)
+    // Assert
+    assert_eq!(count_dead_letters(&app).await, 0);
+    // Mock verifies on Drop that the email has been sent twice
 }
 
-async fn create_confirmed_subscriber(app: &TestApp) {
-    let confirmation_link = create_unconfirmed_subscriber(app).await;
+#[actix_web::test]
+async fn deliveries_claimed_by_a_worker_that_died_are_sent_once_the_claim_expires() {
+    // Arrange
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+    publish_issue(&app).await;
+    // A worker claimed the delivery, then died before recording anything
+    sqlx::query!("UPDATE issue_delivery_queue SET execute_after = now() + interval '5 minutes'")
+        .execute(&app.db_pool)
+        .await
+        .unwrap();
+
+    // Act - Part 1 - The claim holds
+    {
+        let _mock_guard = Mock::given(any())
+            .respond_with(ResponseTemplate::new(200))
+            .expect(0)
+            .mount_as_scoped(&app.email_server)
+            .await;
+        app.dispatch_all_pending_emails().await;
+    }
 
-    reqwest::get(confirmation_link.html)
+    // Act - Part 2 - The claim expires
+    sqlx::query!("UPDATE issue_delivery_queue SET execute_after = now()")
+        .execute(&app.db_pool)
         .await
-        .unwrap()
-        .error_for_status()
         .unwrap();
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+    app.dispatch_all_pending_emails().await;
+
+    // Assert
+    let n_pending_tasks =
+        sqlx::query_scalar!(r#"SELECT COUNT(*) AS "count!" FROM issue_delivery_queue"#)
+            .fetch_one(&app.db_pool)
+            .await
+            .unwrap();
+    assert_eq!(n_pending_tasks, 0);
+}
+
+#[actix_web::test]
+async fn deliveries_are_dead_lettered_after_max_attempts() {
+    // Arrange
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(503))
+        .expect(u64::from(app.retry_policy.max_attempts))
+        .mount(&app.email_server)
+        .await;
+
+    // Act
+    let newsletter_request_body = serde_json::json!({
+        "title": "Newsletter title",
+        "content": {
+            "text": "Newsletter body as plain text",
+            "html": "<p>Newsletter body as HTML</p>",
+        }
+    });
+    app.post_newsletters(newsletter_request_body).await;
+    app.dispatch_all_pending_emails().await;
+
+    // Assert
+    assert_eq!(count_dead_letters(&app).await, 1);
+}
+
+#[actix_web::test]
+async fn permanent_delivery_failures_are_not_retried() {
+    // Arrange
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(422))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    // Act
+    let newsletter_request_body = serde_json::json!({
+        "title": "Newsletter title",
+        "content": {
+            "text": "Newsletter body as plain text",
+            "html": "<p>Newsletter body as HTML</p>",
+        }
+    });
+    app.post_newsletters(newsletter_request_body).await;
+    app.dispatch_all_pending_emails().await;
+
+    // Assert
+    assert_eq!(count_dead_letters(&app).await, 1);
+}
+
+#[actix_web::test]
+async fn an_issue_that_cannot_be_prepared_does_not_hold_up_the_others() {
+    // Arrange
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+    let broken_issue_id = publish_issue(&app).await;
+    let newsletter_issue_id = publish_issue(&app).await;
+    // The worker can no longer load the first issue
+    sqlx::query!(
+        "UPDATE newsletter_issues SET slug = NULL WHERE newsletter_issue_id = $1",
+        broken_issue_id
+    )
+    .execute(&app.db_pool)
+    .await
+    .unwrap();
+
+    // Act
+    app.dispatch_all_pending_emails().await;
+
+    // Assert
+    assert_eq!(count_dead_letters(&app).await, 1);
+    assert_eq!(app.get_newsletter_stats(broken_issue_id).await["failed"], 1);
+    assert_eq!(
+        app.get_newsletter_stats(newsletter_issue_id).await["sent"],
+        1
+    );
+}
+
+#[actix_web::test]
+async fn newsletters_with_a_send_at_are_scheduled() {
+    // Arrange
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    // Act - Part 1 - Schedule the issue
+    let newsletter_request_body = serde_json::json!({
+        "title": "Newsletter title",
+        "content": {
+            "html": "<p>Newsletter body as HTML</p>",
+        },
+        "send_at": chrono::Utc::now() + chrono::Duration::hours(1),
+    });
+    let response = app.post_newsletters(newsletter_request_body).await;
+    assert_eq!(response.status().as_u16(), 200);
+    let body: serde_json::Value = response.json().await.unwrap();
+    assert_eq!(body["status"], "scheduled");
+
+    // Act - Part 2 - Nothing goes out before `send_at`
+    app.dispatch_all_pending_emails().await;
+    assert_eq!(publish_due_issues(&app.db_pool).await.unwrap(), 0);
+
+    // Act - Part 3 - `send_at` has come
+    sqlx::query!("UPDATE newsletter_issues SET send_at = now() - interval '1 minute'")
+        .execute(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(publish_due_issues(&app.db_pool).await.unwrap(), 1);
+    app.dispatch_all_pending_emails().await;
+
+    // Mock verifies on Drop that we have sent the newsletter email once
+}
+
+#[actix_web::test]
+async fn a_scheduled_issue_that_fails_to_publish_does_not_hold_up_the_others() {
+    // Arrange
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+    let broken_issue_id = create_scheduled_issue(&app).await;
+    let newsletter_issue_id = create_scheduled_issue(&app).await;
+    // A segment that no longer parses, e.g. stored by an older version
+    sqlx::query!(
+        "UPDATE newsletter_issues SET segment = 'tag:' WHERE newsletter_issue_id = $1",
+        broken_issue_id
+    )
+    .execute(&app.db_pool)
+    .await
+    .unwrap();
+    sqlx::query!("UPDATE newsletter_issues SET send_at = now() - interval '1 minute'")
+        .execute(&app.db_pool)
+        .await
+        .unwrap();
+
+    // Act
+    let n_published = publish_due_issues(&app.db_pool).await.unwrap();
+    app.dispatch_all_pending_emails().await;
+
+    // Assert
+    assert_eq!(n_published, 1);
+    let body: serde_json::Value = app
+        .get_newsletter(newsletter_issue_id)
+        .await
+        .json()
+        .await
+        .unwrap();
+    assert_eq!(body["status"], "published");
+    let body: serde_json::Value = app
+        .get_newsletter(broken_issue_id)
+        .await
+        .json()
+        .await
+        .unwrap();
+    assert_eq!(body["status"], "scheduled");
+}
+
+#[actix_web::test]
+async fn newsletters_cannot_be_scheduled_in_the_past() {
+    // Arrange
+    let app = spawn_app().await;
+
+    // Act
+    let response = app
+        .post_newsletters(serde_json::json!({
+            "title": "Newsletter title",
+            "content": {
+                "html": "<p>Newsletter body as HTML</p>",
+            },
+            "send_at": chrono::Utc::now() - chrono::Duration::hours(1),
+        }))
+        .await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 400);
+}
+
+#[actix_web::test]
+async fn scheduled_newsletters_can_be_cancelled() {
+    // Arrange
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+    let response = app
+        .post_newsletters(serde_json::json!({
+            "title": "Newsletter title",
+            "content": {
+                "html": "<p>Newsletter body as HTML</p>",
+            },
+            "send_at": chrono::Utc::now() + chrono::Duration::hours(1),
+        }))
+        .await;
+    let body: serde_json::Value = response.json().await.unwrap();
+    let newsletter_issue_id: Uuid = body["newsletter_issue_id"]
+        .as_str()
+        .unwrap()
+        .parse()
+        .unwrap();
+
+    Mock::given(any())
+        .respond_with(ResponseTemplate::new(200))
+        .expect(0)
+        .mount(&app.email_server)
+        .await;
+
+    // Act
+    let response = app.post_cancel_newsletter(newsletter_issue_id).await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 200);
+    sqlx::query!("UPDATE newsletter_issues SET send_at = now() - interval '1 minute'")
+        .execute(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(publish_due_issues(&app.db_pool).await.unwrap(), 0);
+    app.dispatch_all_pending_emails().await;
+    // Only scheduled issues can be cancelled
+    let response = app.post_cancel_newsletter(newsletter_issue_id).await;
+    assert_eq!(response.status().as_u16(), 409);
+    let response = app.post_cancel_newsletter(Uuid::new_v4()).await;
+    assert_eq!(response.status().as_u16(), 404);
+}
+
+#[actix_web::test]
+async fn newsletters_without_a_send_at_are_published_straight_away() {
+    // Arrange
+    let app = spawn_app().await;
+
+    // Act
+    let response = app
+        .post_newsletters(serde_json::json!({
+            "title": "Newsletter title",
+            "content": {
+                "html": "<p>Newsletter body as HTML</p>",
+            },
+        }))
+        .await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 200);
+    let body: serde_json::Value = response.json().await.unwrap();
+    assert_eq!(body["status"], "published");
+    let response = app
+        .post_cancel_newsletter(
+            body["newsletter_issue_id"]
+                .as_str()
+                .unwrap()
+                .parse()
+                .unwrap(),
+        )
+        .await;
+    assert_eq!(response.status().as_u16(), 409);
+}
+
+/// Schedule an issue through the API and return its id.
+async fn create_scheduled_issue(app: &TestApp) -> Uuid {
+    let response = app
+        .post_newsletters(serde_json::json!({
+            "title": "Newsletter title",
+            "content": {
+                "html": "<p>Hello {{name}}</p>",
+            },
+            "send_at": chrono::Utc::now() + chrono::Duration::hours(1),
+        }))
+        .await;
+    let body: serde_json::Value = response.json().await.unwrap();
+    body["newsletter_issue_id"]
+        .as_str()
+        .unwrap()
+        .parse()
+        .unwrap()
+}
+
+#[actix_web::test]
+async fn test_issues_are_only_sent_to_the_given_addresses() {
+    // Arrange
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+    let newsletter_issue_id = create_scheduled_issue(&app).await;
+
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    // Act
+    let response = app
+        .post_test_newsletter(
+            newsletter_issue_id,
+            &serde_json::json!({ "recipients": ["editor@example.com"] }),
+        )
+        .await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 200);
+    // The first request is the confirmation email of the subscriber
+    let email_requests = app.email_server.received_requests().await.unwrap();
+    let body: serde_json::Value =
+        serde_json::from_slice(&email_requests.last().unwrap().body).unwrap();
+    assert_eq!(body["To"], "editor@example.com");
+    assert_eq!(body["Subject"], "[TEST] Newsletter title");
+    assert!(body["HtmlBody"]
+        .as_str()
+        .unwrap()
+        .contains(&format!("<p>Hello {}</p>", app.test_user.username)));
+    // Nothing is recorded as a delivery
+    let n_queued = sqlx::query_scalar!(r#"SELECT count(*) as "count!" FROM issue_delivery_queue"#)
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(n_queued, 0);
+    let status = sqlx::query_scalar!("SELECT status FROM newsletter_issues")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(status, "scheduled");
+}
+
+#[actix_web::test]
+async fn test_issues_return_400_for_invalid_recipients() {
+    // Arrange
+    let app = spawn_app().await;
+    let newsletter_issue_id = create_scheduled_issue(&app).await;
+    let test_cases = vec![
+        (serde_json::json!({ "recipients": [] }), "no recipients"),
+        (
+            serde_json::json!({ "recipients": ["editor@example.com", "not-an-email"] }),
+            "an invalid address",
+        ),
+        (
+            serde_json::json!({ "recipients": vec!["editor@example.com"; 11] }),
+            "too many recipients",
+        ),
+        (serde_json::json!({}), "missing recipients"),
+    ];
+
+    Mock::given(any())
+        .respond_with(ResponseTemplate::new(200))
+        .expect(0)
+        .mount(&app.email_server)
+        .await;
+
+    for (body, description) in test_cases {
+        // Act
+        let response = app.post_test_newsletter(newsletter_issue_id, &body).await;
+
+        // Assert
+        assert_eq!(
+            response.status().as_u16(),
+            400,
+            "The API did not fail with 400 Bad Request when the payload had {}.",
+            description
+        );
+    }
+}
+
+#[actix_web::test]
+async fn test_issues_return_404_for_an_unknown_issue() {
+    // Arrange
+    let app = spawn_app().await;
+
+    // Act
+    let response = app
+        .post_test_newsletter(
+            Uuid::new_v4(),
+            &serde_json::json!({ "recipients": ["editor@example.com"] }),
+        )
+        .await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 404);
+}
+
+/// Publish an issue through the API and return its id.
+async fn publish_issue(app: &TestApp) -> Uuid {
+    let response = app
+        .post_newsletters(serde_json::json!({
+            "title": "Newsletter title",
+            "content": {
+                "html": "<p>Newsletter body as HTML</p>",
+            },
+        }))
+        .await;
+    let body: serde_json::Value = response.json().await.unwrap();
+    body["newsletter_issue_id"]
+        .as_str()
+        .unwrap()
+        .parse()
+        .unwrap()
+}
+
+#[actix_web::test]
+async fn delivery_statistics_are_reported_per_issue() {
+    // Arrange
+    let app = spawn_app().await;
+    create_confirmed_subscriber_with_email(&app, "ursula_le_guin@gmail.com").await;
+    create_confirmed_subscriber_with_email(&app, "octavia_butler@gmail.com").await;
+    create_confirmed_subscriber_with_email(&app, "n_k_jemisin@gmail.com").await;
+    // An address stored before it was validated
+    sqlx::query!(
+        "UPDATE subscriptions SET email = 'not-an-email' WHERE email = 'n_k_jemisin@gmail.com'"
+    )
+    .execute(&app.db_pool)
+    .await
+    .unwrap();
+
+    Mock::given(path("/email/batch"))
+        .and(method("POST"))
+        .respond_with(|request: &Request| batch_response(request, Some("ursula_le_guin@gmail.com")))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    // Act
+    let newsletter_issue_id = publish_issue(&app).await;
+    app.dispatch_all_pending_emails().await;
+
+    // Assert
+    let stats = app.get_newsletter_stats(newsletter_issue_id).await;
+    assert_eq!(
+        stats,
+        serde_json::json!({
+            "queued": 3,
+            "sent": 1,
+            "failed": 1,
+            "skipped_invalid": 1,
+            "skipped_unsubscribed": 0,
+            "bounced": 0,
+        })
+    );
+    // The emails carry the issue they belong to, for bounces to be attributed to it
+    let email_request = app
+        .email_server
+        .received_requests()
+        .await
+        .unwrap()
+        .into_iter()
+        .find(|r| r.url.path() == "/email/batch")
+        .unwrap();
+    let messages: Vec<serde_json::Value> = serde_json::from_slice(&email_request.body).unwrap();
+    for message in messages {
+        assert_eq!(
+            message["Metadata"]["newsletter_issue_id"],
+            newsletter_issue_id.to_string()
+        );
+    }
+}
+
+#[actix_web::test]
+async fn deliveries_to_subscribers_who_left_are_counted_as_skipped() {
+    // Arrange
+    let app = spawn_app().await;
+    create_confirmed_subscriber_with_email(&app, "octavia_butler@gmail.com").await;
+    // An earlier issue gives us the unsubscribe link of the subscriber
+    let unsubscribe_link = {
+        let _mock_guard = Mock::given(path("/email"))
+            .and(method("POST"))
+            .respond_with(ResponseTemplate::new(200))
+            .expect(1)
+            .mount_as_scoped(&app.email_server)
+            .await;
+        publish_issue(&app).await;
+        app.dispatch_all_pending_emails().await;
+        let email_request = app
+            .email_server
+            .received_requests()
+            .await
+            .unwrap()
+            .pop()
+            .unwrap();
+        app.get_unsubscribe_link(&email_request)
+    };
+    create_confirmed_subscriber_with_email(&app, "ursula_le_guin@gmail.com").await;
+    let newsletter_issue_id = publish_issue(&app).await;
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    // Act
+    // They leave after the issue was enqueued
+    let response = app.post_unsubscribe(&unsubscribe_link).await;
+    assert_eq!(response.status().as_u16(), 200);
+    app.dispatch_all_pending_emails().await;
+
+    // Assert
+    let stats = app.get_newsletter_stats(newsletter_issue_id).await;
+    assert_eq!(stats["sent"], 1);
+    assert_eq!(stats["skipped_unsubscribed"], 1);
+    app.test_user.login(&app).await;
+    let html_page = app.get_admin_newsletter_html(newsletter_issue_id).await;
+    assert!(html_page.contains("<th>Skipped (unsubscribed)</th>"));
+}
+
+#[actix_web::test]
+async fn unpublished_issues_have_no_statistics() {
+    // Arrange
+    let app = spawn_app().await;
+    let newsletter_issue_id = create_scheduled_issue(&app).await;
+
+    // Act
+    let response = app.get_newsletter(newsletter_issue_id).await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 200);
+    let body: serde_json::Value = response.json().await.unwrap();
+    assert_eq!(body["status"], "scheduled");
+    assert_eq!(body["stats"], serde_json::Value::Null);
+    let response = app.get_newsletter(Uuid::new_v4()).await;
+    assert_eq!(response.status().as_u16(), 404);
+}
+
+#[actix_web::test]
+async fn redriven_deliveries_no_longer_count_as_failed() {
+    // Arrange
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+    app.test_user.login(&app).await;
+
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(422))
+        .mount(&app.email_server)
+        .await;
+    let newsletter_issue_id = publish_issue(&app).await;
+    app.dispatch_all_pending_emails().await;
+    assert_eq!(
+        app.get_newsletter_stats(newsletter_issue_id).await["failed"],
+        1
+    );
+
+    // Act
+    app.post_redrive_dead_letters(&serde_json::json!({})).await;
+
+    // Assert
+    assert_eq!(
+        app.get_newsletter_stats(newsletter_issue_id).await["failed"],
+        0
+    );
+}
+
+async fn count_dead_letters(app: &TestApp) -> i64 {
+    sqlx::query!("SELECT COUNT(*) as \"count!\" FROM issue_delivery_dead_letters")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap()
+        .count
 }
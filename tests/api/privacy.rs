@@ -0,0 +1,347 @@
+use crate::helpers::{
+    batch_response, create_confirmed_subscriber_with_email, spawn_app, spawn_app_with, TestApp,
+};
+use chrono::{Duration, Utc};
+use uuid::Uuid;
+use wiremock::matchers::{any, method, path};
+use wiremock::{Mock, Request, ResponseTemplate};
+use zero2prod::configuration::EmailTransport;
+use zero2prod::domain::{PrivacyToken, UnsubscribeToken};
+
+async fn get_subscriber_id(app: &TestApp, email: &str) -> Uuid {
+    sqlx::query_scalar!("SELECT id FROM subscriptions WHERE email = $1", email)
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap()
+}
+
+/// Ask for the privacy link of the address, and return the token it carries.
+async fn request_privacy_token(app: &TestApp, email: &str) -> String {
+    let _mock_guard = Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount_as_scoped(&app.email_server)
+        .await;
+    app.post_privacy_request(email)
+        .await
+        .error_for_status()
+        .unwrap();
+
+    let email_request = app
+        .email_server
+        .received_requests()
+        .await
+        .unwrap()
+        .pop()
+        .unwrap();
+    let privacy_link = app.get_confirmation_links(&email_request).html;
+    assert_eq!(privacy_link.path(), "/privacy/manage");
+    privacy_link
+        .query_pairs()
+        .find(|(key, _)| key == "token")
+        .unwrap()
+        .1
+        .into_owned()
+}
+
+/// Publish an issue and deliver it, then record a bounce for `bounced_email`.
+async fn deliver_issue(app: &TestApp, bounced_email: &str) -> Uuid {
+    Mock::given(path("/email/batch"))
+        .and(method("POST"))
+        .respond_with(|request: &Request| batch_response(request, None))
+        .mount(&app.email_server)
+        .await;
+    let response = app
+        .post_newsletters(serde_json::json!({
+            "title": "Newsletter title",
+            "content": {
+                "html": "<p>Newsletter body as HTML</p>",
+            },
+        }))
+        .await;
+    let body: serde_json::Value = response.json().await.unwrap();
+    let newsletter_issue_id: Uuid = body["newsletter_issue_id"]
+        .as_str()
+        .unwrap()
+        .parse()
+        .unwrap();
+    app.dispatch_all_pending_emails().await;
+    sqlx::query!(
+        r#"
+        INSERT INTO delivery_events (
+            event_id, record_type, message_id, subscriber_email, newsletter_issue_id,
+            bounce_type, details, occurred_at, received_at
+        )
+        VALUES ($1, 'Bounce', 'message-id', $2, $3, 'SoftBounce', $4, now(), now())
+        "#,
+        Uuid::new_v4(),
+        bounced_email,
+        newsletter_issue_id,
+        format!("The mailbox of {} is full", bounced_email)
+    )
+    .execute(&app.db_pool)
+    .await
+    .unwrap();
+    newsletter_issue_id
+}
+
+#[actix_web::test]
+async fn subscribers_receive_a_link_to_their_data() {
+    // Arrange
+    let app = spawn_app().await;
+    create_confirmed_subscriber_with_email(&app, "ursula@example.com").await;
+
+    // Act
+    let token = request_privacy_token(&app, "ursula@example.com").await;
+    let response = app.get_privacy_page(&token).await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 200);
+    let html_page = response.text().await.unwrap();
+    assert!(html_page.contains(r#"<a href="/privacy/data?token="#));
+    assert!(html_page.contains(r#"<form action="/privacy/erase?token="#));
+}
+
+#[actix_web::test]
+async fn unknown_addresses_get_the_same_answer_but_no_email() {
+    // Arrange
+    let app = spawn_app().await;
+    Mock::given(any())
+        .respond_with(ResponseTemplate::new(200))
+        .expect(0)
+        .mount(&app.email_server)
+        .await;
+
+    // Act
+    let response = app.post_privacy_request("ursula@example.com").await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 200);
+    assert!(response
+        .text()
+        .await
+        .unwrap()
+        .contains("If we store anything about this address"));
+}
+
+#[actix_web::test]
+async fn a_failure_to_send_the_link_gets_the_same_answer() {
+    // Arrange
+    let app = spawn_app().await;
+    create_confirmed_subscriber_with_email(&app, "ursula@example.com").await;
+    Mock::given(any())
+        .respond_with(ResponseTemplate::new(500))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    // Act
+    let response = app.post_privacy_request("ursula@example.com").await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 200);
+    assert!(response
+        .text()
+        .await
+        .unwrap()
+        .contains("If we store anything about this address"));
+}
+
+#[actix_web::test]
+async fn links_are_not_sent_again_to_the_same_address_right_away() {
+    // Arrange
+    let app = spawn_app().await;
+    create_confirmed_subscriber_with_email(&app, "ursula@example.com").await;
+    request_privacy_token(&app, "ursula@example.com").await;
+    Mock::given(any())
+        .respond_with(ResponseTemplate::new(200))
+        .expect(0)
+        .mount(&app.email_server)
+        .await;
+
+    // Act
+    let response = app.post_privacy_request("ursula@example.com").await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 200);
+    assert!(response
+        .text()
+        .await
+        .unwrap()
+        .contains("If we store anything about this address"));
+}
+
+#[actix_web::test]
+async fn links_can_be_kept_in_memory_instead_of_sent() {
+    // Arrange
+    let app = spawn_app_with(|c| c.email_client.transport = EmailTransport::InMemory).await;
+    sqlx::query!(
+        r#"
+        INSERT INTO subscriptions (id, email, name, subscribed_at, status)
+        VALUES ($1, 'ursula@example.com', 'Ursula', now(), 'confirmed')
+        "#,
+        Uuid::new_v4()
+    )
+    .execute(&app.db_pool)
+    .await
+    .unwrap();
+    Mock::given(any())
+        .respond_with(ResponseTemplate::new(200))
+        .expect(0)
+        .mount(&app.email_server)
+        .await;
+
+    // Act
+    let response = app.post_privacy_request("ursula@example.com").await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 200);
+    let sent_emails = app.email_outbox.as_ref().unwrap().sent_emails();
+    assert_eq!(sent_emails.len(), 1);
+    assert_eq!(sent_emails[0].to, "ursula@example.com");
+    assert!(sent_emails[0].html_body.contains("/privacy/manage?token="));
+}
+
+#[actix_web::test]
+async fn an_invalid_address_is_rejected() {
+    // Arrange
+    let app = spawn_app().await;
+
+    // Act
+    let response = app.post_privacy_request("not-an-email").await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 400);
+}
+
+#[actix_web::test]
+async fn the_data_export_contains_everything_stored_about_the_subscriber() {
+    // Arrange
+    let app = spawn_app().await;
+    create_confirmed_subscriber_with_email(&app, "ursula@example.com").await;
+    create_confirmed_subscriber_with_email(&app, "ferris@example.com").await;
+    let newsletter_issue_id = deliver_issue(&app, "ursula@example.com").await;
+    let token = request_privacy_token(&app, "ursula@example.com").await;
+
+    // Act
+    let response = app.get_privacy_data(&token).await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 200);
+    assert_eq!(
+        response.headers()["Content-Disposition"],
+        "attachment; filename=\"my-data.json\""
+    );
+    let data: serde_json::Value = response.json().await.unwrap();
+    assert_eq!(data["subscriber"]["email"], "ursula@example.com");
+    assert_eq!(data["subscriber"]["status"], "confirmed");
+    assert_eq!(data["list_subscriptions"][0]["list"], "newsletter");
+    assert_eq!(data["list_subscriptions"][0]["status"], "confirmed");
+    assert_eq!(data["subscription_tokens"].as_array().unwrap().len(), 1);
+    assert!(data["subscription_tokens"][0]["consumed_at"].is_string());
+    assert_eq!(
+        data["deliveries"],
+        serde_json::json!([{
+            "newsletter_issue_id": newsletter_issue_id,
+            "issue_title": "Newsletter title",
+            "outcome": "sent",
+            "n_attempts": 1,
+            "recorded_at": data["deliveries"][0]["recorded_at"],
+            "last_error": null,
+        }])
+    );
+    assert_eq!(data["delivery_events"][0]["record_type"], "Bounce");
+    assert_eq!(data["delivery_events"][0]["bounce_type"], "SoftBounce");
+    // Nothing about the other subscribers
+    assert!(!data.to_string().contains("ferris@example.com"));
+}
+
+#[actix_web::test]
+async fn the_privacy_page_does_not_erase_anything() {
+    // Arrange
+    let app = spawn_app().await;
+    create_confirmed_subscriber_with_email(&app, "ursula@example.com").await;
+    let token = request_privacy_token(&app, "ursula@example.com").await;
+
+    // Act
+    app.get_privacy_page(&token).await;
+    app.get_privacy_data(&token).await;
+
+    // Assert
+    get_subscriber_id(&app, "ursula@example.com").await;
+}
+
+#[actix_web::test]
+async fn erasure_removes_the_subscriber_and_pseudonymizes_the_deliveries() {
+    // Arrange
+    let app = spawn_app().await;
+    create_confirmed_subscriber_with_email(&app, "ursula@example.com").await;
+    create_confirmed_subscriber_with_email(&app, "ferris@example.com").await;
+    let newsletter_issue_id = deliver_issue(&app, "ursula@example.com").await;
+    let stats_before = app.get_newsletter_stats(newsletter_issue_id).await;
+    let token = request_privacy_token(&app, "ursula@example.com").await;
+
+    // Act
+    let response = app.post_privacy_erase(&token).await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 200);
+    let n_subscribers = sqlx::query_scalar!(
+        r#"SELECT COUNT(*) AS "count!" FROM subscriptions WHERE email = 'ursula@example.com'"#
+    )
+    .fetch_one(&app.db_pool)
+    .await
+    .unwrap();
+    assert_eq!(n_subscribers, 0);
+    let deliveries = sqlx::query_scalar!(
+        "SELECT subscriber_email FROM issue_delivery_results WHERE newsletter_issue_id = $1",
+        newsletter_issue_id
+    )
+    .fetch_all(&app.db_pool)
+    .await
+    .unwrap();
+    assert_eq!(deliveries.len(), 2);
+    assert!(deliveries.contains(&"ferris@example.com".to_string()));
+    assert!(!deliveries.contains(&"ursula@example.com".to_string()));
+    let event = sqlx::query!("SELECT subscriber_email, details FROM delivery_events")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert!(event.subscriber_email.starts_with("erased-"));
+    assert!(!event.details.unwrap().contains("ursula@example.com"));
+    assert_eq!(
+        app.get_newsletter_stats(newsletter_issue_id).await,
+        stats_before
+    );
+    // The link is now useless
+    assert_eq!(app.get_privacy_data(&token).await.status().as_u16(), 404);
+    assert_eq!(app.post_privacy_erase(&token).await.status().as_u16(), 200);
+}
+
+#[actix_web::test]
+async fn invalid_expired_or_unsubscribe_tokens_are_rejected() {
+    // Arrange
+    let app = spawn_app().await;
+    create_confirmed_subscriber_with_email(&app, "ursula@example.com").await;
+    let subscriber_id = get_subscriber_id(&app, "ursula@example.com").await;
+    let expired = PrivacyToken::generate(
+        subscriber_id,
+        Utc::now() - Duration::minutes(1),
+        &app.hmac_secret,
+    );
+    let unsubscribe = UnsubscribeToken::generate(subscriber_id, &app.hmac_secret);
+
+    for token in ["not-a-token", expired.as_ref(), unsubscribe.as_ref()] {
+        // Act
+        let page = app.get_privacy_page(token).await;
+        let data = app.get_privacy_data(token).await;
+        let erase = app.post_privacy_erase(token).await;
+
+        // Assert
+        assert_eq!(page.status().as_u16(), 401);
+        assert_eq!(data.status().as_u16(), 401);
+        assert_eq!(erase.status().as_u16(), 401);
+    }
+    get_subscriber_id(&app, "ursula@example.com").await;
+}
@@ -0,0 +1,427 @@
+use crate::helpers::{
+    assert_is_redirect_to, batch_response, create_confirmed_subscriber_with_email, spawn_app,
+    TestApp,
+};
+use std::collections::HashSet;
+use uuid::Uuid;
+use wiremock::matchers::{any, method, path};
+use wiremock::{Mock, Request, ResponseTemplate};
+
+async fn get_tags(app: &TestApp, email: &str) -> Vec<String> {
+    sqlx::query_scalar!("SELECT tags FROM subscriptions WHERE email = $1", email)
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap()
+}
+
+async fn get_attributes(app: &TestApp, email: &str) -> serde_json::Value {
+    sqlx::query_scalar!(
+        "SELECT attributes FROM subscriptions WHERE email = $1",
+        email
+    )
+    .fetch_one(&app.db_pool)
+    .await
+    .unwrap()
+}
+
+async fn get_subscriber_id(app: &TestApp, email: &str) -> Uuid {
+    sqlx::query_scalar!("SELECT id FROM subscriptions WHERE email = $1", email)
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap()
+}
+
+async fn get_newsletter_recipients(app: &TestApp) -> HashSet<String> {
+    app.email_server
+        .received_requests()
+        .await
+        .unwrap()
+        .iter()
+        .flat_map(
+            |r| match serde_json::from_slice::<serde_json::Value>(&r.body).unwrap() {
+                serde_json::Value::Array(messages) => messages,
+                message => vec![message],
+            },
+        )
+        .filter(|body| body["Subject"] == "Newsletter title")
+        .map(|body| body["To"].as_str().unwrap().to_owned())
+        .collect()
+}
+
+/// Three confirmed subscribers:
+/// - ursula@example.com, tagged `rust`, in Germany;
+/// - ferris@example.com, tagged `rust` and `go`, in France;
+/// - gopher@example.com, tagged `go`, in Germany.
+async fn create_tagged_subscribers(app: &TestApp) {
+    for email in [
+        "ursula@example.com",
+        "ferris@example.com",
+        "gopher@example.com",
+    ] {
+        create_confirmed_subscriber_with_email(app, email).await;
+    }
+    let updates = [
+        (
+            serde_json::json!(["ursula@example.com", "ferris@example.com"]),
+            "rust",
+            "DE",
+        ),
+        (serde_json::json!(["gopher@example.com"]), "go", "DE"),
+    ];
+    for (emails, tag, country) in updates {
+        app.post_subscriber_tags(serde_json::json!({ "emails": emails, "add": [tag] }))
+            .await
+            .error_for_status()
+            .unwrap();
+        app.post_subscriber_attributes(serde_json::json!({
+            "emails": emails,
+            "set": { "country": country },
+        }))
+        .await
+        .error_for_status()
+        .unwrap();
+    }
+    app.post_subscriber_tags(serde_json::json!({
+        "emails": ["ferris@example.com"],
+        "add": ["go"],
+    }))
+    .await
+    .error_for_status()
+    .unwrap();
+    app.post_subscriber_attributes(serde_json::json!({
+        "emails": ["ferris@example.com"],
+        "set": { "country": "FR" },
+    }))
+    .await
+    .error_for_status()
+    .unwrap();
+}
+
+#[actix_web::test]
+async fn bulk_updates_require_authentication() {
+    // Arrange
+    let app = spawn_app().await;
+    let body = serde_json::json!({ "emails": ["ursula@example.com"], "add": ["rust"] });
+
+    for path in ["tags", "attributes"] {
+        // Act
+        let response = reqwest::Client::new()
+            .post(format!("{}/subscribers/{}", &app.address, path))
+            .json(&body)
+            .send()
+            .await
+            .expect("failed to execute request");
+
+        // Assert
+        assert_eq!(401, response.status().as_u16());
+    }
+}
+
+#[actix_web::test]
+async fn tags_are_added_and_removed_by_email() {
+    // Arrange
+    let app = spawn_app().await;
+    create_confirmed_subscriber_with_email(&app, "ursula@example.com").await;
+    create_confirmed_subscriber_with_email(&app, "ferris@example.com").await;
+
+    // Act
+    let response = app
+        .post_subscriber_tags(serde_json::json!({
+            "emails": ["ursula@example.com", "ferris@example.com"],
+            "add": ["Rust", "beta"],
+        }))
+        .await;
+    let n_subscribers: serde_json::Value = response.json().await.unwrap();
+    app.post_subscriber_tags(serde_json::json!({
+        "emails": ["ursula@example.com"],
+        "add": ["rust"],
+        "remove": ["beta"],
+    }))
+    .await
+    .error_for_status()
+    .unwrap();
+
+    // Assert
+    assert_eq!(n_subscribers["n_subscribers"], 2);
+    assert_eq!(get_tags(&app, "ursula@example.com").await, vec!["rust"]);
+    assert_eq!(
+        get_tags(&app, "ferris@example.com").await,
+        vec!["beta", "rust"]
+    );
+}
+
+#[actix_web::test]
+async fn tags_are_updated_for_the_subscribers_of_a_segment() {
+    // Arrange
+    let app = spawn_app().await;
+    create_tagged_subscribers(&app).await;
+
+    // Act
+    let response = app
+        .post_subscriber_tags(serde_json::json!({
+            "segment": "tag:go AND country = DE",
+            "add": ["gopher-de"],
+        }))
+        .await;
+    let body: serde_json::Value = response.json().await.unwrap();
+
+    // Assert
+    assert_eq!(body["n_subscribers"], 1);
+    assert_eq!(
+        get_tags(&app, "gopher@example.com").await,
+        vec!["go", "gopher-de"]
+    );
+    assert_eq!(
+        get_tags(&app, "ferris@example.com").await,
+        vec!["go", "rust"]
+    );
+}
+
+#[actix_web::test]
+async fn bulk_updates_with_invalid_data_are_rejected() {
+    // Arrange
+    let app = spawn_app().await;
+    let test_cases = vec![
+        (
+            serde_json::json!({ "add": ["rust"] }),
+            "no subscribers selected",
+        ),
+        (
+            serde_json::json!({
+                "emails": ["ursula@example.com"],
+                "segment": "tag:rust",
+                "add": ["rust"],
+            }),
+            "both emails and a segment",
+        ),
+        (
+            serde_json::json!({ "segment": "tag:rust AND", "add": ["rust"] }),
+            "invalid segment",
+        ),
+        (
+            serde_json::json!({ "emails": ["ursula@example.com"], "add": ["rust lang"] }),
+            "invalid tag",
+        ),
+    ];
+
+    for (body, description) in test_cases {
+        // Act
+        let response = app.post_subscriber_tags(body).await;
+
+        // Assert
+        assert_eq!(
+            400,
+            response.status().as_u16(),
+            "The API did not fail with 400 Bad Request when the payload had {}.",
+            description
+        );
+    }
+}
+
+#[actix_web::test]
+async fn attributes_are_set_and_unset() {
+    // Arrange
+    let app = spawn_app().await;
+    create_confirmed_subscriber_with_email(&app, "ursula@example.com").await;
+    app.post_subscriber_attributes(serde_json::json!({
+        "emails": ["ursula@example.com"],
+        "set": { "country": "DE", "age": 42, "vip": true },
+    }))
+    .await
+    .error_for_status()
+    .unwrap();
+
+    // Act
+    app.post_subscriber_attributes(serde_json::json!({
+        "emails": ["ursula@example.com"],
+        "set": { "country": "FR" },
+        "unset": ["vip"],
+    }))
+    .await
+    .error_for_status()
+    .unwrap();
+
+    // Assert
+    assert_eq!(
+        get_attributes(&app, "ursula@example.com").await,
+        serde_json::json!({ "country": "FR", "age": 42 })
+    );
+}
+
+#[actix_web::test]
+async fn nested_attributes_are_rejected() {
+    // Arrange
+    let app = spawn_app().await;
+    create_confirmed_subscriber_with_email(&app, "ursula@example.com").await;
+
+    // Act
+    let response = app
+        .post_subscriber_attributes(serde_json::json!({
+            "emails": ["ursula@example.com"],
+            "set": { "address": { "city": "Berlin" } },
+        }))
+        .await;
+
+    // Assert
+    assert_eq!(400, response.status().as_u16());
+    assert_eq!(
+        get_attributes(&app, "ursula@example.com").await,
+        serde_json::json!({})
+    );
+}
+
+#[actix_web::test]
+async fn issues_with_a_segment_only_reach_the_matching_subscribers() {
+    // Arrange
+    let app = spawn_app().await;
+    create_tagged_subscribers(&app).await;
+    Mock::given(path("/email/batch"))
+        .and(method("POST"))
+        .respond_with(|request: &Request| batch_response(request, None))
+        .mount(&app.email_server)
+        .await;
+
+    // Act
+    let response = app
+        .post_newsletters(serde_json::json!({
+            "title": "Newsletter title",
+            "content": {
+                "text": "Newsletter body as plain text",
+                "html": "<p>Newsletter body as HTML</p>",
+            },
+            "segment": "tag:rust AND NOT country=FR OR tag:go AND country=DE",
+        }))
+        .await;
+    app.dispatch_all_pending_emails().await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 200);
+    assert_eq!(
+        get_newsletter_recipients(&app).await,
+        HashSet::from([
+            "ursula@example.com".to_string(),
+            "gopher@example.com".to_string(),
+        ])
+    );
+}
+
+#[actix_web::test]
+async fn publishing_with_an_invalid_segment_is_rejected() {
+    // Arrange
+    let app = spawn_app().await;
+    create_tagged_subscribers(&app).await;
+    Mock::given(any())
+        .respond_with(ResponseTemplate::new(200))
+        .expect(0)
+        .mount(&app.email_server)
+        .await;
+
+    // Act
+    let response = app
+        .post_newsletters(serde_json::json!({
+            "title": "Newsletter title",
+            "content": {
+                "text": "Newsletter body as plain text",
+                "html": "<p>Newsletter body as HTML</p>",
+            },
+            "segment": "tag:rust AND (country=DE",
+        }))
+        .await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 400);
+}
+
+#[actix_web::test]
+async fn admins_can_tag_the_subscribers_of_a_segment() {
+    // Arrange
+    let app = spawn_app().await;
+    create_tagged_subscribers(&app).await;
+    app.test_user.login(&app).await;
+
+    // Act - Part 1 - Add a tag
+    let response = app
+        .post_admin_subscriber_tags(&serde_json::json!({
+            "segment": "country=DE",
+            "tag": "german",
+            "action": "add",
+        }))
+        .await;
+    assert_is_redirect_to(&response, "/admin/subscribers");
+
+    // Act - Part 2 - Follow the redirect
+    let html_page = app.get_admin_subscribers_html("").await;
+    assert!(html_page.contains("<p><i>The tag has been added to 2 subscribers.</i></p>"));
+
+    // Act - Part 3 - Remove it from some of them
+    app.post_admin_subscriber_tags(&serde_json::json!({
+        "segment": "tag:go",
+        "tag": "german",
+        "action": "remove",
+    }))
+    .await;
+    let subscriber_id = get_subscriber_id(&app, "ursula@example.com").await;
+    let html_page = app.get_admin_subscriber_html(subscriber_id).await;
+
+    // Assert
+    assert!(html_page.contains("<dt>Tags</dt><dd>german, rust</dd>"));
+    assert!(html_page.contains("<tr><td>country</td><td>DE</td></tr>"));
+    assert_eq!(get_tags(&app, "gopher@example.com").await, vec!["go"]);
+}
+
+#[actix_web::test]
+async fn admins_are_told_when_the_segment_is_invalid() {
+    // Arrange
+    let app = spawn_app().await;
+    create_tagged_subscribers(&app).await;
+    app.test_user.login(&app).await;
+
+    // Act
+    let response = app
+        .post_admin_subscriber_tags(&serde_json::json!({
+            "segment": "tag:rust OR",
+            "tag": "german",
+            "action": "add",
+        }))
+        .await;
+    assert_is_redirect_to(&response, "/admin/subscribers");
+    let html_page = app.get_admin_subscribers_html("").await;
+
+    // Assert
+    assert!(html_page.contains("<p><i>The segment is not valid"));
+    assert_eq!(get_tags(&app, "ursula@example.com").await, vec!["rust"]);
+}
+
+#[actix_web::test]
+async fn the_admin_page_of_a_draft_shows_how_many_subscribers_match_its_segment() {
+    // Arrange
+    let app = spawn_app().await;
+    create_tagged_subscribers(&app).await;
+    app.test_user.login(&app).await;
+
+    // Act
+    let response = app
+        .post_publish_newsletter(&serde_json::json!({
+            "title": "Newsletter title",
+            "text_content": "Newsletter body as plain text",
+            "html_content": "<p>Newsletter body as HTML</p>",
+            "segment": "tag:rust",
+            "idempotency_key": Uuid::new_v4().to_string(),
+        }))
+        .await;
+    let newsletter_issue_id: Uuid = response
+        .headers()
+        .get("Location")
+        .unwrap()
+        .to_str()
+        .unwrap()
+        .strip_prefix("/admin/newsletters/")
+        .unwrap()
+        .parse()
+        .unwrap();
+    let html_page = app.get_admin_newsletter_html(newsletter_issue_id).await;
+
+    // Assert
+    assert!(html_page.contains("<p>Segment: <code>tag:rust</code></p>"));
+    assert!(html_page.contains("<p>Matching subscribers: 2</p>"));
+}
@@ -0,0 +1,364 @@
+use crate::helpers::{
+    assert_is_redirect_to, batch_response, create_confirmed_subscriber_with_email, spawn_app,
+    TestApp,
+};
+use wiremock::matchers::{any, method, path};
+use wiremock::{Mock, Request, ResponseTemplate};
+
+async fn get_subscriber(app: &TestApp, email: &str) -> Option<(String, String)> {
+    sqlx::query!(
+        "SELECT name, status FROM subscriptions WHERE email = $1",
+        email
+    )
+    .fetch_optional(&app.db_pool)
+    .await
+    .unwrap()
+    .map(|r| (r.name, r.status))
+}
+
+async fn get_newsletter_list_status(app: &TestApp, email: &str) -> String {
+    sqlx::query_scalar!(
+        r#"
+        SELECT ls.status
+        FROM list_subscriptions ls
+        JOIN lists l ON l.list_id = ls.list_id
+        JOIN subscriptions s ON s.id = ls.subscriber_id
+        WHERE l.slug = 'newsletter' AND s.email = $1
+        "#,
+        email
+    )
+    .fetch_one(&app.db_pool)
+    .await
+    .unwrap()
+}
+
+#[actix_web::test]
+async fn you_must_be_logged_in_to_import_subscribers() {
+    // Arrange
+    let app = spawn_app().await;
+
+    // Act
+    let response = app
+        .post_import_subscribers("confirmed", "email,name\nursula@example.com,Ursula\n")
+        .await;
+
+    // Assert
+    assert_is_redirect_to(&response, "/login");
+    assert!(get_subscriber(&app, "ursula@example.com").await.is_none());
+}
+
+#[actix_web::test]
+async fn the_import_form_is_linked_from_the_subscribers_page() {
+    // Arrange
+    let app = spawn_app().await;
+    app.test_user.login(&app).await;
+
+    // Act
+    let subscribers_page = app.get_admin_subscribers_html("").await;
+    let import_page = app.get_import_subscribers_html().await;
+
+    // Assert
+    assert!(subscribers_page.contains(r#"<a href="/admin/subscribers/import">"#));
+    assert!(import_page.contains(r#"enctype="multipart/form-data""#));
+}
+
+#[actix_web::test]
+async fn confirmed_imports_receive_the_next_issue_without_confirming() {
+    // Arrange
+    let app = spawn_app().await;
+    app.test_user.login(&app).await;
+    Mock::given(path("/email/batch"))
+        .and(method("POST"))
+        .respond_with(|request: &Request| batch_response(request, None))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    // Act - Part 1 - Import
+    let response = app
+        .post_import_subscribers(
+            "confirmed",
+            "name,email,country\nUrsula,ursula@example.com,US\n\"Le Guin, Ursula\",le.guin@example.com,US\n",
+        )
+        .await;
+    assert_eq!(response.status().as_u16(), 200);
+    let report = response.text().await.unwrap();
+
+    // Act - Part 2 - Publish
+    app.post_newsletters(serde_json::json!({
+        "title": "Newsletter title",
+        "content": {
+            "text": "Newsletter body as plain text",
+            "html": "<p>Newsletter body as HTML</p>",
+        },
+    }))
+    .await
+    .error_for_status()
+    .unwrap();
+    app.dispatch_all_pending_emails().await;
+
+    // Assert
+    assert!(report.contains("<p>Imported subscribers: 2</p>"));
+    assert!(report.contains("<p>Errors: 0</p>"));
+    assert_eq!(
+        get_subscriber(&app, "le.guin@example.com").await,
+        Some(("Le Guin, Ursula".to_string(), "confirmed".to_string()))
+    );
+    assert_eq!(
+        get_newsletter_list_status(&app, "ursula@example.com").await,
+        "confirmed"
+    );
+    let batch: serde_json::Value =
+        serde_json::from_slice(&app.email_server.received_requests().await.unwrap()[0].body)
+            .unwrap();
+    assert_eq!(batch.as_array().unwrap().len(), 2);
+}
+
+#[actix_web::test]
+async fn imports_can_send_a_confirmation_email_to_each_new_subscriber() {
+    // Arrange
+    let app = spawn_app().await;
+    app.test_user.login(&app).await;
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(2)
+        .mount(&app.email_server)
+        .await;
+
+    // Act
+    let response = app
+        .post_import_subscribers(
+            "send_confirmation",
+            "email,name\nursula@example.com,Ursula\nferris@example.com,Ferris\n",
+        )
+        .await;
+    // The emails are sent in the background, not while the admin waits
+    assert!(app
+        .email_server
+        .received_requests()
+        .await
+        .unwrap()
+        .is_empty());
+    app.dispatch_all_pending_emails().await;
+    let email_request = &app.email_server.received_requests().await.unwrap()[0];
+    let confirmation_links = app.get_confirmation_links(email_request);
+    reqwest::get(confirmation_links.html)
+        .await
+        .unwrap()
+        .error_for_status()
+        .unwrap();
+
+    // Assert
+    assert!(response
+        .text()
+        .await
+        .unwrap()
+        .contains("<p>Imported subscribers: 2</p>"));
+    assert_eq!(
+        get_subscriber(&app, "ursula@example.com").await.unwrap().1,
+        "confirmed"
+    );
+    assert_eq!(
+        get_newsletter_list_status(&app, "ursula@example.com").await,
+        "confirmed"
+    );
+    assert_eq!(
+        get_subscriber(&app, "ferris@example.com").await.unwrap().1,
+        "pending_confirmation"
+    );
+}
+
+#[actix_web::test]
+async fn confirmation_emails_are_retried_as_the_retry_policy_says() {
+    // Arrange
+    let app = spawn_app().await;
+    app.test_user.login(&app).await;
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(503))
+        .expect(u64::from(app.retry_policy.max_attempts))
+        .mount(&app.email_server)
+        .await;
+
+    // Act
+    app.post_import_subscribers(
+        "send_confirmation",
+        "email,name\nursula@example.com,Ursula\n",
+    )
+    .await
+    .error_for_status()
+    .unwrap();
+    app.dispatch_all_pending_emails().await;
+
+    // Assert
+    assert_eq!(count_queued_confirmation_emails(&app).await, 0);
+}
+
+#[actix_web::test]
+async fn permanent_confirmation_email_failures_are_not_retried() {
+    // Arrange
+    let app = spawn_app().await;
+    app.test_user.login(&app).await;
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(422))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    // Act
+    app.post_import_subscribers(
+        "send_confirmation",
+        "email,name\nursula@example.com,Ursula\n",
+    )
+    .await
+    .error_for_status()
+    .unwrap();
+    app.dispatch_all_pending_emails().await;
+
+    // Assert
+    assert_eq!(count_queued_confirmation_emails(&app).await, 0);
+}
+
+async fn count_queued_confirmation_emails(app: &TestApp) -> i64 {
+    sqlx::query_scalar!("SELECT COUNT(*) AS \"count!\" FROM confirmation_email_queue")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap()
+}
+
+#[actix_web::test]
+async fn invalid_lines_are_reported_and_the_others_imported() {
+    // Arrange
+    let app = spawn_app().await;
+    app.test_user.login(&app).await;
+
+    // Act
+    let response = app
+        .post_import_subscribers(
+            "confirmed",
+            "email,name\nursula@example.com,Ursula\nnot-an-email,Someone\n\nferris@example.com,\nrustacean@example.com,Rustacean\n",
+        )
+        .await;
+    let report = response.text().await.unwrap();
+
+    // Assert
+    assert!(report.contains("<p>Imported subscribers: 2</p>"));
+    assert!(report.contains("<p>Errors: 2</p>"));
+    assert!(report.contains("<tr><td>3</td><td>Email address is not valid: not-an-email</td></tr>"));
+    assert!(report.contains("<tr><td>5</td><td> is not a valid subscriber name.</td></tr>"));
+    assert!(get_subscriber(&app, "rustacean@example.com")
+        .await
+        .is_some());
+    assert!(get_subscriber(&app, "ferris@example.com").await.is_none());
+}
+
+#[actix_web::test]
+async fn known_and_repeated_addresses_are_skipped() {
+    // Arrange
+    let app = spawn_app().await;
+    create_confirmed_subscriber_with_email(&app, "ursula@example.com").await;
+    sqlx::query!(
+        "UPDATE subscriptions SET status = 'unsubscribed' WHERE email = 'ursula@example.com'"
+    )
+    .execute(&app.db_pool)
+    .await
+    .unwrap();
+    app.test_user.login(&app).await;
+    Mock::given(any())
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    // Act
+    let response = app
+        .post_import_subscribers(
+            "send_confirmation",
+            "email,name\nursula@example.com,Ursula\nferris@example.com,Ferris\nferris@example.com,Ferris again\n",
+        )
+        .await;
+    let report = response.text().await.unwrap();
+    app.dispatch_all_pending_emails().await;
+
+    // Assert
+    assert!(report.contains("<p>Imported subscribers: 1</p>"));
+    assert!(report.contains("<p>Skipped, the address is already known: 2</p>"));
+    assert_eq!(
+        get_subscriber(&app, "ursula@example.com").await.unwrap().1,
+        "unsubscribed"
+    );
+    assert_eq!(
+        get_subscriber(&app, "ferris@example.com").await,
+        Some(("Ferris".to_string(), "pending_confirmation".to_string()))
+    );
+}
+
+#[actix_web::test]
+async fn files_without_email_and_name_columns_are_rejected() {
+    // Arrange
+    let app = spawn_app().await;
+    app.test_user.login(&app).await;
+
+    // Act - Part 1 - Upload
+    let response = app
+        .post_import_subscribers("confirmed", "ursula@example.com,Ursula\n")
+        .await;
+    assert_is_redirect_to(&response, "/admin/subscribers/import");
+
+    // Act - Part 2 - Follow the redirect
+    let html_page = app.get_import_subscribers_html().await;
+
+    // Assert
+    assert!(html_page.contains(
+        "<p><i>The first line of the file must name its columns, including email and name.</i></p>"
+    ));
+    assert!(get_subscriber(&app, "ursula@example.com").await.is_none());
+}
+
+#[actix_web::test]
+async fn an_import_stopped_by_an_invalid_line_reports_what_was_imported() {
+    // Arrange
+    let app = spawn_app().await;
+    app.test_user.login(&app).await;
+    let mut csv = "email,name\n".to_string();
+    for i in 0..500 {
+        csv.push_str(&format!("subscriber{}@example.com,Subscriber {}\n", i, i));
+    }
+    csv.push_str(&format!("ursula@example.com,{}\n", "U".repeat(100_000)));
+
+    // Act - Part 1 - Upload
+    let response = app.post_import_subscribers("confirmed", &csv).await;
+    assert_is_redirect_to(&response, "/admin/subscribers/import");
+
+    // Act - Part 2 - Follow the redirect
+    let html_page = app.get_import_subscribers_html().await;
+
+    // Assert
+    assert!(html_page.contains(
+        "<p><i>Line 502 is longer than 65536 bytes. The import stopped there, \
+        after importing 500 subscribers: import the fixed file again, \
+        the addresses already imported are skipped.</i></p>"
+    ));
+    assert!(get_subscriber(&app, "subscriber499@example.com")
+        .await
+        .is_some());
+    assert!(get_subscriber(&app, "ursula@example.com").await.is_none());
+}
+
+#[actix_web::test]
+async fn an_unknown_import_mode_is_rejected() {
+    // Arrange
+    let app = spawn_app().await;
+    app.test_user.login(&app).await;
+
+    // Act
+    let response = app
+        .post_import_subscribers("whatever", "email,name\nursula@example.com,Ursula\n")
+        .await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 400);
+    assert!(get_subscriber(&app, "ursula@example.com").await.is_none());
+}
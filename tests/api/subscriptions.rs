@@ -1,4 +1,4 @@
-use crate::helpers::spawn_app;
+use crate::helpers::{create_confirmed_subscriber, spawn_app};
 use wiremock::matchers::{method, path};
 use wiremock::{Mock, ResponseTemplate};
 
@@ -15,6 +15,7 @@ async fn subscribe_returns_200_for_valid_form() {
     // Perform
     let body = "name=le%20guin&email=ursula_le_guin%40gmail.com";
     let response = test_app.post_subscriptions(body.into()).await;
+    test_app.dispatch_confirmation_emails().await;
 
     // Assert
     assert_eq!(200, response.status().as_u16());
@@ -88,6 +89,7 @@ async fn subscribe_sends_confirmation_email_for_valid_data() {
 
     // Act
     test_app.post_subscriptions(body.into()).await;
+    test_app.dispatch_confirmation_emails().await;
 
     // Assert
     let email_request = &test_app.email_server.received_requests().await.unwrap()[0];
@@ -96,6 +98,36 @@ async fn subscribe_sends_confirmation_email_for_valid_data() {
     assert_eq!(confirmation_links.html, confirmation_links.text);
 }
 
+#[actix_web::test]
+async fn the_confirmation_email_greets_the_subscriber_by_name() {
+    // Arrange
+    let test_app = spawn_app().await;
+    let body = "name=Ursula%20%26%20co&email=ursula_le_guin%40gmail.com";
+
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&test_app.email_server)
+        .await;
+
+    // Act
+    test_app.post_subscriptions(body.into()).await;
+    test_app.dispatch_confirmation_emails().await;
+
+    // Assert
+    let email_request = &test_app.email_server.received_requests().await.unwrap()[0];
+    let body: serde_json::Value = serde_json::from_slice(&email_request.body).unwrap();
+    assert!(body["HtmlBody"]
+        .as_str()
+        .unwrap()
+        .contains("Welcome to our newsletter, Ursula &amp; co!"));
+    assert!(body["TextBody"]
+        .as_str()
+        .unwrap()
+        .contains("Welcome to our newsletter, Ursula & co!"));
+}
+
 #[actix_web::test]
 async fn subscribe_fails_if_there_is_a_fatal_database_error() {
     // Arrange
@@ -113,3 +145,150 @@ async fn subscribe_fails_if_there_is_a_fatal_database_error() {
     // Assert
     assert_eq!(response.status().as_u16(), 500);
 }
+
+#[actix_web::test]
+async fn subscribing_twice_while_pending_sends_the_same_confirmation_link_again() {
+    // Arrange
+    let app = spawn_app().await;
+    let body = "name=le%20guin&email=ursula_le_guin%40gmail.com";
+
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(2)
+        .mount(&app.email_server)
+        .await;
+
+    // Act
+    let first_response = app.post_subscriptions(body.into()).await;
+    app.dispatch_confirmation_emails().await;
+    let second_response = app.post_subscriptions(body.into()).await;
+    app.dispatch_confirmation_emails().await;
+
+    // Assert
+    assert_eq!(first_response.status().as_u16(), 200);
+    assert_eq!(second_response.status().as_u16(), 200);
+    let email_requests = app.email_server.received_requests().await.unwrap();
+    let first_links = app.get_confirmation_links(&email_requests[0]);
+    let second_links = app.get_confirmation_links(&email_requests[1]);
+    assert_eq!(first_links.html, second_links.html);
+
+    let n_subscribers = sqlx::query!(r#"SELECT COUNT(*) AS "count!" FROM subscriptions"#)
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap()
+        .count;
+    assert_eq!(n_subscribers, 1);
+}
+
+#[actix_web::test]
+async fn subscribing_again_once_confirmed_returns_200_without_sending_emails() {
+    // Arrange
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+    let body = "name=le%20guin&email=ursula_le_guin%40gmail.com";
+
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(0)
+        .mount(&app.email_server)
+        .await;
+
+    // Act
+    let response = app.post_subscriptions(body.into()).await;
+    app.dispatch_confirmation_emails().await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 200);
+    let saved = sqlx::query!("SELECT status FROM subscriptions")
+        .fetch_all(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(saved.len(), 1);
+    assert_eq!(saved[0].status, "confirmed");
+}
+
+#[actix_web::test]
+async fn known_and_new_addresses_get_the_same_response_even_if_emails_fail() {
+    // Arrange
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(500))
+        .expect(0)
+        .mount(&app.email_server)
+        .await;
+
+    // Act
+    let known_response = app
+        .post_subscriptions("name=le%20guin&email=ursula_le_guin%40gmail.com".into())
+        .await;
+    let new_response = app
+        .post_subscriptions("name=octavia&email=octavia_butler%40gmail.com".into())
+        .await;
+
+    // Assert
+    // The confirmation email is only sent later, by the background worker
+    assert_eq!(known_response.status().as_u16(), 200);
+    assert_eq!(new_response.status().as_u16(), 200);
+    let n_queued =
+        sqlx::query_scalar!(r#"SELECT COUNT(*) AS "count!" FROM confirmation_email_queue"#)
+            .fetch_one(&app.db_pool)
+            .await
+            .unwrap();
+    assert_eq!(n_queued, 1);
+}
+
+#[actix_web::test]
+async fn unsubscribed_subscribers_can_opt_in_again() {
+    // Arrange
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+    sqlx::query!("UPDATE subscriptions SET status = 'unsubscribed'")
+        .execute(&app.db_pool)
+        .await
+        .unwrap();
+    let body = "name=ursula&email=ursula_le_guin%40gmail.com";
+
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    // Act - Part 1 - Subscribe again
+    let response = app.post_subscriptions(body.into()).await;
+    assert_eq!(response.status().as_u16(), 200);
+    app.dispatch_confirmation_emails().await;
+    let saved = sqlx::query!("SELECT name, status FROM subscriptions")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(saved.name, "ursula");
+    assert_eq!(saved.status, "pending_confirmation");
+
+    // Act - Part 2 - Confirm
+    let email_request = app
+        .email_server
+        .received_requests()
+        .await
+        .unwrap()
+        .pop()
+        .unwrap();
+    let confirmation_links = app.get_confirmation_links(&email_request);
+    reqwest::get(confirmation_links.html)
+        .await
+        .unwrap()
+        .error_for_status()
+        .unwrap();
+
+    // Assert
+    let saved = sqlx::query!("SELECT status FROM subscriptions")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(saved.status, "confirmed");
+}
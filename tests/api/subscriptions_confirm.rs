@@ -1,6 +1,10 @@
-use crate::helpers::spawn_app;
+use crate::helpers::{
+    create_unconfirmed_subscriber, create_unconfirmed_subscriber_with_email, spawn_app, TestApp,
+};
+use std::time::Duration;
 use wiremock::matchers::{method, path};
 use wiremock::{Mock, ResponseTemplate};
+use zero2prod::subscription_cleanup::delete_stale_pending_subscriptions;
 
 #[actix_web::test]
 async fn confirmations_without_token_rejected_with_400() {
@@ -25,6 +29,7 @@ async fn link_returned_by_subscribe_returns_200_if_called() {
         .await;
 
     app.post_subscriptions(body.into()).await;
+    app.dispatch_confirmation_emails().await;
 
     let email_request = &app.email_server.received_requests().await.unwrap()[0];
     let confirmation_links = app.get_confirmation_links(email_request);
@@ -32,6 +37,12 @@ async fn link_returned_by_subscribe_returns_200_if_called() {
     let response = reqwest::get(confirmation_links.html).await.unwrap();
 
     assert_eq!(response.status().as_u16(), 200);
+    assert_eq!(
+        response.headers().get("Content-Type").unwrap(),
+        "text/html; charset=utf-8"
+    );
+    let html_page = response.text().await.unwrap();
+    assert!(html_page.contains("Subscription confirmed"));
 }
 
 #[actix_web::test]
@@ -47,6 +58,7 @@ async fn clicking_on_confirmation_link_confirms_subscriber() {
         .await;
 
     app.post_subscriptions(body.into()).await;
+    app.dispatch_confirmation_emails().await;
     let email_request = &app.email_server.received_requests().await.unwrap()[0];
     let confirmation_links = app.get_confirmation_links(email_request);
 
@@ -67,3 +79,158 @@ async fn clicking_on_confirmation_link_confirms_subscriber() {
     assert_eq!(saved.name, "le guin");
     assert_eq!(saved.status, "confirmed");
 }
+
+/// Pretend that all the subscription tokens were issued two days ago,
+/// past the one-day TTL of the test configuration.
+async fn expire_tokens(app: &TestApp) {
+    sqlx::query!("UPDATE subscription_tokens SET created_at = now() - interval '2 days'")
+        .execute(&app.db_pool)
+        .await
+        .unwrap();
+}
+
+#[actix_web::test]
+async fn unknown_tokens_are_rejected_with_401() {
+    // Arrange
+    let app = spawn_app().await;
+
+    // Act
+    let response = reqwest::get(&format!(
+        "{}/subscriptions/confirm?subscription_token=aB3dE5gH7jK9mN1pQ3sT5vW7y",
+        &app.address
+    ))
+    .await
+    .unwrap();
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 401);
+    let html_page = response.text().await.unwrap();
+    assert!(html_page.contains("Unknown confirmation link"));
+}
+
+#[actix_web::test]
+async fn malformed_tokens_are_rejected_with_400_without_querying_the_database() {
+    // Arrange
+    let app = spawn_app().await;
+    // Sabotage the database: any query on the tokens would fail with a 500
+    sqlx::query!("ALTER TABLE subscription_tokens DROP COLUMN subscription_token;")
+        .execute(&app.db_pool)
+        .await
+        .unwrap();
+
+    let test_cases = vec![
+        ("short", "too short"),
+        ("aB3dE5gH7jK9mN1pQ3sT5vW7yZ", "too long"),
+        ("aB3dE5gH7jK9mN1pQ3sT5vW7%21", "non alphanumeric"),
+    ];
+
+    for (token, description) in test_cases {
+        // Act
+        let response = reqwest::get(&format!(
+            "{}/subscriptions/confirm?subscription_token={}",
+            &app.address, token
+        ))
+        .await
+        .unwrap();
+
+        // Assert
+        assert_eq!(
+            response.status().as_u16(),
+            400,
+            "The API did not fail with 400 Bad Request when the token was {}.",
+            description
+        );
+        let html_page = response.text().await.unwrap();
+        assert!(html_page.contains("Invalid confirmation link"));
+    }
+}
+
+#[actix_web::test]
+async fn a_confirmation_link_can_only_be_used_once() {
+    // Arrange
+    let app = spawn_app().await;
+    let confirmation_links = create_unconfirmed_subscriber(&app).await;
+    reqwest::get(confirmation_links.html.clone())
+        .await
+        .unwrap()
+        .error_for_status()
+        .unwrap();
+
+    // Act
+    let response = reqwest::get(confirmation_links.html).await.unwrap();
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 409);
+    let html_page = response.text().await.unwrap();
+    assert!(html_page.contains("Subscription already confirmed"));
+}
+
+#[actix_web::test]
+async fn expired_confirmation_links_are_rejected_with_410() {
+    // Arrange
+    let app = spawn_app().await;
+    let confirmation_links = create_unconfirmed_subscriber(&app).await;
+    expire_tokens(&app).await;
+
+    // Act
+    let response = reqwest::get(confirmation_links.html).await.unwrap();
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 410);
+    assert!(response
+        .text()
+        .await
+        .unwrap()
+        .contains("Expired confirmation link"));
+    let saved = sqlx::query!("SELECT status FROM subscriptions")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(saved.status, "pending_confirmation");
+}
+
+#[actix_web::test]
+async fn subscribing_again_after_the_token_expired_sends_a_new_token() {
+    // Arrange
+    let app = spawn_app().await;
+    let expired_links = create_unconfirmed_subscriber(&app).await;
+    expire_tokens(&app).await;
+
+    // Act
+    let new_links = create_unconfirmed_subscriber(&app).await;
+
+    // Assert
+    assert_ne!(expired_links.html, new_links.html);
+    reqwest::get(new_links.html)
+        .await
+        .unwrap()
+        .error_for_status()
+        .unwrap();
+}
+
+#[actix_web::test]
+async fn stale_pending_subscriptions_are_cleaned_up() {
+    // Arrange
+    let app = spawn_app().await;
+    create_unconfirmed_subscriber(&app).await;
+    sqlx::query!("UPDATE subscriptions SET subscribed_at = now() - interval '2 days'")
+        .execute(&app.db_pool)
+        .await
+        .unwrap();
+    expire_tokens(&app).await;
+    create_unconfirmed_subscriber_with_email(&app, "octavia_butler@gmail.com").await;
+
+    // Act
+    let n_deleted = delete_stale_pending_subscriptions(&app.db_pool, Duration::from_secs(86400))
+        .await
+        .unwrap();
+
+    // Assert
+    assert_eq!(n_deleted, 1);
+    let remaining = sqlx::query!("SELECT email FROM subscriptions")
+        .fetch_all(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(remaining.len(), 1);
+    assert_eq!(remaining[0].email, "octavia_butler@gmail.com");
+}
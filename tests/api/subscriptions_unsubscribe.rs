@@ -0,0 +1,218 @@
+use crate::helpers::{
+    create_confirmed_subscriber, create_unconfirmed_subscriber, spawn_app, TestApp,
+};
+use reqwest::Url;
+use wiremock::matchers::{any, method, path};
+use wiremock::{Mock, ResponseTemplate};
+
+async fn publish_newsletter(app: &TestApp) {
+    let newsletter_request_body = serde_json::json!({
+        "title": "Newsletter title",
+        "content": {
+            "text": "Newsletter body as plain text",
+            "html": "<p>Newsletter body as HTML</p>",
+        }
+    });
+    app.post_newsletters(newsletter_request_body)
+        .await
+        .error_for_status()
+        .unwrap();
+}
+
+/// Deliver a newsletter issue to the (only) confirmed subscriber
+/// and return the unsubscribe link it carries.
+async fn receive_unsubscribe_link(app: &TestApp) -> Url {
+    let _mock_guard = Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount_as_scoped(&app.email_server)
+        .await;
+    publish_newsletter(app).await;
+    app.dispatch_all_pending_emails().await;
+
+    let email_request = app
+        .email_server
+        .received_requests()
+        .await
+        .unwrap()
+        .pop()
+        .unwrap();
+    app.get_unsubscribe_link(&email_request)
+}
+
+/// The status of the subscriber on the list the issues are sent to.
+async fn get_status(app: &TestApp) -> String {
+    sqlx::query_scalar!("SELECT status FROM list_subscriptions")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap()
+}
+
+#[actix_web::test]
+async fn newsletters_carry_an_unsubscribe_link_and_headers() {
+    // Arrange
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+
+    // Act
+    let unsubscribe_link = receive_unsubscribe_link(&app).await;
+
+    // Assert
+    let email_request = app
+        .email_server
+        .received_requests()
+        .await
+        .unwrap()
+        .pop()
+        .unwrap();
+    let body: serde_json::Value = serde_json::from_slice(&email_request.body).unwrap();
+    let headers = body["Headers"].as_array().unwrap();
+    assert!(headers.contains(&serde_json::json!({
+        "Name": "List-Unsubscribe-Post",
+        "Value": "List-Unsubscribe=One-Click"
+    })));
+
+    let mut link_in_body = unsubscribe_link.clone();
+    link_in_body.set_port(None).unwrap();
+    assert!(body["HtmlBody"]
+        .as_str()
+        .unwrap()
+        .contains(link_in_body.as_str()));
+    assert!(body["TextBody"]
+        .as_str()
+        .unwrap()
+        .contains(link_in_body.as_str()));
+}
+
+#[actix_web::test]
+async fn the_unsubscribe_link_shows_a_confirmation_form_without_unsubscribing() {
+    // Arrange
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+    let unsubscribe_link = receive_unsubscribe_link(&app).await;
+
+    // Act
+    let response = app.get_unsubscribe(&unsubscribe_link).await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 200);
+    let html_page = response.text().await.unwrap();
+    assert!(html_page.contains(r#"method="post""#));
+    assert_eq!(get_status(&app).await, "confirmed");
+}
+
+#[actix_web::test]
+async fn one_click_unsubscribe_marks_the_subscriber_as_unsubscribed_from_the_list() {
+    // Arrange
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+    let unsubscribe_link = receive_unsubscribe_link(&app).await;
+
+    // Act
+    let response = app.post_unsubscribe(&unsubscribe_link).await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 200);
+    assert_eq!(get_status(&app).await, "unsubscribed");
+}
+
+#[actix_web::test]
+async fn unsubscribed_subscribers_do_not_receive_newsletters() {
+    // Arrange
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+    let unsubscribe_link = receive_unsubscribe_link(&app).await;
+    app.post_unsubscribe(&unsubscribe_link)
+        .await
+        .error_for_status()
+        .unwrap();
+
+    Mock::given(any())
+        .respond_with(ResponseTemplate::new(200))
+        .expect(0)
+        .mount(&app.email_server)
+        .await;
+
+    // Act
+    publish_newsletter(&app).await;
+    app.dispatch_all_pending_emails().await;
+
+    // Assert
+    // Mock verifies on Drop that we haven't sent the newsletter email
+}
+
+#[actix_web::test]
+async fn deliveries_already_queued_are_skipped_after_unsubscribing() {
+    // Arrange
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+    let unsubscribe_link = receive_unsubscribe_link(&app).await;
+    // The next issue is enqueued, but not delivered yet
+    publish_newsletter(&app).await;
+
+    Mock::given(any())
+        .respond_with(ResponseTemplate::new(200))
+        .expect(0)
+        .mount(&app.email_server)
+        .await;
+
+    // Act
+    app.post_unsubscribe(&unsubscribe_link)
+        .await
+        .error_for_status()
+        .unwrap();
+    app.dispatch_all_pending_emails().await;
+
+    // Assert
+    // Mock verifies on Drop that we haven't sent the newsletter email
+}
+
+#[actix_web::test]
+async fn a_tampered_token_is_rejected_with_a_401() {
+    // Arrange
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+    let mut unsubscribe_link = receive_unsubscribe_link(&app).await;
+    let token = unsubscribe_link
+        .query_pairs()
+        .find(|(key, _)| key == "token")
+        .unwrap()
+        .1
+        .into_owned();
+    let last_char = if token.ends_with('A') { 'B' } else { 'A' };
+    let tampered_token = format!("{}{}", &token[..token.len() - 1], last_char);
+    unsubscribe_link.set_query(Some(&format!("token={}", tampered_token)));
+
+    // Act
+    let get_response = app.get_unsubscribe(&unsubscribe_link).await;
+    let post_response = app.post_unsubscribe(&unsubscribe_link).await;
+
+    // Assert
+    assert_eq!(get_response.status().as_u16(), 401);
+    assert_eq!(post_response.status().as_u16(), 401);
+    assert_eq!(get_status(&app).await, "confirmed");
+}
+
+#[actix_web::test]
+async fn an_old_confirmation_link_does_not_resubscribe() {
+    // Arrange
+    let app = spawn_app().await;
+    let confirmation_link = create_unconfirmed_subscriber(&app).await;
+    reqwest::get(confirmation_link.html.clone())
+        .await
+        .unwrap()
+        .error_for_status()
+        .unwrap();
+    let unsubscribe_link = receive_unsubscribe_link(&app).await;
+    app.post_unsubscribe(&unsubscribe_link)
+        .await
+        .error_for_status()
+        .unwrap();
+
+    // Act
+    reqwest::get(confirmation_link.html).await.unwrap();
+
+    // Assert
+    assert_eq!(get_status(&app).await, "unsubscribed");
+}
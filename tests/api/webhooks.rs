@@ -0,0 +1,371 @@
+use crate::helpers::{create_confirmed_subscriber, spawn_app, TestApp};
+use uuid::Uuid;
+use wiremock::matchers::{any, method, path};
+use wiremock::{Mock, ResponseTemplate};
+
+fn bounce(bounce_type: &str, email: &str) -> serde_json::Value {
+    serde_json::json!({
+        "RecordType": "Bounce",
+        "ID": 4323372036854775807u64,
+        "Type": bounce_type,
+        "TypeCode": 1,
+        "Name": "Hard bounce",
+        "MessageID": "883953f4-6105-42a2-a16a-77a8eac79483",
+        "Description": "The server was unable to deliver your message",
+        "Details": "smtp;550 5.1.1 The email account that you tried to reach does not exist",
+        "Email": email,
+        "From": "sender@example.com",
+        "BouncedAt": "2025-04-16T16:33:54.9070259Z",
+        "Inactive": true,
+        "CanActivate": true,
+        "Subject": "Newsletter title"
+    })
+}
+
+fn spam_complaint(email: &str) -> serde_json::Value {
+    serde_json::json!({
+        "RecordType": "SpamComplaint",
+        "ID": 42,
+        "Type": "SpamComplaint",
+        "TypeCode": 512,
+        "MessageID": "00000000-0000-0000-0000-000000000000",
+        "Details": "Test spam complaint details",
+        "Email": email,
+        "BouncedAt": "2025-04-16T16:33:54Z",
+        "Inactive": true
+    })
+}
+
+fn delivery(email: &str) -> serde_json::Value {
+    serde_json::json!({
+        "RecordType": "Delivery",
+        "ServerID": 23,
+        "MessageStream": "outbound",
+        "MessageID": "00000000-0000-0000-0000-000000000000",
+        "Recipient": email,
+        "Tag": "",
+        "DeliveredAt": "2025-04-16T16:33:54.9070259Z",
+        "Details": "Test delivery webhook details"
+    })
+}
+
+async fn subscriber_status(app: &TestApp) -> String {
+    sqlx::query!("SELECT status FROM subscriptions")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap()
+        .status
+}
+
+async fn recorded_events(app: &TestApp) -> Vec<(String, Option<String>)> {
+    sqlx::query!("SELECT record_type, bounce_type FROM delivery_events ORDER BY occurred_at")
+        .fetch_all(&app.db_pool)
+        .await
+        .unwrap()
+        .into_iter()
+        .map(|r| (r.record_type, r.bounce_type))
+        .collect()
+}
+
+#[actix_web::test]
+async fn requests_without_credentials_are_rejected() {
+    // Arrange
+    let app = spawn_app().await;
+
+    // Act
+    let response = app
+        .api_client
+        .post(format!("{}/webhooks/email", &app.address))
+        .json(&delivery("ursula_le_guin@gmail.com"))
+        .send()
+        .await
+        .unwrap();
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 401);
+    assert_eq!(
+        r#"Basic realm="webhooks""#,
+        response.headers()["WWW-Authenticate"]
+    );
+}
+
+#[actix_web::test]
+async fn requests_with_the_wrong_password_are_rejected() {
+    // Arrange
+    let app = spawn_app().await;
+
+    // Act
+    let response = app
+        .api_client
+        .post(format!("{}/webhooks/email", &app.address))
+        .basic_auth(&app.webhooks.username, Some("not-the-password"))
+        .json(&delivery("ursula_le_guin@gmail.com"))
+        .send()
+        .await
+        .unwrap();
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 401);
+    assert!(recorded_events(&app).await.is_empty());
+}
+
+#[actix_web::test]
+async fn malformed_events_are_rejected_with_400() {
+    // Arrange
+    let app = spawn_app().await;
+    let test_cases = [
+        (
+            serde_json::json!({ "Email": "ursula_le_guin@gmail.com" }),
+            "missing record type",
+        ),
+        (
+            serde_json::json!({ "RecordType": "Bounce", "Type": "HardBounce" }),
+            "missing fields",
+        ),
+    ];
+
+    for (body, description) in test_cases {
+        // Act
+        let response = app.post_email_webhook(&body).await;
+
+        // Assert
+        assert_eq!(
+            response.status().as_u16(),
+            400,
+            "The webhook did not fail with 400 Bad Request when the payload had {}.",
+            description
+        );
+    }
+}
+
+#[actix_web::test]
+async fn a_hard_bounce_suppresses_the_subscriber() {
+    // Arrange
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+
+    // Act
+    let response = app
+        .post_email_webhook(&bounce("HardBounce", "ursula_le_guin@gmail.com"))
+        .await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 200);
+    assert_eq!(subscriber_status(&app).await, "suppressed");
+    assert_eq!(
+        recorded_events(&app).await,
+        [("Bounce".to_string(), Some("HardBounce".to_string()))]
+    );
+}
+
+#[actix_web::test]
+async fn a_soft_bounce_is_recorded_without_suppressing_the_subscriber() {
+    // Arrange
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+
+    // Act
+    let response = app
+        .post_email_webhook(&bounce("SoftBounce", "ursula_le_guin@gmail.com"))
+        .await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 200);
+    assert_eq!(subscriber_status(&app).await, "confirmed");
+    assert_eq!(
+        recorded_events(&app).await,
+        [("Bounce".to_string(), Some("SoftBounce".to_string()))]
+    );
+}
+
+#[actix_web::test]
+async fn a_spam_complaint_suppresses_the_subscriber() {
+    // Arrange
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+
+    // Act
+    let response = app
+        .post_email_webhook(&spam_complaint("ursula_le_guin@gmail.com"))
+        .await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 200);
+    assert_eq!(subscriber_status(&app).await, "suppressed");
+    assert_eq!(
+        recorded_events(&app).await,
+        [(
+            "SpamComplaint".to_string(),
+            Some("SpamComplaint".to_string())
+        )]
+    );
+}
+
+#[actix_web::test]
+async fn deliveries_are_recorded() {
+    // Arrange
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+
+    // Act
+    let response = app
+        .post_email_webhook(&delivery("ursula_le_guin@gmail.com"))
+        .await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 200);
+    assert_eq!(subscriber_status(&app).await, "confirmed");
+    assert_eq!(
+        recorded_events(&app).await,
+        [("Delivery".to_string(), None)]
+    );
+}
+
+#[actix_web::test]
+async fn events_delivered_twice_are_recorded_once() {
+    // Arrange
+    let app = spawn_app().await;
+    let event = bounce("SoftBounce", "ursula_le_guin@gmail.com");
+
+    // Act
+    let response1 = app.post_email_webhook(&event).await;
+    let response2 = app.post_email_webhook(&event).await;
+
+    // Assert
+    assert_eq!(response1.status().as_u16(), 200);
+    assert_eq!(response2.status().as_u16(), 200);
+    assert_eq!(recorded_events(&app).await.len(), 1);
+}
+
+#[actix_web::test]
+async fn unsupported_events_are_acknowledged() {
+    // Arrange
+    let app = spawn_app().await;
+
+    // Act
+    let response = app
+        .post_email_webhook(&serde_json::json!({
+            "RecordType": "Open",
+            "MessageID": "00000000-0000-0000-0000-000000000000",
+            "Recipient": "ursula_le_guin@gmail.com"
+        }))
+        .await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 200);
+    assert!(recorded_events(&app).await.is_empty());
+}
+
+#[actix_web::test]
+async fn suppressed_subscribers_do_not_receive_newsletters() {
+    // Arrange
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+    app.post_email_webhook(&bounce("HardBounce", "ursula_le_guin@gmail.com"))
+        .await
+        .error_for_status()
+        .unwrap();
+
+    Mock::given(any())
+        .respond_with(ResponseTemplate::new(200))
+        .expect(0)
+        .mount(&app.email_server)
+        .await;
+
+    // Act
+    let newsletter_request_body = serde_json::json!({
+        "title": "Newsletter title",
+        "content": {
+            "text": "Newsletter body as plain text",
+            "html": "<p>Newsletter body as HTML</p>",
+        }
+    });
+    let response = app.post_newsletters(newsletter_request_body).await;
+    app.dispatch_all_pending_emails().await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 200);
+}
+
+#[actix_web::test]
+async fn queued_deliveries_are_dropped_when_a_subscriber_is_suppressed() {
+    // Arrange
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+    let newsletter_request_body = serde_json::json!({
+        "title": "Newsletter title",
+        "content": {
+            "text": "Newsletter body as plain text",
+            "html": "<p>Newsletter body as HTML</p>",
+        }
+    });
+    app.post_newsletters(newsletter_request_body).await;
+
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(0)
+        .mount(&app.email_server)
+        .await;
+
+    // Act
+    app.post_email_webhook(&spam_complaint("ursula_le_guin@gmail.com"))
+        .await
+        .error_for_status()
+        .unwrap();
+    app.dispatch_all_pending_emails().await;
+
+    // Assert
+    let n_pending_tasks = sqlx::query!("SELECT COUNT(*) as \"count!\" FROM issue_delivery_queue")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap()
+        .count;
+    assert_eq!(n_pending_tasks, 0);
+}
+
+#[actix_web::test]
+async fn hard_bounces_are_counted_against_their_issue() {
+    // Arrange
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+    let response = app
+        .post_newsletters(serde_json::json!({
+            "title": "Newsletter title",
+            "content": {
+                "html": "<p>Newsletter body as HTML</p>",
+            }
+        }))
+        .await;
+    let body: serde_json::Value = response.json().await.unwrap();
+    let newsletter_issue_id: Uuid = body["newsletter_issue_id"]
+        .as_str()
+        .unwrap()
+        .parse()
+        .unwrap();
+    let mut soft_bounce = bounce("SoftBounce", "ursula_le_guin@gmail.com");
+    soft_bounce["MessageID"] = serde_json::json!("00000000-0000-0000-0000-000000000001");
+    soft_bounce["Metadata"] = serde_json::json!({ "newsletter_issue_id": newsletter_issue_id });
+    let mut hard_bounce = bounce("HardBounce", "ursula_le_guin@gmail.com");
+    hard_bounce["Metadata"] = serde_json::json!({ "newsletter_issue_id": newsletter_issue_id });
+
+    // Act - Postmark might deliver the same event twice
+    for event in [&soft_bounce, &hard_bounce, &hard_bounce] {
+        app.post_email_webhook(event)
+            .await
+            .error_for_status()
+            .unwrap();
+    }
+
+    // Assert
+    let stats = app.get_newsletter_stats(newsletter_issue_id).await;
+    assert_eq!(stats["bounced"], 1);
+    let recorded_issue_ids = sqlx::query_scalar!("SELECT newsletter_issue_id FROM delivery_events")
+        .fetch_all(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(
+        recorded_issue_ids,
+        vec![Some(newsletter_issue_id), Some(newsletter_issue_id)]
+    );
+}